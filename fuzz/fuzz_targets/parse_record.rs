@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// run with `cargo fuzz run parse_record`, parsing arbitrary bytes must return an error instead of panicking
+fuzz_target!(|data: &[u8]| {
+    let _ = tren::parser::parse_record(data);
+});
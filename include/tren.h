@@ -0,0 +1,35 @@
+/* C ABI of the tren payment engine, available when the library is built with `--features cdylib`, e.g.
+ * `cargo rustc --lib --release --features cdylib --crate-type cdylib` */
+#ifndef TREN_H
+#define TREN_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define TREN_OK 0
+#define TREN_NULL -1
+#define TREN_INVALID_RECORD -2
+#define TREN_PANIC -3
+
+typedef struct Engine TrenEngine;
+
+/* Creates engine with empty accounts, release it with tren_engine_free, NULL if the engine panicked */
+TrenEngine *tren_engine_new(void);
+
+/* Applies single CSV record in the default column order, e.g. "deposit,1,1,10.5" */
+int tren_engine_submit(TrenEngine *engine, const char *record);
+
+/* Writes the report as NUL-terminated JSON array into the buffer if it fits, returns its size including the NUL,
+ * 0 if the engine is NULL or panicked */
+size_t tren_engine_report_json(const TrenEngine *engine, char *buffer, size_t len);
+
+void tren_engine_free(TrenEngine *engine);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -1,93 +1,1138 @@
 use crate::aliases::*;
-use eyre::{eyre, Result};
-use rust_decimal::Decimal;
+use crate::amount::Rounding;
+use crate::channel::TransactionKind;
+use crate::fees::{Fee, FeeSchedule};
+use crate::fraud::{FraudCounters, FraudRules};
+use crate::limits::LimitPolicy;
+use crate::memory;
+use crate::report::ReportVersion;
+use crate::segments::Segments;
+use crate::state::AccountState;
+use crossbeam_channel::Sender;
+use eyre::{eyre, Context};
 use serde::Deserializer;
-use std::collections::HashMap;
-use tracing::warn;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use tracing::{debug, error, warn};
+
+/// Number of clients formatted at once by a thread when the report is written on multiple threads
+const REPORT_CHUNK: usize = 16_384;
 
 #[derive(Default)]
-pub struct Accounts(HashMap<ClientID, AccountDetails>);
+pub struct Accounts {
+    accounts: HashMap<ClientID, AccountDetails>,
+    /// Decides what happens when disputed amount is higher than client's available funds
+    dispute_policy: DisputePolicy,
+    /// Decides how disputes of withdrawals change the balances
+    withdrawal_disputes: WithdrawalDisputePolicy,
+    /// Disputes of client who already has this many open disputes are rejected and the account is flagged
+    max_open_disputes: Option<usize>,
+    /// Rules used to flag suspicious clients, evaluated against client's [FraudCounters]
+    fraud_rules: FraudRules,
+    /// Deposit, withdrawal and balance limits checked before the operation is applied
+    limits: LimitPolicy,
+    /// Fees of withdrawals and chargebacks and the house account they are credited to
+    fees: FeeSchedule,
+    /// Resolve of the charged back transaction unfreezes the account frozen by the chargeback
+    unfreeze_on_resolve: bool,
+    /// Report has extra columns with counts of open disputes and chargebacks
+    extended_report: bool,
+    /// Report ends with a row of balances summed over all accounts
+    totals_row: bool,
+    /// Columns describing state of the account in the report
+    report_version: ReportVersion,
+    /// If set, the report has extra `segment` column with the segment of the client
+    segments: Option<Segments>,
+    /// If set, rows of the report are formatted on this many threads, see [Accounts::with_report_threads]
+    report_threads: Option<usize>,
+    /// If set, amounts are rounded to [crate::amount::PRECISION] decimal places before they reach [AccountDetails]
+    /// and in the report, otherwise they are kept exactly as they are in the journal
+    rounding: Option<Rounding>,
+    /// If set, balances in the report are written with exactly this many decimal places, see [crate::currency]
+    report_scale: Option<u32>,
+    /// Money moved into and out of the accounts by applied operations
+    movements: Movements,
+    /// Number of processed operations, used as the time axis by the fraud rules
+    sequence: u64,
+    /// Settled accounts are evicted from memory every this many operations, see [Accounts::evict_settled]
+    eviction_interval: Option<u64>,
+    /// Clients whose accounts were evicted, their accounts are in the same state as newly opened ones
+    /// and are restored once the client is seen again
+    evicted: HashSet<ClientID>,
+}
 
 impl Accounts {
+    pub fn new(dispute_policy: DisputePolicy) -> Self {
+        Accounts {
+            accounts: HashMap::new(),
+            dispute_policy,
+            withdrawal_disputes: WithdrawalDisputePolicy::default(),
+            max_open_disputes: None,
+            fraud_rules: FraudRules::default(),
+            limits: LimitPolicy::default(),
+            fees: FeeSchedule::default(),
+            unfreeze_on_resolve: false,
+            extended_report: false,
+            totals_row: false,
+            report_version: ReportVersion::default(),
+            segments: None,
+            report_threads: None,
+            rounding: None,
+            report_scale: None,
+            movements: Movements::default(),
+            sequence: 0,
+            eviction_interval: None,
+            evicted: HashSet::new(),
+        }
+    }
+
+    pub fn with_withdrawal_disputes(
+        mut self,
+        withdrawal_disputes: WithdrawalDisputePolicy,
+    ) -> Self {
+        self.withdrawal_disputes = withdrawal_disputes;
+        self
+    }
+
+    /// Caps the number of open disputes per client, so a journal spamming disputes can't grow the held ledger
+    /// without bounds. Disputes over the cap are rejected and the account is flagged
+    pub fn with_max_open_disputes(mut self, max_open_disputes: Option<usize>) -> Self {
+        self.max_open_disputes = max_open_disputes;
+        self
+    }
+
+    pub fn with_fraud_rules(mut self, fraud_rules: FraudRules) -> Self {
+        self.fraud_rules = fraud_rules;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: LimitPolicy) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_fees(mut self, fees: FeeSchedule) -> Self {
+        self.fees = fees;
+        self
+    }
+
+    pub fn with_unfreeze_on_resolve(mut self, unfreeze_on_resolve: bool) -> Self {
+        self.unfreeze_on_resolve = unfreeze_on_resolve;
+        self
+    }
+
+    pub fn with_extended_report(mut self, extended_report: bool) -> Self {
+        self.extended_report = extended_report;
+        self
+    }
+
+    pub fn with_totals_row(mut self, totals_row: bool) -> Self {
+        self.totals_row = totals_row;
+        self
+    }
+
+    pub fn with_report_version(mut self, report_version: ReportVersion) -> Self {
+        self.report_version = report_version;
+        self
+    }
+
+    pub fn with_segments(mut self, segments: Option<Segments>) -> Self {
+        self.segments = segments;
+        self
+    }
+
+    /// Formats rows of the report on multiple threads, the report is sorted by client then. Worth it only
+    /// for millions of accounts, a single thread keeps the unsorted report
+    pub fn with_report_threads(mut self, threads: Option<usize>) -> Self {
+        self.report_threads = threads.filter(|threads| *threads > 1);
+        self
+    }
+
+    pub fn with_rounding(mut self, rounding: Option<Rounding>) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    /// Writes balances in the report with the decimal scale of the journal's currency
+    pub fn with_report_scale(mut self, scale: Option<u32>) -> Self {
+        self.report_scale = scale;
+        self
+    }
+
+    /// Evicts settled accounts every `interval` operations, see [Accounts::evict_settled]
+    pub fn with_eviction_interval(mut self, interval: Option<u64>) -> Self {
+        self.eviction_interval = interval.filter(|interval| *interval > 0);
+        self
+    }
+
+    /// Rounds the amount by the configured [Rounding], amounts are kept as they are without one
+    fn round(&self, amount: Amount) -> Amount {
+        self.rounding
+            .map_or(amount, |rounding| rounding.round(amount))
+    }
+
+    /// Balance as written in the report, rounded and then scaled by [Accounts::with_report_scale]
+    fn reported(&self, amount: Amount) -> ReportedAmount {
+        let amount = self.round(amount);
+        match self.report_scale {
+            Some(scale) => ReportedAmount::Scaled(crate::currency::with_scale(amount, scale)),
+            None => ReportedAmount::Exact(amount),
+        }
+    }
+
+    /// Moves the sequence used by velocity rules, should be called once per processed record
+    pub fn advance_sequence(&mut self) {
+        self.sequence += 1;
+        if let Some(interval) = self.eviction_interval {
+            if self.sequence.is_multiple_of(interval) {
+                self.evict_settled();
+            }
+        }
+    }
+
+    /// Removes accounts which are in the same state as newly opened ones from memory, only their clients
+    /// are remembered. That is active and not flagged accounts with zero balances, no open disputes and
+    /// no chargebacks, for example clients who deposited once and withdrew everything. They are restored
+    /// once the client is seen again and still show up in the report. Nothing is evicted with [FraudRules],
+    /// they depend on the history of the account. Returns number of evicted accounts
+    pub fn evict_settled(&mut self) -> usize {
+        if self.fraud_rules != FraudRules::default() {
+            return 0;
+        }
+        let evicted = &mut self.evicted;
+        let before = evicted.len();
+        self.accounts.retain(|client_id, details| {
+            let settled = details.is_settled();
+            if settled {
+                evicted.insert(*client_id);
+            }
+            !settled
+        });
+        let count = evicted.len() - before;
+        debug!(%count, "evicted settled accounts");
+        count
+    }
+
+    /// Number of accounts currently evicted from memory
+    pub fn evicted_count(&self) -> usize {
+        self.evicted.len()
+    }
+
+    /// Estimated memory of the accounts and evicted clients, see [crate::memory]
+    pub fn estimated_memory(&self) -> u64 {
+        memory::map_bytes::<ClientID, AccountDetails>(self.accounts.len())
+            .saturating_add(memory::map_bytes::<ClientID, ()>(self.evicted.len()))
+    }
+
+    /// Releases memory of the evicted accounts, [Accounts::evict_settled] keeps it for new accounts
+    pub fn shrink_to_fit(&mut self) {
+        self.accounts.shrink_to_fit();
+    }
+
+    /// Brings evicted account back into memory before it is changed
+    fn restore(&mut self, client_id: ClientID) {
+        if self.evicted.remove(&client_id) {
+            self.accounts.insert(client_id, AccountDetails::default());
+        }
+    }
+
+    /// Flags the account by the rule, e.g. a validation rule, operations are still allowed.
+    /// Clients without an account are not flagged, returns whether the account was flagged
+    pub fn flag(&mut self, client_id: ClientID, rule: &str) -> bool {
+        self.restore(client_id);
+        match self.accounts.get_mut(&client_id) {
+            Some(acc_details) => {
+                acc_details.flag(client_id, rule);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Number of accounts flagged by the fraud screening or the validation rules
+    pub fn flagged_count(&self) -> usize {
+        self.accounts.values().filter(|acc| acc.flagged).count()
+    }
+
+    /// Returns current state of client's account, `None` if the client has no account
+    pub fn get(&self, client_id: ClientID) -> Option<AccountView> {
+        match self.accounts.get(&client_id) {
+            Some(details) => Some(AccountView::new(client_id, details)),
+            None => self
+                .evicted
+                .contains(&client_id)
+                .then(|| AccountView::new(client_id, &AccountDetails::default())),
+        }
+    }
+
+    /// Iterates over all accounts in no particular order, evicted ones included
+    pub fn iter(&self) -> impl Iterator<Item = AccountView> + '_ {
+        let evicted = AccountDetails::default();
+        self.accounts
+            .iter()
+            .map(|(client_id, details)| AccountView::new(*client_id, details))
+            .chain(
+                self.evicted
+                    .iter()
+                    .map(move |client_id| AccountView::new(*client_id, &evicted)),
+            )
+    }
+
+    /// Number of accounts, evicted ones included
+    pub fn len(&self) -> usize {
+        self.accounts.len() + self.evicted.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty() && self.evicted.is_empty()
+    }
+
+    /// Iterates over all disputes which are neither resolved nor charged back, as client, disputed transaction
+    /// and the amount still held for it
+    pub fn open_disputes(&self) -> impl Iterator<Item = (ClientID, TransactionID, Amount)> + '_ {
+        self.accounts.iter().flat_map(|(client_id, details)| {
+            details
+                .held_by
+                .iter()
+                .map(|(transaction_id, (held, _))| (*client_id, *transaction_id, *held))
+        })
+    }
+
+    /// Iterates over funds held by open disputes for at least `min_age` processed records, in no particular order
+    pub fn aged_holds(&self, min_age: u64) -> impl Iterator<Item = HeldFunds> + '_ {
+        self.accounts.iter().flat_map(move |(client_id, details)| {
+            details
+                .held_by
+                .iter()
+                .filter_map(move |(transaction_id, (amount, since))| {
+                    let age = self.sequence.saturating_sub(*since);
+                    (age >= min_age).then_some(HeldFunds {
+                        client_id: *client_id,
+                        transaction_id: *transaction_id,
+                        amount: *amount,
+                        age,
+                    })
+                })
+        })
+    }
+
+    /// Iterates over accounts frozen by chargeback, as client and the charged back transaction
+    pub fn charged_back(&self) -> impl Iterator<Item = (ClientID, TransactionID)> + '_ {
+        self.accounts.iter().filter_map(|(client_id, details)| {
+            details
+                .frozen_by
+                .map(|transaction_id| (*client_id, transaction_id))
+        })
+    }
+
+    /// Sums balances of all accounts, sums saturate instead of overflowing
+    pub fn totals(&self) -> Totals {
+        self.accounts
+            .values()
+            .fold(Totals::default(), |totals, details| Totals {
+                available: totals.available.saturating_add(details.available),
+                held: totals.held.saturating_add(details.held),
+                total: totals.total.saturating_add(details.total),
+            })
+    }
+
+    /// Sums balances of accounts in every segment, rounded like the report. Clients without a segment are summed
+    /// under the empty segment. Evicted accounts count as clients with zero balances
+    pub fn segment_totals(&self) -> BTreeMap<&str, SegmentTotals> {
+        let mut segments: BTreeMap<&str, SegmentTotals> = BTreeMap::new();
+        let segment_of = |client_id| {
+            self.segments
+                .as_ref()
+                .and_then(|segments| segments.get(client_id))
+                .unwrap_or_default()
+        };
+        for (client_id, details) in &self.accounts {
+            let segment = segments.entry(segment_of(*client_id)).or_default();
+            segment.clients += 1;
+            segment.totals = Totals {
+                available: segment.totals.available.saturating_add(details.available),
+                held: segment.totals.held.saturating_add(details.held),
+                total: segment.totals.total.saturating_add(details.total),
+            };
+        }
+        for client_id in &self.evicted {
+            segments.entry(segment_of(*client_id)).or_default().clients += 1;
+        }
+        for segment in segments.values_mut() {
+            segment.totals = Totals {
+                available: self.round(segment.totals.available),
+                held: self.round(segment.totals.held),
+                total: self.round(segment.totals.total),
+            };
+        }
+        segments
+    }
+
+    /// Loads accounts from the report of the previous run as the starting state, reports of both [ReportVersion]s
+    /// are accepted and the totals row is skipped. Frozen, closed and flagged accounts stay so. Only the balances
+    /// are known, funds held by disputes of the previous run stay held since the disputes can't be resolved nor
+    /// charged back and fraud rules start counting again. Returns number of loaded accounts
+    pub fn load_initial_state(&mut self, path: &Path) -> eyre::Result<usize> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_path(path)
+            .wrap_err_with(|| format!("failed to open initial state {}", path.display()))?;
+        let headers = reader.headers()?.clone();
+        let client = headers
+            .iter()
+            .position(|header| header == "client")
+            .ok_or_else(|| eyre!("initial state {} is missing client column", path.display()))?;
+
+        let mut loaded = 0;
+        for (index, record) in reader.records().enumerate() {
+            let record = record.wrap_err_with(|| format!("failed to read {}", path.display()))?;
+            let client_id = record.get(client).unwrap_or_default();
+            if client_id == "totals" {
+                continue;
+            }
+            let context = || format!("malformed line {} of {}", index + 2, path.display());
+            let client_id = client_id.parse::<ClientID>().wrap_err_with(context)?;
+            let details: AccountDetails =
+                record.deserialize(Some(&headers)).wrap_err_with(context)?;
+            if self.accounts.contains_key(&client_id) {
+                return Err(eyre!("client {client_id} is in {} twice", path.display()));
+            }
+            self.movements.opening = self.movements.opening.saturating_add(details.total);
+            self.accounts.insert(client_id, details);
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+
+    /// State of every account for [crate::state::save], evicted accounts are in the state of newly opened ones
+    pub fn export_state(&self) -> Vec<AccountState> {
+        let evicted = AccountDetails::default();
+        self.accounts
+            .iter()
+            .chain(self.evicted.iter().map(|client_id| (client_id, &evicted)))
+            .map(|(client_id, details)| AccountState {
+                client_id: *client_id,
+                status: details.account_status,
+                flagged: details.flagged,
+                total: details.total,
+                available: details.available,
+                held: details.held,
+                frozen_by: details.frozen_by,
+                holds: details
+                    .held_by
+                    .iter()
+                    .map(|(transaction_id, (amount, _))| (*transaction_id, *amount))
+                    .collect(),
+                reversing: details.reversing.iter().copied().collect(),
+                reversed: details.reversed.iter().copied().collect(),
+                deposits: details.fraud_counters.deposits(),
+                chargebacks: details.fraud_counters.chargebacks(),
+            })
+            .collect()
+    }
+
+    /// Loads accounts from the snapshot of a previous run as the starting state, see [crate::state]. Unlike
+    /// [Accounts::load_initial_state] the disputes of the previous run stay open and can be resolved or charged back,
+    /// the dispute look-up still has to find their transactions, e.g. in `--dispute-cache-file`. Funds are held
+    /// since the start of the run and the deposit velocity window starts empty. Returns number of loaded accounts
+    pub fn import_state(&mut self, states: Vec<AccountState>) -> eyre::Result<usize> {
+        let loaded = states.len();
+        for state in states {
+            let client_id = state.client_id;
+            if self.accounts.contains_key(&client_id) {
+                return Err(eyre!("client {client_id} is in the snapshot twice"));
+            }
+            let held = state
+                .holds
+                .iter()
+                .try_fold(Amount::ZERO, |held, (_, amount)| held.checked_add(*amount));
+            if state.available.checked_add(state.held) != Some(state.total)
+                || held != Some(state.held)
+            {
+                return Err(eyre!(
+                    "balances of client {client_id} don't add up, total {} available {} held {}",
+                    state.total,
+                    state.available,
+                    state.held
+                ));
+            }
+            self.movements.opening = self.movements.opening.saturating_add(state.total);
+            self.accounts.insert(
+                client_id,
+                AccountDetails {
+                    account_status: state.status,
+                    total: state.total,
+                    available: state.available,
+                    held: state.held,
+                    flagged: state.flagged,
+                    frozen_by: state.frozen_by,
+                    held_by: state
+                        .holds
+                        .into_iter()
+                        .map(|(transaction_id, amount)| (transaction_id, (amount, 0)))
+                        .collect(),
+                    reversing: state.reversing.into_iter().collect(),
+                    reversed: state.reversed.into_iter().collect(),
+                    fraud_counters: FraudCounters::restored(state.deposits, state.chargebacks),
+                },
+            );
+        }
+        Ok(loaded)
+    }
+
+    pub fn fees(&self) -> &FeeSchedule {
+        &self.fees
+    }
+
+    /// Money moved into and out of the accounts by all applied operations
+    pub fn movements(&self) -> Movements {
+        self.movements
+    }
+
+    /// Moves the accounts of disjoint set of clients into these, e.g. shards of
+    /// [crate::engine::ShardedEngineHandle]. Settings of these accounts are kept, fails if a client has account
+    /// in both
+    pub fn absorb(&mut self, other: Accounts) -> eyre::Result<()> {
+        let overlapping = other
+            .accounts
+            .keys()
+            .chain(other.evicted.iter())
+            .find(|client_id| {
+                self.accounts.contains_key(client_id) || self.evicted.contains(client_id)
+            });
+        if let Some(client_id) = overlapping {
+            return Err(eyre::eyre!(
+                "client {client_id} has account in both merged accounts"
+            ));
+        }
+        self.accounts.extend(other.accounts);
+        self.evicted.extend(other.evicted);
+        self.movements.add(&other.movements);
+        self.sequence = self.sequence.max(other.sequence);
+        Ok(())
+    }
+
+    /// Reconciles the grand total of all accounts with [Movements::expected_total].
+    /// Returns by how much the grand total differs, `None` if they match
+    pub fn integrity_mismatch(&self) -> Option<Amount> {
+        let difference = self
+            .totals()
+            .total
+            .saturating_sub(self.movements.expected_total());
+        (!difference.is_zero()).then_some(difference)
+    }
+
     /// Processes deposit done by the client, creates client's account if client doesn't have one yet
     /// # Arguments
     /// * client_id - used to look up client's [AccountDetails]
     /// * amount - value of how much client deposited
-    pub fn deposit(&mut self, client_id: ClientID, amount: Decimal) {
-        let acc_details = self
-            .0
-            .entry(client_id)
-            .or_insert_with(AccountDetails::default);
-        acc_details.deposit(amount)
+    pub fn deposit(&mut self, client_id: ClientID, amount: Amount) -> Result<(), AccountError> {
+        let amount = self.round(amount);
+        let (sequence, rules) = (self.sequence, self.fraud_rules);
+        let limits = self.limits.for_client(client_id);
+        let acc_details = self.open_account_or_default(client_id)?;
+        acc_details.ensure_not_frozen(client_id)?;
+        limits.check_deposit(amount, acc_details.total)?;
+        acc_details.deposit(amount)?;
+
+        if let Some(rule) = acc_details.fraud_counters.record_deposit(sequence, &rules) {
+            acc_details.flag(client_id, rule);
+        }
+        self.movements.deposits = self.movements.deposits.saturating_add(amount);
+        Ok(())
     }
 
     /// Processes withdrawal done by the client, creates client's account if client doesn't have one yet
     /// # Arguments
     /// * client_id - used to look up client's [AccountDetails]
     /// * amount - value of how much client wants to withdraw
-    pub fn withdraw(&mut self, client_id: ClientID, amount: Decimal) {
-        let acc_details = self
-            .0
-            .entry(client_id)
-            .or_insert_with(AccountDetails::default);
+    ///
+    /// Returns [AccountError::InsufficientFunds] if the client doesn't have enough available funds for the withdrawal
+    /// and its fee, in which case the account is left untouched. Returns the fee charged on top of the withdrawal
+    pub fn withdraw(
+        &mut self,
+        client_id: ClientID,
+        amount: Amount,
+    ) -> Result<Option<Amount>, AccountError> {
+        let amount = self.round(amount);
+        self.limits.for_client(client_id).check_withdrawal(amount)?;
+        let fee = self.fee(client_id, self.fees.withdrawal, amount)?;
+        let debit = match fee {
+            Some((_, fee)) => amount.checked_add(fee).ok_or(AccountError::Overflow)?,
+            None => amount,
+        };
+        let acc_details = self.open_account_or_default(client_id)?;
+        acc_details.ensure_not_frozen(client_id)?;
+        acc_details.withdraw(debit)?;
+        if let Some((house_account, fee)) = fee {
+            self.credit_house(client_id, house_account, fee, debit)?;
+        }
+        self.movements.withdrawals = self.movements.withdrawals.saturating_add(amount);
+        Ok(fee.map(|(_, fee)| fee))
+    }
+
+    /// House account and the fee of client's operation, `None` if the client isn't charged
+    fn fee(
+        &self,
+        client_id: ClientID,
+        fee: Option<Fee>,
+        amount: Amount,
+    ) -> Result<Option<(ClientID, Amount)>, AccountError> {
+        let (Some(house_account), Some(fee)) = (self.fees.house_for(client_id), fee) else {
+            return Ok(None);
+        };
+        let fee = self.round(fee.of(amount).ok_or(AccountError::Overflow)?);
+        Ok((!fee.is_zero()).then_some((house_account, fee)))
+    }
 
-        if amount > acc_details.available {
-            warn!(available = %acc_details.available, "client requested withdrawal with amount higher than available funds");
-            return;
+    /// Credits the fee already taken from the client to the house account. If it fails, the whole debited amount
+    /// is returned to the client
+    fn credit_house(
+        &mut self,
+        client_id: ClientID,
+        house_account: ClientID,
+        fee: Amount,
+        debited: Amount,
+    ) -> Result<(), AccountError> {
+        self.restore(house_account);
+        if let Err(err) = self.accounts.entry(house_account).or_default().deposit(fee) {
+            if let Some(acc_details) = self.accounts.get_mut(&client_id) {
+                acc_details.increase_balance(debited)?;
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Moves funds from one client's account to another's, creates receiving account if client doesn't have one yet.
+    /// Either both accounts are updated or none of them.
+    /// # Arguments
+    /// * from_client_id - client whose account is debited
+    /// * to_client_id - client whose account is credited
+    /// * amount - how much is transferred
+    ///
+    /// Transfer is rejected if sending client doesn't have enough available funds, any of the accounts is frozen or closed
+    /// or the transfer exceeds withdrawal limit of the sending client or deposit limits of the receiving client
+    pub fn transfer(
+        &mut self,
+        from_client_id: ClientID,
+        to_client_id: ClientID,
+        amount: Amount,
+    ) -> Result<(), AccountError> {
+        if from_client_id == to_client_id {
+            return Err(AccountError::SelfTransfer);
+        }
+        let amount = self.round(amount);
+        self.restore(from_client_id);
+        self.restore(to_client_id);
+
+        for client_id in [from_client_id, to_client_id] {
+            if let Some(acc_details) = self.accounts.get(&client_id) {
+                acc_details.ensure_open(client_id)?;
+                if acc_details.account_status.is_frozen() {
+                    return Err(AccountError::AccountFrozen(client_id));
+                }
+            }
         }
 
-        acc_details.withdraw(amount);
+        self.limits
+            .for_client(from_client_id)
+            .check_withdrawal(amount)?;
+        let to_total = self
+            .accounts
+            .get(&to_client_id)
+            .map_or(Amount::ZERO, |acc| acc.total);
+        self.limits
+            .for_client(to_client_id)
+            .check_deposit(amount, to_total)?;
+
+        let from =
+            self.accounts
+                .get_mut(&from_client_id)
+                .ok_or(AccountError::InsufficientFunds {
+                    available: Amount::ZERO,
+                    requested: amount,
+                })?;
+        from.withdraw(amount)?;
+
+        if let Err(err) = self
+            .accounts
+            .entry(to_client_id)
+            .or_default()
+            .deposit(amount)
+        {
+            // put the funds back, so the sending account is not debited when the transfer failed
+            if let Some(from) = self.accounts.get_mut(&from_client_id) {
+                from.deposit(amount)?;
+            }
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    /// Administrative correction increasing client's balance, creates client's account if client doesn't have one yet
+    /// # Arguments
+    /// * client_id - used to look up client's [AccountDetails]
+    /// * amount - value which will be added to the total and available
+    pub fn adjust_credit(
+        &mut self,
+        client_id: ClientID,
+        amount: Amount,
+    ) -> Result<(), AccountError> {
+        let amount = self.round(amount);
+        self.open_account_or_default(client_id)?.deposit(amount)?;
+        self.movements.adjustments = self.movements.adjustments.saturating_add(amount);
+        Ok(())
+    }
+
+    /// Administrative correction decreasing client's balance. Unlike withdrawal it is not limited by available funds
+    /// # Arguments
+    /// * client_id - used to look up client's [AccountDetails]
+    /// * amount - value which will be subtracted from the total and available
+    pub fn adjust_debit(
+        &mut self,
+        client_id: ClientID,
+        amount: Amount,
+    ) -> Result<(), AccountError> {
+        let amount = self.round(amount);
+        self.open_account(client_id)?.decrease_balance(amount)?;
+        self.movements.adjustments = self.movements.adjustments.saturating_sub(amount);
+        Ok(())
+    }
+
+    /// Manually freezes client's account
+    /// # Arguments
+    /// * client_id - used to look up client's [AccountDetails]
+    pub fn lock(&mut self, client_id: ClientID) -> Result<(), AccountError> {
+        self.set_status(client_id, AccountStatus::Frozen)
+    }
+
+    /// Manually unfreezes client's account
+    /// # Arguments
+    /// * client_id - used to look up client's [AccountDetails]
+    pub fn unlock(&mut self, client_id: ClientID) -> Result<(), AccountError> {
+        self.set_status(client_id, AccountStatus::Active)
+    }
+
+    fn set_status(
+        &mut self,
+        client_id: ClientID,
+        status: AccountStatus,
+    ) -> Result<(), AccountError> {
+        let acc_details = self.open_account(client_id)?;
+        acc_details.account_status = status;
+        acc_details.frozen_by = None;
+        Ok(())
+    }
+
+    /// Closes client's account, closed account rejects all further operations
+    /// # Arguments
+    /// * client_id - used to look up client's [AccountDetails]
+    ///
+    /// Account with held funds can't be closed, its disputes have to be resolved or charged back first
+    pub fn close(&mut self, client_id: ClientID) -> Result<(), AccountError> {
+        let acc_details = self.open_account(client_id)?;
+        if !acc_details.held.is_zero() {
+            return Err(AccountError::HeldFundsOnClose(acc_details.held));
+        }
+
+        acc_details.account_status = AccountStatus::Closed;
+        Ok(())
+    }
+
+    /// Returns client's account, creates it if client doesn't have one yet. Fails if the account is closed
+    fn open_account_or_default(
+        &mut self,
+        client_id: ClientID,
+    ) -> Result<&mut AccountDetails, AccountError> {
+        self.restore(client_id);
+        let acc_details = self.accounts.entry(client_id).or_default();
+        acc_details.ensure_open(client_id)?;
+        Ok(acc_details)
+    }
+
+    /// Returns existing client's account. Fails if the account doesn't exist or is closed
+    fn open_account(&mut self, client_id: ClientID) -> Result<&mut AccountDetails, AccountError> {
+        self.restore(client_id);
+        let acc_details = self
+            .accounts
+            .get_mut(&client_id)
+            .ok_or(AccountError::AccountNotFound)?;
+        acc_details.ensure_open(client_id)?;
+        Ok(acc_details)
+    }
+
+    /// Returns existing client's account which can take dispute of the transaction. Fails if the account doesn't
+    /// exist, is closed or already has the maximum of open disputes, the account is flagged in the last case
+    fn disputable_account(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+    ) -> Result<&mut AccountDetails, AccountError> {
+        let max_open_disputes = self.max_open_disputes;
+        let acc_details = self.open_account(client_id)?;
+        if let Some(max) = max_open_disputes.filter(|max| {
+            acc_details.held_by.len() >= *max && !acc_details.held_by.contains_key(&transaction_id)
+        }) {
+            acc_details.flag(client_id, "max open disputes");
+            return Err(AccountError::TooManyOpenDisputes(max));
+        }
+        Ok(acc_details)
     }
 
     /// Handles dispute for given client and amount
     /// # Arguments
     /// * client_id - used to look up client's [AccountDetails]
+    /// * transaction_id - ID of the disputed transaction, the held amount is recorded under it
     /// * amount - value of disputed transaction
-    pub fn dispute(&mut self, client_id: ClientID, amount: Amount) -> Result<()> {
-        match self.0.get_mut(&client_id) {
-            Some(acc_details) => {
-                acc_details.dispute(amount);
-                Ok(())
-            }
-            None => Err(eyre!("cannot dispute transaction for non-existent account")),
+    ///
+    /// If the disputed amount is higher than client's available funds, the configured [DisputePolicy] is applied
+    pub fn dispute(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+        amount: Amount,
+    ) -> Result<DisputeOutcome, AccountError> {
+        let (policy, amount, sequence) = (self.dispute_policy, self.round(amount), self.sequence);
+        self.disputable_account(client_id, transaction_id)?.dispute(
+            transaction_id,
+            amount,
+            policy,
+            sequence,
+        )
+    }
+
+    /// Handles dispute of client's withdrawal according to the configured [WithdrawalDisputePolicy]. With
+    /// [WithdrawalDisputePolicy::Reverse] the withdrawn amount is put on hold, `held` and `total` increase and
+    /// `available` stays as it is. Resolve drops the hold, the withdrawal stands, chargeback releases the held
+    /// amount into `available`, the withdrawal is reversed
+    pub fn dispute_withdrawal(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+        amount: Amount,
+    ) -> Result<DisputeOutcome, AccountError> {
+        if self.withdrawal_disputes == WithdrawalDisputePolicy::AsDeposit {
+            return self.dispute(client_id, transaction_id, amount);
         }
+
+        let (amount, sequence) = (self.round(amount), self.sequence);
+        self.disputable_account(client_id, transaction_id)?
+            .dispute_withdrawal(transaction_id, amount, sequence)?;
+        self.movements.returned_withdrawals =
+            self.movements.returned_withdrawals.saturating_add(amount);
+        Ok(DisputeOutcome::Held(amount))
     }
 
     /// Resolves dispute for given client and amount
     /// # Arguments
     /// * client_id - used to look up client's [AccountDetails]
-    /// * amount - value of disputed transaction
-    pub fn resolve(&mut self, client_id: ClientID, amount: Amount) -> Result<()> {
-        match self.0.get_mut(&client_id) {
-            Some(acc_details) => {
-                acc_details.resolve(amount);
-                Ok(())
-            }
-            None => Err(eyre!(
-                "cannot resolve disputed transaction for non-existent account"
-            )),
+    /// * transaction_id - ID of the disputed transaction, exactly the amount held for it is released
+    ///
+    /// If enabled, resolve of the transaction whose chargeback froze the account unfreezes it instead,
+    /// the balances were already settled by the chargeback
+    pub fn resolve(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+    ) -> Result<ResolveOutcome, AccountError> {
+        self.release(client_id, transaction_id, None)
+    }
+
+    /// Same as [Accounts::resolve], only at most `amount` of the held amount is released and the rest of it
+    /// stays held, the dispute stays open until it is all released or charged back. Resolve of partial dispute
+    /// row releases just the part given by it
+    pub fn resolve_up_to(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+        amount: Amount,
+    ) -> Result<ResolveOutcome, AccountError> {
+        self.release(client_id, transaction_id, Some(amount))
+    }
+
+    fn release(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+        part: Option<Amount>,
+    ) -> Result<ResolveOutcome, AccountError> {
+        let unfreeze_on_resolve = self.unfreeze_on_resolve;
+        let acc_details = self.open_account(client_id)?;
+        if unfreeze_on_resolve && acc_details.frozen_by == Some(transaction_id) {
+            acc_details.account_status = AccountStatus::Active;
+            acc_details.frozen_by = None;
+            return Ok(ResolveOutcome::Unfrozen);
+        }
+
+        let released = part.map_or(acc_details.held_amount(transaction_id)?, |part| part);
+        if acc_details.reversing.contains(&transaction_id) {
+            let amount = acc_details.resolve_withdrawal(transaction_id, released)?;
+            self.movements.returned_withdrawals =
+                self.movements.returned_withdrawals.saturating_sub(amount);
+            return Ok(ResolveOutcome::Upheld(amount));
         }
+
+        acc_details
+            .resolve(transaction_id, released)
+            .map(ResolveOutcome::Released)
     }
 
     /// Does chargeback for provided client and amount
     /// # Arguments
     /// * client_id - used to look up client's [AccountDetails]
-    /// * amount - value of disputed transaction
-    pub fn chargeback(&mut self, client_id: ClientID, amount: Amount) -> Result<()> {
-        match self.0.get_mut(&client_id) {
-            Some(acc_details) => {
-                acc_details.chargeback(amount);
-                Ok(())
+    /// * transaction_id - ID of the disputed transaction, exactly the amount held for it is charged back.
+    ///   The account remembers it as the reason of the freeze
+    ///
+    /// Returns the fee charged from the available funds after the chargeback, at most what is available
+    pub fn chargeback(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+    ) -> Result<Option<Amount>, AccountError> {
+        self.charge_back(client_id, transaction_id, None)
+    }
+
+    /// Same as [Accounts::chargeback], only at most `amount` of the held amount is charged back and the rest
+    /// of it is released like by resolve. Chargeback of partial dispute row charges back just the part given by it
+    pub fn chargeback_up_to(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+        amount: Amount,
+    ) -> Result<Option<Amount>, AccountError> {
+        self.charge_back(client_id, transaction_id, Some(amount))
+    }
+
+    fn charge_back(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+        part: Option<Amount>,
+    ) -> Result<Option<Amount>, AccountError> {
+        let rules = self.fraud_rules;
+        let held = self.open_account(client_id)?.held_amount(transaction_id)?;
+        let charged = part.map_or(held, |part| part.min(held));
+        let fee = self.fee(client_id, self.fees.chargeback, charged)?;
+        let acc_details = self.open_account(client_id)?;
+        let reversed = acc_details.reversing.contains(&transaction_id);
+        let amount = match reversed {
+            true => acc_details.chargeback_withdrawal(transaction_id, charged)?,
+            false => acc_details.chargeback(transaction_id, charged)?,
+        };
+        acc_details.frozen_by = Some(transaction_id);
+
+        if let Some(rule) = acc_details.fraud_counters.record_chargeback(&rules) {
+            acc_details.flag(client_id, rule);
+        }
+        let fee = fee
+            .map(|(house_account, fee)| (house_account, fee.min(acc_details.available)))
+            .filter(|(_, fee)| *fee > Amount::ZERO);
+        if let Some((_, fee)) = fee {
+            acc_details.decrease_balance(fee)?;
+        }
+        // charged back part of reversed withdrawal stays in the returned withdrawals, its money is back in the
+        // account, the rest was dropped
+        match reversed {
+            true => {
+                self.movements.returned_withdrawals = self
+                    .movements
+                    .returned_withdrawals
+                    .saturating_sub(held.saturating_sub(amount))
+            }
+            false => self.movements.chargebacks = self.movements.chargebacks.saturating_add(amount),
+        }
+
+        let Some((house_account, fee)) = fee else {
+            return Ok(None);
+        };
+        self.credit_house(client_id, house_account, fee, fee)?;
+        Ok(Some(fee))
+    }
+
+    /// Reverses client's deposit or withdrawal, the inverse operation is applied in full. Unlike chargeback it
+    /// doesn't freeze the account and it is not preceded by a dispute
+    /// # Arguments
+    /// * client_id - used to look up client's [AccountDetails]
+    /// * transaction_id - ID of the reversed transaction, every transaction is reversed at most once
+    /// * amount - value of the reversed transaction
+    /// * kind - whether the reversed transaction is a deposit or a withdrawal
+    ///
+    /// Returns [AccountError::InsufficientFunds] if the reversed deposit is not available anymore,
+    /// [AccountError::AlreadyDisputed] if the transaction is under dispute and [AccountError::AlreadyReversed]
+    /// if it was reversed before, in which case the account is left untouched
+    pub fn reverse(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+        amount: Amount,
+        kind: TransactionKind,
+    ) -> Result<(), AccountError> {
+        let amount = self.round(amount);
+        let acc_details = self.open_account(client_id)?;
+        acc_details.ensure_not_frozen(client_id)?;
+        acc_details.reverse(transaction_id, amount, kind)?;
+        match kind {
+            TransactionKind::Deposit => {
+                self.movements.deposits = self.movements.deposits.saturating_sub(amount)
+            }
+            TransactionKind::Withdrawal => {
+                self.movements.withdrawals = self.movements.withdrawals.saturating_sub(amount)
             }
-            None => Err(eyre!(
-                "cannot chargeback transaction for non-existent account"
-            )),
         }
+        Ok(())
+    }
+
+    /// State of client's account after the transaction of given kind would be disputed, and after the dispute
+    /// would be resolved or charged back. Already disputed transaction is resolved or charged back right away.
+    /// The accounts are left as they were, fails only if the client has no account
+    pub fn preview_dispute(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+        amount: Amount,
+        kind: TransactionKind,
+    ) -> Result<DisputePreview, AccountError> {
+        let current = self.get(client_id).ok_or(AccountError::AccountNotFound)?;
+        let apply = move |accounts: &mut Self| match kind {
+            TransactionKind::Deposit => accounts.dispute(client_id, transaction_id, amount),
+            TransactionKind::Withdrawal => {
+                accounts.dispute_withdrawal(client_id, transaction_id, amount)
+            }
+        };
+        let dispute = |accounts: &mut Self| match apply(accounts) {
+            Ok(_) | Err(AccountError::AlreadyDisputed(_)) => Ok(()),
+            Err(err) => Err(err),
+        };
+        Ok(DisputePreview {
+            current,
+            dispute: self.preview(client_id, |accounts| apply(accounts).map(drop)),
+            resolve: self.preview(client_id, |accounts| {
+                dispute(accounts)?;
+                accounts.resolve(client_id, transaction_id).map(drop)
+            }),
+            chargeback: self.preview(client_id, |accounts| {
+                dispute(accounts)?;
+                accounts.chargeback(client_id, transaction_id).map(drop)
+            }),
+        })
+    }
+
+    /// Applies the operations and returns the resulting state of client's account, then puts the account back
+    fn preview(
+        &mut self,
+        client_id: ClientID,
+        operations: impl FnOnce(&mut Self) -> Result<(), AccountError>,
+    ) -> Result<AccountView, AccountError> {
+        // fees of the operations are credited to the house account, it is put back as well
+        let saved: Vec<_> = std::iter::once(client_id)
+            .chain(self.fees.house_for(client_id))
+            .map(|client_id| {
+                (
+                    client_id,
+                    self.accounts.get(&client_id).cloned(),
+                    self.evicted.contains(&client_id),
+                )
+            })
+            .collect();
+        let movements = self.movements;
+        let result = operations(self)
+            .and_then(|()| self.get(client_id).ok_or(AccountError::AccountNotFound));
+
+        for (client_id, details, evicted) in saved {
+            match details {
+                Some(details) => self.accounts.insert(client_id, details),
+                None => self.accounts.remove(&client_id),
+            };
+            if evicted {
+                self.evicted.insert(client_id);
+            }
+        }
+        self.movements = movements;
+        result
     }
 
     /// Prints out the report of all client's and their account state as described in requirements
     pub fn print_report(&self) {
-        println!("client,available,held,total,locked");
+        if let Err(err) = self.write_report(&mut std::io::stdout().lock()) {
+            error!(%err, "failed to print report");
+        }
+    }
+
+    /// Writes the report into any writer, doesn't consume the accounts so it can be used for snapshots
+    /// while the journal is still being processed. With [Accounts::with_report_threads] clients are sorted by ID
+    pub fn write_report(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        let clients = self.accounts.keys().chain(self.evicted.iter()).copied();
+        match self.report_threads {
+            Some(threads) => {
+                let mut clients: Vec<_> = clients.collect();
+                clients.sort_unstable();
+                self.write_header(writer)?;
+                self.write_rows_parallel(writer, &clients, threads)?;
+            }
+            None => self.write_clients_report(writer, clients)?,
+        }
+        if self.totals_row {
+            let Totals {
+                available,
+                held,
+                total,
+            } = self.totals();
+            write!(
+                writer,
+                "totals,{},{},{},",
+                self.reported(available),
+                self.reported(held),
+                self.reported(total),
+            )?;
+            if self.extended_report {
+                write!(writer, ",,")?;
+            }
+            if self.segments.is_some() {
+                write!(writer, ",")?;
+            }
+            writeln!(writer)?;
+        }
+        writer.flush()
+    }
+
+    /// Writes the report of only the given clients, clients without an account are left out
+    pub fn write_clients_report(
+        &self,
+        writer: &mut impl Write,
+        clients: impl IntoIterator<Item = ClientID>,
+    ) -> std::io::Result<()> {
+        self.write_header(writer)?;
+        self.write_rows(writer, clients)?;
+        writer.flush()
+    }
+
+    fn write_header(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        write!(
+            writer,
+            "client,available,held,total,{}",
+            self.report_version.state_header()
+        )?;
+        if self.extended_report {
+            write!(writer, ",open_disputes,chargebacks")?;
+        }
+        if self.segments.is_some() {
+            write!(writer, ",segment")?;
+        }
+        writeln!(writer)
+    }
+
+    fn write_rows(
+        &self,
+        writer: &mut impl Write,
+        clients: impl IntoIterator<Item = ClientID>,
+    ) -> std::io::Result<()> {
+        let evicted = AccountDetails::default();
         for (
             k,
             AccountDetails {
@@ -95,51 +1140,531 @@ impl Accounts {
                 total,
                 available,
                 held,
+                flagged,
+                fraud_counters,
+                held_by,
                 ..
             },
-        ) in self.0.iter()
-        {
-            println!(
-                "{k},{available},{held},{total},{}",
-                account_status.is_frozen()
-            )
+        ) in clients.into_iter().filter_map(|client_id| {
+            self.accounts.get_key_value(&client_id).or_else(|| {
+                self.evicted
+                    .get(&client_id)
+                    .map(|client_id| (client_id, &evicted))
+            })
+        }) {
+            write!(
+                writer,
+                "{k},{},{},{},",
+                self.reported(*available),
+                self.reported(*held),
+                self.reported(*total)
+            )?;
+            self.report_version.write_state(
+                writer,
+                account_status.is_frozen(),
+                account_status.is_closed(),
+                *flagged,
+            )?;
+            if self.extended_report {
+                write!(
+                    writer,
+                    ",{},{}",
+                    held_by.len(),
+                    fraud_counters.chargebacks()
+                )?;
+            }
+            if let Some(segments) = &self.segments {
+                write!(writer, ",{}", segments.get(*k).unwrap_or_default())?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Formats rows of the clients in chunks of [REPORT_CHUNK] on `threads` threads and writes the chunks in the
+    /// order of the clients as soon as they are formatted. At most two chunks per thread are kept in memory
+    fn write_rows_parallel(
+        &self,
+        writer: &mut impl Write,
+        clients: &[ClientID],
+        threads: usize,
+    ) -> std::io::Result<()> {
+        let (work, jobs) = crossbeam_channel::bounded::<(&[ClientID], Sender<_>)>(threads);
+        std::thread::scope(|scope| {
+            // dropped on return, so the threads exit even if writing failed
+            let work = work;
+            for _ in 0..threads {
+                let jobs = jobs.clone();
+                scope.spawn(move || {
+                    for (chunk, formatted) in jobs {
+                        let mut rows = Vec::with_capacity(chunk.len() * 32);
+                        let rows = self
+                            .write_rows(&mut rows, chunk.iter().copied())
+                            .map(|_| rows);
+                        // the writer stops waiting for the chunk if writing of previous one failed
+                        let _ = formatted.send(rows);
+                    }
+                });
+            }
+
+            let mut chunks = clients.chunks(REPORT_CHUNK);
+            let mut pending = VecDeque::with_capacity(2 * threads);
+            loop {
+                while pending.len() < 2 * threads {
+                    let Some(chunk) = chunks.next() else {
+                        break;
+                    };
+                    let (formatted, rows) = crossbeam_channel::bounded(1);
+                    work.send((chunk, formatted))
+                        .map_err(|_| std::io::Error::other("report formatting threads exited"))?;
+                    pending.push_back(rows);
+                }
+                let Some(rows) = pending.pop_front() else {
+                    return Ok(());
+                };
+                let rows: Vec<u8> = rows
+                    .recv()
+                    .map_err(|_| std::io::Error::other("report formatting thread panicked"))??;
+                writer.write_all(&rows)?;
+            }
+        })
+    }
+}
+
+/// Balance in the report, see [Accounts::with_report_scale]
+enum ReportedAmount {
+    Exact(Amount),
+    Scaled(rust_decimal::Decimal),
+}
+
+impl Display for ReportedAmount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReportedAmount::Exact(amount) => amount.fmt(f),
+            ReportedAmount::Scaled(amount) => amount.fmt(f),
+        }
+    }
+}
+
+/// Reasons why an operation on the account was rejected
+#[derive(PartialEq, Eq, Debug)]
+pub enum AccountError {
+    /// Client requested to withdraw more than is currently available on the account
+    InsufficientFunds {
+        available: Amount,
+        requested: Amount,
+    },
+    /// Dispute, resolve or chargeback referenced client who doesn't have an account
+    AccountNotFound,
+    /// Operation would overflow one of the balances, account has been frozen for manual review
+    Overflow,
+    /// Disputed amount is higher than client's available funds and [DisputePolicy::Reject] is used
+    DisputeExceedsAvailable { available: Amount, disputed: Amount },
+    /// Operation involves frozen account of the given client
+    AccountFrozen(ClientID),
+    /// Transfer where the sending and the receiving client are the same
+    SelfTransfer,
+    /// Operation involves closed account of the given client
+    AccountClosed(ClientID),
+    /// Account can't be closed while it has held funds
+    HeldFundsOnClose(Amount),
+    /// Operation exceeds one of the client's limits, see [crate::limits::Limits]
+    LimitExceeded {
+        limit: &'static str,
+        max: Amount,
+        requested: Amount,
+    },
+    /// Transaction already has funds held by an earlier dispute
+    AlreadyDisputed(TransactionID),
+    /// Resolve or chargeback of transaction which has no held funds
+    NotDisputed(TransactionID),
+    /// Client already has the maximum number of open disputes
+    TooManyOpenDisputes(usize),
+    /// Reversal of transaction which was already reversed, or dispute of reversed transaction
+    AlreadyReversed(TransactionID),
+    /// Deposit, withdrawal or transfer of zero or negative amount, see [crate::limits::Limits]
+    NonPositiveAmount(Amount),
+}
+
+impl AccountError {
+    /// Name of the error without its details, used to roll up repeated warnings
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AccountError::InsufficientFunds { .. } => "insufficient_funds",
+            AccountError::AccountNotFound => "account_not_found",
+            AccountError::Overflow => "overflow",
+            AccountError::DisputeExceedsAvailable { .. } => "dispute_exceeds_available",
+            AccountError::AccountFrozen(_) => "account_frozen",
+            AccountError::SelfTransfer => "self_transfer",
+            AccountError::AccountClosed(_) => "account_closed",
+            AccountError::HeldFundsOnClose(_) => "held_funds_on_close",
+            AccountError::LimitExceeded { .. } => "limit_exceeded",
+            AccountError::AlreadyDisputed(_) => "already_disputed",
+            AccountError::NotDisputed(_) => "not_disputed",
+            AccountError::TooManyOpenDisputes(_) => "too_many_open_disputes",
+            AccountError::AlreadyReversed(_) => "already_reversed",
+            AccountError::NonPositiveAmount(_) => "non_positive_amount",
+        }
+    }
+}
+
+impl Display for AccountError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccountError::InsufficientFunds {
+                available,
+                requested,
+            } => write!(
+                f,
+                "insufficient funds, requested {requested} but only {available} is available"
+            ),
+            AccountError::AccountNotFound => write!(f, "account does not exist"),
+            AccountError::Overflow => write!(
+                f,
+                "balance overflow, account has been frozen for manual review"
+            ),
+            AccountError::DisputeExceedsAvailable {
+                available,
+                disputed,
+            } => write!(
+                f,
+                "disputed amount {disputed} is higher than available funds {available}"
+            ),
+            AccountError::AccountFrozen(client_id) => {
+                write!(f, "account of client {client_id} is frozen")
+            }
+            AccountError::SelfTransfer => write!(f, "cannot transfer funds to the same account"),
+            AccountError::AccountClosed(client_id) => {
+                write!(f, "account of client {client_id} is closed")
+            }
+            AccountError::HeldFundsOnClose(held) => {
+                write!(f, "cannot close account with {held} held funds")
+            }
+            AccountError::LimitExceeded {
+                limit,
+                max,
+                requested,
+            } => write!(f, "{limit} limit {max} exceeded, requested {requested}"),
+            AccountError::AlreadyDisputed(transaction_id) => {
+                write!(f, "transaction {transaction_id} is already disputed")
+            }
+            AccountError::NotDisputed(transaction_id) => {
+                write!(f, "transaction {transaction_id} has no held funds")
+            }
+            AccountError::TooManyOpenDisputes(max) => {
+                write!(f, "client already has {max} open disputes")
+            }
+            AccountError::AlreadyReversed(transaction_id) => {
+                write!(f, "transaction {transaction_id} is already reversed")
+            }
+            AccountError::NonPositiveAmount(amount) => write!(f, "amount {amount} is not positive"),
+        }
+    }
+}
+
+impl std::error::Error for AccountError {}
+
+/// What to do when a dispute would drive client's available funds below zero
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisputePolicy {
+    /// Hold the whole disputed amount even if `available` goes negative
+    #[default]
+    Allow,
+    /// Hold only what is available, `available` stays at zero
+    Clamp,
+    /// Reject the dispute, account is left untouched
+    Reject,
+}
+
+impl FromStr for DisputePolicy {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(DisputePolicy::Allow),
+            "clamp" => Ok(DisputePolicy::Clamp),
+            "reject" => Ok(DisputePolicy::Reject),
+            _ => Err(eyre::eyre!(
+                "invalid dispute policy '{s}', expected one of allow, clamp, reject"
+            )),
+        }
+    }
+}
+
+/// How disputes of withdrawals change the balances
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WithdrawalDisputePolicy {
+    /// Same as disputes of deposits, the amount is moved from `available` to `held`
+    #[default]
+    AsDeposit,
+    /// The withdrawn amount is held until the dispute ends, chargeback returns it to the client,
+    /// see [Accounts::dispute_withdrawal]
+    Reverse,
+}
+
+impl FromStr for WithdrawalDisputePolicy {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "as-deposit" => Ok(WithdrawalDisputePolicy::AsDeposit),
+            "reverse" => Ok(WithdrawalDisputePolicy::Reverse),
+            _ => Err(eyre::eyre!(
+                "invalid withdrawal dispute policy '{s}', expected one of as-deposit, reverse"
+            )),
+        }
+    }
+}
+
+/// Result of successfully applied resolve
+#[derive(Debug, PartialEq, Eq)]
+pub enum ResolveOutcome {
+    /// Disputed amount was moved from `held` back to `available`
+    Released(Amount),
+    /// Amount held by dispute of withdrawal was dropped, the withdrawal stands
+    Upheld(Amount),
+    /// Account frozen by chargeback of the resolved transaction was unfrozen
+    Unfrozen,
+}
+
+impl Display for ResolveOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResolveOutcome::Released(amount) => write!(f, "released {amount}"),
+            ResolveOutcome::Upheld(amount) => write!(f, "upheld withdrawal {amount}"),
+            ResolveOutcome::Unfrozen => write!(f, "unfrozen"),
+        }
+    }
+}
+
+/// Result of successfully applied dispute
+#[derive(Debug, PartialEq, Eq)]
+pub enum DisputeOutcome {
+    /// Whole disputed amount was moved to `held`
+    Held(Amount),
+    /// Only part of the disputed amount was moved to `held` because of [DisputePolicy::Clamp]
+    Clamped { disputed: Amount, held: Amount },
+}
+
+impl Display for DisputeOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisputeOutcome::Held(amount) => write!(f, "held {amount}"),
+            DisputeOutcome::Clamped { disputed, held } => {
+                write!(f, "clamped, held {held} of disputed {disputed}")
+            }
         }
     }
 }
 
-#[derive(PartialEq, Eq, Debug, serde::Deserialize)]
-enum AccountStatus {
+/// Read-only copy of client's account state returned by [Accounts::get] and [Accounts::iter]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AccountView {
+    pub client_id: ClientID,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub status: AccountStatus,
+    pub flagged: bool,
+    /// Number of disputes which are neither resolved nor charged back
+    pub open_disputes: usize,
+    /// Number of chargebacks since the account was opened
+    pub chargebacks: u64,
+}
+
+impl AccountView {
+    fn new(client_id: ClientID, details: &AccountDetails) -> Self {
+        AccountView {
+            client_id,
+            available: details.available,
+            held: details.held,
+            total: details.total,
+            status: details.account_status,
+            flagged: details.flagged,
+            open_disputes: details.held_by.len(),
+            chargebacks: details.fraud_counters.chargebacks(),
+        }
+    }
+}
+
+/// Funds held by an open dispute, returned by [Accounts::aged_holds]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeldFunds {
+    pub client_id: ClientID,
+    /// Disputed transaction
+    pub transaction_id: TransactionID,
+    pub amount: Amount,
+    /// Number of records processed since the dispute put the funds on hold
+    pub age: u64,
+}
+
+/// Client's account before and after every step of a dispute, returned by [Accounts::preview_dispute].
+/// Rejected steps hold the reason of the rejection
+#[derive(Debug, PartialEq, Eq)]
+pub struct DisputePreview {
+    pub current: AccountView,
+    /// After the transaction is disputed
+    pub dispute: Result<AccountView, AccountError>,
+    /// After the dispute is resolved
+    pub resolve: Result<AccountView, AccountError>,
+    /// After the dispute is charged back
+    pub chargeback: Result<AccountView, AccountError>,
+}
+
+/// Money which entered or left the accounts, see [Accounts::movements]. Transfers only move money
+/// between accounts and disputes only between `available` and `held`, so they are not counted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Movements {
+    /// Balances loaded from the previous report, see [Accounts::load_initial_state]
+    pub opening: Amount,
+    pub deposits: Amount,
+    pub withdrawals: Amount,
+    /// Administrative credits minus debits
+    pub adjustments: Amount,
+    pub chargebacks: Amount,
+    /// Withdrawals held by disputes or reversed by chargebacks, see [WithdrawalDisputePolicy::Reverse]
+    pub returned_withdrawals: Amount,
+}
+
+impl Movements {
+    /// Grand total all accounts should hold together
+    pub fn expected_total(&self) -> Amount {
+        self.opening
+            .saturating_add(self.deposits)
+            .saturating_add(self.adjustments)
+            .saturating_sub(self.withdrawals)
+            .saturating_add(self.returned_withdrawals)
+            .saturating_sub(self.chargebacks)
+    }
+
+    fn add(&mut self, other: &Movements) {
+        self.opening = self.opening.saturating_add(other.opening);
+        self.deposits = self.deposits.saturating_add(other.deposits);
+        self.withdrawals = self.withdrawals.saturating_add(other.withdrawals);
+        self.adjustments = self.adjustments.saturating_add(other.adjustments);
+        self.chargebacks = self.chargebacks.saturating_add(other.chargebacks);
+        self.returned_withdrawals = self
+            .returned_withdrawals
+            .saturating_add(other.returned_withdrawals);
+    }
+}
+
+/// Sum of balances of all accounts, see [Accounts::totals]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Totals {
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+}
+
+/// Number of clients in a segment and sum of their balances, see [Accounts::segment_totals]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SegmentTotals {
+    pub clients: u64,
+    pub totals: Totals,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Deserialize)]
+pub enum AccountStatus {
     Active,
     Frozen,
+    Closed,
 }
 
 impl AccountStatus {
     pub fn is_frozen(&self) -> bool {
         matches!(self, AccountStatus::Frozen)
     }
+
+    pub fn is_closed(&self) -> bool {
+        matches!(self, AccountStatus::Closed)
+    }
 }
 
-#[derive(serde::Deserialize)]
-/// AccountDetails encapsulates all relevant data for transaction processing. It tracks the values of `total`, `available` and `held` as well as account's status
+#[derive(Clone, serde::Deserialize)]
+#[serde(try_from = "ReportRow")]
+/// AccountDetails encapsulates all relevant data for transaction processing. It tracks the values of `total`, `available` and `held` as well as account's status.
+/// It is deserialized from a row of the report, see [Accounts::load_initial_state]
 struct AccountDetails {
     /// Status of the account, for example Active, Frozen etc.. See [AccountStatus] for possible values
     account_status: AccountStatus,
+    total: Amount,
+    available: Amount,
+    held: Amount,
+    /// Set when the client broke any of the [FraudRules]
+    flagged: bool,
+    fraud_counters: FraudCounters,
+    /// Transaction whose chargeback froze the account, `None` if it was frozen for other reason
+    frozen_by: Option<TransactionID>,
+    /// Amounts held by open disputes with the sequence number of the dispute, `held` is their sum
+    held_by: HashMap<TransactionID, (Amount, u64)>,
+    /// Disputed withdrawals among `held_by` which are reversed by chargeback, see [Accounts::dispute_withdrawal]
+    reversing: HashSet<TransactionID>,
+    /// Deposits and withdrawals reversed by [Accounts::reverse], so none of them is reversed twice
+    reversed: HashSet<TransactionID>,
+}
+
+/// Row of the report of either [ReportVersion], state columns of the other version are missing
+#[derive(serde::Deserialize)]
+struct ReportRow {
     #[serde(deserialize_with = "de_decimal")]
-    total: Decimal,
+    total: Amount,
     #[serde(deserialize_with = "de_decimal")]
-    available: Decimal,
+    available: Amount,
     #[serde(deserialize_with = "de_decimal")]
-    held: Decimal,
+    held: Amount,
+    #[serde(default)]
+    locked: bool,
+    #[serde(default)]
+    closed: bool,
+    #[serde(default)]
+    flagged: bool,
+    status: Option<String>,
+}
+
+impl TryFrom<ReportRow> for AccountDetails {
+    type Error = String;
+
+    fn try_from(row: ReportRow) -> Result<Self, Self::Error> {
+        let (locked, closed, flagged) = match row.status.as_deref() {
+            None => (row.locked, row.closed, row.flagged),
+            Some("active") => (false, false, false),
+            Some("frozen") => (true, false, false),
+            Some("closed") => (false, true, false),
+            Some("flagged") => (false, false, true),
+            Some(other) => return Err(format!("invalid status '{other}'")),
+        };
+        if row.available.checked_add(row.held) != Some(row.total) {
+            return Err(format!(
+                "total {} is not available {} + held {}",
+                row.total, row.available, row.held
+            ));
+        }
+        Ok(AccountDetails {
+            account_status: match (locked, closed) {
+                (_, true) => AccountStatus::Closed,
+                (true, false) => AccountStatus::Frozen,
+                (false, false) => AccountStatus::Active,
+            },
+            total: row.total,
+            available: row.available,
+            held: row.held,
+            flagged,
+            ..AccountDetails::default()
+        })
+    }
 }
 
-fn de_decimal<'de, D>(deserializer: D) -> Result<Decimal, D::Error>
+fn de_decimal<'de, D>(deserializer: D) -> Result<Amount, D::Error>
 where
     D: Deserializer<'de>,
 {
     use serde::de::{Deserialize, Error, Unexpected};
 
     let s = String::deserialize(deserializer)?;
-    Decimal::from_str_exact(&s).map_err(|err| {
+    Amount::from_str_exact(&s).map_err(|err| {
         Error::invalid_value(
             Unexpected::Str(&s),
             &format!("valid Decimal, error parsing decimal '{err}'").as_str(),
@@ -148,61 +1673,303 @@ where
 }
 
 impl AccountDetails {
+    /// Marks the account as suspicious, operations are still allowed
+    fn flag(&mut self, client_id: ClientID, rule: &str) {
+        if !self.flagged {
+            warn!(client_id, rule, "client flagged by fraud screening");
+            self.flagged = true;
+        }
+    }
+
+    /// Fails with [AccountError::AccountFrozen] if the account is frozen, used by deposits and withdrawals
+    fn ensure_not_frozen(&self, client_id: ClientID) -> Result<(), AccountError> {
+        match self.account_status.is_frozen() {
+            true => Err(AccountError::AccountFrozen(client_id)),
+            false => Ok(()),
+        }
+    }
+
+    /// Account is in the same state as newly opened one, so it can be evicted from memory
+    fn is_settled(&self) -> bool {
+        self.account_status == AccountStatus::Active
+            && !self.flagged
+            && self.total.is_zero()
+            && self.available.is_zero()
+            && self.held.is_zero()
+            && self.held_by.is_empty()
+            && self.reversed.is_empty()
+            && self.frozen_by.is_none()
+            && self.fraud_counters.chargebacks() == 0
+    }
+
+    /// Fails with [AccountError::AccountClosed] if the account is closed
+    fn ensure_open(&self, client_id: ClientID) -> Result<(), AccountError> {
+        match self.account_status.is_closed() {
+            true => Err(AccountError::AccountClosed(client_id)),
+            false => Ok(()),
+        }
+    }
+
     /// Increases `total` and `available` amounts
     /// # Arguments
     /// * amount - amount of the deposit which will be added to the total and available
-    pub fn deposit(&mut self, amount: Decimal) {
-        self.increase_balance(amount);
+    pub fn deposit(&mut self, amount: Amount) -> Result<(), AccountError> {
+        self.increase_balance(amount)
     }
 
-    /// Decreases `total` and `available` amounts
-    /// /// # Arguments
+    /// Decreases `total` and `available` amounts, rejects the withdrawal if `available` is not high enough
+    /// # Arguments
     /// * amount - amount of the withdrawal which will be subtracted from the total and available
-    pub fn withdraw(&mut self, amount: Decimal) {
-        // todo: we should probably deny withdrawal if the balance is not high enough
-        self.decrease_balance(amount);
+    pub fn withdraw(&mut self, amount: Amount) -> Result<(), AccountError> {
+        if amount > self.available {
+            return Err(AccountError::InsufficientFunds {
+                available: self.available,
+                requested: amount,
+            });
+        }
+
+        self.decrease_balance(amount)
     }
 
     /// Does a dispute - increases `held` and decreases `availaible` by provided amount
     /// If found changes transactions state to [InDispute], moves it to in-dispute cache.
     /// # Arguments
+    /// * transaction_id - ID of the disputed transaction, held amount is recorded under it
     /// * amount - value of the disputed transaction
-    pub fn dispute(&mut self, amount: Decimal) {
-        self.held += amount;
-        self.available -= amount;
+    /// * policy - what to do if the amount is higher than `available`
+    /// * sequence - sequence number of the dispute, age of the hold is measured from it
+    pub fn dispute(
+        &mut self,
+        transaction_id: TransactionID,
+        amount: Amount,
+        policy: DisputePolicy,
+        sequence: u64,
+    ) -> Result<DisputeOutcome, AccountError> {
+        self.ensure_disputable(transaction_id)?;
+
+        let outcome = match policy {
+            DisputePolicy::Clamp if amount > self.available => DisputeOutcome::Clamped {
+                disputed: amount,
+                held: self.available.max(Amount::ZERO),
+            },
+            DisputePolicy::Reject if amount > self.available => {
+                return Err(AccountError::DisputeExceedsAvailable {
+                    available: self.available,
+                    disputed: amount,
+                })
+            }
+            _ => DisputeOutcome::Held(amount),
+        };
+
+        let held = match outcome {
+            DisputeOutcome::Held(held) | DisputeOutcome::Clamped { held, .. } => held,
+        };
+        self.update_balances(
+            Some(self.total),
+            self.available.checked_sub(held),
+            self.held.checked_add(held),
+        )?;
+        self.held_by.insert(transaction_id, (held, sequence));
+        Ok(outcome)
     }
 
-    /// Resolves dispute - reduces `held` and increaes `available` by amount provided
-    /// If found changes transactions state to [Resolved], moves it to resolved cache.
+    /// Resolves dispute - reduces `held` and increases `available` by the released part of the amount held
+    /// for the transaction. Returns the released amount
     /// # Arguments
-    /// * amount - value of the disputed transaction
-    pub fn resolve(&mut self, amount: Decimal /* id: &TransactionID */) /* -> Result<()> */
-    {
-        self.held -= amount;
-        self.available += amount;
+    /// * transaction_id - ID of the disputed transaction
+    /// * released - part of the held amount which is released, at most the held amount. The rest stays held
+    pub fn resolve(
+        &mut self,
+        transaction_id: TransactionID,
+        released: Amount,
+    ) -> Result<Amount, AccountError> {
+        let amount = released.min(self.held_amount(transaction_id)?);
+        self.update_balances(
+            Some(self.total),
+            self.available.checked_add(amount),
+            self.held.checked_sub(amount),
+        )?;
+        self.release_hold(transaction_id, amount);
+        Ok(amount)
     }
 
-    /// Processes chargeback - decreases `held` and `total` and sets account's status to [AccountStatus::Frozen]
-    /// If found changes transactions state to [Chargedback], moves it to chardeback cache.
+    /// Processes chargeback - decreases `held` by the amount held for the transaction, `total` by the charged
+    /// back part of it and `available` gets the rest. Sets account's status to [AccountStatus::Frozen].
+    /// Returns the charged back amount
     /// # Arguments
-    /// * amount - value of the disputed transaction
-    pub fn chargeback(&mut self, amount: Decimal /* id: &TransactionID */) /* -> Result<()> */
-    {
-        self.held -= amount;
-        self.total -= amount;
+    /// * transaction_id - ID of the disputed transaction
+    /// * charged - part of the held amount which is charged back, at most the held amount
+    pub fn chargeback(
+        &mut self,
+        transaction_id: TransactionID,
+        charged: Amount,
+    ) -> Result<Amount, AccountError> {
+        let held = self.held_amount(transaction_id)?;
+        let amount = charged.min(held);
+        self.update_balances(
+            self.total.checked_sub(amount),
+            self.available.checked_add(held.saturating_sub(amount)),
+            self.held.checked_sub(held),
+        )?;
+        self.held_by.remove(&transaction_id);
         self.account_status = AccountStatus::Frozen;
+        Ok(amount)
+    }
+
+    /// Dispute of withdrawal - increases `held` and `total` by the withdrawn amount, `available` stays the same
+    /// # Arguments
+    /// * transaction_id - ID of the disputed withdrawal, held amount is recorded under it
+    /// * amount - value of the disputed withdrawal
+    /// * sequence - sequence number of the dispute, age of the hold is measured from it
+    pub fn dispute_withdrawal(
+        &mut self,
+        transaction_id: TransactionID,
+        amount: Amount,
+        sequence: u64,
+    ) -> Result<(), AccountError> {
+        self.ensure_disputable(transaction_id)?;
+
+        self.update_balances(
+            self.total.checked_add(amount),
+            Some(self.available),
+            self.held.checked_add(amount),
+        )?;
+        self.held_by.insert(transaction_id, (amount, sequence));
+        self.reversing.insert(transaction_id);
+        Ok(())
+    }
+
+    /// Resolves dispute of withdrawal - the withdrawal stands, `held` and `total` decrease by the dropped part
+    /// of the held amount, the rest stays held. Returns the dropped amount
+    pub fn resolve_withdrawal(
+        &mut self,
+        transaction_id: TransactionID,
+        dropped: Amount,
+    ) -> Result<Amount, AccountError> {
+        let amount = dropped.min(self.held_amount(transaction_id)?);
+        self.update_balances(
+            self.total.checked_sub(amount),
+            Some(self.available),
+            self.held.checked_sub(amount),
+        )?;
+        if self.release_hold(transaction_id, amount) {
+            self.reversing.remove(&transaction_id);
+        }
+        Ok(amount)
+    }
+
+    /// Chargeback of withdrawal - the charged back part of the withdrawal is reversed and moves from `held` to
+    /// `available`, the rest of the held amount is dropped like by resolve. Account's status is set to
+    /// [AccountStatus::Frozen]. Returns the returned amount
+    pub fn chargeback_withdrawal(
+        &mut self,
+        transaction_id: TransactionID,
+        charged: Amount,
+    ) -> Result<Amount, AccountError> {
+        let held = self.held_amount(transaction_id)?;
+        let amount = charged.min(held);
+        self.update_balances(
+            self.total.checked_sub(held.saturating_sub(amount)),
+            self.available.checked_add(amount),
+            self.held.checked_sub(held),
+        )?;
+        self.held_by.remove(&transaction_id);
+        self.reversing.remove(&transaction_id);
+        self.account_status = AccountStatus::Frozen;
+        Ok(amount)
+    }
+
+    /// Reverses deposit or withdrawal - deposit decreases `total` and `available` like a withdrawal and is rejected
+    /// if `available` is not high enough, withdrawal increases them like a deposit
+    /// # Arguments
+    /// * transaction_id - ID of the reversed transaction, it can't be under dispute nor reversed before
+    /// * amount - value of the reversed transaction
+    /// * kind - whether the reversed transaction is a deposit or a withdrawal
+    pub fn reverse(
+        &mut self,
+        transaction_id: TransactionID,
+        amount: Amount,
+        kind: TransactionKind,
+    ) -> Result<(), AccountError> {
+        self.ensure_disputable(transaction_id)?;
+        match kind {
+            TransactionKind::Deposit => self.withdraw(amount)?,
+            TransactionKind::Withdrawal => self.increase_balance(amount)?,
+        }
+        self.reversed.insert(transaction_id);
+        Ok(())
+    }
+
+    /// Fails if the transaction is under dispute or was reversed
+    fn ensure_disputable(&self, transaction_id: TransactionID) -> Result<(), AccountError> {
+        if self.held_by.contains_key(&transaction_id) {
+            return Err(AccountError::AlreadyDisputed(transaction_id));
+        }
+        if self.reversed.contains(&transaction_id) {
+            return Err(AccountError::AlreadyReversed(transaction_id));
+        }
+        Ok(())
+    }
+
+    /// Lowers the amount held for the transaction by the released part, the hold keeps its age. Returns whether
+    /// the whole hold was released and the dispute is over
+    fn release_hold(&mut self, transaction_id: TransactionID, released: Amount) -> bool {
+        let Some((held, _)) = self.held_by.get_mut(&transaction_id) else {
+            return true;
+        };
+        *held = held.saturating_sub(released);
+        if *held > Amount::ZERO {
+            return false;
+        }
+        self.held_by.remove(&transaction_id);
+        true
+    }
+
+    fn held_amount(&self, transaction_id: TransactionID) -> Result<Amount, AccountError> {
+        self.held_by
+            .get(&transaction_id)
+            .map(|(amount, _)| *amount)
+            .ok_or(AccountError::NotDisputed(transaction_id))
     }
 
     #[inline(always)]
-    fn increase_balance(&mut self, amount: Decimal) {
-        self.total += amount;
-        self.available += amount;
+    fn increase_balance(&mut self, amount: Amount) -> Result<(), AccountError> {
+        self.update_balances(
+            self.total.checked_add(amount),
+            self.available.checked_add(amount),
+            Some(self.held),
+        )
     }
 
     #[inline(always)]
-    fn decrease_balance(&mut self, amount: Decimal) {
-        self.total -= amount;
-        self.available -= amount;
+    fn decrease_balance(&mut self, amount: Amount) -> Result<(), AccountError> {
+        self.update_balances(
+            self.total.checked_sub(amount),
+            self.available.checked_sub(amount),
+            Some(self.held),
+        )
+    }
+
+    /// Stores new balances computed with checked arithmetic. Balances are stored only if none of them overflowed,
+    /// so the account is never left half-updated. On overflow the account is frozen for manual review.
+    fn update_balances(
+        &mut self,
+        total: Option<Amount>,
+        available: Option<Amount>,
+        held: Option<Amount>,
+    ) -> Result<(), AccountError> {
+        match (total, available, held) {
+            (Some(total), Some(available), Some(held)) => {
+                self.total = total;
+                self.available = available;
+                self.held = held;
+                Ok(())
+            }
+            _ => {
+                self.account_status = AccountStatus::Frozen;
+                Err(AccountError::Overflow)
+            }
+        }
     }
 }
 
@@ -210,9 +1977,605 @@ impl Default for AccountDetails {
     fn default() -> Self {
         AccountDetails {
             account_status: AccountStatus::Active,
-            total: Decimal::ZERO,
-            available: Decimal::ZERO,
-            held: Decimal::ZERO,
+            total: Amount::ZERO,
+            available: Amount::ZERO,
+            held: Amount::ZERO,
+            flagged: false,
+            fraud_counters: FraudCounters::default(),
+            frozen_by: None,
+            held_by: HashMap::new(),
+            reversing: HashSet::new(),
+            reversed: HashSet::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Report of the client's account
+    fn client_report(accounts: &Accounts, client_id: ClientID) -> String {
+        let mut report = Vec::new();
+        accounts
+            .write_clients_report(&mut report, [client_id])
+            .unwrap();
+        String::from_utf8(report).unwrap()
+    }
+
+    #[test]
+    fn test_withdraw() {
+        let mut accounts = Accounts::default();
+        accounts.deposit(1, amount!(10)).unwrap();
+
+        assert_eq!(accounts.withdraw(1, amount!(4)), Ok(None));
+        assert_eq!(
+            accounts.withdraw(1, amount!(7)),
+            Err(AccountError::InsufficientFunds {
+                available: amount!(6),
+                requested: amount!(7)
+            })
+        );
+
+        let acc_details = &accounts.accounts[&1];
+        assert_eq!(acc_details.available, amount!(6));
+        assert_eq!(acc_details.total, amount!(6));
+    }
+
+    #[test]
+    fn test_query() {
+        let mut accounts = Accounts::default();
+        accounts.deposit(1, amount!(10)).unwrap();
+        accounts.deposit(2, amount!(5)).unwrap();
+        accounts.dispute(2, 2, amount!(2)).unwrap();
+
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts.get(3), None);
+        assert_eq!(
+            accounts.get(2),
+            Some(AccountView {
+                client_id: 2,
+                available: amount!(3),
+                held: amount!(2),
+                total: amount!(5),
+                status: AccountStatus::Active,
+                flagged: false,
+                open_disputes: 1,
+                chargebacks: 0,
+            })
+        );
+        assert_eq!(accounts.iter().count(), 2);
+        assert_eq!(
+            accounts.totals(),
+            Totals {
+                available: amount!(13),
+                held: amount!(2),
+                total: amount!(15),
+            }
+        );
+    }
+
+    #[test]
+    fn test_movements() {
+        let mut accounts = Accounts::default();
+        accounts.deposit(1, amount!(10)).unwrap();
+        accounts.deposit(2, amount!(5)).unwrap();
+        accounts.withdraw(1, amount!(3)).unwrap();
+        accounts.withdraw(2, amount!(6)).unwrap_err();
+        accounts.transfer(1, 2, amount!(1)).unwrap();
+        accounts.adjust_debit(2, amount!(0.5)).unwrap();
+        accounts.dispute(2, 2, amount!(5)).unwrap();
+        accounts.chargeback(2, 2).unwrap();
+
+        assert_eq!(
+            accounts.movements(),
+            Movements {
+                deposits: amount!(15),
+                withdrawals: amount!(3),
+                adjustments: amount!(-0.5),
+                chargebacks: amount!(5),
+                ..Default::default()
+            }
+        );
+        assert_eq!(accounts.integrity_mismatch(), None);
+
+        // balances changed behind the ledger's back
+        let details = accounts.accounts.get_mut(&1).unwrap();
+        details.total = details.total.saturating_add(amount!(1));
+        assert_eq!(accounts.integrity_mismatch(), Some(amount!(1)));
+    }
+
+    #[test]
+    fn test_overflow_freezes_account() {
+        let mut accounts = Accounts::default();
+        accounts.deposit(1, Amount::MAX).unwrap();
+
+        assert_eq!(accounts.deposit(1, amount!(1)), Err(AccountError::Overflow));
+
+        let acc_details = &accounts.accounts[&1];
+        assert_eq!(acc_details.total, Amount::MAX);
+        assert_eq!(acc_details.available, Amount::MAX);
+        assert!(acc_details.account_status.is_frozen());
+    }
+
+    #[test]
+    fn test_transfer() {
+        let mut accounts = Accounts::default();
+        accounts.deposit(1, amount!(10)).unwrap();
+        accounts.deposit(3, amount!(10)).unwrap();
+        accounts.dispute(3, 2, amount!(10)).unwrap();
+        accounts.chargeback(3, 2).unwrap();
+
+        assert_eq!(accounts.transfer(1, 2, amount!(4)), Ok(()));
+        assert_eq!(
+            accounts.transfer(1, 2, amount!(7)),
+            Err(AccountError::InsufficientFunds {
+                available: amount!(6),
+                requested: amount!(7)
+            })
+        );
+        assert_eq!(
+            accounts.transfer(1, 3, amount!(1)),
+            Err(AccountError::AccountFrozen(3))
+        );
+        assert_eq!(
+            accounts.transfer(4, 1, amount!(1)),
+            Err(AccountError::InsufficientFunds {
+                available: amount!(0),
+                requested: amount!(1)
+            })
+        );
+
+        assert_eq!(accounts.accounts[&1].available, amount!(6));
+        assert_eq!(accounts.accounts[&2].available, amount!(4));
+        assert_eq!(accounts.accounts[&2].total, amount!(4));
+        assert!(!accounts.accounts.contains_key(&4));
+    }
+
+    #[test]
+    fn test_frozen_account() {
+        let mut accounts = Accounts::default().with_unfreeze_on_resolve(true);
+        accounts.deposit(1, amount!(10)).unwrap();
+        accounts.deposit(1, amount!(5)).unwrap();
+        accounts.dispute(1, 1, amount!(10)).unwrap();
+        accounts.dispute(1, 2, amount!(5)).unwrap();
+        accounts.chargeback(1, 1).unwrap();
+
+        assert_eq!(
+            accounts.deposit(1, amount!(1)),
+            Err(AccountError::AccountFrozen(1))
+        );
+        assert_eq!(
+            accounts.withdraw(1, amount!(1)),
+            Err(AccountError::AccountFrozen(1))
+        );
+
+        // only resolve of the charged back transaction unfreezes the account
+        assert_eq!(
+            accounts.resolve(1, 2),
+            Ok(ResolveOutcome::Released(amount!(5)))
+        );
+        assert!(accounts.accounts[&1].account_status.is_frozen());
+        assert_eq!(accounts.resolve(1, 1), Ok(ResolveOutcome::Unfrozen));
+
+        let acc_details = &accounts.accounts[&1];
+        assert!(!acc_details.account_status.is_frozen());
+        assert_eq!(acc_details.available, amount!(5));
+        assert_eq!(acc_details.held, amount!(0));
+        assert_eq!(acc_details.total, amount!(5));
+    }
+
+    #[test]
+    fn test_adjustments() {
+        let mut accounts = Accounts::default();
+        accounts.adjust_credit(1, amount!(5)).unwrap();
+        accounts.adjust_debit(1, amount!(8)).unwrap();
+        accounts.lock(1).unwrap();
+
+        let acc_details = &accounts.accounts[&1];
+        assert_eq!(acc_details.available, amount!(-3));
+        assert_eq!(acc_details.total, amount!(-3));
+        assert!(acc_details.account_status.is_frozen());
+
+        accounts.unlock(1).unwrap();
+        assert!(!accounts.accounts[&1].account_status.is_frozen());
+        assert_eq!(accounts.lock(2), Err(AccountError::AccountNotFound));
+    }
+
+    #[test]
+    fn test_close() {
+        let mut accounts = Accounts::default();
+        accounts.deposit(1, amount!(10)).unwrap();
+        accounts.dispute(1, 1, amount!(10)).unwrap();
+
+        assert_eq!(
+            accounts.close(1),
+            Err(AccountError::HeldFundsOnClose(amount!(10)))
+        );
+        accounts.resolve(1, 1).unwrap();
+        assert_eq!(accounts.close(1), Ok(()));
+
+        assert_eq!(
+            accounts.deposit(1, amount!(1)),
+            Err(AccountError::AccountClosed(1))
+        );
+        assert_eq!(
+            accounts.withdraw(1, amount!(1)),
+            Err(AccountError::AccountClosed(1))
+        );
+        assert_eq!(accounts.unlock(1), Err(AccountError::AccountClosed(1)));
+        assert_eq!(
+            accounts.transfer(2, 1, amount!(1)),
+            Err(AccountError::AccountClosed(1))
+        );
+        assert_eq!(accounts.close(1), Err(AccountError::AccountClosed(1)));
+        assert_eq!(accounts.accounts[&1].total, amount!(10));
+
+        // closure is reported only by the status column of report version 2
+        assert_eq!(
+            client_report(&accounts, 1),
+            "client,available,held,total,locked\n1,10,0,10,false\n"
+        );
+        let accounts = accounts.with_report_version(ReportVersion::V2);
+        assert_eq!(
+            client_report(&accounts, 1),
+            "client,available,held,total,status\n1,10,0,10,closed\n"
+        );
+    }
+
+    #[test]
+    fn test_dispute_policy() {
+        let tests = vec![
+            (
+                "allow",
+                DisputePolicy::Allow,
+                Ok(DisputeOutcome::Held(amount!(5))),
+                amount!(-2),
+                amount!(5),
+            ),
+            (
+                "clamp",
+                DisputePolicy::Clamp,
+                Ok(DisputeOutcome::Clamped {
+                    disputed: amount!(5),
+                    held: amount!(3),
+                }),
+                amount!(0),
+                amount!(3),
+            ),
+            (
+                "reject",
+                DisputePolicy::Reject,
+                Err(AccountError::DisputeExceedsAvailable {
+                    available: amount!(3),
+                    disputed: amount!(5),
+                }),
+                amount!(3),
+                amount!(0),
+            ),
+        ];
+
+        for (name, policy, want, want_available, want_held) in tests {
+            let mut accounts = Accounts::new(policy);
+            accounts.deposit(1, amount!(5)).unwrap();
+            accounts.withdraw(1, amount!(2)).unwrap();
+
+            assert_eq!(
+                accounts.dispute(1, 1, amount!(5)),
+                want,
+                "failed test {name}"
+            );
+            let acc_details = &accounts.accounts[&1];
+            assert_eq!(acc_details.available, want_available, "failed test {name}");
+            assert_eq!(acc_details.held, want_held, "failed test {name}");
+            assert_eq!(acc_details.total, amount!(3), "failed test {name}");
+        }
+    }
+
+    #[test]
+    fn test_held_ledger() {
+        let mut accounts = Accounts::new(DisputePolicy::Clamp);
+        accounts.deposit(1, amount!(5)).unwrap();
+        accounts.withdraw(1, amount!(2)).unwrap();
+        accounts.deposit(1, amount!(4)).unwrap();
+        accounts.dispute(1, 3, amount!(4)).unwrap();
+        accounts.dispute(1, 1, amount!(5)).unwrap();
+
+        assert_eq!(
+            accounts.dispute(1, 1, amount!(5)),
+            Err(AccountError::AlreadyDisputed(1))
+        );
+        assert_eq!(accounts.resolve(1, 2), Err(AccountError::NotDisputed(2)));
+        assert_eq!(accounts.get(1).unwrap().open_disputes, 2);
+
+        // only the clamped amount was held, so only that is released
+        assert_eq!(
+            accounts.resolve(1, 1),
+            Ok(ResolveOutcome::Released(amount!(3)))
+        );
+        assert_eq!(accounts.chargeback(1, 1), Err(AccountError::NotDisputed(1)));
+        // only part of the held amount is charged back, the rest is released
+        assert_eq!(accounts.chargeback_up_to(1, 3, amount!(1.5)), Ok(None));
+
+        let view = accounts.get(1).unwrap();
+        assert_eq!(view.available, amount!(5.5));
+        assert_eq!(view.held, amount!(0));
+        assert_eq!(view.total, amount!(5.5));
+        assert_eq!(view.open_disputes, 0);
+        assert_eq!(view.chargebacks, 1);
+    }
+
+    #[test]
+    fn test_dispute_withdrawal() {
+        let tests = vec![
+            (
+                "as deposit",
+                WithdrawalDisputePolicy::AsDeposit,
+                None,
+                (amount!(2), amount!(4), amount!(6), false),
+            ),
+            (
+                "reverse",
+                WithdrawalDisputePolicy::Reverse,
+                None,
+                (amount!(6), amount!(4), amount!(10), false),
+            ),
+            (
+                "reverse resolved",
+                WithdrawalDisputePolicy::Reverse,
+                Some("resolve"),
+                (amount!(6), amount!(0), amount!(6), false),
+            ),
+            (
+                "reverse charged back",
+                WithdrawalDisputePolicy::Reverse,
+                Some("chargeback"),
+                (amount!(10), amount!(0), amount!(10), true),
+            ),
+            (
+                "reverse partly charged back",
+                WithdrawalDisputePolicy::Reverse,
+                Some("partial chargeback"),
+                (amount!(7), amount!(0), amount!(7), true),
+            ),
+        ];
+
+        for (name, policy, end, want) in tests {
+            let mut accounts = Accounts::default().with_withdrawal_disputes(policy);
+            accounts.deposit(1, amount!(10)).unwrap();
+            accounts.withdraw(1, amount!(4)).unwrap();
+            accounts.dispute_withdrawal(1, 2, amount!(4)).unwrap();
+            assert_eq!(
+                accounts.dispute_withdrawal(1, 2, amount!(4)),
+                Err(AccountError::AlreadyDisputed(2)),
+                "failed test {name}"
+            );
+            match end {
+                Some("resolve") => assert_eq!(
+                    accounts.resolve(1, 2),
+                    Ok(ResolveOutcome::Upheld(amount!(4))),
+                    "failed test {name}"
+                ),
+                Some("partial chargeback") => assert_eq!(
+                    accounts.chargeback_up_to(1, 2, amount!(1)),
+                    Ok(None),
+                    "failed test {name}"
+                ),
+                Some(_) => assert_eq!(accounts.chargeback(1, 2), Ok(None), "failed test {name}"),
+                None => (),
+            }
+
+            let view = accounts.get(1).unwrap();
+            assert_eq!(
+                (
+                    view.available,
+                    view.held,
+                    view.total,
+                    view.status.is_frozen()
+                ),
+                want,
+                "failed test {name}"
+            );
+            assert_eq!(accounts.integrity_mismatch(), None, "failed test {name}");
         }
     }
+
+    #[test]
+    fn test_max_open_disputes() {
+        let mut accounts = Accounts::default().with_max_open_disputes(Some(2));
+        accounts.deposit(1, amount!(3)).unwrap();
+        accounts.dispute(1, 1, amount!(1)).unwrap();
+        accounts.dispute(1, 2, amount!(1)).unwrap();
+        assert!(!accounts.get(1).unwrap().flagged, "exactly at the cap");
+
+        assert_eq!(
+            accounts.dispute(1, 3, amount!(1)),
+            Err(AccountError::TooManyOpenDisputes(2))
+        );
+        assert_eq!(
+            accounts.dispute(1, 2, amount!(1)),
+            Err(AccountError::AlreadyDisputed(2)),
+            "only disputes of new transactions are over the cap"
+        );
+        let view = accounts.get(1).unwrap();
+        assert_eq!((view.held, view.open_disputes), (amount!(2), 2));
+        assert!(view.flagged);
+
+        // resolved dispute makes room for another one
+        accounts.resolve(1, 1).unwrap();
+        accounts.dispute(1, 3, amount!(1)).unwrap();
+    }
+
+    #[test]
+    fn test_fraud_rules_flag() {
+        let mut accounts = Accounts::default().with_fraud_rules(FraudRules {
+            deposit_velocity: Some(crate::fraud::DepositVelocity {
+                max_deposits: 1,
+                within_records: 10,
+            }),
+            ..Default::default()
+        });
+        accounts.deposit(1, amount!(1)).unwrap();
+        accounts.deposit(2, amount!(1)).unwrap();
+        assert!(!accounts.get(1).unwrap().flagged, "exactly at the velocity");
+
+        accounts.deposit(1, amount!(2)).unwrap();
+        assert!(accounts.get(1).unwrap().flagged);
+        assert!(!accounts.get(2).unwrap().flagged);
+        assert_eq!(accounts.flagged_count(), 1);
+        accounts.withdraw(1, amount!(1)).unwrap();
+        assert_eq!(
+            accounts.get(1).unwrap().available,
+            amount!(2),
+            "flagged account still operates"
+        );
+
+        // the flag is reported only by the status column of report version 2
+        assert_eq!(
+            client_report(&accounts, 1),
+            "client,available,held,total,locked\n1,2,0,2,false\n"
+        );
+        let accounts = accounts.with_report_version(ReportVersion::V2);
+        assert_eq!(
+            client_report(&accounts, 1),
+            "client,available,held,total,status\n1,2,0,2,flagged\n"
+        );
+    }
+
+    #[test]
+    fn test_evict_settled() {
+        let mut accounts = Accounts::new(DisputePolicy::default());
+        accounts.deposit(1, amount!(5)).unwrap();
+        accounts.withdraw(1, amount!(5)).unwrap();
+        accounts.deposit(2, amount!(5)).unwrap();
+        accounts.deposit(3, amount!(1)).unwrap();
+        accounts.withdraw(3, amount!(1)).unwrap();
+        accounts.lock(3).unwrap();
+
+        assert_eq!(accounts.evict_settled(), 1);
+        assert_eq!(accounts.len(), 3, "evicted account is still counted");
+        assert_eq!(accounts.get(1).unwrap().total, amount!(0));
+
+        accounts.deposit(1, amount!(2)).unwrap();
+        assert_eq!(accounts.evicted_count(), 0, "restored when seen again");
+        assert_eq!(accounts.get(1).unwrap().available, amount!(2));
+
+        let mut accounts = accounts.with_fraud_rules(FraudRules {
+            max_chargeback_ratio: Some(rust_decimal_macros::dec!(50)),
+            ..Default::default()
+        });
+        accounts.withdraw(1, amount!(2)).unwrap();
+        assert_eq!(accounts.evict_settled(), 0, "fraud rules need the history");
+    }
+
+    #[test]
+    fn test_aged_holds() {
+        let mut accounts = Accounts::new(DisputePolicy::default());
+        for (client_id, transaction_id) in [(1, 1), (1, 2), (2, 3)] {
+            accounts.advance_sequence();
+            accounts.deposit(client_id, amount!(5)).unwrap();
+            accounts.advance_sequence();
+            accounts
+                .dispute(client_id, transaction_id, amount!(1))
+                .unwrap();
+        }
+        let tests = vec![
+            ("all", 0, 3),
+            ("older", 2, 2),
+            ("oldest", 4, 1),
+            ("none", 5, 0),
+        ];
+
+        for (name, min_age, want) in tests {
+            assert_eq!(
+                accounts.aged_holds(min_age).count(),
+                want,
+                "failed test {name}"
+            );
+        }
+        let oldest = accounts.aged_holds(4).next().unwrap();
+        assert_eq!((oldest.client_id, oldest.transaction_id), (1, 1));
+    }
+
+    #[test]
+    fn test_load_initial_state() {
+        let path = std::env::temp_dir().join(format!(
+            "tren-test-initial-state-{}.csv",
+            std::process::id()
+        ));
+        let tests = vec![
+            (
+                "v1",
+                "client,available,held,total,locked\n1,1.5,0.5,2,false\n2,3,0,3,true\ntotals,4.5,0.5,5,\n",
+                Some(vec![(1, amount!(1.5), AccountStatus::Active, false), (2, amount!(3), AccountStatus::Frozen, false)]),
+            ),
+            (
+                "v1 with closed and flagged",
+                "client,available,held,total,locked,closed,flagged\n1,1.5,0.5,2,false,false,true\n2,3,0,3,true,false,false\ntotals,4.5,0.5,5,,,\n",
+                Some(vec![(1, amount!(1.5), AccountStatus::Active, true), (2, amount!(3), AccountStatus::Frozen, false)]),
+            ),
+            (
+                "v2 extended",
+                "client,available,held,total,status,open_disputes,chargebacks\n1,0,0,0,closed,0,1\n2, 3 ,0,3,frozen,0,0\n",
+                Some(vec![(1, amount!(0), AccountStatus::Closed, false), (2, amount!(3), AccountStatus::Frozen, false)]),
+            ),
+            (
+                "unbalanced",
+                "client,available,held,total,status\n1,1,0,2,active\n",
+                None,
+            ),
+            (
+                "duplicate client",
+                "client,available,held,total,status\n1,1,0,1,active\n1,1,0,1,active\n",
+                None,
+            ),
+            ("missing column", "client,available,total,status\n1,1,1,active\n", None),
+        ];
+
+        for (name, report, want) in tests {
+            std::fs::write(&path, report).unwrap();
+            let mut accounts = Accounts::default();
+            let got = accounts.load_initial_state(&path).ok().map(|_| {
+                let mut got: Vec<_> = accounts
+                    .iter()
+                    .map(|view| (view.client_id, view.available, view.status, view.flagged))
+                    .collect();
+                got.sort_unstable_by_key(|(client_id, ..)| *client_id);
+                got
+            });
+            assert_eq!(got, want, "failed test {name}");
+            if want.is_some() {
+                assert_eq!(accounts.integrity_mismatch(), None, "failed test {name}");
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parallel_report() {
+        let mut accounts = Accounts::default().with_totals_row(true);
+        for client_id in 1..=40_000 {
+            accounts.deposit(client_id, amount!(1.5)).unwrap();
+        }
+        accounts.lock(7).unwrap();
+        let mut serial = Vec::new();
+        accounts.write_report(&mut serial).unwrap();
+
+        let accounts = accounts.with_report_threads(Some(3));
+        let mut parallel = Vec::new();
+        accounts.write_report(&mut parallel).unwrap();
+
+        let serial = String::from_utf8(serial).unwrap();
+        let mut want: Vec<_> = serial.lines().collect();
+        want[1..40_001].sort_unstable_by_key(|row| {
+            row.split(',').next().unwrap().parse::<ClientID>().unwrap()
+        });
+        assert_eq!(
+            String::from_utf8(parallel)
+                .unwrap()
+                .lines()
+                .collect::<Vec<_>>(),
+            want
+        );
+    }
 }
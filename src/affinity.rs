@@ -0,0 +1,110 @@
+//! Pinning of the pipeline threads to CPUs, on NUMA machines keeping the parser and the processing on the same
+//! node avoids moving every parsed record across the nodes
+use eyre::{eyre, Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+/// CPUs the pipeline threads may run on, empty list leaves the thread to the scheduler
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CpuAffinity {
+    pub parser: Vec<usize>,
+    pub disputes: Vec<usize>,
+    pub accounts: Vec<usize>,
+}
+
+impl CpuAffinity {
+    /// Sets CPUs of one thread from `<thread>=<cpus>`, e.g. `parser=0-3,8`. Threads are parser, disputes and accounts
+    pub fn set(&mut self, value: &str) -> Result<()> {
+        let (thread, cpus) = value.split_once('=').ok_or(eyre!(
+            "invalid cpu affinity '{value}', expected <thread>=<cpus>, e.g. parser=0-3"
+        ))?;
+        let cpus = parse_cpus(cpus).wrap_err_with(|| format!("invalid cpu list of {thread}"))?;
+        match thread {
+            "parser" => self.parser = cpus,
+            "disputes" => self.disputes = cpus,
+            "accounts" => self.accounts = cpus,
+            _ => {
+                return Err(eyre!(
+                    "invalid thread '{thread}', expected one of parser, disputes, accounts"
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses comma separated CPUs and inclusive ranges, e.g. `0-3,8`
+fn parse_cpus(cpus: &str) -> Result<Vec<usize>> {
+    let mut parsed = Vec::new();
+    for part in cpus.split(',').map(str::trim) {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (start.parse::<usize>()?, end.parse::<usize>()?);
+                if start > end {
+                    return Err(eyre!("cpu range {part} is reversed"));
+                }
+                parsed.extend(start..=end);
+            }
+            None => parsed.push(part.parse()?),
+        }
+    }
+    Ok(parsed)
+}
+
+/// Pins the current thread to the CPUs, failure is only logged so the run continues unpinned
+pub fn pin_current_thread(cpus: &[usize]) {
+    if cpus.is_empty() {
+        return;
+    }
+    match set_affinity(cpus) {
+        Ok(()) => debug!(?cpus, "pinned thread"),
+        Err(err) => warn!(%err, ?cpus, "failed to pin thread, leaving it to the scheduler"),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_affinity(cpus: &[usize]) -> Result<()> {
+    // SAFETY: cpu_set_t is plain bit mask, zeroed is an empty set and CPU_SET checks the bounds
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        for cpu in cpus {
+            if *cpu >= libc::CPU_SETSIZE as usize {
+                return Err(eyre!("cpu {cpu} is out of range"));
+            }
+            libc::CPU_SET(*cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_affinity(_cpus: &[usize]) -> Result<()> {
+    Err(eyre!("cpu affinity is supported only on linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set() {
+        let tests = vec![
+            ("single", "parser=2", Some(vec![2])),
+            ("range and list", "parser=0-2,8", Some(vec![0, 1, 2, 8])),
+            ("reversed range", "parser=3-1", None),
+            ("unknown thread", "reporter=1", None),
+            ("missing cpus", "parser", None),
+        ];
+
+        for (name, value, want) in tests {
+            let mut affinity = CpuAffinity::default();
+            let got = affinity.set(value).ok().map(|()| affinity.parser);
+            assert_eq!(got, want, "failed test {name}");
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! Balance thresholds set by repeated `--alert`, e.g. `--alert 'available<0' --alert 'held>1000'`. Once the
+//! journal is processed every account is checked against them and the accounts crossing any of them are listed
+//! in the [crate::summary::Summary], so negative balances left by disputes don't have to be looked for in the
+//! report. An account crossing several thresholds is listed once for each of them
+use crate::accounts::{AccountView, Accounts};
+use crate::aliases::*;
+use eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Balance of the account compared with the threshold
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Balance {
+    Available,
+    Held,
+    Total,
+}
+
+impl Balance {
+    fn of(self, account: &AccountView) -> Amount {
+        match self {
+            Balance::Available => account.available,
+            Balance::Held => account.held,
+            Balance::Total => account.total,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Balance::Available => "available",
+            Balance::Held => "held",
+            Balance::Total => "total",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    Below,
+    Above,
+}
+
+/// Threshold given by `--alert`, the account crosses it if its balance is strictly below or above the amount
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Threshold {
+    pub balance: Balance,
+    pub comparison: Comparison,
+    pub amount: Amount,
+}
+
+impl FromStr for Threshold {
+    type Err = eyre::Report;
+
+    /// Parses `<balance><comparison><amount>`, e.g. `available<0` or `held>1000`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (balance, comparison, amount) = match (s.split_once('<'), s.split_once('>')) {
+            (Some((balance, amount)), None) => (balance, Comparison::Below, amount),
+            (None, Some((balance, amount))) => (balance, Comparison::Above, amount),
+            _ => {
+                return Err(eyre!(
+                    "invalid alert '{s}', expected <balance><comparison><amount>, e.g. available<0"
+                ))
+            }
+        };
+        let balance = match balance.trim() {
+            "available" => Balance::Available,
+            "held" => Balance::Held,
+            "total" => Balance::Total,
+            balance => {
+                return Err(eyre!(
+                    "invalid balance '{balance}', expected one of available, held, total"
+                ))
+            }
+        };
+        let amount = amount
+            .trim()
+            .parse()
+            .wrap_err_with(|| format!("invalid amount of alert '{s}'"))?;
+        Ok(Threshold {
+            balance,
+            comparison,
+            amount,
+        })
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let comparison = match self.comparison {
+            Comparison::Below => '<',
+            Comparison::Above => '>',
+        };
+        write!(f, "{}{comparison}{}", self.balance.name(), self.amount)
+    }
+}
+
+impl Threshold {
+    /// Balance of the account if it crosses the threshold
+    pub fn crossed_by(&self, account: &AccountView) -> Option<Amount> {
+        let balance = self.balance.of(account);
+        let crossed = match self.comparison {
+            Comparison::Below => balance < self.amount,
+            Comparison::Above => balance > self.amount,
+        };
+        crossed.then_some(balance)
+    }
+}
+
+/// Account which crossed the threshold at the end of the run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Alert {
+    pub client_id: ClientID,
+    pub threshold: Threshold,
+    /// Balance the threshold was compared with
+    pub balance: Amount,
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "client {} {} {} crossed {}",
+            self.client_id,
+            self.threshold.balance.name(),
+            self.balance,
+            self.threshold
+        )
+    }
+}
+
+/// Alerts of all accounts crossing any of the thresholds, ordered by client
+pub fn check(accounts: &Accounts, thresholds: &[Threshold]) -> Vec<Alert> {
+    if thresholds.is_empty() {
+        return Vec::new();
+    }
+    let mut alerts: Vec<_> = accounts
+        .iter()
+        .flat_map(|account| {
+            thresholds.iter().filter_map(move |threshold| {
+                threshold.crossed_by(&account).map(|balance| Alert {
+                    client_id: account.client_id,
+                    threshold: *threshold,
+                    balance,
+                })
+            })
+        })
+        .collect();
+    alerts.sort_by_key(|alert| alert.client_id);
+    alerts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::DisputePolicy;
+
+    #[test]
+    fn test_check() {
+        let mut accounts = Accounts::new(DisputePolicy::Allow);
+        accounts.deposit(1, amount!(5)).unwrap();
+        accounts.deposit(2, amount!(10)).unwrap();
+        accounts.deposit(3, amount!(2)).unwrap();
+        // dispute of a withdrawn deposit leaves the available balance negative
+        accounts.withdraw(3, amount!(2)).unwrap();
+        accounts.dispute(3, 1, amount!(2)).unwrap();
+
+        let tests = vec![
+            ("none", vec![], vec![]),
+            ("negative available", vec!["available<0"], vec![(3, "-2")]),
+            ("held above", vec!["held > 1.5"], vec![(3, "2")]),
+            (
+                "several thresholds",
+                vec!["total>4", "available<0"],
+                vec![(1, "5"), (2, "10"), (3, "-2")],
+            ),
+        ];
+
+        for (name, thresholds, want) in tests {
+            let thresholds: Vec<Threshold> = thresholds
+                .into_iter()
+                .map(|threshold| threshold.parse().unwrap())
+                .collect();
+            let got: Vec<_> = check(&accounts, &thresholds)
+                .into_iter()
+                .map(|alert| (alert.client_id, alert.balance.to_string()))
+                .collect();
+            let want: Vec<_> = want
+                .into_iter()
+                .map(|(client_id, balance)| (client_id as ClientID, balance.to_string()))
+                .collect();
+            assert_eq!(got, want, "failed test {name}");
+        }
+
+        for invalid in ["available", "available<>0", "free<0", "held>x"] {
+            assert!(
+                invalid.parse::<Threshold>().is_err(),
+                "failed test {invalid}"
+            );
+        }
+    }
+}
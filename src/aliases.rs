@@ -1,8 +1,18 @@
-use rust_decimal::Decimal;
-
 /// These are just helper aliases for types to make it easier for reading
-/// when using HashMaps
-
+/// when using HashMaps. With `wide-ids` feature enabled, IDs are widened
+/// for systems with more than 65 535 clients or more than `u32::MAX` transactions
+#[cfg(not(feature = "wide-ids"))]
 pub type ClientID = u16;
+#[cfg(not(feature = "wide-ids"))]
 pub type TransactionID = u32;
-pub type Amount = Decimal;
+#[cfg(feature = "wide-ids")]
+pub type ClientID = u32;
+#[cfg(feature = "wide-ids")]
+pub type TransactionID = u64;
+/// With `fixed-amount` feature, amounts are [crate::fixed::FixedAmount] with 4 decimal places
+#[cfg(not(feature = "fixed-amount"))]
+pub type Amount = rust_decimal::Decimal;
+#[cfg(feature = "fixed-amount")]
+pub type Amount = crate::fixed::FixedAmount;
+/// Unix timestamp in seconds
+pub type Timestamp = i64;
@@ -0,0 +1,483 @@
+use crate::aliases::Amount;
+use eyre::{eyre, Context, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::str::{from_utf8, FromStr};
+
+/// Number of decimal places amounts are rounded to when [Rounding] is set
+pub const PRECISION: u32 = 4;
+
+/// How amounts with more than [PRECISION] decimal places are rounded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    /// Banker's rounding, midpoint goes to the even digit, `0.00005` is `0.0000` and `0.00015` is `0.0002`
+    HalfEven,
+    /// Midpoint goes away from zero, `0.00005` is `0.0001`
+    HalfUp,
+    /// Extra decimal places are cut off
+    Truncate,
+}
+
+impl Rounding {
+    #[cfg(not(feature = "fixed-amount"))]
+    pub fn round(self, amount: Amount) -> Amount {
+        use rust_decimal::RoundingStrategy;
+
+        let strategy = match self {
+            Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::Truncate => RoundingStrategy::ToZero,
+        };
+        amount.round_dp_with_strategy(PRECISION, strategy)
+    }
+
+    /// Fixed amounts never have more than [PRECISION] decimal places, amounts which would need rounding
+    /// are rejected when parsed
+    #[cfg(feature = "fixed-amount")]
+    pub fn round(self, amount: Amount) -> Amount {
+        amount
+    }
+}
+
+/// Converts [Decimal] into [Amount], fails with `fixed-amount` feature if it has more than [PRECISION]
+/// decimal places or doesn't fit
+#[cfg(not(feature = "fixed-amount"))]
+pub fn from_decimal(amount: Decimal) -> Result<Amount> {
+    Ok(amount)
+}
+
+#[cfg(feature = "fixed-amount")]
+pub fn from_decimal(amount: Decimal) -> Result<Amount> {
+    Amount::try_from(amount)
+}
+
+#[cfg(not(feature = "fixed-amount"))]
+pub fn into_decimal(amount: Amount) -> Decimal {
+    amount
+}
+
+#[cfg(feature = "fixed-amount")]
+pub fn into_decimal(amount: Amount) -> Decimal {
+    Decimal::from(amount)
+}
+
+impl FromStr for Rounding {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "half-even" => Ok(Rounding::HalfEven),
+            "half-up" => Ok(Rounding::HalfUp),
+            "truncate" => Ok(Rounding::Truncate),
+            _ => Err(eyre!(
+                "invalid rounding '{s}', expected one of half-even, half-up, truncate"
+            )),
+        }
+    }
+}
+
+/// How the number of the amount is written
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AmountNotation {
+    /// Decimal number, e.g. `12.50`
+    #[default]
+    Decimal,
+    /// Integer number of minor units, e.g. `1250` for `12.50`, usually in `amount_cents` column
+    Cents,
+    /// Decimal number with optional exponent, e.g. `1.25e1`
+    Scientific,
+}
+
+impl FromStr for AmountNotation {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "decimal" => Ok(AmountNotation::Decimal),
+            "cents" => Ok(AmountNotation::Cents),
+            "scientific" => Ok(AmountNotation::Scientific),
+            _ => Err(eyre!(
+                "invalid amount notation '{s}', expected one of decimal, cents, scientific"
+            )),
+        }
+    }
+}
+
+/// How amounts are written in the journal. Default is plain decimal number with `.` as decimal separator
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AmountFormat {
+    pub decimal_separator: char,
+    /// Separator of thousands groups, e.g. `' '` for `1 234,56`
+    pub thousands_separator: Option<char>,
+    /// Currency symbol or code stripped from the start or the end of the amount, e.g. `€` or `USD`
+    pub currency_symbol: Option<String>,
+    pub notation: AmountNotation,
+    /// If set, amounts with more decimal places are rejected and cents are minor units with this many digits,
+    /// resolved from `--currency` by [crate::config::Config::journal_amount_format]
+    #[serde(skip)]
+    pub scale: Option<u32>,
+}
+
+impl Default for AmountFormat {
+    fn default() -> Self {
+        AmountFormat {
+            decimal_separator: '.',
+            thousands_separator: None,
+            currency_symbol: None,
+            notation: AmountNotation::default(),
+            scale: None,
+        }
+    }
+}
+
+impl AmountFormat {
+    /// `true` if amounts are written as plain decimal numbers, the scale is checked after they are parsed
+    fn is_default(&self) -> bool {
+        self.decimal_separator == '.'
+            && self.thousands_separator.is_none()
+            && self.currency_symbol.is_none()
+            && self.notation == AmountNotation::Decimal
+    }
+}
+
+/// Parses amount field of the journal. Whitespace anywhere in the amount is ignored, thousands separators
+/// are dropped and the currency symbol is stripped. With other decimal separator than `.`, amount containing
+/// `.` which is not the thousands separator is rejected, so `1.5` is not silently read as fifteen or one and half.
+/// Cents are converted into amount with scale 2, or the scale of the currency if it is set
+pub fn parse_amount(field: &[u8], format: &AmountFormat) -> Result<Amount> {
+    check_scale(parse_trimmed(field, format)?, format.scale)
+}
+
+/// Same as [parse_amount] for journals without whitespace, amounts in the default format containing whitespace
+/// are rejected instead of trimmed. Clean amounts take the fast path without scanning for whitespace first
+pub fn parse_untrimmed_amount(field: &[u8], format: &AmountFormat) -> Result<Amount> {
+    check_scale(parse_untrimmed(field, format)?, format.scale)
+}
+
+fn check_scale(amount: Amount, scale: Option<u32>) -> Result<Amount> {
+    match scale {
+        Some(scale) if crate::currency::exceeds_scale(amount, scale) => Err(eyre!(
+            "amount {amount} has more than {scale} decimal places of the currency"
+        )),
+        _ => Ok(amount),
+    }
+}
+
+fn parse_trimmed(field: &[u8], format: &AmountFormat) -> Result<Amount> {
+    if format.is_default() {
+        if let Some(amount) = parse_plain(field) {
+            return Ok(amount);
+        }
+    }
+    let amount = from_utf8(field)
+        .wrap_err("failed to parse amount to string")?
+        .trim();
+    // fast path for the most common journals
+    if format.is_default() && !amount.contains(|c: char| c.is_ascii_whitespace()) {
+        return to_decimal(amount, format);
+    }
+
+    let amount = match format.currency_symbol.as_deref() {
+        Some(symbol) => amount
+            .strip_prefix(symbol)
+            .or_else(|| amount.strip_suffix(symbol))
+            .unwrap_or(amount),
+        None => amount,
+    };
+    let mut normalized = String::with_capacity(amount.len());
+    for c in amount.chars() {
+        match c {
+            c if c.is_whitespace() || Some(c) == format.thousands_separator => (),
+            c if c == format.decimal_separator => normalized.push('.'),
+            '.' => return Err(eyre!("unexpected '.' in amount '{amount}'")),
+            c => normalized.push(c),
+        }
+    }
+
+    to_decimal(&normalized, format)
+}
+
+fn parse_untrimmed(field: &[u8], format: &AmountFormat) -> Result<Amount> {
+    if !format.is_default() {
+        return parse_trimmed(field, format);
+    }
+    if let Some(amount) = parse_plain(field) {
+        return Ok(amount);
+    }
+    if field.iter().any(u8::is_ascii_whitespace) {
+        return Err(eyre!(
+            "unexpected whitespace in amount, journal with whitespace needs --trim-whitespace"
+        ));
+    }
+    parse_trimmed(field, format)
+}
+
+/// Most digits of plain amount parsed by [parse_plain], any such amount fits into `i64`
+const PLAIN_DIGITS: usize = 18;
+
+/// Fast path of [parse_amount] for the most common shape of amounts, unsigned digits with optional decimal
+/// part, e.g. `123.4567`. Bytes are turned into the scaled integer directly, without UTF-8 validation and
+/// allocation. Returns `None` for anything else, e.g. whitespace, sign or more than [PLAIN_DIGITS] digits,
+/// those are left to the general parser. The scale is kept as written, same as [Decimal::from_str_exact] does
+fn parse_plain(field: &[u8]) -> Option<Amount> {
+    let (integer, fraction) = match field.iter().position(|byte| *byte == b'.') {
+        Some(dot) => (&field[..dot], &field[dot + 1..]),
+        None => (field, &[][..]),
+    };
+    if integer.is_empty()
+        || (fraction.is_empty() && integer.len() < field.len())
+        || integer.len() + fraction.len() > PLAIN_DIGITS
+    {
+        return None;
+    }
+
+    let mut mantissa: i64 = 0;
+    for byte in integer.iter().chain(fraction) {
+        if !byte.is_ascii_digit() {
+            return None;
+        }
+        mantissa = mantissa * 10 + i64::from(byte - b'0');
+    }
+    from_scaled(mantissa, fraction.len() as u32)
+}
+
+/// Amount of `mantissa` with `scale` decimal places, `None` if it can't be represented exactly
+#[cfg(not(feature = "fixed-amount"))]
+fn from_scaled(mantissa: i64, scale: u32) -> Option<Amount> {
+    Some(Decimal::new(mantissa, scale))
+}
+
+#[cfg(feature = "fixed-amount")]
+fn from_scaled(mantissa: i64, scale: u32) -> Option<Amount> {
+    let missing = crate::fixed::SCALE.checked_sub(scale)?;
+    mantissa
+        .checked_mul(10i64.pow(missing))
+        .map(Amount::from_scaled)
+}
+
+fn to_decimal(amount: &str, format: &AmountFormat) -> Result<Amount> {
+    match format.notation {
+        AmountNotation::Decimal => {
+            Amount::from_str_exact(amount).wrap_err("failed to convert str to decimal")
+        }
+        AmountNotation::Cents => {
+            let cents = amount
+                .parse::<i128>()
+                .wrap_err_with(|| format!("amount in cents '{amount}' is not an integer"))?;
+            from_decimal(
+                Decimal::try_from_i128_with_scale(cents, format.scale.unwrap_or(2))
+                    .wrap_err("amount in cents is too large")?,
+            )
+        }
+        AmountNotation::Scientific if amount.contains(['e', 'E']) => from_decimal(
+            Decimal::from_scientific(amount).wrap_err("failed to convert scientific notation")?,
+        ),
+        AmountNotation::Scientific => {
+            Amount::from_str_exact(amount).wrap_err("failed to convert str to decimal")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_amount() {
+        let european = AmountFormat {
+            decimal_separator: ',',
+            thousands_separator: Some(' '),
+            currency_symbol: Some("€".into()),
+            ..Default::default()
+        };
+        let cents = AmountFormat {
+            notation: AmountNotation::Cents,
+            ..Default::default()
+        };
+        let scientific = AmountFormat {
+            notation: AmountNotation::Scientific,
+            ..Default::default()
+        };
+        let yen = AmountFormat {
+            scale: Some(0),
+            ..Default::default()
+        };
+        let tests = vec![
+            ("plain", "1.5", AmountFormat::default(), Some(amount!(1.5))),
+            (
+                "spaces",
+                " 10 . 0",
+                AmountFormat::default(),
+                Some(amount!(10.0)),
+            ),
+            (
+                "comma decimal",
+                "1 234,56",
+                european.clone(),
+                Some(amount!(1234.56)),
+            ),
+            (
+                "no-break space",
+                "1\u{a0}234,5",
+                european.clone(),
+                Some(amount!(1234.5)),
+            ),
+            (
+                "symbol before",
+                "€12,5",
+                european.clone(),
+                Some(amount!(12.5)),
+            ),
+            (
+                "symbol after",
+                "12,5 €",
+                european.clone(),
+                Some(amount!(12.5)),
+            ),
+            ("dot with comma decimal", "1.5", european, None),
+            (
+                "dot thousands",
+                "1.234.567,8",
+                AmountFormat {
+                    decimal_separator: ',',
+                    thousands_separator: Some('.'),
+                    ..Default::default()
+                },
+                Some(amount!(1234567.8)),
+            ),
+            (
+                "comma thousands",
+                "USD 1,000.25",
+                AmountFormat {
+                    thousands_separator: Some(','),
+                    currency_symbol: Some("USD".into()),
+                    ..Default::default()
+                },
+                Some(amount!(1000.25)),
+            ),
+            ("not a number", "abc", AmountFormat::default(), None),
+            ("cents", "1250", cents.clone(), Some(amount!(12.50))),
+            ("negative cents", "-5", cents.clone(), Some(amount!(-0.05))),
+            ("fractional cents", "12.5", cents, None),
+            (
+                "exponent",
+                "1.25e1",
+                scientific.clone(),
+                Some(amount!(12.5)),
+            ),
+            (
+                "negative exponent",
+                "5E-3",
+                scientific.clone(),
+                Some(amount!(0.005)),
+            ),
+            ("without exponent", "2.5", scientific, Some(amount!(2.5))),
+            ("within scale", "100.00", yen.clone(), Some(amount!(100))),
+            ("over scale", "1.5", yen.clone(), None),
+            (
+                "cents over scale",
+                "1250",
+                AmountFormat {
+                    notation: AmountNotation::Cents,
+                    ..yen
+                },
+                Some(amount!(1250)),
+            ),
+            (
+                "cents of three decimals",
+                "1250",
+                AmountFormat {
+                    notation: AmountNotation::Cents,
+                    scale: Some(3),
+                    ..Default::default()
+                },
+                Some(amount!(1.250)),
+            ),
+        ];
+
+        for (name, input, format, want) in tests {
+            let got = parse_amount(input.as_bytes(), &format).ok();
+            assert_eq!(got, want, "failed test {name}");
+        }
+    }
+
+    #[test]
+    fn test_parse_plain() {
+        let tests = vec![
+            ("integer", "12", true),
+            ("decimal", "123.4567", true),
+            ("trailing zeros", "1.50", true),
+            ("leading zeros", "007.5", true),
+            ("zero", "0", true),
+            ("max digits", "99999999999999.9999", true),
+            ("too many digits", "999999999999999.9999", false),
+            ("leading dot", ".5", false),
+            ("trailing dot", "5.", false),
+            ("sign", "-1", false),
+            ("whitespace", " 1", false),
+            ("two dots", "1.2.3", false),
+            ("letter", "1a", false),
+            ("empty", "", false),
+        ];
+
+        for (name, input, fast) in tests {
+            let got = parse_plain(input.as_bytes());
+            assert_eq!(got.is_some(), fast, "failed test {name}");
+            if let Some(got) = got {
+                let want = Amount::from_str_exact(input).unwrap();
+                assert_eq!(got, want, "failed test {name}");
+                assert_eq!(got.to_string(), want.to_string(), "failed test {name}");
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "fixed-amount"))]
+    fn test_rounding() {
+        let tests = vec![
+            (
+                "half-even down",
+                Rounding::HalfEven,
+                amount!(0.00005),
+                amount!(0.0000),
+            ),
+            (
+                "half-even up",
+                Rounding::HalfEven,
+                amount!(0.00015),
+                amount!(0.0002),
+            ),
+            (
+                "half-up",
+                Rounding::HalfUp,
+                amount!(0.00005),
+                amount!(0.0001),
+            ),
+            (
+                "half-up negative",
+                Rounding::HalfUp,
+                amount!(-0.00005),
+                amount!(-0.0001),
+            ),
+            (
+                "truncate",
+                Rounding::Truncate,
+                amount!(1.99999),
+                amount!(1.9999),
+            ),
+            (
+                "already precise",
+                Rounding::HalfEven,
+                amount!(2.5),
+                amount!(2.5),
+            ),
+        ];
+
+        for (name, rounding, amount, want) in tests {
+            assert_eq!(rounding.round(amount), want, "failed test {name}");
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use crate::aliases::*;
+use crate::provenance::Provenance;
+use eyre::{Context, Result};
+use std::fmt::Display;
+use std::fs::File;
+use std::path::Path;
+use tracing::error;
+
+/// Audit trail of all operations applied to the accounts together with their outcome, written as CSV.
+/// With [Provenance] rows end with `source` column with `<file>:<line>` of the record of the operation
+pub struct AuditLog {
+    writer: csv::Writer<File>,
+    provenance: Option<Provenance>,
+}
+
+impl AuditLog {
+    /// Creates (or truncates) the audit file and writes the header
+    pub fn create(path: &Path, provenance: Option<Provenance>) -> Result<Self> {
+        let mut writer = csv::Writer::from_path(path)
+            .wrap_err_with(|| format!("failed to create audit file {}", path.display()))?;
+        let header = [
+            "operation",
+            "client",
+            "tx",
+            "amount",
+            "outcome",
+            "adjustment",
+        ];
+        let source = provenance.as_ref().map(|_| "source");
+        writer
+            .write_record(header.into_iter().chain(source))
+            .wrap_err("failed to write audit header")?;
+        Ok(AuditLog { writer, provenance })
+    }
+
+    /// Records single operation, errors are handled internally the same way as in [crate::channel::Sender]
+    /// # Arguments
+    /// * index - index of the journal record, located by [Provenance] if there is one
+    /// * operation - type of the operation, for example `dispute`
+    /// * client_id - client whose account was affected
+    /// * transaction_id - ID of the transaction from the journal, disputes carry ID of the disputed transaction
+    /// * amount - amount of the operation, if it had any
+    /// * outcome - what happened, for example `applied` or reason of the rejection
+    /// * adjustment - whether it was administrative operation, these are flagged in the audit trail
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &mut self,
+        index: u64,
+        operation: &str,
+        client_id: ClientID,
+        transaction_id: Option<TransactionID>,
+        amount: Option<Amount>,
+        outcome: &dyn Display,
+        adjustment: bool,
+    ) {
+        let transaction_id = transaction_id.map(|tx| tx.to_string()).unwrap_or_default();
+        let amount = amount.map(|a| a.to_string()).unwrap_or_default();
+        let source = self
+            .provenance
+            .as_ref()
+            .map(|provenance| provenance.locate(index).to_string());
+        let fields = [
+            operation,
+            &client_id.to_string(),
+            &transaction_id,
+            &amount,
+            &outcome.to_string(),
+            if adjustment { "true" } else { "false" },
+        ];
+        if let Err(err) = self
+            .writer
+            .write_record(fields.iter().copied().chain(source.as_deref()))
+        {
+            error!(%err, "failed to write record into audit file");
+        }
+    }
+}
+
+impl Drop for AuditLog {
+    fn drop(&mut self) {
+        if let Err(err) = self.writer.flush() {
+            error!(%err, "failed to flush audit file");
+        }
+    }
+}
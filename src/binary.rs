@@ -0,0 +1,852 @@
+use crate::aliases::*;
+use crate::amount::{from_decimal, into_decimal, AmountFormat};
+use crate::channel::{DisputeLookUpMessage, Indexed, Sender, TransactionMessage};
+use crate::checkpoint::{Checkpoint, ResumePoint};
+use crate::client_filter::ClientFilter;
+use crate::client_remap::ClientRemap;
+use crate::dispute_look_up::{DisputeFinder, DisputeResolver, TransactionIndex};
+use crate::format::{FormatRegistry, InputFormat, Journal};
+use crate::offsets::{merge_indexed, OffsetIndex};
+use crate::order::TransactionOrder;
+use crate::parser::{BatchLookUp, CsvParser, Found, JournalEntry, JournalSource, ParseErrorPolicy};
+use crate::progress::Progress;
+use crate::read_ahead::ReadAhead;
+use crate::rules::Validator;
+use crate::run_guard::{self, RunGuard};
+use crate::sample::Sample;
+use crate::seen::{is_unseen, look_up_seen, unseen_error, SeenTransactions};
+use crate::spill::SpillingSender;
+use crate::summary::Summary;
+use crate::timestamp::TimeWindow;
+use crate::timings::TIMING_BATCH;
+use eyre::{eyre, Context, Result};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use tracing::{debug, info};
+
+/// Start of every binary journal, the last byte is the version of the format
+const MAGIC: [u8; 5] = *b"TREN\x01";
+
+/// Size of single record: type, client, tx, amount, to_client, timestamp flag and timestamp
+const RECORD_SIZE: usize = 1 + 8 + 8 + 8 + 8 + 1 + 8;
+
+/// Amounts are stored as `i64` in ten-thousandths
+const AMOUNT_SCALE: u32 = 4;
+
+/// Compact journal of fixed-width little-endian records, written by [convert]. IDs are always stored
+/// as `u64` so the format doesn't depend on `wide-ids` feature. Reading it skips all text and decimal parsing
+pub struct Binary;
+
+impl InputFormat for Binary {
+    fn name(&self) -> &'static str {
+        "binary"
+    }
+
+    fn detect(&self, head: &[u8]) -> bool {
+        is_binary(head)
+    }
+
+    fn open(&self, input: &Path) -> Result<Journal> {
+        Ok(Journal::binary(input))
+    }
+}
+
+/// Returns `true` if the start of the file is the binary journal header
+pub(crate) fn is_binary(head: &[u8]) -> bool {
+    head.starts_with(&MAGIC)
+}
+
+/// Number of complete records in binary journal of `size` bytes
+pub(crate) fn record_count(size: u64) -> u64 {
+    size.saturating_sub(MAGIC.len() as u64) / RECORD_SIZE as u64
+}
+
+/// Byte offset of the record with `index`
+fn record_offset(index: u64) -> u64 {
+    MAGIC.len() as u64 + index * RECORD_SIZE as u64
+}
+
+/// Converts the journal in the format given by name, or in the detected one, into binary journal.
+/// Records of unknown type are dropped, malformed ones depending on [ParseErrorPolicy]. Returns number of records
+pub fn convert(
+    input: &Path,
+    output: &Path,
+    formats: &FormatRegistry,
+    input_format: Option<&str>,
+    parse_errors: ParseErrorPolicy,
+    amount_format: &AmountFormat,
+) -> Result<u64> {
+    let journal = formats.open(input, input_format)?;
+    let delimiter = journal
+        .delimiter()
+        .ok_or(eyre!("journal {} is already binary", input.display()))?;
+    let file = File::open(&journal.path)
+        .wrap_err_with(|| format!("failed to open journal {}", input.display()))?;
+    let mut writer = BufWriter::new(
+        File::create(output).wrap_err_with(|| format!("failed to create {}", output.display()))?,
+    );
+
+    writer.write_all(&MAGIC)?;
+    let count = CsvParser::new(file)
+        .with_delimiter(delimiter)
+        .with_parse_errors(parse_errors)
+        .with_amount_format(amount_format.clone())
+        .read_entries(|entry, timestamp| {
+            writer
+                .write_all(&encode(&entry, timestamp)?)
+                .wrap_err("failed to write binary journal")
+        })?;
+    writer.flush().wrap_err("failed to write binary journal")?;
+
+    info!(%count, output = %output.display(), "converted journal");
+    Ok(count)
+}
+
+/// Reads binary journal, see [Binary]
+pub struct BinaryParser {
+    reader: BufReader<File>,
+    /// Only records inside of this window are processed
+    window: TimeWindow,
+    progress: Option<Progress>,
+    /// Parsing stops after this many records
+    limit: Option<u64>,
+    /// Parsing stops past the max runtime or once the run was stopped by other thread
+    guard: Option<RunGuard>,
+    /// Only records of sampled clients are processed
+    sample: Option<Sample>,
+    /// Only records of clients allowed by the filter are processed
+    client_filter: Option<ClientFilter>,
+    /// Clients of records are replaced by their new IDs
+    client_remap: Option<ClientRemap>,
+    /// Records are checked against the validation rules, rejected ones are skipped
+    validator: Option<Validator>,
+    /// Records which were already applied are skipped
+    checkpoint: Checkpoint,
+    /// Parsing starts at this position instead of the first record
+    resume: Option<ResumePoint>,
+    /// Transaction IDs are not increasing, so look-ups can't stop at higher ID
+    unordered: bool,
+    /// Disputes are resolved while parsing instead of being sent to the dispute look-up
+    inline: Option<DisputeFinder<TransactionIndex>>,
+    /// Offsets of deposits and withdrawals, recorded while parsing and used by the look-ups
+    offsets: Option<OffsetIndex>,
+    /// IDs of deposits and withdrawals, recorded while parsing so look-ups of unseen transactions fail right away
+    seen: Option<SeenTransactions>,
+    /// Reads blocks of indexed transactions before they are looked up
+    read_ahead: Option<ReadAhead>,
+    /// Transaction IDs of deposits and withdrawals are checked to be increasing while parsing
+    order: Option<TransactionOrder>,
+    /// Counters of skipped records, returned once the journal is parsed
+    summary: Summary,
+}
+
+impl BinaryParser {
+    /// Fails if the file doesn't start with binary journal header
+    pub fn new(file: File) -> Result<BinaryParser> {
+        BinaryParser::with_capacity(file, None)
+    }
+
+    /// Same as [BinaryParser::new] with read buffer of `capacity` bytes, `None` keeps the default
+    pub fn with_capacity(file: File, capacity: Option<usize>) -> Result<BinaryParser> {
+        let mut reader = match capacity {
+            Some(capacity) => BufReader::with_capacity(capacity, file),
+            None => BufReader::new(file),
+        };
+        let mut magic = [0; MAGIC.len()];
+        reader
+            .read_exact(&mut magic)
+            .wrap_err("failed to read binary journal header")?;
+        if magic != MAGIC {
+            return Err(eyre!("not a binary journal or unsupported version"));
+        }
+
+        Ok(BinaryParser {
+            reader,
+            window: TimeWindow::default(),
+            progress: None,
+            limit: None,
+            guard: None,
+            sample: None,
+            client_filter: None,
+            client_remap: None,
+            validator: None,
+            checkpoint: Checkpoint::default(),
+            resume: None,
+            unordered: false,
+            inline: None,
+            offsets: None,
+            seen: None,
+            read_ahead: None,
+            order: None,
+            summary: Summary::default(),
+        })
+    }
+
+    /// Sets the time window, records with timestamp outside of it are skipped
+    pub fn with_window(mut self, window: TimeWindow) -> BinaryParser {
+        self.window = window;
+        self
+    }
+
+    /// Shows progress of [JournalSource::parse_journal]
+    pub fn with_progress(mut self, progress: Option<Progress>) -> BinaryParser {
+        self.progress = progress;
+        self
+    }
+
+    /// Stops [JournalSource::parse_journal] after `limit` records
+    pub fn with_limit(mut self, limit: Option<u64>) -> BinaryParser {
+        self.limit = limit;
+        self
+    }
+
+    /// Stops [JournalSource::parse_journal] once the guard trips, see [crate::run_guard]
+    pub fn with_run_guard(mut self, guard: Option<RunGuard>) -> BinaryParser {
+        self.guard = guard;
+        self
+    }
+
+    /// Skips records of clients which are not in the sample
+    pub fn with_sample(mut self, sample: Option<Sample>) -> BinaryParser {
+        self.sample = sample;
+        self
+    }
+
+    /// Skips records of clients excluded by the filter
+    pub fn with_client_filter(mut self, client_filter: Option<ClientFilter>) -> BinaryParser {
+        self.client_filter = client_filter;
+        self
+    }
+
+    /// Replaces clients of records by their new IDs before they are sampled, filtered or applied
+    pub fn with_client_remap(mut self, client_remap: Option<ClientRemap>) -> BinaryParser {
+        self.client_remap = client_remap;
+        self
+    }
+
+    /// Checks records which pass the sample and client filter against the rules, see [crate::rules]
+    pub fn with_validator(mut self, validator: Option<Validator>) -> BinaryParser {
+        self.validator = validator;
+        self
+    }
+
+    /// Skips records which were already applied
+    pub fn with_checkpoint(mut self, checkpoint: Checkpoint) -> BinaryParser {
+        self.checkpoint = checkpoint;
+        self
+    }
+
+    /// See [CsvParser::with_resume_point]
+    pub fn with_resume_point(mut self, resume: Option<ResumePoint>) -> BinaryParser {
+        self.resume = resume;
+        self
+    }
+
+    /// Makes [DisputeResolver::find_transaction] search the whole journal, for journals whose transaction IDs
+    /// are not increasing
+    pub fn with_unordered_input(mut self, unordered: bool) -> BinaryParser {
+        self.unordered = unordered;
+        self
+    }
+
+    /// See [CsvParser::with_inline_disputes]
+    pub fn with_inline_disputes(
+        mut self,
+        inline: Option<DisputeFinder<TransactionIndex>>,
+    ) -> BinaryParser {
+        self.inline = inline;
+        self
+    }
+
+    /// See [CsvParser::with_offset_index]
+    pub fn with_offset_index(mut self, offsets: Option<OffsetIndex>) -> BinaryParser {
+        self.offsets = offsets;
+        self
+    }
+
+    /// See [CsvParser::with_seen_transactions]
+    pub fn with_seen_transactions(mut self, seen: Option<SeenTransactions>) -> BinaryParser {
+        self.seen = seen;
+        self
+    }
+
+    /// Reads blocks of indexed transactions in the background once they are passed to
+    /// [DisputeResolver::read_ahead], needs [BinaryParser::with_offset_index]
+    pub fn with_read_ahead(mut self, read_ahead: Option<ReadAhead>) -> BinaryParser {
+        self.read_ahead = read_ahead;
+        self
+    }
+
+    /// See [CsvParser::with_order_check]
+    pub fn with_order_check(mut self, check: bool) -> BinaryParser {
+        self.order = check.then(TransactionOrder::default);
+        self
+    }
+
+    /// Reads next record, `None` at the end of the journal
+    fn next_record(&mut self) -> Result<Option<[u8; RECORD_SIZE]>> {
+        let mut record = [0; RECORD_SIZE];
+        match self.reader.read_exact(&mut record) {
+            Ok(()) => Ok(Some(record)),
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => Err(err).wrap_err("failed to read binary journal"),
+        }
+    }
+}
+
+impl JournalSource for BinaryParser {
+    #[tracing::instrument(skip(self, transaction_sender, dispute_look_up_sender))]
+    fn parse_journal(
+        &mut self,
+        transaction_sender: Sender<Indexed<TransactionMessage>>,
+        mut dispute_look_up_sender: SpillingSender,
+    ) -> Result<Summary> {
+        info!("starting to parse binary journal");
+        let first = match self.resume {
+            Some(resume) => {
+                if resume.offset != record_offset(resume.index) {
+                    return Err(eyre!(
+                        "resume point at byte {} is not the start of record {}",
+                        resume.offset,
+                        resume.index
+                    ));
+                }
+                info!(
+                    offset = resume.offset,
+                    index = resume.index,
+                    "resuming journal"
+                );
+                self.reader
+                    .seek(SeekFrom::Start(resume.offset))
+                    .wrap_err("failed to seek to the resume point")?;
+                resume.index
+            }
+            None => 0,
+        };
+        let (mut count, mut reached_limit) = (first, false);
+        let mut batch_timer = std::time::Instant::now();
+        while let Some(record) = self.next_record()? {
+            if self.limit.is_some_and(|limit| count - first >= limit) {
+                info!(%count, "reached record limit, stopping");
+                reached_limit = true;
+                break;
+            }
+            // the end of the journal is set before this record, so the next run continues with it
+            if run_guard::should_stop(self.guard.as_ref(), count) {
+                info!(%count, "run was stopped, stopping");
+                break;
+            }
+            if count > 0 && count.is_multiple_of(TIMING_BATCH) {
+                self.summary.timings.parse.record(batch_timer.elapsed());
+                batch_timer = std::time::Instant::now();
+            }
+            let index = count;
+            count += 1;
+
+            if let Some(progress) = self.progress.as_mut() {
+                progress.update(record_offset(count), count);
+            }
+
+            let (entry, timestamp) = self
+                .decode_remapped(&record)
+                .wrap_err_with(|| format!("malformed record {index}"))?;
+            if !self.window.contains(timestamp) {
+                self.summary.outside_window += 1;
+                continue;
+            }
+            if let Some(order) = self.order.as_mut() {
+                order.check_entry(&entry, index, &mut self.summary);
+            }
+            if self
+                .sample
+                .is_some_and(|sample| !sample.contains(entry.client_id()))
+            {
+                self.summary.sampled_out += 1;
+                continue;
+            }
+            if self
+                .client_filter
+                .as_ref()
+                .is_some_and(|filter| !filter.allows(&entry))
+            {
+                self.summary.filtered_clients += 1;
+                continue;
+            }
+            if let Some(validator) = self.validator.as_ref() {
+                if !validator.check(&entry, index, &mut self.summary) {
+                    continue;
+                }
+            }
+            // indexed even when already applied, later disputes can still refer to it
+            if let (Some(inline), JournalEntry::Transaction(message)) =
+                (self.inline.as_mut(), &entry)
+            {
+                inline.index(message, timestamp);
+            }
+            if let (
+                Some(offsets),
+                JournalEntry::Transaction(
+                    TransactionMessage::Deposit(transaction)
+                    | TransactionMessage::Withdrawal(transaction),
+                ),
+            ) = (self.offsets.as_ref(), &entry)
+            {
+                offsets.record(transaction.transaction_id, record_offset(index));
+            }
+            if let (Some(seen), JournalEntry::Transaction(message)) = (self.seen.as_ref(), &entry) {
+                if let Some((transaction, _)) = message.disputable() {
+                    seen.record(transaction.transaction_id);
+                }
+            }
+            if self
+                .checkpoint
+                .is_applied(index, entry.is_dispute_look_up())
+            {
+                self.summary.already_applied += 1;
+                continue;
+            }
+
+            match entry {
+                JournalEntry::Transaction(message) => transaction_sender.send_timed(
+                    Indexed::new(index, message),
+                    &mut self.summary.timings.parse_queue_wait,
+                ),
+                JournalEntry::DisputeLookUp(message) => {
+                    debug!(?message, %index, "found dispute look-up request");
+                    let request = Indexed::new(index, message);
+                    match self.inline.as_mut() {
+                        Some(inline) => inline.look_up(request, &transaction_sender),
+                        None => {
+                            // transactions parsed before the dispute have to reach processing before it
+                            transaction_sender.flush();
+                            dispute_look_up_sender.send(request)
+                        }
+                    }
+                }
+            }
+            if let Some(inline) = self.inline.as_mut() {
+                inline.retry_pending(index, &transaction_sender);
+            }
+        }
+        info!(%count, "finished parsing binary journal");
+        if let Some(progress) = self.progress.as_mut() {
+            progress.finish(count);
+        }
+        if !reached_limit {
+            self.summary.journal_end = Some(ResumePoint {
+                offset: record_offset(count),
+                index: count,
+            });
+        }
+        self.summary.spilled_disputes = dispute_look_up_sender.finish();
+        if let Some(inline) = self.inline.as_mut() {
+            inline.flush_pending(&transaction_sender);
+            inline.save_cache();
+            self.summary.merge(inline.take_summary());
+        }
+        Ok(std::mem::take(&mut self.summary))
+    }
+}
+
+impl DisputeResolver for BinaryParser {
+    /// Goes through the journal from the start the same way as [CsvParser] does
+    fn find_transaction(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+    ) -> Result<Found> {
+        if is_unseen(self.seen.as_ref(), transaction_id) {
+            return Err(unseen_error(transaction_id));
+        }
+        if let Some(found) = self.find_indexed(client_id, transaction_id)? {
+            return Ok(found);
+        }
+
+        self.reader.seek(SeekFrom::Start(MAGIC.len() as u64))?;
+        while let Some(record) = self.next_record()? {
+            let (JournalEntry::Transaction(message), timestamp) = self.decode_remapped(&record)?
+            else {
+                continue;
+            };
+            let Some((transaction, kind)) = message.disputable() else {
+                continue;
+            };
+
+            if transaction.client_id == client_id && transaction.transaction_id == transaction_id {
+                return Ok((
+                    client_id,
+                    transaction_id,
+                    transaction.amount,
+                    timestamp,
+                    kind,
+                ));
+            }
+            if !self.unordered && transaction.transaction_id > transaction_id {
+                return Err(eyre!("Transaction for given dispute not found"));
+            }
+        }
+        Err(eyre!(
+            "transaction for requested client id and transaction id not found"
+        ))
+    }
+
+    /// Single pass over the journal the same way as [CsvParser] does
+    fn find_transactions(&mut self, requests: &[(ClientID, TransactionID)]) -> Vec<Result<Found>> {
+        let seen = self.seen.clone();
+        look_up_seen(seen.as_ref(), requests, |requests| {
+            let indexed = requests
+                .iter()
+                .map(|(client_id, transaction_id)| {
+                    self.find_indexed(*client_id, *transaction_id)
+                        .ok()
+                        .flatten()
+                })
+                .collect();
+            merge_indexed(requests, indexed, |missing| {
+                let mut batch = BatchLookUp::new(missing);
+                if let Err(err) = self.scan_batch(&mut batch) {
+                    return missing.iter().map(|_| Err(eyre!("{err:#}"))).collect();
+                }
+                batch.finish()
+            })
+        })
+    }
+
+    /// Goes through the journal from the start the same way as [CsvParser] does
+    fn find_owner(&mut self, transaction_id: TransactionID) -> Result<Option<ClientID>> {
+        if is_unseen(self.seen.as_ref(), transaction_id) {
+            return Ok(None);
+        }
+        self.reader.seek(SeekFrom::Start(MAGIC.len() as u64))?;
+        while let Some(record) = self.next_record()? {
+            let (JournalEntry::Transaction(TransactionMessage::Deposit(transaction))
+            | JournalEntry::Transaction(TransactionMessage::Withdrawal(transaction))) =
+                self.decode_remapped(&record)?.0
+            else {
+                continue;
+            };
+
+            if transaction.transaction_id == transaction_id {
+                return Ok(Some(transaction.client_id));
+            }
+            if !self.unordered && transaction.transaction_id > transaction_id {
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads blocks of the indexed transactions in the background, see [ReadAhead]
+    fn read_ahead(&mut self, transaction_ids: &[TransactionID]) {
+        if let (Some(offsets), Some(read_ahead)) = (self.offsets.as_ref(), self.read_ahead.as_ref())
+        {
+            for offset in transaction_ids.iter().filter_map(|tx| offsets.get(*tx)) {
+                read_ahead.request(offset);
+            }
+        }
+    }
+}
+
+impl BinaryParser {
+    /// Decodes the record with its clients remapped, see [BinaryParser::with_client_remap]
+    fn decode_remapped(
+        &self,
+        record: &[u8; RECORD_SIZE],
+    ) -> Result<(JournalEntry, Option<Timestamp>)> {
+        let (entry, timestamp) = decode(record)?;
+        match self.client_remap.as_ref() {
+            Some(remap) => Ok((remap.apply(entry), timestamp)),
+            None => Ok((entry, timestamp)),
+        }
+    }
+
+    /// See [CsvParser] `find_indexed`, records have fixed size so the offset is computed from the record index
+    fn find_indexed(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+    ) -> Result<Option<Found>> {
+        let Some(offset) = self
+            .offsets
+            .as_ref()
+            .and_then(|offsets| offsets.get(transaction_id))
+        else {
+            return Ok(None);
+        };
+
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let Some(record) = self.next_record()? else {
+            return Ok(None);
+        };
+        let (JournalEntry::Transaction(message), timestamp) = self.decode_remapped(&record)? else {
+            return Ok(None);
+        };
+        match message.disputable() {
+            Some((transaction, kind))
+                if transaction.client_id == client_id
+                    && transaction.transaction_id == transaction_id =>
+            {
+                Ok(Some((
+                    client_id,
+                    transaction_id,
+                    transaction.amount,
+                    timestamp,
+                    kind,
+                )))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn scan_batch(&mut self, batch: &mut BatchLookUp) -> Result<()> {
+        self.reader.seek(SeekFrom::Start(MAGIC.len() as u64))?;
+        while let Some(record) = self.next_record()? {
+            let (JournalEntry::Transaction(message), timestamp) = self.decode_remapped(&record)?
+            else {
+                continue;
+            };
+            let Some((transaction, kind)) = message.disputable() else {
+                continue;
+            };
+
+            if batch.is_requested(transaction.client_id, transaction.transaction_id) {
+                batch.found((
+                    transaction.client_id,
+                    transaction.transaction_id,
+                    transaction.amount,
+                    timestamp,
+                    kind,
+                ));
+            }
+            if batch.is_done(transaction.transaction_id, self.unordered) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[allow(clippy::useless_conversion)]
+fn encode(entry: &JournalEntry, timestamp: Option<Timestamp>) -> Result<[u8; RECORD_SIZE]> {
+    let (tag, client_id, transaction_id, amount, to_client_id) = match entry {
+        JournalEntry::Transaction(message) => match message {
+            TransactionMessage::Deposit(t) => (0, t.client_id, t.transaction_id, t.amount, 0),
+            TransactionMessage::Withdrawal(t) => (1, t.client_id, t.transaction_id, t.amount, 0),
+            TransactionMessage::Transfer(t) => (
+                5,
+                t.from_client_id,
+                t.transaction_id,
+                t.amount,
+                t.to_client_id,
+            ),
+            TransactionMessage::AdjustmentCredit(t) => {
+                (6, t.client_id, t.transaction_id, t.amount, 0)
+            }
+            TransactionMessage::AdjustmentDebit(t) => {
+                (7, t.client_id, t.transaction_id, t.amount, 0)
+            }
+            TransactionMessage::Lock(client_id) => (8, *client_id, 0, Amount::ZERO, 0),
+            TransactionMessage::Unlock(client_id) => (9, *client_id, 0, Amount::ZERO, 0),
+            TransactionMessage::Close(client_id) => (10, *client_id, 0, Amount::ZERO, 0),
+            // amounts of disputes and reversals are only known once they are looked up
+            TransactionMessage::Dispute(_)
+            | TransactionMessage::Resolve(_)
+            | TransactionMessage::Chargeback(_)
+            | TransactionMessage::Reversal(_) => {
+                return Err(eyre!("{} can't be stored in journal", message.name()))
+            }
+        },
+        JournalEntry::DisputeLookUp(message) => {
+            let tag = match message {
+                DisputeLookUpMessage::Dispute(..) => 2,
+                DisputeLookUpMessage::Resolve(..) => 3,
+                DisputeLookUpMessage::Chargeback(..) => 4,
+                DisputeLookUpMessage::Reversal(..) => 11,
+            };
+            // whole transaction is disputed if the amount is zero, partial disputes are always positive
+            (
+                tag,
+                message.client_id(),
+                message.transaction_id(),
+                message.amount().unwrap_or(Amount::ZERO),
+                0,
+            )
+        }
+    };
+
+    let amount = into_decimal(amount);
+    let scaled = amount * Decimal::from(10i64.pow(AMOUNT_SCALE));
+    if !scaled.fract().is_zero() {
+        return Err(eyre!(
+            "amount {amount} has more than {AMOUNT_SCALE} decimal places"
+        ));
+    }
+    let scaled = scaled
+        .to_i64()
+        .ok_or_else(|| eyre!("amount {amount} is too big for binary journal"))?;
+
+    let mut record = [0; RECORD_SIZE];
+    record[0] = tag;
+    record[1..9].copy_from_slice(&u64::from(client_id).to_le_bytes());
+    record[9..17].copy_from_slice(&u64::from(transaction_id).to_le_bytes());
+    record[17..25].copy_from_slice(&scaled.to_le_bytes());
+    record[25..33].copy_from_slice(&u64::from(to_client_id).to_le_bytes());
+    record[33] = u8::from(timestamp.is_some());
+    record[34..].copy_from_slice(&timestamp.unwrap_or_default().to_le_bytes());
+    Ok(record)
+}
+
+#[allow(clippy::useless_conversion)]
+fn decode(record: &[u8; RECORD_SIZE]) -> Result<(JournalEntry, Option<Timestamp>)> {
+    let client_id = ClientID::try_from(u64::from_le_bytes(record[1..9].try_into()?))?;
+    let transaction_id = TransactionID::try_from(u64::from_le_bytes(record[9..17].try_into()?))?;
+    let amount = from_decimal(
+        Decimal::new(i64::from_le_bytes(record[17..25].try_into()?), AMOUNT_SCALE).normalize(),
+    )?;
+    let timestamp = Timestamp::from_le_bytes(record[34..].try_into()?);
+    let timestamp = (record[33] == 1).then_some(timestamp);
+    let disputed = (amount != Amount::ZERO).then_some(amount);
+
+    let entry = match record[0] {
+        0 => JournalEntry::Transaction(TransactionMessage::deposit(
+            client_id,
+            transaction_id,
+            amount,
+        )),
+        1 => JournalEntry::Transaction(TransactionMessage::withdrawal(
+            client_id,
+            transaction_id,
+            amount,
+        )),
+        2 => JournalEntry::DisputeLookUp(DisputeLookUpMessage::Dispute(
+            client_id,
+            transaction_id,
+            timestamp,
+            disputed,
+        )),
+        3 => JournalEntry::DisputeLookUp(DisputeLookUpMessage::Resolve(
+            client_id,
+            transaction_id,
+            disputed,
+        )),
+        4 => JournalEntry::DisputeLookUp(DisputeLookUpMessage::Chargeback(
+            client_id,
+            transaction_id,
+            disputed,
+        )),
+        5 => JournalEntry::Transaction(TransactionMessage::transfer(
+            client_id,
+            ClientID::try_from(u64::from_le_bytes(record[25..33].try_into()?))?,
+            transaction_id,
+            amount,
+        )),
+        6 => JournalEntry::Transaction(TransactionMessage::adjustment_credit(
+            client_id,
+            transaction_id,
+            amount,
+        )),
+        7 => JournalEntry::Transaction(TransactionMessage::adjustment_debit(
+            client_id,
+            transaction_id,
+            amount,
+        )),
+        8 => JournalEntry::Transaction(TransactionMessage::Lock(client_id)),
+        9 => JournalEntry::Transaction(TransactionMessage::Unlock(client_id)),
+        10 => JournalEntry::Transaction(TransactionMessage::Close(client_id)),
+        11 => {
+            JournalEntry::DisputeLookUp(DisputeLookUpMessage::Reversal(client_id, transaction_id))
+        }
+        tag => return Err(eyre!("invalid record type {tag}")),
+    };
+
+    Ok((entry, timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::pipeline;
+
+    #[test]
+    fn test_convert() {
+        let input = Path::new("test_data/fixtures/interleaved_clients.csv");
+        let output = std::env::temp_dir().join(format!("tren-test-{}.trn", std::process::id()));
+        let config = Config {
+            progress: false,
+            ..Default::default()
+        };
+
+        convert(
+            input,
+            &output,
+            &FormatRegistry::default(),
+            None,
+            ParseErrorPolicy::Strict,
+            &AmountFormat::default(),
+        )
+        .unwrap();
+        let (want, _) = pipeline::run(input, config.clone()).unwrap();
+        let got = pipeline::run(&output, config);
+        std::fs::remove_file(&output).unwrap();
+
+        let mut got = got.unwrap().0.iter().collect::<Vec<_>>();
+        let mut want = want.iter().collect::<Vec<_>>();
+        got.sort_by_key(|view| view.client_id);
+        want.sort_by_key(|view| view.client_id);
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let tests = vec![
+            (
+                JournalEntry::Transaction(TransactionMessage::deposit(1, 2, amount!(1.2345))),
+                Some(1661990399),
+            ),
+            (
+                JournalEntry::Transaction(TransactionMessage::transfer(1, 3, 2, amount!(-5))),
+                None,
+            ),
+            (
+                JournalEntry::DisputeLookUp(DisputeLookUpMessage::Dispute(
+                    1,
+                    2,
+                    Some(1661990400),
+                    None,
+                )),
+                Some(1661990400),
+            ),
+            (
+                JournalEntry::DisputeLookUp(DisputeLookUpMessage::Chargeback(
+                    1,
+                    2,
+                    Some(amount!(0.5)),
+                )),
+                None,
+            ),
+            (
+                JournalEntry::Transaction(TransactionMessage::Close(7)),
+                None,
+            ),
+        ];
+
+        for (entry, timestamp) in tests {
+            let record = encode(&entry, timestamp).unwrap();
+            assert_eq!(decode(&record).unwrap(), (entry, timestamp));
+        }
+
+        #[cfg(not(feature = "fixed-amount"))]
+        assert!(
+            encode(
+                &JournalEntry::Transaction(TransactionMessage::deposit(1, 2, amount!(0.00001))),
+                None
+            )
+            .is_err(),
+            "too precise amount"
+        );
+    }
+}
@@ -1,98 +1,377 @@
-use crate::{Amount, ClientID, TransactionID};
-use rust_decimal::Decimal;
+use crate::timings::Histogram;
+use crate::{Amount, ClientID, Timestamp, TransactionID};
+use crossbeam_channel::TrySendError;
+use std::cell::RefCell;
 use std::fmt::Debug;
+use std::time::Instant;
 use tracing::{error, trace};
 
-/// Helper wrapper around channel with only `send`  method.
-pub struct Sender<T>(crossbeam_channel::Sender<T>);
+/// Helper wrapper around channel with only `send`  method. Messages are sent in batches, so the channel
+/// is locked once per batch instead of once per message, see [Sender::with_batch_size]
+pub struct Sender<T> {
+    sender: crossbeam_channel::Sender<Vec<T>>,
+    batch_size: usize,
+    /// Messages which were not sent yet, the rest of the batch is sent by [Sender::flush] or when the sender is dropped
+    batch: RefCell<Vec<T>>,
+    /// Faults injected into the messages before they are batched, see [crate::fault]
+    #[cfg(feature = "fault-injection")]
+    faults: Option<crate::fault::InjectedFaults<T>>,
+}
 
 impl<T: Debug> Sender<T> {
-    pub fn new(sender: crossbeam_channel::Sender<T>) -> Self {
-        Sender(sender)
+    /// Sender of batches with single message
+    pub fn new(sender: crossbeam_channel::Sender<Vec<T>>) -> Self {
+        Sender {
+            sender,
+            batch_size: 1,
+            batch: RefCell::new(Vec::new()),
+            #[cfg(feature = "fault-injection")]
+            faults: None,
+        }
+    }
+
+    /// Collects up to `batch_size` messages before sending them. Messages of the same sender keep their order
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
     }
 
     /// Handles internally errors so we don't get warning all over the code base about not used errors
     pub fn send(&self, message: T) {
-        match self.0.send(message) {
+        if let Some(batch) = self.push(message) {
+            self.send_batch(batch);
+        }
+    }
+
+    /// Sends the message the same way as [Sender::send], time blocked on the full channel is recorded into `wait`
+    pub fn send_timed(&self, message: T, wait: &mut Histogram) {
+        let Some(batch) = self.push(message) else {
+            return;
+        };
+        match self.sender.try_send(batch) {
+            Ok(_) => trace!("successfully send message over channel"),
+            Err(TrySendError::Full(batch)) => {
+                let start = Instant::now();
+                self.send_batch(batch);
+                wait.record(start.elapsed());
+            }
+            Err(TrySendError::Disconnected(batch)) => self.send_batch(batch),
+        }
+    }
+
+    /// Sends the messages collected so far without waiting for the batch to fill up
+    pub fn flush(&self) {
+        let batch = std::mem::take(&mut *self.batch.borrow_mut());
+        if !batch.is_empty() {
+            self.send_batch(batch);
+        }
+    }
+
+    /// Adds the message to the batch, returns the batch once it is full
+    fn push(&self, message: T) -> Option<Vec<T>> {
+        let mut batch = self.batch.borrow_mut();
+        #[cfg(feature = "fault-injection")]
+        match self.faults.as_ref() {
+            Some(faults) => batch.extend(faults.inject(message)),
+            None => batch.push(message),
+        }
+        #[cfg(not(feature = "fault-injection"))]
+        batch.push(message);
+        (batch.len() >= self.batch_size)
+            .then(|| std::mem::replace(&mut *batch, Vec::with_capacity(self.batch_size)))
+    }
+
+    fn send_batch(&self, batch: Vec<T>) {
+        match self.sender.send(batch) {
             Ok(_) => trace!("successfully send message over channel"),
             Err(err) => error!(%err, "failed to send message over channel"),
         }
     }
 }
 
-#[derive(Debug)]
+#[cfg(feature = "fault-injection")]
+impl<T: Debug + Clone> Sender<T> {
+    /// Drops, duplicates or delays the sent messages, see [crate::fault::ChannelFaults]
+    pub fn with_faults(mut self, faults: crate::fault::ChannelFaults) -> Self {
+        self.faults = (!faults.is_empty()).then(|| crate::fault::InjectedFaults::new(faults));
+        self
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let batch = std::mem::take(self.batch.get_mut());
+        if !batch.is_empty() && self.sender.send(batch).is_err() {
+            error!("failed to send the last batch over channel");
+        }
+    }
+}
+
+/// Message together with index of the journal record it was parsed from, the first record after the header has index 0
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Indexed<T> {
+    pub index: u64,
+    pub message: T,
+}
+
+impl<T> Indexed<T> {
+    pub fn new(index: u64, message: T) -> Self {
+        Indexed { index, message }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Transaction {
     pub client_id: ClientID,
+    pub transaction_id: TransactionID,
     pub amount: Amount,
 }
 
 impl Transaction {
-    pub fn new(client_id: u16, amount: Decimal) -> Self {
-        Transaction { amount, client_id }
+    pub fn new(client_id: ClientID, transaction_id: TransactionID, amount: Amount) -> Self {
+        Transaction {
+            client_id,
+            transaction_id,
+            amount,
+        }
     }
 }
 
-#[derive(Debug)]
+/// Dispute, resolve, chargeback or reversal, `transaction_id` is ID of the disputed or reversed transaction
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Dispute {
     pub client_id: ClientID,
+    pub transaction_id: TransactionID,
     pub amount: Amount,
+    /// Kind of the disputed or reversed transaction, set only for disputes and reversals, resolves and chargebacks
+    /// keep the default
+    pub kind: TransactionKind,
 }
 
 impl Dispute {
     #[inline(always)]
-    pub fn new(client_id: u16, amount: Decimal) -> Self {
-        Dispute { client_id, amount }
+    pub fn new(client_id: ClientID, transaction_id: TransactionID, amount: Amount) -> Self {
+        Dispute {
+            client_id,
+            transaction_id,
+            amount,
+            kind: TransactionKind::Deposit,
+        }
+    }
+
+    pub fn with_kind(mut self, kind: TransactionKind) -> Self {
+        self.kind = kind;
+        self
     }
 }
 
-#[derive(Debug)]
+/// Kind of the disputed transaction, disputes of withdrawals are applied according to
+/// [crate::accounts::WithdrawalDisputePolicy]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TransactionKind {
+    #[default]
+    Deposit,
+    Withdrawal,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transfer {
+    pub from_client_id: ClientID,
+    pub to_client_id: ClientID,
+    pub transaction_id: TransactionID,
+    pub amount: Amount,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum TransactionMessage {
     Deposit(Transaction),
     Withdrawal(Transaction),
     Dispute(Dispute),
     Resolve(Dispute),
     Chargeback(Dispute),
+    /// Inverse of a deposit or withdrawal applied in full, see [crate::accounts::Accounts::reverse]
+    Reversal(Dispute),
+    Transfer(Transfer),
+    AdjustmentCredit(Transaction),
+    AdjustmentDebit(Transaction),
+    Lock(ClientID),
+    Unlock(ClientID),
+    Close(ClientID),
 }
 
 impl TransactionMessage {
-    pub fn deposit(client_id: ClientID, amount: Amount) -> Self {
-        Self::Deposit(Transaction::new(client_id, amount))
+    /// Name of the operation as used in the journal
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Deposit(_) => "deposit",
+            Self::Withdrawal(_) => "withdrawal",
+            Self::Dispute(_) => "dispute",
+            Self::Resolve(_) => "resolve",
+            Self::Chargeback(_) => "chargeback",
+            Self::Reversal(_) => "reversal",
+            Self::Transfer(_) => "transfer",
+            Self::AdjustmentCredit(_) => "adjustment_credit",
+            Self::AdjustmentDebit(_) => "adjustment_debit",
+            Self::Lock(_) => "lock",
+            Self::Unlock(_) => "unlock",
+            Self::Close(_) => "close",
+        }
     }
-    pub fn withdrawal(client_id: ClientID, amount: Amount) -> Self {
-        Self::Withdrawal(Transaction::new(client_id, amount))
+
+    /// Deposit or withdrawal which can be disputed with its kind, `None` for other messages
+    pub fn disputable(&self) -> Option<(&Transaction, TransactionKind)> {
+        match self {
+            Self::Deposit(transaction) => Some((transaction, TransactionKind::Deposit)),
+            Self::Withdrawal(transaction) => Some((transaction, TransactionKind::Withdrawal)),
+            _ => None,
+        }
+    }
+
+    /// Client who sent the message, for transfers the sending client
+    pub fn client_id(&self) -> ClientID {
+        match self {
+            Self::Deposit(Transaction { client_id, .. })
+            | Self::Withdrawal(Transaction { client_id, .. })
+            | Self::AdjustmentCredit(Transaction { client_id, .. })
+            | Self::AdjustmentDebit(Transaction { client_id, .. })
+            | Self::Dispute(Dispute { client_id, .. })
+            | Self::Resolve(Dispute { client_id, .. })
+            | Self::Chargeback(Dispute { client_id, .. })
+            | Self::Reversal(Dispute { client_id, .. })
+            | Self::Lock(client_id)
+            | Self::Unlock(client_id)
+            | Self::Close(client_id) => *client_id,
+            Self::Transfer(transfer) => transfer.from_client_id,
+        }
+    }
+
+    /// Clients whose accounts can be changed by the message, transfer affects both of its clients
+    pub fn client_ids(&self) -> impl Iterator<Item = ClientID> {
+        let receiving = match self {
+            Self::Transfer(transfer) => Some(transfer.to_client_id),
+            _ => None,
+        };
+        std::iter::once(self.client_id()).chain(receiving)
     }
-    pub fn dispute(client_id: ClientID, amount: Amount) -> Self {
-        Self::Dispute(Dispute::new(client_id, amount))
+
+    pub fn deposit(client_id: ClientID, transaction_id: TransactionID, amount: Amount) -> Self {
+        Self::Deposit(Transaction::new(client_id, transaction_id, amount))
     }
-    pub fn resolve(client_id: ClientID, amount: Amount) -> Self {
-        Self::Resolve(Dispute::new(client_id, amount))
+    pub fn withdrawal(client_id: ClientID, transaction_id: TransactionID, amount: Amount) -> Self {
+        Self::Withdrawal(Transaction::new(client_id, transaction_id, amount))
     }
-    pub fn chargeback(client_id: ClientID, amount: Amount) -> Self {
-        Self::Chargeback(Dispute::new(client_id, amount))
+    pub fn dispute(client_id: ClientID, transaction_id: TransactionID, amount: Amount) -> Self {
+        Self::Dispute(Dispute::new(client_id, transaction_id, amount))
+    }
+    pub fn resolve(client_id: ClientID, transaction_id: TransactionID, amount: Amount) -> Self {
+        Self::Resolve(Dispute::new(client_id, transaction_id, amount))
+    }
+    pub fn chargeback(client_id: ClientID, transaction_id: TransactionID, amount: Amount) -> Self {
+        Self::Chargeback(Dispute::new(client_id, transaction_id, amount))
+    }
+    pub fn reversal(
+        client_id: ClientID,
+        transaction_id: TransactionID,
+        amount: Amount,
+        kind: TransactionKind,
+    ) -> Self {
+        Self::Reversal(Dispute::new(client_id, transaction_id, amount).with_kind(kind))
+    }
+    pub fn adjustment_credit(
+        client_id: ClientID,
+        transaction_id: TransactionID,
+        amount: Amount,
+    ) -> Self {
+        Self::AdjustmentCredit(Transaction::new(client_id, transaction_id, amount))
+    }
+    pub fn adjustment_debit(
+        client_id: ClientID,
+        transaction_id: TransactionID,
+        amount: Amount,
+    ) -> Self {
+        Self::AdjustmentDebit(Transaction::new(client_id, transaction_id, amount))
+    }
+    pub fn transfer(
+        from_client_id: ClientID,
+        to_client_id: ClientID,
+        transaction_id: TransactionID,
+        amount: Amount,
+    ) -> Self {
+        Self::Transfer(Transfer {
+            from_client_id,
+            to_client_id,
+            transaction_id,
+            amount,
+        })
     }
 }
 
-#[derive(Debug)]
+/// Amounts of disputes, resolves and chargebacks are given only by partial-dispute rows, e.g.
+/// `dispute,1,5,30.0`, otherwise the whole amount of the looked-up transaction is used
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum DisputeLookUpMessage {
-    Dispute(ClientID, TransactionID),
-    Resolve(ClientID, TransactionID),
-    Chargeback(ClientID, TransactionID),
+    /// Timestamp of the dispute itself, if the journal has timestamps
+    Dispute(ClientID, TransactionID, Option<Timestamp>, Option<Amount>),
+    Resolve(ClientID, TransactionID, Option<Amount>),
+    Chargeback(ClientID, TransactionID, Option<Amount>),
+    /// Reversal of the deposit or withdrawal, its amount and kind are looked up like those of disputes
+    Reversal(ClientID, TransactionID),
 }
 
 impl DisputeLookUpMessage {
-    pub fn client_id(&self) -> u16 {
+    pub fn client_id(&self) -> ClientID {
+        match self {
+            Self::Dispute(client_id, ..)
+            | Self::Resolve(client_id, ..)
+            | Self::Chargeback(client_id, ..)
+            | Self::Reversal(client_id, _) => *client_id,
+        }
+    }
+
+    pub fn transaction_id(&self) -> TransactionID {
         match self {
-            Self::Dispute(client_id, _)
-            | Self::Resolve(client_id, _)
-            | Self::Chargeback(client_id, _) => *client_id,
+            Self::Dispute(_, transaction_id, ..)
+            | Self::Resolve(_, transaction_id, _)
+            | Self::Chargeback(_, transaction_id, _)
+            | Self::Reversal(_, transaction_id) => *transaction_id,
         }
     }
 
-    pub fn transaction_id(&self) -> u32 {
+    /// Amount given by the partial-dispute row, `None` if the whole transaction is disputed
+    pub fn amount(&self) -> Option<Amount> {
         match self {
-            Self::Dispute(_, transaction_id)
-            | Self::Resolve(_, transaction_id)
-            | Self::Chargeback(_, transaction_id) => *transaction_id,
+            Self::Dispute(.., amount)
+            | Self::Resolve(.., amount)
+            | Self::Chargeback(.., amount) => *amount,
+            Self::Reversal(..) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batches() {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let sender = Sender::new(sender).with_batch_size(2);
+        let mut wait = Histogram::default();
+        for message in 0..5 {
+            sender.send_timed(message, &mut wait);
         }
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            [vec![0, 1], vec![2, 3]]
+        );
+
+        sender.send(5);
+        sender.flush();
+        sender.send(6);
+        drop(sender);
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            [vec![4, 5], vec![6]]
+        );
     }
 }
@@ -0,0 +1,168 @@
+use crate::channel::{Indexed, TransactionMessage};
+use eyre::{eyre, Context, Result};
+use std::path::Path;
+
+/// Journal records which were already applied to the accounts, these are skipped when the journal is processed again.
+/// Processing receives records from the parser and the dispute look-up, each of them sends in the journal order,
+/// so the last applied record of each of them is enough to tell whether a record was applied
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Index of the last applied record sent to processing by the parser
+    pub transactions: Option<u64>,
+    /// Index of the last applied dispute, resolve or chargeback, these are sent by the dispute look-up
+    pub disputes: Option<u64>,
+}
+
+impl Checkpoint {
+    /// All records before `index` were applied
+    pub fn skip_until(index: u64) -> Checkpoint {
+        Checkpoint {
+            transactions: index.checked_sub(1),
+            disputes: index.checked_sub(1),
+        }
+    }
+
+    /// Moves the checkpoint past the applied message
+    pub fn record(&mut self, applied: &Indexed<TransactionMessage>) {
+        let last = match applied.message {
+            TransactionMessage::Dispute(_)
+            | TransactionMessage::Resolve(_)
+            | TransactionMessage::Chargeback(_) => &mut self.disputes,
+            _ => &mut self.transactions,
+        };
+        *last = (*last).max(Some(applied.index));
+    }
+
+    /// Returns checkpoint covering records applied according to either of them
+    pub fn merge(self, other: Checkpoint) -> Checkpoint {
+        Checkpoint {
+            transactions: self.transactions.max(other.transactions),
+            disputes: self.disputes.max(other.disputes),
+        }
+    }
+
+    /// Returns `true` if the record was already applied
+    /// # Arguments
+    /// * index - index of the record in the journal
+    /// * dispute_look_up - whether the record goes through the dispute look-up
+    pub fn is_applied(&self, index: u64, dispute_look_up: bool) -> bool {
+        let last = match dispute_look_up {
+            true => self.disputes,
+            false => self.transactions,
+        };
+        last.is_some_and(|last| index <= last)
+    }
+}
+
+/// Where parsing of append-only journal stopped, the next run continues from it with `--since-offset`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResumePoint {
+    /// Byte offset of the first record which was not parsed
+    pub offset: u64,
+    /// Index of that record
+    pub index: u64,
+}
+
+impl ResumePoint {
+    /// Reads the offset file, `None` if it doesn't exist yet
+    pub fn load(path: &Path) -> Result<Option<ResumePoint>> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err)
+                    .wrap_err_with(|| format!("failed to read offset file {}", path.display()))
+            }
+        };
+        ResumePoint::parse(&content)
+            .map(Some)
+            .wrap_err_with(|| format!("failed to parse offset file {}", path.display()))
+    }
+
+    /// Parses `offset <bytes>` and `index <record>` lines
+    fn parse(content: &str) -> Result<ResumePoint> {
+        let (mut offset, mut index) = (None, None);
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            match line.split_once(' ') {
+                Some(("offset", value)) => offset = Some(value.trim().parse()?),
+                Some(("index", value)) => index = Some(value.trim().parse()?),
+                _ => return Err(eyre!("invalid line '{line}'")),
+            }
+        }
+        Ok(ResumePoint {
+            offset: offset.ok_or(eyre!("missing offset"))?,
+            index: index.ok_or(eyre!("missing index"))?,
+        })
+    }
+
+    /// Replaces the offset file, it is written next to it first so it is never left half-written
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let temporary = path.with_extension("tmp");
+        std::fs::write(
+            &temporary,
+            format!("offset {}\nindex {}\n", self.offset, self.index),
+        )
+        .and_then(|_| std::fs::rename(&temporary, path))
+        .wrap_err_with(|| format!("failed to write offset file {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint() {
+        let mut checkpoint = Checkpoint::default();
+        checkpoint.record(&Indexed::new(
+            4,
+            TransactionMessage::deposit(1, 4, amount!(1)),
+        ));
+        checkpoint.record(&Indexed::new(
+            2,
+            TransactionMessage::dispute(1, 1, amount!(1)),
+        ));
+
+        let tests = vec![
+            ("applied transaction", 4, false, true),
+            ("later transaction", 5, false, false),
+            ("applied dispute", 2, true, true),
+            ("dispute still in look-up", 3, true, false),
+        ];
+        for (name, index, dispute_look_up, want) in tests {
+            assert_eq!(
+                checkpoint.is_applied(index, dispute_look_up),
+                want,
+                "failed test {name}"
+            );
+        }
+
+        let checkpoint = checkpoint.merge(Checkpoint::skip_until(4));
+        assert!(checkpoint.is_applied(3, true), "skipped by --skip-until");
+        assert!(!Checkpoint::skip_until(0).is_applied(0, false));
+    }
+
+    #[test]
+    fn test_resume_point() {
+        let path =
+            std::env::temp_dir().join(format!("tren-test-resume-{}.offset", std::process::id()));
+        assert_eq!(ResumePoint::load(&path).unwrap(), None);
+
+        let resume = ResumePoint {
+            offset: 1024,
+            index: 40,
+        };
+        resume.save(&path).unwrap();
+        let loaded = ResumePoint::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), Some(resume));
+
+        assert_eq!(
+            ResumePoint::parse("offset 1024\r\nindex 40\r\n").unwrap(),
+            resume,
+            "crlf"
+        );
+        assert!(ResumePoint::parse("offset 10\n").is_err(), "missing index");
+        assert!(ResumePoint::parse("offset ten\nindex 1\n").is_err());
+    }
+}
@@ -0,0 +1,467 @@
+use crate::aliases::*;
+use crate::config::Config;
+use eyre::{eyre, Context, Result};
+use std::path::PathBuf;
+
+/// Usage text printed for `--help` and with errors of the command line arguments
+pub const USAGE: &str = "\
+usage: tren <journal> [--<option> <value>]... [--config <file>]
+       tren test-fixtures <dir>
+       tren convert <journal> <output>
+       tren sort <journal> <output>
+       tren split <journal> --shards <n> [-o <dir>]
+       tren merge <report> <report>... [-o <output>]
+       tren simulate <journal> --disputes <disputes>
+       tren preview-dispute <journal> --client <client> --tx <tx>
+       tren validate <journal>
+       tren checksum <journal>
+       tren replay-decisions <log>
+       tren replay-deadletter <file> --initial-state <report>
+       tren --help
+
+Options set the fields of the configuration, they override values from the --config file.";
+
+/// Command line arguments, first positional argument is the path to the journal, options can follow in any order.
+/// Every option of [Config] can be passed as `--<option> <value>`, options override values from `--config` file.
+/// `tren test-fixtures <dir>` runs golden-file fixtures from the directory instead, see [crate::fixtures].
+/// `tren convert <journal> <output>` converts the journal into binary journal, see [crate::binary].
+/// `tren sort <journal> <output>` sorts the journal, see [crate::sort].
+/// `tren split <journal> --shards <n> [-o <dir>]` splits the journal by client into shards, see [crate::split].
+/// `tren merge <report> <report>... [-o <output>]` merges reports, see [crate::report::merge_reports].
+/// `tren simulate <journal> --disputes <disputes>` applies hypothetical disputes, see [crate::simulate].
+/// `tren preview-dispute <journal> --client <client> --tx <tx>` previews dispute of one transaction, see [crate::simulate].
+/// `tren validate <journal>` checks the order of transaction IDs, see [crate::order].
+/// `tren checksum <journal>` prints manifest of the journal, see [crate::manifest].
+/// `tren replay-decisions <log>` applies operations logged by `--record-decisions`, see [crate::decisions]
+/// `tren replay-deadletter <file> --initial-state <report>` applies rows rejected by `--dead-letter` again, see
+/// [crate::dead_letter]
+/// `tren --help` prints [USAGE]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    pub command: Command,
+    /// Path to the transaction journal, the fixtures directory or the first merged report
+    pub input: PathBuf,
+    pub config: Config,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub enum Command {
+    /// Processes the journal and prints the report
+    #[default]
+    Process,
+    /// Prints the usage text, the rest of the arguments is ignored
+    Help,
+    /// Runs golden-file fixtures
+    TestFixtures,
+    /// Checks the order of transaction IDs in the journal
+    Validate,
+    /// Prints checksum manifest of the journal
+    Checksum,
+    /// Applies operations from the decision log in the logged order and prints the report
+    ReplayDecisions,
+    /// Applies rows of the dead-letter file on top of the initial state and prints the report
+    ReplayDeadLetter,
+    /// Converts the journal into binary journal written to the path
+    Convert(PathBuf),
+    /// Sorts the journal into CSV file written to the path
+    Sort(PathBuf),
+    /// Splits the journal by client into this many journals written into the directory
+    Split { shards: u64, output: PathBuf },
+    /// Merges the input report with the other reports, written to the output or printed if it is not set
+    Merge {
+        reports: Vec<PathBuf>,
+        output: Option<PathBuf>,
+    },
+    /// Processes the journal, applies the disputes from the path on top of it and prints the changed accounts
+    Simulate(PathBuf),
+    /// Processes the journal and prints the client's account after dispute, resolve and chargeback of the transaction
+    PreviewDispute {
+        client_id: ClientID,
+        transaction_id: TransactionID,
+    },
+}
+
+impl Args {
+    pub fn parse() -> Result<Args> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    fn parse_from(mut args: impl Iterator<Item = String>) -> Result<Args> {
+        let mut command = Command::Process;
+        // subcommand which expects path to the output after the journal
+        let mut with_output = None;
+        let mut input = None;
+        let mut config_path = None;
+        let mut options = Vec::new();
+        // `None` until `simulate` is given, then path to the disputes once `--disputes` follows
+        let mut simulated = None;
+        // `None` until `preview-dispute` is given, then the client and the transaction once `--client` and `--tx` follow
+        let mut previewed: Option<(Option<ClientID>, Option<TransactionID>)> = None;
+        // `None` until `split` is given, then the number of shards and the output directory once they follow
+        let mut split: Option<(Option<u64>, Option<PathBuf>)> = None;
+
+        while let Some(arg) = args.next() {
+            if let (Some(disputes), "--disputes") = (&mut simulated, arg.as_str()) {
+                *disputes = Some(PathBuf::from(value(&arg, args.next())?));
+                continue;
+            }
+            if let (Some((client_id, transaction_id)), "--client" | "--tx") =
+                (&mut previewed, arg.as_str())
+            {
+                let parsed = value(&arg, args.next())?;
+                let invalid = || format!("invalid value '{parsed}' of {arg}");
+                match arg.as_str() {
+                    "--client" => *client_id = Some(parsed.parse().wrap_err_with(invalid)?),
+                    _ => *transaction_id = Some(parsed.parse().wrap_err_with(invalid)?),
+                }
+                continue;
+            }
+            if let (Some((shards, output)), "--shards" | "-o" | "--output") =
+                (&mut split, arg.as_str())
+            {
+                let parsed = value(&arg, args.next())?;
+                match arg.as_str() {
+                    "--shards" => {
+                        *shards = Some(
+                            parsed
+                                .parse()
+                                .ok()
+                                .filter(|shards| *shards > 0)
+                                .ok_or(eyre!("invalid number of shards '{parsed}'"))?,
+                        )
+                    }
+                    _ => *output = Some(parsed.into()),
+                }
+                continue;
+            }
+            if let (Command::Merge { output, .. }, "-o" | "--output") = (&mut command, arg.as_str())
+            {
+                *output = Some(value(&arg, args.next())?.into());
+                continue;
+            }
+
+            match arg.strip_prefix("--") {
+                Some("help") => {
+                    return Ok(Args {
+                        command: Command::Help,
+                        ..Default::default()
+                    })
+                }
+                None if arg == "-h" => {
+                    return Ok(Args {
+                        command: Command::Help,
+                        ..Default::default()
+                    })
+                }
+                Some("config") => config_path = Some(PathBuf::from(value(&arg, args.next())?)),
+                Some(flag) if Config::is_flag(flag) => options.push((flag.to_string(), None)),
+                Some(option) => options.push((option.to_string(), Some(value(&arg, args.next())?))),
+                None if input.is_none()
+                    && command == Command::Process
+                    && arg == "test-fixtures" =>
+                {
+                    command = Command::TestFixtures
+                }
+                None if input.is_none()
+                    && command == Command::Process
+                    && simulated.is_none()
+                    && arg == "simulate" =>
+                {
+                    simulated = Some(None)
+                }
+                None if input.is_none()
+                    && command == Command::Process
+                    && previewed.is_none()
+                    && arg == "preview-dispute" =>
+                {
+                    previewed = Some((None, None))
+                }
+                None if input.is_none()
+                    && command == Command::Process
+                    && split.is_none()
+                    && arg == "split" =>
+                {
+                    split = Some((None, None))
+                }
+                None if input.is_none() && command == Command::Process && arg == "validate" => {
+                    command = Command::Validate
+                }
+                None if input.is_none() && command == Command::Process && arg == "checksum" => {
+                    command = Command::Checksum
+                }
+                None if input.is_none()
+                    && command == Command::Process
+                    && arg == "replay-decisions" =>
+                {
+                    command = Command::ReplayDecisions
+                }
+                None if input.is_none()
+                    && command == Command::Process
+                    && arg == "replay-deadletter" =>
+                {
+                    command = Command::ReplayDeadLetter
+                }
+                None if input.is_none() && command == Command::Process && arg == "merge" => {
+                    command = Command::Merge {
+                        reports: Vec::new(),
+                        output: None,
+                    }
+                }
+                None if input.is_none()
+                    && with_output.is_none()
+                    && (arg == "convert" || arg == "sort") =>
+                {
+                    with_output = Some(arg)
+                }
+                None if input.is_none() => input = Some(arg.into()),
+                None if command == Command::Process => match with_output.as_deref() {
+                    Some("convert") => command = Command::Convert(arg.into()),
+                    Some(_) => command = Command::Sort(arg.into()),
+                    None => return Err(eyre!("unexpected argument '{arg}'")),
+                },
+                None => match &mut command {
+                    Command::Merge { reports, .. } => reports.push(arg.into()),
+                    _ => return Err(eyre!("unexpected argument '{arg}'")),
+                },
+            }
+        }
+
+        if let Some(subcommand) = with_output.filter(|_| command == Command::Process) {
+            return Err(eyre!(
+                "{subcommand} expects path to the journal and to the output"
+            ));
+        }
+
+        if let Some(disputes) = simulated {
+            command = Command::Simulate(disputes.ok_or(eyre!(
+                "simulate expects path to the disputes after --disputes"
+            ))?);
+        }
+
+        if let Some((client_id, transaction_id)) = previewed {
+            command = Command::PreviewDispute {
+                client_id: client_id
+                    .ok_or(eyre!("preview-dispute expects client after --client"))?,
+                transaction_id: transaction_id
+                    .ok_or(eyre!("preview-dispute expects transaction after --tx"))?,
+            };
+        }
+
+        if let Some((shards, output)) = split {
+            command = Command::Split {
+                shards: shards.ok_or(eyre!("split expects number of shards after --shards"))?,
+                output: output.unwrap_or_else(|| ".".into()),
+            };
+        }
+
+        let mut config = match config_path {
+            Some(path) => Config::load(&path)?,
+            None => Config::default(),
+        };
+        for (option, value) in options {
+            match value {
+                Some(value) => config.set(&option, value)?,
+                None => config.set_flag(&option)?,
+            }
+        }
+
+        Ok(Args {
+            command,
+            input: input.ok_or(eyre!(
+                "expected path to file to parse as the first argument, but got nothing"
+            ))?,
+            config,
+        })
+    }
+}
+
+fn value(option: &str, value: Option<String>) -> Result<String> {
+    value.ok_or(eyre!("option '{option}' requires a value"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::DisputePolicy;
+    use crate::timestamp::TimeWindow;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn test_parse_from() {
+        let got = Args::parse_from(args(&[
+            "journal.csv",
+            "--dead-letter",
+            "rejected.csv",
+            "--dispute-policy",
+            "clamp",
+            "--to",
+            "2022-09-01",
+            "--no-progress",
+        ]))
+        .expect("failed to parse valid arguments");
+        assert_eq!(
+            got,
+            Args {
+                command: Command::Process,
+                input: "journal.csv".into(),
+                config: Config {
+                    dead_letter: Some("rejected.csv".into()),
+                    dispute_policy: DisputePolicy::Clamp,
+                    window: TimeWindow {
+                        from: None,
+                        to: Some(1661990400)
+                    },
+                    progress: false,
+                    ..Default::default()
+                },
+            }
+        );
+
+        assert_eq!(
+            Args::parse_from(args(&["journal.csv", "--help"]))
+                .expect("failed to parse valid arguments")
+                .command,
+            Command::Help
+        );
+        assert_eq!(
+            Args::parse_from(args(&["-h", "--unknown"]))
+                .expect("failed to parse valid arguments")
+                .command,
+            Command::Help
+        );
+        assert_eq!(
+            Args::parse_from(args(&["test-fixtures", "fixtures"]))
+                .expect("failed to parse valid arguments")
+                .command,
+            Command::TestFixtures
+        );
+        let got = Args::parse_from(args(&["convert", "journal.csv", "journal.trn"]))
+            .expect("failed to parse valid arguments");
+        assert_eq!(
+            (got.command, got.input),
+            (Command::Convert("journal.trn".into()), "journal.csv".into())
+        );
+        let got = Args::parse_from(args(&["sort", "journal.csv", "sorted.csv"]))
+            .expect("failed to parse valid arguments");
+        assert_eq!(got.command, Command::Sort("sorted.csv".into()));
+        assert_eq!(
+            Args::parse_from(args(&["validate", "journal.csv"]))
+                .expect("failed to parse valid arguments")
+                .command,
+            Command::Validate
+        );
+        assert_eq!(
+            Args::parse_from(args(&["checksum", "journal.csv"]))
+                .expect("failed to parse valid arguments")
+                .command,
+            Command::Checksum
+        );
+        assert_eq!(
+            Args::parse_from(args(&["replay-decisions", "decisions.csv"]))
+                .expect("failed to parse valid arguments")
+                .command,
+            Command::ReplayDecisions
+        );
+        assert_eq!(
+            Args::parse_from(args(&["replay-deadletter", "rejected.csv"]))
+                .expect("failed to parse valid arguments")
+                .command,
+            Command::ReplayDeadLetter
+        );
+        let got = Args::parse_from(args(&["merge", "eu.csv", "us.csv", "-o", "all.csv"]))
+            .expect("failed to parse valid arguments");
+        assert_eq!(
+            (got.command, got.input),
+            (
+                Command::Merge {
+                    reports: vec!["us.csv".into()],
+                    output: Some("all.csv".into())
+                },
+                "eu.csv".into()
+            )
+        );
+        let got = Args::parse_from(args(&[
+            "simulate",
+            "journal.csv",
+            "--disputes",
+            "disputes.csv",
+        ]))
+        .expect("failed to parse valid arguments");
+        assert_eq!(
+            (got.command, got.input),
+            (
+                Command::Simulate("disputes.csv".into()),
+                "journal.csv".into()
+            )
+        );
+        let got = Args::parse_from(args(&[
+            "preview-dispute",
+            "journal.csv",
+            "--client",
+            "7",
+            "--tx",
+            "1234",
+        ]))
+        .expect("failed to parse valid arguments");
+        assert_eq!(
+            (got.command, got.input),
+            (
+                Command::PreviewDispute {
+                    client_id: 7,
+                    transaction_id: 1234
+                },
+                "journal.csv".into()
+            )
+        );
+        let got = Args::parse_from(args(&[
+            "split",
+            "journal.csv",
+            "--shards",
+            "8",
+            "-o",
+            "out/",
+        ]))
+        .expect("failed to parse valid arguments");
+        assert_eq!(
+            (got.command, got.input),
+            (
+                Command::Split {
+                    shards: 8,
+                    output: "out/".into()
+                },
+                "journal.csv".into()
+            )
+        );
+        assert!(
+            Args::parse_from(args(&["split", "journal.csv", "--shards", "0"])).is_err(),
+            "no shards"
+        );
+        assert!(
+            Args::parse_from(args(&["simulate", "journal.csv"])).is_err(),
+            "missing simulated disputes"
+        );
+        assert!(
+            Args::parse_from(args(&["preview-dispute", "journal.csv", "--client", "7"])).is_err(),
+            "missing previewed transaction"
+        );
+        assert!(
+            Args::parse_from(args(&["convert", "journal.csv"])).is_err(),
+            "missing convert output"
+        );
+        assert!(Args::parse_from(args(&[])).is_err(), "missing input");
+        assert!(
+            Args::parse_from(args(&["journal.csv", "--dead-letter"])).is_err(),
+            "missing option value"
+        );
+        assert!(
+            Args::parse_from(args(&["journal.csv", "--unknown", "value"])).is_err(),
+            "unknown option"
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use crate::aliases::*;
+use crate::channel::TransactionMessage;
+use crate::parser::JournalEntry;
+use eyre::{eyre, Context, Result};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Clients whose records are processed, built from `--only-clients` and `--ignore-clients` files.
+/// Transfers are skipped if either of the clients is excluded, so excluded client never gets an account
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientFilter {
+    /// If set, only these clients are processed
+    only: Option<HashSet<ClientID>>,
+    /// These clients are never processed, even if they are in `only`
+    ignore: HashSet<ClientID>,
+}
+
+impl ClientFilter {
+    /// Loads the lists of clients, returns `None` if neither of them is set
+    pub fn load(only: Option<&Path>, ignore: Option<&Path>) -> Result<Option<ClientFilter>> {
+        if only.is_none() && ignore.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(ClientFilter {
+            only: only.map(read_clients).transpose()?,
+            ignore: ignore.map(read_clients).transpose()?.unwrap_or_default(),
+        }))
+    }
+
+    pub fn contains(&self, client_id: ClientID) -> bool {
+        !self.ignore.contains(&client_id)
+            && self
+                .only
+                .as_ref()
+                .is_none_or(|only| only.contains(&client_id))
+    }
+
+    /// Returns `true` if all clients of the entry are processed
+    pub fn allows(&self, entry: &JournalEntry) -> bool {
+        let receiving = match entry {
+            JournalEntry::Transaction(TransactionMessage::Transfer(transfer)) => {
+                self.contains(transfer.to_client_id)
+            }
+            _ => true,
+        };
+        receiving && self.contains(entry.client_id())
+    }
+}
+
+/// Reads one client ID per line, empty lines and lines starting with `#` are skipped
+fn read_clients(path: &Path) -> Result<HashSet<ClientID>> {
+    let content = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("failed to read clients file {}", path.display()))?;
+
+    content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| {
+            line.parse::<ClientID>().wrap_err_with(|| {
+                eyre!(
+                    "invalid client id '{line}' on line {} of {}",
+                    index + 1,
+                    path.display()
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows() {
+        let filter = ClientFilter {
+            only: Some(HashSet::from([1, 2, 3])),
+            ignore: HashSet::from([3]),
+        };
+        let tests = vec![
+            ("only", TransactionMessage::deposit(1, 1, amount!(1)), true),
+            (
+                "not in only",
+                TransactionMessage::deposit(4, 1, amount!(1)),
+                false,
+            ),
+            ("ignored", TransactionMessage::Lock(3), false),
+            (
+                "transfer",
+                TransactionMessage::transfer(1, 2, 1, amount!(1)),
+                true,
+            ),
+            (
+                "transfer to ignored",
+                TransactionMessage::transfer(1, 3, 1, amount!(1)),
+                false,
+            ),
+        ];
+
+        for (name, message, want) in tests {
+            let got = filter.allows(&JournalEntry::Transaction(message));
+            assert_eq!(got, want, "failed test {name}");
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use crate::aliases::*;
+use crate::channel::{DisputeLookUpMessage, TransactionMessage};
+use crate::parser::JournalEntry;
+use eyre::{eyre, Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Client IDs replaced while parsing, loaded from `--client-remap` file of `old_client_id,new_client_id` lines.
+/// Books merged after clients were given new IDs are processed as one journal: records of the old ID go to the
+/// account of the new one, so if the new ID already has records their balances are combined. Transactions looked
+/// up for disputes are remapped too, so disputes of either ID find them
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientRemap(Arc<HashMap<ClientID, ClientID>>);
+
+impl ClientRemap {
+    /// Reads one `old,new` pair per line, empty lines and lines starting with `#` are skipped. Client can't be
+    /// remapped twice and remapped client can't be a new ID of another one, the remapping is done once
+    pub fn load(path: &Path) -> Result<ClientRemap> {
+        let content = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read client remap file {}", path.display()))?;
+
+        let mut remap = HashMap::new();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let context = || format!("line {} of {}", index + 1, path.display());
+            let (old, new) = line
+                .split_once(',')
+                .ok_or_else(|| eyre!("expected old_client_id,new_client_id"))
+                .wrap_err_with(context)?;
+            let parse = |client_id: &str| {
+                client_id
+                    .trim()
+                    .parse::<ClientID>()
+                    .wrap_err_with(|| format!("invalid client id '{}'", client_id.trim()))
+                    .wrap_err_with(context)
+            };
+            let (old, new) = (parse(old)?, parse(new)?);
+            if remap.insert(old, new).is_some() {
+                return Err(eyre!("client {old} is remapped twice")).wrap_err_with(context);
+            }
+        }
+        if let Some(chained) = remap.values().find(|new| remap.contains_key(new)) {
+            return Err(eyre!(
+                "client {chained} in {} is both remapped and a new id of another client",
+                path.display()
+            ));
+        }
+        Ok(ClientRemap(Arc::new(remap)))
+    }
+
+    /// New ID of the client, the same one if it is not remapped
+    pub fn map(&self, client_id: ClientID) -> ClientID {
+        self.0.get(&client_id).copied().unwrap_or(client_id)
+    }
+
+    /// Entry with all of its clients remapped
+    pub fn apply(&self, entry: JournalEntry) -> JournalEntry {
+        match entry {
+            JournalEntry::Transaction(message) => {
+                JournalEntry::Transaction(self.apply_transaction(message))
+            }
+            JournalEntry::DisputeLookUp(message) => JournalEntry::DisputeLookUp(match message {
+                DisputeLookUpMessage::Dispute(client_id, transaction_id, timestamp, amount) => {
+                    DisputeLookUpMessage::Dispute(
+                        self.map(client_id),
+                        transaction_id,
+                        timestamp,
+                        amount,
+                    )
+                }
+                DisputeLookUpMessage::Resolve(client_id, transaction_id, amount) => {
+                    DisputeLookUpMessage::Resolve(self.map(client_id), transaction_id, amount)
+                }
+                DisputeLookUpMessage::Chargeback(client_id, transaction_id, amount) => {
+                    DisputeLookUpMessage::Chargeback(self.map(client_id), transaction_id, amount)
+                }
+                DisputeLookUpMessage::Reversal(client_id, transaction_id) => {
+                    DisputeLookUpMessage::Reversal(self.map(client_id), transaction_id)
+                }
+            }),
+        }
+    }
+
+    fn apply_transaction(&self, mut message: TransactionMessage) -> TransactionMessage {
+        match &mut message {
+            TransactionMessage::Deposit(transaction)
+            | TransactionMessage::Withdrawal(transaction)
+            | TransactionMessage::AdjustmentCredit(transaction)
+            | TransactionMessage::AdjustmentDebit(transaction) => {
+                transaction.client_id = self.map(transaction.client_id)
+            }
+            TransactionMessage::Dispute(dispute)
+            | TransactionMessage::Resolve(dispute)
+            | TransactionMessage::Chargeback(dispute)
+            | TransactionMessage::Reversal(dispute) => {
+                dispute.client_id = self.map(dispute.client_id)
+            }
+            TransactionMessage::Transfer(transfer) => {
+                transfer.from_client_id = self.map(transfer.from_client_id);
+                transfer.to_client_id = self.map(transfer.to_client_id);
+            }
+            TransactionMessage::Lock(client_id)
+            | TransactionMessage::Unlock(client_id)
+            | TransactionMessage::Close(client_id) => *client_id = self.map(*client_id),
+        }
+        message
+    }
+}
+
+/// New ID of the client if there is a remapping
+pub(crate) fn remapped(remap: Option<&ClientRemap>, client_id: ClientID) -> ClientID {
+    remap.map_or(client_id, |remap| remap.map(client_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let path = std::env::temp_dir().join(format!("tren-test-remap-{}.csv", std::process::id()));
+        std::fs::write(&path, "# acquired book\n101, 1\n102,2\n\n").unwrap();
+        let remap = ClientRemap::load(&path).unwrap();
+
+        let tests = vec![
+            (
+                "deposit",
+                JournalEntry::Transaction(TransactionMessage::deposit(101, 1, amount!(1))),
+                JournalEntry::Transaction(TransactionMessage::deposit(1, 1, amount!(1))),
+            ),
+            (
+                "not remapped",
+                JournalEntry::Transaction(TransactionMessage::Lock(3)),
+                JournalEntry::Transaction(TransactionMessage::Lock(3)),
+            ),
+            (
+                "transfer",
+                JournalEntry::Transaction(TransactionMessage::transfer(101, 102, 2, amount!(1))),
+                JournalEntry::Transaction(TransactionMessage::transfer(1, 2, 2, amount!(1))),
+            ),
+            (
+                "dispute",
+                JournalEntry::DisputeLookUp(DisputeLookUpMessage::Dispute(102, 5, None, None)),
+                JournalEntry::DisputeLookUp(DisputeLookUpMessage::Dispute(2, 5, None, None)),
+            ),
+        ];
+        for (name, entry, want) in tests {
+            assert_eq!(remap.apply(entry), want, "failed test {name}");
+        }
+
+        for invalid in ["101", "101,x", "101,1\n101,2", "101,1\n1,2"] {
+            std::fs::write(&path, invalid).unwrap();
+            assert!(ClientRemap::load(&path).is_err(), "failed test {invalid:?}");
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
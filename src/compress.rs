@@ -0,0 +1,183 @@
+//! Compressed report output. Reports of millions of clients are compressed before archiving anyway, so they can be
+//! written compressed right away. The data is compressed in-process into gzip or zstd format
+use eyre::{eyre, Context, Result};
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(eyre!(
+                "invalid compression '{s}', expected one of none, gzip, zstd"
+            )),
+        }
+    }
+}
+
+impl Compression {
+    /// Compression given by the extension of the path, `.gz` or `.zst`
+    pub fn of_path(path: &Path) -> Compression {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Configured compression, or the one given by the extension of the path if it is not configured
+    pub fn resolve(configured: Option<Compression>, path: &Path) -> Compression {
+        configured.unwrap_or_else(|| Compression::of_path(path))
+    }
+
+    /// Extension of the compressed file without the dot, e.g. `gz`
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gz"),
+            Compression::Zstd => Some("zst"),
+        }
+    }
+}
+
+/// Writer of report which is compressed unless the compression is [Compression::None].
+/// [CompressedWriter::finish] has to be called to write the end of the compressed data
+pub enum CompressedWriter {
+    Plain(BufWriter<Box<dyn Write>>),
+    Gzip(GzEncoder<BufWriter<Box<dyn Write>>>),
+    Zstd(zstd::Encoder<'static, BufWriter<Box<dyn Write>>>),
+}
+
+impl CompressedWriter {
+    /// Creates the file, with compression the compressed data is written into it
+    pub fn create(path: &Path, compression: Compression) -> Result<CompressedWriter> {
+        let file = File::create(path)
+            .wrap_err_with(|| format!("failed to create report file {}", path.display()))?;
+        CompressedWriter::new(compression, Box::new(file))
+    }
+
+    /// Writes to stdout, e.g. for redirecting the compressed report into a file
+    pub fn stdout(compression: Compression) -> Result<CompressedWriter> {
+        CompressedWriter::new(compression, Box::new(std::io::stdout()))
+    }
+
+    fn new(compression: Compression, output: Box<dyn Write>) -> Result<CompressedWriter> {
+        let output = BufWriter::new(output);
+        Ok(match compression {
+            Compression::None => CompressedWriter::Plain(output),
+            Compression::Gzip => {
+                CompressedWriter::Gzip(GzEncoder::new(output, flate2::Compression::default()))
+            }
+            Compression::Zstd => CompressedWriter::Zstd(
+                zstd::Encoder::new(output, zstd::DEFAULT_COMPRESSION_LEVEL)
+                    .wrap_err("failed to start zstd compression of report")?,
+            ),
+        })
+    }
+
+    /// Writes the end of the compressed data and flushes the report
+    pub fn finish(self) -> Result<()> {
+        let mut output = match self {
+            CompressedWriter::Plain(writer) => writer,
+            CompressedWriter::Gzip(encoder) => encoder
+                .finish()
+                .wrap_err("failed to write compressed report")?,
+            CompressedWriter::Zstd(encoder) => encoder
+                .finish()
+                .wrap_err("failed to write compressed report")?,
+        };
+        Ok(output.flush()?)
+    }
+}
+
+impl Write for CompressedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            CompressedWriter::Plain(writer) => writer.write(buf),
+            CompressedWriter::Gzip(encoder) => encoder.write(buf),
+            CompressedWriter::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            CompressedWriter::Plain(writer) => writer.flush(),
+            CompressedWriter::Gzip(encoder) => encoder.flush(),
+            CompressedWriter::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_of_path() {
+        let tests = vec![
+            ("plain", "report.csv", Compression::None),
+            ("gzip", "report.csv.gz", Compression::Gzip),
+            ("zstd", "out/report.csv.zst", Compression::Zstd),
+            ("no extension", "report", Compression::None),
+        ];
+
+        for (name, path, want) in tests {
+            assert_eq!(
+                Compression::of_path(Path::new(path)),
+                want,
+                "failed test {name}"
+            );
+        }
+    }
+
+    type Decode = fn(File) -> Vec<u8>;
+
+    fn read_all(mut reader: impl Read) -> Vec<u8> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_compressed_writer() {
+        let tests: Vec<(&str, &str, Decode)> = vec![
+            ("plain", "csv", read_all),
+            ("gzip", "csv.gz", |file| {
+                read_all(flate2::read::GzDecoder::new(file))
+            }),
+            ("zstd", "csv.zst", |file| zstd::decode_all(file).unwrap()),
+        ];
+
+        for (name, extension, decode) in tests {
+            let path = std::env::temp_dir().join(format!(
+                "tren-test-compress-{}.{extension}",
+                std::process::id()
+            ));
+            let mut writer = CompressedWriter::create(&path, Compression::of_path(&path)).unwrap();
+            writeln!(writer, "client,available,held,total").unwrap();
+            writer.finish().unwrap();
+
+            let got = decode(File::open(&path).unwrap());
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(got, b"client,available,held,total\n", "failed test {name}");
+        }
+    }
+}
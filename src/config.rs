@@ -0,0 +1,566 @@
+use crate::accounts::{DisputePolicy, WithdrawalDisputePolicy};
+use crate::affinity::CpuAffinity;
+use crate::alerts::Threshold;
+use crate::amount::{AmountFormat, Rounding};
+use crate::compress::Compression;
+use crate::currency::{self, CurrencyScale};
+use crate::dispute_look_up::{MismatchedDisputePolicy, PendingRetry};
+use crate::fees::FeeSchedule;
+use crate::fraud::FraudRules;
+use crate::interest::InterestConfig;
+use crate::invariants::InvariantMode;
+use crate::logger::LogFormat;
+use crate::parser::ParseErrorPolicy;
+use crate::report::{MergeConflicts, Partition, ReportVersion};
+use crate::rules::Rule;
+use crate::sink::{Output, OutputErrors};
+use crate::sort::SortKey;
+use crate::timestamp::{parse_duration, parse_timestamp, TimeWindow};
+use crate::webhook::WebhookConfig;
+use eyre::{eyre, Context, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// All processing options. Can be loaded from RON file passed by `--config`, options passed on the command line
+/// override the values from the file. Library users can construct it directly, every field has a default.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Format of the journal, one of the names in [crate::format::FormatRegistry], detected if not set
+    pub input_format: Option<String>,
+    /// If set, rejected records are written into this file
+    pub dead_letter: Option<PathBuf>,
+    /// If set, all operations applied to the accounts and their outcome are written into this file
+    pub audit: Option<PathBuf>,
+    /// What to do with disputes higher than client's available funds
+    pub dispute_policy: DisputePolicy,
+    /// How disputes of withdrawals change the balances
+    pub withdrawal_disputes: WithdrawalDisputePolicy,
+    /// If set, disputes of client with this many open disputes are rejected and the account is flagged
+    pub max_open_disputes: Option<usize>,
+    /// What to do with disputes of transactions which belong to another client
+    pub mismatched_disputes: MismatchedDisputePolicy,
+    /// If set, disputes of transactions which were not seen yet are looked up again later instead of being dropped,
+    /// `--pending-disputes 100` retries them 100 records later and `--pending-disputes flush` at the end of the journal
+    pub pending_disputes: Option<PendingRetry>,
+    /// Only records with timestamp inside of this window are processed
+    pub window: TimeWindow,
+    /// Resolve of charged back transaction unfreezes the account frozen by the chargeback
+    pub unfreeze_on_resolve: bool,
+    /// Transaction IDs in the journal are not increasing, disputed transactions are searched for in the whole journal
+    pub unordered_input: bool,
+    /// Parser warns about and counts deposits and withdrawals with out of order or reused transaction IDs.
+    /// Needs memory for every transaction ID, see [crate::order]
+    pub check_order: bool,
+    /// Disputes are resolved by the parser from an index of seen transactions instead of a second reader
+    /// of the journal. Needs memory for every deposit and withdrawal but reads the journal only once
+    pub single_pass: bool,
+    /// Parser records offsets of deposits and withdrawals, so the dispute look-up seeks to them instead of
+    /// scanning the journal. Costs 12 bytes per transaction
+    pub offset_index: bool,
+    /// If set, parser keeps a filter of seen transactions sized for this many of them, so disputes of transactions
+    /// which never appeared fail without scanning the journal, see [crate::seen]
+    pub transaction_filter: Option<u64>,
+    /// Report has extra columns with per-client counts of open disputes and chargebacks
+    pub extended_report: bool,
+    /// Report ends with a row of balances summed over all accounts
+    pub totals_row: bool,
+    /// Disputes filed more than this many days after the disputed transaction are rejected
+    pub dispute_max_age_days: Option<u32>,
+    /// What to do with records which can't be parsed
+    pub parse_errors: ParseErrorPolicy,
+    /// Decimal and thousands separators and currency symbol of amounts in the journal
+    pub amount_format: AmountFormat,
+    /// ISO 4217 code of the currency of the journal, e.g. `JPY`. If set, amounts are validated and reported with
+    /// its decimal scale, see [crate::currency]
+    pub currency: Option<String>,
+    /// Scales of currencies replacing the ISO 4217 ones, set by repeated `--currency-scale <code>=<scale>`
+    pub currency_scales: Vec<CurrencyScale>,
+    /// If set, amounts are rounded to 4 decimal places by this strategy, otherwise they are kept exact
+    pub rounding: Option<Rounding>,
+    /// Rules of the optional fraud screening
+    pub fraud_rules: FraudRules,
+    /// CSV file with global and per-client deposit, withdrawal and balance limits
+    pub limits: Option<PathBuf>,
+    /// Fees of withdrawals and chargebacks and the house account they are credited to
+    pub fees: FeeSchedule,
+    /// Interest credited to the accounts once the journal is processed
+    pub interest: InterestConfig,
+    /// Capacity of the channel between parser and accounts processing
+    pub channel_size: usize,
+    /// Parser sends messages to accounts processing in batches of this size, larger batches mean less time spent
+    /// in the channel. The channel holds about [Config::channel_size] messages regardless of the batch size
+    pub channel_batch_size: usize,
+    /// Deposits, withdrawals, transfers and adjustments repeated among this many last transactions are skipped,
+    /// for journals collected from at-least-once sources
+    pub dedup_window: Option<usize>,
+    /// Capacity of the journal read buffers in bytes, e.g. `4M` for fast NVMe disks. Default of the readers if not set
+    pub read_buffer: Option<usize>,
+    /// CPUs the parser, dispute look-up and processing threads are pinned to, set by repeated
+    /// `--cpu-affinity <thread>=<cpus>`
+    pub cpu_affinity: CpuAffinity,
+    /// Up to this many queued dispute look-up requests are satisfied by a single pass over the journal
+    pub dispute_batch_size: usize,
+    /// If set, journal blocks of up to this many dispute look-up requests queued after the current batch are read
+    /// in the background, see [crate::read_ahead]. Needs [Config::offset_index]
+    pub dispute_read_ahead: Option<usize>,
+    /// If set, cached transactions of open disputes are loaded from this file and written back into it at the end
+    /// of the run, so disputes from previous runs are resolved without scanning the journal, see
+    /// [crate::dispute_cache]
+    pub dispute_cache_file: Option<PathBuf>,
+    /// Dispute look-up requests over this many queued are spilled to temporary file, queue is unbounded if not set
+    pub dispute_spill_threshold: Option<usize>,
+    /// If set, snapshot of the report is written every this many seconds while processing
+    pub report_interval: Option<u64>,
+    /// File the report snapshots are written into, `report.csv` if not set. Partitioned report files are named after it
+    pub report_file: Option<PathBuf>,
+    /// If set, every report snapshot also writes accounts changed since the previous one into this file
+    pub report_changes: Option<PathBuf>,
+    /// Number of previous report snapshots kept next to the latest one with `.1` to `.<n>` suffixes
+    pub report_keep: usize,
+    /// If set, this symbolic link is pointed to the report snapshots once the first one is written
+    pub report_link: Option<PathBuf>,
+    /// Compression of the written reports, if not set it is given by the `.gz` or `.zst` extension of the file
+    pub compress: Option<Compression>,
+    /// Notifications of frozen accounts and large chargebacks, see [WebhookConfig]
+    pub webhook: WebhookConfig,
+    /// If set, settled accounts are evicted from memory every this many records, see [crate::accounts::Accounts::evict_settled]
+    pub eviction_interval: Option<u64>,
+    /// Soft limit of the estimated memory in bytes, e.g. `4G`. Over it caches are dropped, settled accounts evicted
+    /// and dispute look-ups degrade to scanning the journal, see [crate::memory]
+    pub max_memory: Option<usize>,
+    /// If set, the run stops after this many seconds as if the journal ended there and reports the records applied
+    /// so far, see [crate::run_guard]
+    pub max_runtime: Option<u64>,
+    /// If set, the run stops once no record arrives for this many seconds, e.g. when the journal is a pipe whose
+    /// writer got stuck, see [crate::run_guard]
+    pub idle_timeout: Option<u64>,
+    /// If set, funds held by open disputes are written into this file after processing, see [Config::aging_min_records]
+    pub aging_report: Option<PathBuf>,
+    /// Only holds at least this many records old are written into [Config::aging_report]
+    pub aging_min_records: u64,
+    /// If set, the report has extra `segment` column with segments of clients from this `client,segment` file
+    pub segments: Option<PathBuf>,
+    /// If set, balances summed over every segment are written into this file after processing
+    pub segment_totals: Option<PathBuf>,
+    /// If set, rows of the report are formatted on this many threads and the report is sorted by client
+    pub report_threads: Option<usize>,
+    /// If set, the final report is split into multiple files instead of being printed
+    pub partition_output: Option<Partition>,
+    /// If set, the final report is written into these outputs instead of being printed, set by repeated
+    /// `--output <kind>:<path>`, see [crate::sink]
+    pub outputs: Vec<Output>,
+    /// What happens with the other outputs when one of [Config::outputs] fails
+    pub output_errors: OutputErrors,
+    /// Rejections in the logs, the dead-letter file and the audit trail point to the line of the input file
+    /// of their record, see [crate::provenance]
+    pub provenance: bool,
+    /// Accounts crossing these balance thresholds at the end of the run are listed in the summary, set by
+    /// repeated `--alert`, e.g. `--alert 'available<0'`, see [crate::alerts]
+    pub alerts: Vec<Threshold>,
+    /// If set, every operation is appended to this write-ahead log before it is applied
+    pub wal: Option<PathBuf>,
+    /// Accounts are rebuilt from this write-ahead log before the journal is processed
+    pub recover: Option<PathBuf>,
+    /// If set, every applied operation is logged into this file in the apply order, see [crate::decisions]
+    pub record_decisions: Option<PathBuf>,
+    /// If set, accounts start with the balances and state from this report of a previous run,
+    /// see [crate::accounts::Accounts::load_initial_state]
+    pub initial_state: Option<PathBuf>,
+    /// If set, accounts start with the exact state from this snapshot of a previous run, see [crate::state]
+    pub load_state: Option<PathBuf>,
+    /// If set, snapshot of the exact state of the accounts is written into this file at the end of the run,
+    /// see [crate::state]
+    pub save_state: Option<PathBuf>,
+    /// Records before this index were already applied and are skipped, the first record after the header has index 0
+    pub skip_until: Option<u64>,
+    /// What `tren merge` does with client locked in one report and active in another
+    pub merge_conflicts: MergeConflicts,
+    /// Columns describing state of the account in the report, `--report-version 2` replaces the baseline
+    /// `locked` column with single `status` column, which also reports closed and flagged accounts
+    pub report_version: ReportVersion,
+    /// What `tren sort` sorts the journal by
+    pub sort_by: SortKey,
+    /// Number of records `tren sort` holds in memory, larger journals are sorted in runs merged from disk
+    pub sort_buffer: usize,
+    /// If set, account invariants are checked after every operation, broken ones are logged or panic
+    pub check_invariants: Option<InvariantMode>,
+    /// Parsing stops after this many records
+    pub limit: Option<u64>,
+    /// File with client IDs, one per line. If set, only records of these clients are processed
+    pub only_clients: Option<PathBuf>,
+    /// File with client IDs, one per line. Records of these clients are skipped
+    pub ignore_clients: Option<PathBuf>,
+    /// File of `old_client_id,new_client_id` lines, records of old IDs are processed as records of the new ones.
+    /// Client filter and sample see the new IDs
+    pub client_remap: Option<PathBuf>,
+    /// Records matching these rules are rejected, logged or flag their client, set by repeated `--rule`,
+    /// e.g. `--rule 'large:flag:amount>10000'`, see [crate::rules]
+    pub rules: Vec<Rule>,
+    /// Fraction of clients between 0 and 1 whose records are processed, the rest is skipped
+    pub sample: Option<Decimal>,
+    /// Progress of parsing is shown on stderr, unless stderr is not a terminal
+    pub progress: bool,
+    /// If set, repeated warnings of the same kind are logged once per this many seconds with a count of the suppressed ones
+    pub warning_interval: Option<u64>,
+    /// Logging is turned off unless the filter is set, for example `tren=debug`
+    pub log_filter: Option<String>,
+    /// Format of the log file, `json` writes one object per line for log shippers
+    pub log_format: LogFormat,
+    /// Log file is named after the run ID instead of rolling daily, so logs of concurrent runs are not interleaved
+    pub log_per_run: bool,
+    /// Directory of the log file, by default the working directory on Unix and on Windows the directory of the
+    /// executable if it is writable or the temp directory otherwise, see [crate::logger::default_log_dir]
+    pub log_dir: Option<PathBuf>,
+    /// Manifest written by `tren checksum`, the journal is not processed unless it matches
+    pub verify_manifest: Option<PathBuf>,
+    /// If set, manifest of the run with hashes of its inputs, counters and written files is written into this
+    /// JSON file when the run ends, see [crate::run_manifest]
+    pub run_manifest: Option<PathBuf>,
+    /// Offset file of append-only journal. Parsing continues after the record it points to and once the journal
+    /// is parsed to the end, the new end is written into it. Prior state of the accounts is recovered with [Config::recover]
+    pub since_offset: Option<PathBuf>,
+    /// Whitespace in types and amounts is ignored, without it such records are malformed. `None` trims only
+    /// if the first records of the journal have whitespace, see [crate::parser::WHITESPACE_SAMPLE]
+    pub trim_whitespace: Option<bool>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            input_format: None,
+            dead_letter: None,
+            audit: None,
+            dispute_policy: DisputePolicy::default(),
+            withdrawal_disputes: WithdrawalDisputePolicy::default(),
+            max_open_disputes: None,
+            mismatched_disputes: MismatchedDisputePolicy::default(),
+            pending_disputes: None,
+            window: TimeWindow::default(),
+            unfreeze_on_resolve: false,
+            unordered_input: false,
+            check_order: false,
+            single_pass: false,
+            offset_index: false,
+            transaction_filter: None,
+            extended_report: false,
+            totals_row: false,
+            dispute_max_age_days: None,
+            parse_errors: ParseErrorPolicy::default(),
+            amount_format: AmountFormat::default(),
+            currency: None,
+            currency_scales: Vec::new(),
+            rounding: None,
+            fraud_rules: FraudRules::default(),
+            limits: None,
+            fees: FeeSchedule::default(),
+            interest: InterestConfig::default(),
+            channel_size: 10_000,
+            channel_batch_size: 64,
+            dedup_window: None,
+            read_buffer: None,
+            cpu_affinity: CpuAffinity::default(),
+            dispute_batch_size: 64,
+            dispute_read_ahead: None,
+            dispute_cache_file: None,
+            dispute_spill_threshold: None,
+            report_interval: None,
+            report_file: None,
+            report_changes: None,
+            report_keep: 1,
+            report_link: None,
+            compress: None,
+            webhook: WebhookConfig::default(),
+            eviction_interval: None,
+            max_memory: None,
+            max_runtime: None,
+            idle_timeout: None,
+            aging_report: None,
+            aging_min_records: 0,
+            segments: None,
+            segment_totals: None,
+            report_threads: None,
+            partition_output: None,
+            outputs: Vec::new(),
+            output_errors: OutputErrors::default(),
+            provenance: false,
+            alerts: Vec::new(),
+            wal: None,
+            recover: None,
+            record_decisions: None,
+            initial_state: None,
+            load_state: None,
+            save_state: None,
+            skip_until: None,
+            merge_conflicts: MergeConflicts::default(),
+            report_version: ReportVersion::default(),
+            sort_by: SortKey::default(),
+            sort_buffer: 1_000_000,
+            check_invariants: None,
+            limit: None,
+            only_clients: None,
+            ignore_clients: None,
+            client_remap: None,
+            rules: Vec::new(),
+            sample: None,
+            progress: true,
+            warning_interval: None,
+            log_filter: None,
+            log_format: LogFormat::default(),
+            log_per_run: false,
+            log_dir: None,
+            verify_manifest: None,
+            run_manifest: None,
+            since_offset: None,
+            trim_whitespace: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config> {
+        let file = File::open(path)
+            .wrap_err_with(|| format!("failed to open config file {}", path.display()))?;
+        ron::de::from_reader(file)
+            .wrap_err_with(|| format!("failed to parse config file {}", path.display()))
+    }
+
+    /// Sets flag by its command line name (without leading `--`), flags don't take any value
+    pub fn set_flag(&mut self, flag: &str) -> Result<()> {
+        match flag {
+            "no-progress" => self.progress = false,
+            "unfreeze-on-resolve" => self.unfreeze_on_resolve = true,
+            "extended-report" => self.extended_report = true,
+            "unordered-input" => self.unordered_input = true,
+            "check-order" => self.check_order = true,
+            "single-pass" => self.single_pass = true,
+            "offset-index" => self.offset_index = true,
+            "totals-row" => self.totals_row = true,
+            "log-per-run" => self.log_per_run = true,
+            "provenance" => self.provenance = true,
+            "trim-whitespace" => self.trim_whitespace = Some(true),
+            "no-trim-whitespace" => self.trim_whitespace = Some(false),
+            _ => return Err(eyre!("unknown flag '--{flag}'")),
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if the command line option is a flag without value
+    pub fn is_flag(option: &str) -> bool {
+        matches!(
+            option,
+            "no-progress"
+                | "unfreeze-on-resolve"
+                | "extended-report"
+                | "unordered-input"
+                | "check-order"
+                | "single-pass"
+                | "offset-index"
+                | "totals-row"
+                | "log-per-run"
+                | "provenance"
+                | "trim-whitespace"
+                | "no-trim-whitespace"
+        )
+    }
+
+    /// Decimal scale of [Config::currency], `None` without a currency
+    pub fn currency_scale(&self) -> Option<u32> {
+        self.currency
+            .as_deref()
+            .map(|currency| currency::scale_of(currency, &self.currency_scales))
+    }
+
+    /// [Config::amount_format] with the scale of [Config::currency], used by the parsers of the journal
+    pub fn journal_amount_format(&self) -> AmountFormat {
+        AmountFormat {
+            scale: self.currency_scale(),
+            ..self.amount_format.clone()
+        }
+    }
+
+    /// Sets option by its command line name (without leading `--`)
+    pub fn set(&mut self, option: &str, value: String) -> Result<()> {
+        match option {
+            "input-format" => self.input_format = Some(value),
+            "dead-letter" => self.dead_letter = Some(value.into()),
+            "audit" => self.audit = Some(value.into()),
+            "limits" => self.limits = Some(value.into()),
+            "from" => self.window.from = Some(parse_timestamp(&value)?),
+            "to" => self.window.to = Some(parse_timestamp(&value)?),
+            "parse-errors" => self.parse_errors = value.parse()?,
+            "decimal-separator" => self.amount_format.decimal_separator = value.parse()?,
+            "thousands-separator" => self.amount_format.thousands_separator = Some(value.parse()?),
+            "currency-symbol" => self.amount_format.currency_symbol = Some(value),
+            "amount-notation" => self.amount_format.notation = value.parse()?,
+            "currency" => self.currency = Some(value),
+            "currency-scale" => self.currency_scales.push(value.parse()?),
+            "rounding" => self.rounding = Some(value.parse()?),
+            "dispute-policy" => self.dispute_policy = value.parse()?,
+            "withdrawal-disputes" => self.withdrawal_disputes = value.parse()?,
+            "max-open-disputes" => self.max_open_disputes = Some(value.parse()?),
+            "mismatched-disputes" => self.mismatched_disputes = value.parse()?,
+            "pending-disputes" => self.pending_disputes = Some(value.parse()?),
+            "dispute-max-age-days" => self.dispute_max_age_days = Some(value.parse()?),
+            "fraud-deposit-velocity" => self.fraud_rules.deposit_velocity = Some(value.parse()?),
+            "fraud-chargeback-ratio" => {
+                self.fraud_rules.max_chargeback_ratio = Some(value.parse()?)
+            }
+            "withdrawal-fee" => self.fees.withdrawal = Some(value.parse()?),
+            "chargeback-fee" => self.fees.chargeback = Some(value.parse()?),
+            "fee-account" => self.fees.house_account = Some(value.parse()?),
+            "interest-rate" => self.interest.rate = Some(value.parse()?),
+            "periods" => self.interest.periods = Some(value.parse()?),
+            "interest-period" => self.interest.period = Some(parse_duration(&value)?),
+            "channel-size" => self.channel_size = value.parse()?,
+            "channel-batch-size" => {
+                self.channel_batch_size =
+                    value.parse().ok().filter(|size| *size > 0).ok_or_else(|| {
+                        eyre!("invalid channel batch size '{value}', expected positive number")
+                    })?
+            }
+            "dedup-window" => self.dedup_window = Some(value.parse()?),
+            "read-buffer" => self.read_buffer = Some(parse_size(&value)?),
+            "max-memory" => self.max_memory = Some(parse_size(&value)?),
+            "max-runtime" => self.max_runtime = Some(parse_duration(&value)?),
+            "idle-timeout" => self.idle_timeout = Some(parse_duration(&value)?),
+            "cpu-affinity" => self.cpu_affinity.set(&value)?,
+            "dispute-batch-size" => self.dispute_batch_size = value.parse()?,
+            "dispute-read-ahead" => self.dispute_read_ahead = Some(value.parse()?),
+            "dispute-cache-file" => self.dispute_cache_file = Some(value.into()),
+            "dispute-spill-threshold" => self.dispute_spill_threshold = Some(value.parse()?),
+            "report-interval" => self.report_interval = Some(parse_duration(&value)?),
+            "report-file" => self.report_file = Some(value.into()),
+            "report-changes" => self.report_changes = Some(value.into()),
+            "report-keep" => self.report_keep = value.parse()?,
+            "report-link" => self.report_link = Some(value.into()),
+            "compress" => self.compress = Some(value.parse()?),
+            "webhook-url" => self.webhook.url = Some(value),
+            "webhook-template" => self.webhook.template = Some(value),
+            "webhook-chargeback-threshold" => {
+                self.webhook.chargeback_threshold = Some(value.parse()?)
+            }
+            "webhook-retries" => self.webhook.retries = value.parse()?,
+            "webhook-retry-queue" => self.webhook.retry_queue = value.parse()?,
+            "webhook-failure-file" => self.webhook.failure_file = Some(value.into()),
+            "eviction-interval" => self.eviction_interval = Some(value.parse()?),
+            "aging-report" => self.aging_report = Some(value.into()),
+            "aging-min-records" => self.aging_min_records = value.parse()?,
+            "segments" => self.segments = Some(value.into()),
+            "segment-totals" => self.segment_totals = Some(value.into()),
+            "report-threads" => self.report_threads = Some(value.parse()?),
+            "transaction-filter" => self.transaction_filter = Some(value.parse()?),
+            "partition-output" => self.partition_output = Some(value.parse()?),
+            "output" => self.outputs.push(value.parse()?),
+            "output-errors" => self.output_errors = value.parse()?,
+            "alert" => self.alerts.push(value.parse()?),
+            "wal" => self.wal = Some(value.into()),
+            "recover" => self.recover = Some(value.into()),
+            "record-decisions" => self.record_decisions = Some(value.into()),
+            "initial-state" => self.initial_state = Some(value.into()),
+            "load-state" => self.load_state = Some(value.into()),
+            "save-state" => self.save_state = Some(value.into()),
+            "skip-until" => self.skip_until = Some(value.parse()?),
+            "merge-conflicts" => self.merge_conflicts = value.parse()?,
+            "report-version" => self.report_version = value.parse()?,
+            "sort-by" => self.sort_by = value.parse()?,
+            "sort-buffer" => self.sort_buffer = value.parse()?,
+            "check-invariants" => self.check_invariants = Some(value.parse()?),
+            "limit" => self.limit = Some(value.parse()?),
+            "only-clients" => self.only_clients = Some(value.into()),
+            "ignore-clients" => self.ignore_clients = Some(value.into()),
+            "client-remap" => self.client_remap = Some(value.into()),
+            "rule" => self.rules.push(value.parse()?),
+            "sample" => self.sample = Some(value.parse()?),
+            "warning-interval" => self.warning_interval = Some(parse_duration(&value)?),
+            "log" => self.log_filter = Some(value),
+            "log-format" => self.log_format = value.parse()?,
+            "log-dir" => self.log_dir = Some(value.into()),
+            "verify-manifest" => self.verify_manifest = Some(value.into()),
+            "run-manifest" => self.run_manifest = Some(value.into()),
+            "since-offset" => self.since_offset = Some(value.into()),
+            _ => return Err(eyre!("unknown option '--{option}'")),
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses size in bytes with optional `k`, `M` or `G` binary suffix, e.g. `64k` or `4M`
+fn parse_size(s: &str) -> Result<usize> {
+    let s = s.trim();
+    let (value, multiplier) = match s.as_bytes().last() {
+        Some(b'k' | b'K') => (&s[..s.len() - 1], 1 << 10),
+        Some(b'm' | b'M') => (&s[..s.len() - 1], 1 << 20),
+        Some(b'g' | b'G') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+
+    value
+        .parse::<usize>()
+        .ok()
+        .and_then(|value| value.checked_mul(multiplier))
+        .filter(|size| *size > 0)
+        .ok_or_else(|| eyre!("invalid size '{s}', expected for example 65536, 64k or 4M"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fraud::DepositVelocity;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_parse_ron() {
+        let got: Config = ron::from_str(
+            r#"(
+                dispute_policy: reject,
+                window: (from: Some("2022-08-01"), to: Some(1661990400)),
+                fraud_rules: (
+                    deposit_velocity: Some((max_deposits: 5, within_records: 100)),
+                    max_chargeback_ratio: Some("12.5"),
+                ),
+                channel_size: 100,
+            )"#,
+        )
+        .expect("failed to parse valid config");
+
+        assert_eq!(
+            got,
+            Config {
+                dispute_policy: DisputePolicy::Reject,
+                window: TimeWindow {
+                    from: Some(1659312000),
+                    to: Some(1661990400),
+                },
+                fraud_rules: FraudRules {
+                    deposit_velocity: Some(DepositVelocity {
+                        max_deposits: 5,
+                        within_records: 100
+                    }),
+                    max_chargeback_ratio: Some(dec!(12.5)),
+                },
+                channel_size: 100,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_size() {
+        let tests = vec![
+            ("bytes", "65536", Some(65536)),
+            ("kibibytes", "64k", Some(64 << 10)),
+            ("mebibytes", "4M", Some(4 << 20)),
+            ("zero", "0", None),
+            ("not a number", "fast", None),
+        ];
+
+        for (name, input, want) in tests {
+            assert_eq!(parse_size(input).ok(), want, "failed test {name}");
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! Decimal scales of currencies, the number of digits of their minor unit. Journal in a currency set by
+//! `--currency`, e.g. `--currency JPY`, is read and reported with the scale of that currency:
+//! - amounts with more decimal places than the scale are rejected as malformed, `1.5` is not a valid yen amount
+//! - amounts in [crate::amount::AmountNotation::Cents] are counted in the minor unit of the currency
+//! - balances in the report are written with exactly that many decimal places
+//!
+//! Scales come from ISO 4217, currencies not listed in [ISO_4217_SCALES] have 2 decimal places. They can be
+//! overridden by repeated `--currency-scale <code>=<scale>`, e.g. `--currency-scale BHD=2`
+use crate::aliases::Amount;
+use crate::amount::into_decimal;
+use eyre::{eyre, Context};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Scale of currencies which is not listed in [ISO_4217_SCALES]
+pub const DEFAULT_SCALE: u32 = 2;
+
+/// ISO 4217 currencies whose minor unit has other than [DEFAULT_SCALE] digits
+pub const ISO_4217_SCALES: &[(&str, u32)] = &[
+    ("BHD", 3),
+    ("BIF", 0),
+    ("CLF", 4),
+    ("CLP", 0),
+    ("DJF", 0),
+    ("GNF", 0),
+    ("IQD", 3),
+    ("ISK", 0),
+    ("JOD", 3),
+    ("JPY", 0),
+    ("KMF", 0),
+    ("KRW", 0),
+    ("KWD", 3),
+    ("LYD", 3),
+    ("OMR", 3),
+    ("PYG", 0),
+    ("RWF", 0),
+    ("TND", 3),
+    ("UGX", 0),
+    ("UYI", 0),
+    ("UYW", 4),
+    ("VND", 0),
+    ("VUV", 0),
+    ("XAF", 0),
+    ("XOF", 0),
+    ("XPF", 0),
+];
+
+/// Most decimal places [Decimal] can hold
+const MAX_SCALE: u32 = 28;
+
+/// Scale of a currency given by `--currency-scale`, replaces the ISO 4217 one
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CurrencyScale {
+    /// Upper case currency code
+    pub currency: String,
+    pub scale: u32,
+}
+
+impl FromStr for CurrencyScale {
+    type Err = eyre::Report;
+
+    /// Parses `<code>=<scale>`, e.g. `BHD=2`, the code is case insensitive
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (currency, scale) = s
+            .split_once('=')
+            .filter(|(currency, _)| !currency.trim().is_empty())
+            .ok_or_else(|| {
+                eyre!("invalid currency scale '{s}', expected <code>=<scale>, e.g. BHD=3")
+            })?;
+        let scale = scale
+            .trim()
+            .parse()
+            .wrap_err_with(|| format!("invalid scale of currency scale '{s}'"))?;
+        if scale > MAX_SCALE {
+            return Err(eyre!(
+                "scale of currency scale '{s}' is over the maximum of {MAX_SCALE}"
+            ));
+        }
+        Ok(CurrencyScale {
+            currency: currency.trim().to_ascii_uppercase(),
+            scale,
+        })
+    }
+}
+
+/// Scale of the currency, the last override of it wins over the ISO 4217 scale
+pub fn scale_of(currency: &str, overrides: &[CurrencyScale]) -> u32 {
+    let currency = currency.to_ascii_uppercase();
+    overrides
+        .iter()
+        .rev()
+        .find(|scale| scale.currency == currency)
+        .map(|scale| scale.scale)
+        .or_else(|| {
+            ISO_4217_SCALES
+                .iter()
+                .find(|(code, _)| *code == currency)
+                .map(|(_, scale)| *scale)
+        })
+        .unwrap_or(DEFAULT_SCALE)
+}
+
+/// `true` if the amount has more significant decimal places than the scale, trailing zeros don't count
+pub fn exceeds_scale(amount: Amount, scale: u32) -> bool {
+    into_decimal(amount).normalize().scale() > scale
+}
+
+/// Amount written with exactly `scale` decimal places, extra places e.g. of interest are rounded half to even
+pub fn with_scale(amount: Amount, scale: u32) -> Decimal {
+    let mut amount = into_decimal(amount).round_dp(scale);
+    amount.rescale(scale);
+    amount
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scale_of() {
+        let overrides: Vec<CurrencyScale> = ["bhd=2", "XYZ = 1", "BHD=4"]
+            .into_iter()
+            .map(|scale| scale.parse().unwrap())
+            .collect();
+        let tests = vec![
+            ("zero decimals", "JPY", 0),
+            ("three decimals", "KWD", 3),
+            ("lower case", "jpy", 0),
+            ("not listed", "EUR", 2),
+            ("unknown code", "ABC", 2),
+            ("last override wins", "BHD", 4),
+            ("override of unknown code", "XYZ", 1),
+        ];
+
+        for (name, currency, want) in tests {
+            assert_eq!(scale_of(currency, &overrides), want, "failed test {name}");
+        }
+
+        for invalid in ["JPY", "=2", "JPY=x", "JPY=-1", "JPY=29"] {
+            assert!(
+                invalid.parse::<CurrencyScale>().is_err(),
+                "failed test {invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_with_scale() {
+        let tests = vec![
+            ("padded", amount!(12.5), 2, "12.50"),
+            ("trailing zeros", amount!(100.00), 0, "100"),
+            ("rounded to even", amount!(0.125), 2, "0.12"),
+            ("negative", amount!(-1), 3, "-1.000"),
+        ];
+
+        for (name, amount, scale, want) in tests {
+            assert_eq!(
+                with_scale(amount, scale).to_string(),
+                want,
+                "failed test {name}"
+            );
+        }
+        assert!(exceeds_scale(amount!(1.5), 0));
+        assert!(!exceeds_scale(amount!(100.00), 0));
+    }
+}
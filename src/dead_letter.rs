@@ -0,0 +1,248 @@
+//! Records rejected during processing are written into the dead-letter file given by `--dead-letter`, so they can
+//! be inspected and corrected. `tren replay-deadletter <file> --initial-state <report>` applies the rows of the file
+//! again on top of the report of the run which rejected them, so once the data issues are fixed in the file only
+//! the corrections are applied, see [replay]
+use crate::accounts::Accounts;
+use crate::aliases::*;
+use crate::channel::{Dispute, Indexed, Transaction, TransactionMessage};
+use crate::config::Config;
+use crate::parser::ParseErrorPolicy;
+use crate::pipeline;
+use crate::processor::Processor;
+use crate::provenance::Provenance;
+use crate::summary::Summary;
+use eyre::{eyre, Context, Result};
+use std::fmt::Display;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::{error, info, warn};
+
+const HEADER: [&str; 6] = ["type", "client", "tx", "amount", "error", "reason"];
+
+/// Extra column of the file with [Provenance], `<file>:<line>` of the rejected record
+const SOURCE: &str = "source";
+
+/// Writes records which were rejected during processing into separate CSV file, so they can be inspected
+/// and corrected later. Rows keep the journal's layout with extra `error` column with the kind of the rejection,
+/// e.g. `insufficient_funds`, and `reason` column describing it. With [Provenance] rows end with `source` column.
+/// Can be cloned and shared between threads, all clones write into the same file.
+#[derive(Clone)]
+pub struct DeadLetter {
+    writer: Arc<Mutex<csv::Writer<File>>>,
+    provenance: Option<Provenance>,
+}
+
+impl DeadLetter {
+    /// Creates (or truncates) the dead-letter file and writes the header
+    pub fn create(path: &Path, provenance: Option<Provenance>) -> Result<Self> {
+        let mut writer = csv::Writer::from_path(path)
+            .wrap_err_with(|| format!("failed to create dead-letter file {}", path.display()))?;
+        let source = provenance.as_ref().map(|_| SOURCE);
+        writer
+            .write_record(HEADER.iter().copied().chain(source))
+            .wrap_err("failed to write dead-letter header")?;
+        Ok(DeadLetter {
+            writer: Arc::new(Mutex::new(writer)),
+            provenance,
+        })
+    }
+
+    /// Writes rejected record, errors are handled internally the same way as in [crate::channel::Sender]
+    /// # Arguments
+    /// * index - index of the journal record, located by [Provenance] if there is one
+    /// * record_type - type of the rejected record, for example `withdrawal`
+    /// * client_id - client who sent the record
+    /// * transaction_id - id of the record, if it is known
+    /// * amount - amount of the record, if it had any
+    /// * error - kind of the rejection, for example `insufficient_funds`
+    /// * reason - why the record was rejected
+    #[allow(clippy::too_many_arguments)]
+    pub fn write(
+        &self,
+        index: u64,
+        record_type: &str,
+        client_id: ClientID,
+        transaction_id: Option<TransactionID>,
+        amount: Option<Amount>,
+        error: &str,
+        reason: &dyn Display,
+    ) {
+        let transaction_id = transaction_id.map(|t| t.to_string()).unwrap_or_default();
+        let amount = amount.map(|a| a.to_string()).unwrap_or_default();
+        let source = self
+            .provenance
+            .as_ref()
+            .map(|provenance| provenance.locate(index).to_string());
+        let mut writer = match self.writer.lock() {
+            Ok(writer) => writer,
+            Err(err) => {
+                error!(%err, "dead-letter file lock is poisoned");
+                return;
+            }
+        };
+        let fields = [
+            record_type,
+            &client_id.to_string(),
+            &transaction_id,
+            &amount,
+            error,
+            &reason.to_string(),
+        ];
+        let result = writer.write_record(fields.iter().copied().chain(source.as_deref()));
+
+        if let Err(err) = result.and_then(|_| writer.flush().map_err(Into::into)) {
+            error!(%err, "failed to write record into dead-letter file");
+        }
+    }
+}
+
+/// Applies the rows of the dead-letter file to accounts configured from the [Config], on top of the initial state
+/// which should be the report of the run which rejected them. Rows are applied in the order of the file, rows
+/// rejected again are written into the dead-letter file of the config, which can't be the replayed file.
+/// Disputes, resolves and chargebacks are applied with the amount in the row, transfers, fees and reversals can't be replayed
+/// because the rows don't have the receiving client, they are skipped with a warning. Malformed rows fail the replay
+/// or are skipped by [Config::parse_errors]
+pub fn replay(path: &Path, config: &Config) -> Result<(Accounts, Summary)> {
+    if config.dead_letter.as_deref() == Some(path) {
+        return Err(eyre!(
+            "dead-letter file {} can't be replayed into itself",
+            path.display()
+        ));
+    }
+    if config.initial_state.is_none() && config.load_state.is_none() {
+        warn!("no initial state, dead-letter rows are replayed on empty accounts");
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)
+        .wrap_err_with(|| format!("failed to open dead-letter file {}", path.display()))?;
+    let headers = reader.headers()?;
+    let source = headers.get(HEADER.len()).filter(|column| *column == SOURCE);
+    if headers.iter().ne(HEADER.iter().copied().chain(source)) {
+        return Err(eyre!("{} is not a dead-letter file", path.display()));
+    }
+
+    let dead_letter = config
+        .dead_letter
+        .as_deref()
+        .map(|path| DeadLetter::create(path, None))
+        .transpose()?;
+    let mut processor = Processor::new(pipeline::configured_accounts(config)?, dead_letter, None)
+        .with_invariant_checks(config.check_invariants);
+    let (mut replayed, mut skipped) = (0, 0);
+    for (index, record) in reader.records().enumerate() {
+        let line = index + 2;
+        let message = record
+            .wrap_err("failed to read row")
+            .and_then(|record| decode(&record));
+        match message {
+            Ok(Some(message)) => {
+                processor.apply(Indexed::new(index as u64, message))?;
+                replayed += 1;
+            }
+            Ok(None) => {
+                warn!(line, "row can't be replayed, skipping");
+                skipped += 1;
+            }
+            Err(err) if config.parse_errors == ParseErrorPolicy::Lenient => {
+                warn!(%err, line, "malformed row, skipping");
+                skipped += 1;
+            }
+            Err(err) => {
+                return Err(err.wrap_err(format!("malformed line {line} of {}", path.display())))
+            }
+        }
+    }
+    info!(replayed, skipped, "replayed dead-letter file");
+    Ok(processor.finish())
+}
+
+/// Operation of the row, `None` if it can't be replayed
+fn decode(record: &csv::StringRecord) -> Result<Option<TransactionMessage>> {
+    let field = |column: usize| record.get(column).unwrap_or_default();
+    let client_id = field(1).parse::<ClientID>()?;
+    let transaction_id = || Ok::<_, eyre::Report>(field(2).parse::<TransactionID>()?);
+    let amount = || Amount::from_str_exact(field(3)).map_err(|err| eyre!("{err}"));
+    let transaction =
+        || Ok::<_, eyre::Report>(Transaction::new(client_id, transaction_id()?, amount()?));
+    let dispute = || Ok::<_, eyre::Report>(Dispute::new(client_id, transaction_id()?, amount()?));
+
+    let message = match field(0) {
+        // disputes of transactions of another client are written without amount
+        "dispute" | "resolve" | "chargeback" if field(3).is_empty() => return Ok(None),
+        "deposit" => TransactionMessage::Deposit(transaction()?),
+        "withdrawal" => TransactionMessage::Withdrawal(transaction()?),
+        "dispute" => TransactionMessage::Dispute(dispute()?),
+        "resolve" => TransactionMessage::Resolve(dispute()?),
+        "chargeback" => TransactionMessage::Chargeback(dispute()?),
+        "adjustment_credit" => TransactionMessage::AdjustmentCredit(transaction()?),
+        "adjustment_debit" => TransactionMessage::AdjustmentDebit(transaction()?),
+        "lock" => TransactionMessage::Lock(client_id),
+        "unlock" => TransactionMessage::Unlock(client_id),
+        "close" => TransactionMessage::Close(client_id),
+        // kind of the reversed transaction is not written
+        "transfer" | "fee" | "reversal" => return Ok(None),
+        record_type => return Err(eyre!("unknown record type '{record_type}'")),
+    };
+    Ok(Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay() {
+        let path = |name: &str| {
+            std::env::temp_dir().join(format!(
+                "tren-test-dead-letter-{}-{name}.csv",
+                std::process::id()
+            ))
+        };
+        let (rejected, state, rejected_again) = (path("rejected"), path("state"), path("again"));
+        std::fs::write(
+            &state,
+            "client,available,held,total,locked\n1,5,0,5,false\n",
+        )
+        .unwrap();
+        // the first withdrawal was corrected, the second one is still too high
+        std::fs::write(
+            &rejected,
+            "type,client,tx,amount,error,reason\n\
+             withdrawal,1,3,4,insufficient_funds,\"insufficient funds, requested 8 but only 5 is available\"\n\
+             withdrawal,1,4,100,insufficient_funds,\"insufficient funds, requested 100 but only 5 is available\"\n\
+             transfer,1,5,1,account_frozen,account of client 2 is frozen\n",
+        )
+        .unwrap();
+        let config = Config {
+            initial_state: Some(state.clone()),
+            dead_letter: Some(rejected_again.clone()),
+            ..Default::default()
+        };
+
+        let replayed = replay(&rejected, &config);
+        let again = std::fs::read_to_string(&rejected_again);
+        let into_itself = replay(
+            &rejected,
+            &Config {
+                dead_letter: Some(rejected.clone()),
+                ..Default::default()
+            },
+        );
+        let not_dead_letter = replay(&state, &Config::default());
+        for path in [&rejected, &state, &rejected_again] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let (accounts, _) = replayed.unwrap();
+        assert_eq!(accounts.get(1).unwrap().available.to_string(), "1");
+        assert_eq!(
+            again.unwrap(),
+            "type,client,tx,amount,error,reason\n\
+             withdrawal,1,4,100,insufficient_funds,\"insufficient funds, requested 100 but only 1 is available\"\n"
+        );
+        assert!(into_itself.is_err(), "replayed into itself");
+        assert!(not_dead_letter.is_err(), "report is not dead-letter file");
+    }
+}
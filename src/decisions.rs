@@ -0,0 +1,234 @@
+//! Log of the decisions of the processing thread, every operation in the order it was applied. Runs over the same
+//! journal can apply operations in different order, e.g. disputes resolved by the look-up thread, so comparing their
+//! logs shows the first operation where they diverged. `tren replay-decisions <log>` applies the logged operations
+//! again in the same order, the log can be cut to bisect which operation causes a discrepancy
+use crate::accounts::Accounts;
+use crate::aliases::*;
+use crate::channel::{
+    Dispute, Indexed, Transaction, TransactionKind, TransactionMessage, Transfer,
+};
+use crate::config::Config;
+use crate::pipeline;
+use crate::processor::Processor;
+use crate::summary::Summary;
+use eyre::{eyre, Context, Result};
+use std::fs::File;
+use std::path::Path;
+use tracing::{error, info};
+
+const HEADER: [&str; 7] = [
+    "sequence",
+    "index",
+    "operation",
+    "client",
+    "tx",
+    "amount",
+    "to_client",
+];
+
+/// Writes every applied operation as CSV with its sequence number in the apply order and index of the journal record
+pub struct DecisionLog {
+    writer: csv::Writer<File>,
+    sequence: u64,
+}
+
+impl DecisionLog {
+    /// Creates (or truncates) the log and writes the header
+    pub fn create(path: &Path) -> Result<DecisionLog> {
+        let mut writer = csv::Writer::from_path(path)
+            .wrap_err_with(|| format!("failed to create decision log {}", path.display()))?;
+        writer
+            .write_record(HEADER)
+            .wrap_err("failed to write decision log header")?;
+        Ok(DecisionLog {
+            writer,
+            sequence: 0,
+        })
+    }
+
+    /// Records the operation about to be applied, errors are handled internally the same way as in [crate::audit::AuditLog]
+    pub fn record(&mut self, indexed: &Indexed<TransactionMessage>) {
+        let (client_id, transaction_id, amount, to_client_id) = match &indexed.message {
+            TransactionMessage::Deposit(t)
+            | TransactionMessage::Withdrawal(t)
+            | TransactionMessage::AdjustmentCredit(t)
+            | TransactionMessage::AdjustmentDebit(t) => {
+                (t.client_id, Some(t.transaction_id), Some(t.amount), None)
+            }
+            TransactionMessage::Dispute(d)
+            | TransactionMessage::Resolve(d)
+            | TransactionMessage::Chargeback(d)
+            | TransactionMessage::Reversal(d) => {
+                (d.client_id, Some(d.transaction_id), Some(d.amount), None)
+            }
+            TransactionMessage::Transfer(t) => (
+                t.from_client_id,
+                Some(t.transaction_id),
+                Some(t.amount),
+                Some(t.to_client_id),
+            ),
+            TransactionMessage::Lock(client_id)
+            | TransactionMessage::Unlock(client_id)
+            | TransactionMessage::Close(client_id) => (*client_id, None, None, None),
+        };
+        let operation = match &indexed.message {
+            TransactionMessage::Dispute(d) if d.kind == TransactionKind::Withdrawal => {
+                "withdrawal_dispute"
+            }
+            TransactionMessage::Reversal(d) if d.kind == TransactionKind::Withdrawal => {
+                "withdrawal_reversal"
+            }
+            message => message.name(),
+        };
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        if let Err(err) = self.writer.write_record([
+            &self.sequence.to_string(),
+            &indexed.index.to_string(),
+            operation,
+            &client_id.to_string(),
+            &optional(transaction_id.map(|tx| tx.to_string())),
+            &optional(amount.map(|amount| amount.to_string())),
+            &optional(to_client_id.map(|client_id| client_id.to_string())),
+        ]) {
+            error!(%err, "failed to write record into decision log");
+        }
+        self.sequence += 1;
+    }
+}
+
+impl Drop for DecisionLog {
+    fn drop(&mut self) {
+        if let Err(err) = self.writer.flush() {
+            error!(%err, "failed to flush decision log");
+        }
+    }
+}
+
+/// Calls `f` with every operation of the log in the order of the lines, sequence numbers are only informative
+/// so lines can be removed or moved around. Stops at the first error of `f`. Returns number of replayed operations
+pub fn read(
+    path: &Path,
+    mut f: impl FnMut(Indexed<TransactionMessage>) -> Result<()>,
+) -> Result<u64> {
+    let mut reader = csv::ReaderBuilder::new()
+        .from_path(path)
+        .wrap_err_with(|| format!("failed to open decision log {}", path.display()))?;
+    if reader.headers()?.iter().ne(HEADER) {
+        return Err(eyre!("{} is not a decision log", path.display()));
+    }
+
+    let mut count = 0;
+    for (line, record) in reader.records().enumerate() {
+        let record = record.wrap_err_with(|| format!("failed to read {}", path.display()))?;
+        f(decode(&record)
+            .wrap_err_with(|| format!("malformed line {} of {}", line + 2, path.display()))?)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn decode(record: &csv::StringRecord) -> Result<Indexed<TransactionMessage>> {
+    let field = |column: usize| record.get(column).unwrap_or_default();
+    let index = field(1).parse::<u64>()?;
+    let client_id = field(3).parse::<ClientID>()?;
+    let transaction_id = || Ok::<_, eyre::Report>(field(4).parse::<TransactionID>()?);
+    let amount = || Amount::from_str_exact(field(5)).map_err(|err| eyre!("{err}"));
+    let transaction =
+        || Ok::<_, eyre::Report>(Transaction::new(client_id, transaction_id()?, amount()?));
+    let dispute = || Ok::<_, eyre::Report>(Dispute::new(client_id, transaction_id()?, amount()?));
+
+    let message = match field(2) {
+        "deposit" => TransactionMessage::Deposit(transaction()?),
+        "withdrawal" => TransactionMessage::Withdrawal(transaction()?),
+        "dispute" => TransactionMessage::Dispute(dispute()?),
+        "withdrawal_dispute" => {
+            TransactionMessage::Dispute(dispute()?.with_kind(TransactionKind::Withdrawal))
+        }
+        "resolve" => TransactionMessage::Resolve(dispute()?),
+        "chargeback" => TransactionMessage::Chargeback(dispute()?),
+        "reversal" => TransactionMessage::Reversal(dispute()?),
+        "withdrawal_reversal" => {
+            TransactionMessage::Reversal(dispute()?.with_kind(TransactionKind::Withdrawal))
+        }
+        "transfer" => TransactionMessage::Transfer(Transfer {
+            from_client_id: client_id,
+            to_client_id: field(6).parse()?,
+            transaction_id: transaction_id()?,
+            amount: amount()?,
+        }),
+        "adjustment_credit" => TransactionMessage::AdjustmentCredit(transaction()?),
+        "adjustment_debit" => TransactionMessage::AdjustmentDebit(transaction()?),
+        "lock" => TransactionMessage::Lock(client_id),
+        "unlock" => TransactionMessage::Unlock(client_id),
+        "close" => TransactionMessage::Close(client_id),
+        operation => return Err(eyre!("unknown operation '{operation}'")),
+    };
+    Ok(Indexed::new(index, message))
+}
+
+/// Applies the logged operations to accounts configured from the [Config], with the initial state if it is set.
+/// Nothing is looked up, disputes are applied as they were logged. Write-ahead log recovery is not repeated,
+/// operations recovered by the logged run are not in the log
+pub fn replay(path: &Path, config: &Config) -> Result<(Accounts, Summary)> {
+    let mut processor = Processor::new(pipeline::configured_accounts(config)?, None, None)
+        .with_invariant_checks(config.check_invariants);
+    let replayed = read(path, |indexed| processor.apply(indexed))?;
+    info!(replayed, "replayed decision log");
+    Ok(processor.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("tren-test-decisions-{}.csv", std::process::id()));
+        let amount = Amount::from_str_exact("1.2500").unwrap();
+        let messages = vec![
+            Indexed::new(
+                0,
+                TransactionMessage::Deposit(Transaction::new(1, 1, amount)),
+            ),
+            Indexed::new(
+                1,
+                TransactionMessage::Transfer(Transfer {
+                    from_client_id: 1,
+                    to_client_id: 2,
+                    transaction_id: 2,
+                    amount,
+                }),
+            ),
+            Indexed::new(3, TransactionMessage::Dispute(Dispute::new(2, 7, amount))),
+            Indexed::new(
+                4,
+                TransactionMessage::Dispute(
+                    Dispute::new(1, 3, amount).with_kind(TransactionKind::Withdrawal),
+                ),
+            ),
+            Indexed::new(2, TransactionMessage::Lock(2)),
+            Indexed::new(
+                5,
+                TransactionMessage::reversal(1, 4, amount, TransactionKind::Withdrawal),
+            ),
+        ];
+        let mut log = DecisionLog::create(&path).unwrap();
+        for message in messages.iter() {
+            log.record(message);
+        }
+        drop(log);
+
+        let mut replayed = Vec::new();
+        assert_eq!(
+            read(&path, |message| {
+                replayed.push(message);
+                Ok(())
+            })
+            .unwrap(),
+            6
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(replayed, messages);
+    }
+}
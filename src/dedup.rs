@@ -0,0 +1,118 @@
+use crate::aliases::*;
+use crate::channel::{Transaction, TransactionMessage, Transfer};
+use std::collections::{HashSet, VecDeque};
+
+/// Recently applied transactions, so the same deposit, withdrawal, transfer or adjustment delivered twice by
+/// an at-least-once source is applied only once. Transactions are recognised by client and transaction ID
+/// while they are among the last `capacity` ones, the oldest are forgotten so memory stays bounded
+pub struct DedupWindow {
+    capacity: usize,
+    seen: HashSet<(ClientID, TransactionID)>,
+    /// Seen transactions from the oldest, used to forget them in the same order
+    order: VecDeque<(ClientID, TransactionID)>,
+}
+
+impl DedupWindow {
+    pub fn new(capacity: usize) -> Self {
+        DedupWindow {
+            capacity,
+            seen: HashSet::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Remembers the transaction, returns `false` if it is already in the window. Operations without
+    /// transaction ID of their own, e.g. disputes or locks, are never duplicates
+    pub fn insert(&mut self, message: &TransactionMessage) -> bool {
+        let key = match message {
+            TransactionMessage::Deposit(Transaction {
+                client_id,
+                transaction_id,
+                ..
+            })
+            | TransactionMessage::Withdrawal(Transaction {
+                client_id,
+                transaction_id,
+                ..
+            })
+            | TransactionMessage::AdjustmentCredit(Transaction {
+                client_id,
+                transaction_id,
+                ..
+            })
+            | TransactionMessage::AdjustmentDebit(Transaction {
+                client_id,
+                transaction_id,
+                ..
+            })
+            | TransactionMessage::Transfer(Transfer {
+                from_client_id: client_id,
+                transaction_id,
+                ..
+            }) => (*client_id, *transaction_id),
+            _ => return true,
+        };
+        if self.capacity == 0 {
+            return true;
+        }
+        if !self.seen.insert(key) {
+            return false;
+        }
+
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.order.push_back(key);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert() {
+        let mut window = DedupWindow::new(2);
+        let tests = vec![
+            ("new", TransactionMessage::deposit(1, 1, amount!(1)), true),
+            (
+                "redelivered",
+                TransactionMessage::deposit(1, 1, amount!(1)),
+                false,
+            ),
+            (
+                "other client",
+                TransactionMessage::deposit(2, 1, amount!(1)),
+                true,
+            ),
+            ("lock", TransactionMessage::Lock(1), true),
+            (
+                "evicts oldest",
+                TransactionMessage::withdrawal(1, 2, amount!(1)),
+                true,
+            ),
+            (
+                "forgotten",
+                TransactionMessage::deposit(1, 1, amount!(1)),
+                true,
+            ),
+            (
+                "transfer",
+                TransactionMessage::transfer(1, 2, 3, amount!(1)),
+                true,
+            ),
+            (
+                "transfer redelivered",
+                TransactionMessage::transfer(1, 2, 3, amount!(1)),
+                false,
+            ),
+        ];
+
+        for (name, message, want) in tests {
+            assert_eq!(window.insert(&message), want, "failed test {name}");
+        }
+    }
+}
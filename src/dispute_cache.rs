@@ -0,0 +1,139 @@
+//! Warm-start file of the dispute look-up cache given by `--dispute-cache-file`. At the end of a run the cached
+//! transactions of disputes which are still open are written into it, the next run loads them into its cache, so
+//! resolves and chargebacks of disputes from previous runs don't scan the journal for their transactions again.
+//! It is a CSV file `tx,client,amount,timestamp,kind`, the timestamp is empty for journals without timestamps
+use crate::aliases::*;
+use crate::channel::TransactionKind;
+use eyre::{eyre, Context, Result};
+use std::path::Path;
+
+/// Disputed transaction in the cache with its owner
+pub type CachedTransaction = (
+    TransactionID,
+    ClientID,
+    Amount,
+    Option<Timestamp>,
+    TransactionKind,
+);
+
+const HEADER: [&str; 5] = ["tx", "client", "amount", "timestamp", "kind"];
+
+/// Reads the cached transactions, none if the file doesn't exist yet
+pub fn load(path: &Path) -> Result<Vec<CachedTransaction>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)
+        .wrap_err_with(|| format!("failed to open dispute cache {}", path.display()))?;
+
+    let mut cached = Vec::new();
+    for (index, record) in reader.records().enumerate() {
+        let record = record.wrap_err_with(|| format!("failed to read {}", path.display()))?;
+        let context = || format!("malformed line {} of {}", index + 2, path.display());
+        cached.push(parse(&record).wrap_err_with(context)?);
+    }
+    Ok(cached)
+}
+
+fn parse(record: &csv::StringRecord) -> Result<CachedTransaction> {
+    let field = |column: usize| {
+        record
+            .get(column)
+            .ok_or_else(|| eyre!("missing {} column", HEADER[column]))
+    };
+    let timestamp = match field(3)? {
+        "" => None,
+        timestamp => Some(timestamp.parse()?),
+    };
+    let kind = match field(4)? {
+        "deposit" => TransactionKind::Deposit,
+        "withdrawal" => TransactionKind::Withdrawal,
+        kind => {
+            return Err(eyre!(
+                "invalid kind '{kind}', expected deposit or withdrawal"
+            ))
+        }
+    };
+    Ok((
+        field(0)?.parse()?,
+        field(1)?.parse()?,
+        field(2)?.parse()?,
+        timestamp,
+        kind,
+    ))
+}
+
+/// Replaces the file with the cached transactions, it is written next to it first so it is never left
+/// half-written
+pub fn save(path: &Path, cached: impl IntoIterator<Item = CachedTransaction>) -> Result<()> {
+    let temporary = path.with_extension("tmp");
+    let write = || -> Result<()> {
+        let mut writer = csv::Writer::from_path(&temporary)?;
+        writer.write_record(HEADER)?;
+        for (transaction_id, client_id, amount, timestamp, kind) in cached {
+            let kind = match kind {
+                TransactionKind::Deposit => "deposit",
+                TransactionKind::Withdrawal => "withdrawal",
+            };
+            writer.write_record([
+                transaction_id.to_string(),
+                client_id.to_string(),
+                amount.to_string(),
+                timestamp
+                    .map(|timestamp| timestamp.to_string())
+                    .unwrap_or_default(),
+                kind.to_string(),
+            ])?;
+        }
+        writer.flush()?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    };
+    write().wrap_err_with(|| format!("failed to write dispute cache {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir().join(format!(
+            "tren-test-dispute-cache-{}.csv",
+            std::process::id()
+        ));
+        assert_eq!(load(&path).unwrap(), vec![], "missing file");
+
+        let cached = vec![
+            (1, 2, amount!(1.5), None, TransactionKind::Deposit),
+            (
+                7,
+                1,
+                amount!(3),
+                Some(1_600_000_000),
+                TransactionKind::Withdrawal,
+            ),
+        ];
+        save(&path, cached.clone()).unwrap();
+        assert_eq!(load(&path).unwrap(), cached, "saved cache");
+
+        let tests = vec![
+            (
+                "invalid kind",
+                "tx,client,amount,timestamp,kind\n1,2,1.5,,transfer\n",
+            ),
+            (
+                "invalid amount",
+                "tx,client,amount,timestamp,kind\n1,2,x,,deposit\n",
+            ),
+            ("missing kind", "tx,client,amount,timestamp\n1,2,1.5,\n"),
+        ];
+        for (name, content) in tests {
+            std::fs::write(&path, content).unwrap();
+            assert!(load(&path).is_err(), "failed test {name}");
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
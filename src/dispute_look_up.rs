@@ -1,102 +1,1324 @@
-use crate::channel::Sender;
-use crate::{aliases::*, parser, DisputeLookUpMessage, TransactionMessage};
+use crate::accounts::Accounts;
+use crate::channel::{Dispute, Indexed, Sender, TransactionKind};
+use crate::dead_letter::DeadLetter;
+use crate::dispute_cache::{self, CachedTransaction};
+use crate::memory::{self, Component, MemoryBudget};
+use crate::parser::Found;
+use crate::summary::Summary;
+use crate::timestamp::TimeWindow;
+use crate::warnings::WarningAggregator;
+use crate::{aliases::*, DisputeLookUpMessage, TransactionMessage};
 use crossbeam_channel::Receiver;
 use eyre::{eyre, Result};
-use rust_decimal::Decimal;
-use std::collections::HashMap;
-use std::fs::File;
-use tracing::{debug, error, trace};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, trace, warn};
+
+/// Source of the original transactions of disputes. Implemented by the parsers re-scanning the journal,
+/// by [TransactionIndex] holding the transactions in memory and by deployments which keep them in an external
+/// store or behind a remote service
+pub trait DisputeResolver {
+    /// Looks up deposit or withdrawal of the client, returned timestamp is `None` if the journal doesn't have timestamps.
+    /// The kind tells whether the transaction is a deposit or a withdrawal
+    fn find_transaction(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+    ) -> Result<Found>;
+
+    /// Looks up multiple transactions at once, results are in the order of `requests`.
+    /// By default every transaction is looked up separately
+    fn find_transactions(&mut self, requests: &[(ClientID, TransactionID)]) -> Vec<Result<Found>> {
+        requests
+            .iter()
+            .map(|(client_id, transaction_id)| self.find_transaction(*client_id, *transaction_id))
+            .collect()
+    }
+
+    /// Called with every transaction applied before the disputes referring to it when disputes are resolved
+    /// inline, see [DisputeFinder::index]. Resolvers which don't build their own index ignore it
+    fn record(&mut self, _message: &TransactionMessage, _timestamp: Option<Timestamp>) {}
+
+    /// Client who owns deposit or withdrawal with the ID, asked only when [DisputeResolver::find_transaction]
+    /// didn't find it for the disputing client, see [MismatchedDisputePolicy]. Resolvers which can't tell
+    /// return `None`
+    fn find_owner(&mut self, _transaction_id: TransactionID) -> Result<Option<ClientID>> {
+        Ok(None)
+    }
+
+    /// Hint that the transactions are going to be looked up soon, so the resolver can start reading them.
+    /// Resolvers which don't read anything slow ignore it
+    fn read_ahead(&mut self, _transaction_ids: &[TransactionID]) {}
+
+    /// Estimated memory held by the resolver, see [crate::memory]
+    fn memory_usage(&self) -> u64 {
+        0
+    }
+
+    /// Called while the estimated memory is over the limit, returns `true` if the resolver freed its memory.
+    /// Resolvers which don't hold much ignore it
+    fn shrink_memory(&mut self) -> bool {
+        false
+    }
+}
+
+/// What to do with dispute of transaction which belongs to another client
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MismatchedDisputePolicy {
+    /// Dispute is dropped, it is only counted in the summary
+    Ignore,
+    /// Dispute is dropped and logged
+    #[default]
+    Warn,
+    /// Dispute is dropped and the account of the disputing client is frozen for manual review
+    Freeze,
+    /// Dispute is dropped and written into the dead-letter file
+    DeadLetter,
+}
+
+impl FromStr for MismatchedDisputePolicy {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(MismatchedDisputePolicy::Ignore),
+            "warn" => Ok(MismatchedDisputePolicy::Warn),
+            "freeze" => Ok(MismatchedDisputePolicy::Freeze),
+            "dead-letter" => Ok(MismatchedDisputePolicy::DeadLetter),
+            _ => Err(eyre!(
+                "invalid mismatched dispute policy '{s}', expected one of ignore, warn, freeze, dead-letter"
+            )),
+        }
+    }
+}
+
+/// When disputes of transactions which were not seen yet are looked up again, for streams where a dispute can
+/// arrive before its transaction. Without it such disputes are handled as disputes of unknown transactions
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PendingRetry {
+    /// Looked up again once this many more records were read
+    AfterRecords(u64),
+    /// Looked up again once the whole journal was read or the engine is finished
+    OnFlush,
+}
+
+impl FromStr for PendingRetry {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flush" => Ok(PendingRetry::OnFlush),
+            records => records
+                .parse()
+                .map(PendingRetry::AfterRecords)
+                .map_err(|_| {
+                    eyre!(
+                        "invalid pending dispute retry '{s}', expected number of records or flush"
+                    )
+                }),
+        }
+    }
+}
+
+/// Resolver boxed so it can be chosen at runtime, see [crate::engine::Engine::with_resolver]
+pub type BoxedResolver = Box<dyn DisputeResolver + Send>;
+
+impl<S: DisputeResolver + ?Sized> DisputeResolver for Box<S> {
+    fn find_transaction(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+    ) -> Result<Found> {
+        (**self).find_transaction(client_id, transaction_id)
+    }
+
+    fn find_transactions(&mut self, requests: &[(ClientID, TransactionID)]) -> Vec<Result<Found>> {
+        (**self).find_transactions(requests)
+    }
+
+    fn record(&mut self, message: &TransactionMessage, timestamp: Option<Timestamp>) {
+        (**self).record(message, timestamp)
+    }
+
+    fn find_owner(&mut self, transaction_id: TransactionID) -> Result<Option<ClientID>> {
+        (**self).find_owner(transaction_id)
+    }
+
+    fn read_ahead(&mut self, transaction_ids: &[TransactionID]) {
+        (**self).read_ahead(transaction_ids)
+    }
+
+    fn memory_usage(&self) -> u64 {
+        (**self).memory_usage()
+    }
+
+    fn shrink_memory(&mut self) -> bool {
+        (**self).shrink_memory()
+    }
+}
 
 // dispute finder should have some kind of caching mechanism to speed up search times for big files
 // we could for example cache position for every 10_000th transaction so then we could quicly move to closest postion
 // instead of starting from beginning of the file
-pub struct DisputeFinder<T> {
-    parser: parser::CsvParser<T>,
-    cache: HashMap<TransactionID, Amount>,
+pub struct DisputeFinder<S> {
+    /// Resolver the disputed transactions are looked up in
+    source: S,
+    cache: HashMap<TransactionID, (ClientID, Amount, Option<Timestamp>, TransactionKind)>,
+    /// Transactions which are currently under dispute with the client who disputed them and the disputed amount
+    /// not resolved yet, resolves and chargebacks are only applied to these
+    disputed: HashMap<TransactionID, (ClientID, Amount)>,
+    /// Charged back transactions, tracked only when their resolve can unfreeze the account
+    charged_back: HashMap<TransactionID, ClientID>,
+    unfreeze_on_resolve: bool,
+    /// Disputes of transactions outside of this window are ignored, because those transactions were not applied
+    window: TimeWindow,
+    /// Disputes filed more than this many seconds after the disputed transaction are rejected
+    max_dispute_age: Option<i64>,
+    /// Rejected late disputes are written here
+    dead_letter: Option<DeadLetter>,
+    mismatched_disputes: MismatchedDisputePolicy,
+    /// Counters of ignored look-up requests, merged into the final summary
+    summary: Summary,
+    warnings: WarningAggregator,
+    /// Up to this many queued requests are handled together, their transactions are looked up in a single pass
+    batch_size: usize,
+    /// Up to this many requests queued after the current batch are passed to [DisputeResolver::read_ahead]
+    read_ahead: usize,
+    /// Transactions looked up for the current batch, taken by [DisputeFinder::find_dispute_amount]
+    prefetched: HashMap<(ClientID, TransactionID), Result<Found>>,
+    /// If set, disputes of transactions which were not found wait in `pending` to be looked up again
+    pending_retry: Option<PendingRetry>,
+    /// Disputes waiting for their transaction, in the order they arrived
+    pending: VecDeque<Indexed<DisputeLookUpMessage>>,
+    /// Pending dispute is being looked up again, it is not parked a second time
+    retrying: bool,
+    /// If set, the cache is dropped while the estimated memory is over the limit, see [crate::memory]
+    memory: Option<MemoryBudget>,
+    /// Indexed transactions and look-up requests, the memory is checked every [memory::CHECK_INTERVAL] of them
+    operations: u64,
+    /// Cached transactions of open disputes are written here by [DisputeFinder::save_cache]
+    cache_file: Option<PathBuf>,
 }
 
-impl<T: std::io::Read> DisputeFinder<T> {
-    pub fn new(reader: T) -> DisputeFinder<T> {
+impl<S> DisputeFinder<S> {
+    pub fn new(source: S) -> DisputeFinder<S> {
         DisputeFinder {
-            parser: parser::CsvParser::new(reader),
+            source,
             cache: HashMap::new(),
+            disputed: HashMap::new(),
+            charged_back: HashMap::new(),
+            unfreeze_on_resolve: false,
+            window: TimeWindow::default(),
+            max_dispute_age: None,
+            dead_letter: None,
+            mismatched_disputes: MismatchedDisputePolicy::default(),
+            summary: Summary::default(),
+            warnings: WarningAggregator::new(None),
+            batch_size: 1,
+            read_ahead: 0,
+            prefetched: HashMap::new(),
+            pending_retry: None,
+            pending: VecDeque::new(),
+            retrying: false,
+            memory: None,
+            operations: 0,
+            cache_file: None,
+        }
+    }
+
+    /// Ignored requests of the same kind are logged at most once per interval, see [WarningAggregator]
+    pub fn with_warning_interval(mut self, interval: Option<Duration>) -> DisputeFinder<S> {
+        self.warnings = WarningAggregator::new(interval);
+        self
+    }
+
+    /// Resolver the disputed transactions are looked up in
+    pub fn source_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    pub fn with_memory_budget(mut self, memory: Option<MemoryBudget>) -> DisputeFinder<S> {
+        self.memory = memory;
+        self
+    }
+
+    /// Fills the cache with transactions cached by a previous run, see [crate::dispute_cache]. Transactions outside
+    /// of the time window are left out, has to be called after [DisputeFinder::with_window]
+    pub fn with_warm_cache(mut self, cached: Vec<CachedTransaction>) -> DisputeFinder<S> {
+        for (transaction_id, client_id, amount, timestamp, kind) in cached {
+            if self.window.contains(timestamp) {
+                self.cache
+                    .insert(transaction_id, (client_id, amount, timestamp, kind));
+            }
         }
+        self
+    }
+
+    /// [DisputeFinder::save_cache] writes the cache into the file
+    pub fn with_cache_file(mut self, cache_file: Option<PathBuf>) -> DisputeFinder<S> {
+        self.cache_file = cache_file;
+        self
+    }
+
+    /// Writes cached transactions of the disputes which are still open into the cache file, if it is set.
+    /// Called once no more requests come, failure is only logged
+    pub fn save_cache(&self) {
+        let Some(path) = self.cache_file.as_deref() else {
+            return;
+        };
+        let cached: Vec<_> = self
+            .cache
+            .iter()
+            .filter(|(transaction_id, (client_id, ..))| {
+                self.disputed
+                    .get(transaction_id)
+                    .map(|(disputed_by, _)| disputed_by)
+                    == Some(client_id)
+            })
+            .map(|(transaction_id, (client_id, amount, timestamp, kind))| {
+                (*transaction_id, *client_id, *amount, *timestamp, *kind)
+            })
+            .collect();
+        let count = cached.len();
+        match dispute_cache::save(path, cached) {
+            Ok(()) => info!(count, path = %path.display(), "saved dispute cache"),
+            Err(err) => error!(%err, "failed to save dispute cache"),
+        }
+    }
+
+    /// Queued requests are handled in batches of up to `batch_size`, see [DisputeResolver::find_transactions]
+    pub fn with_batch_size(mut self, batch_size: usize) -> DisputeFinder<S> {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Takes up to `read_ahead` more queued requests than the batch and lets the resolver start reading their
+    /// transactions while the batch is looked up, see [DisputeResolver::read_ahead]
+    pub fn with_read_ahead(mut self, read_ahead: Option<usize>) -> DisputeFinder<S> {
+        self.read_ahead = read_ahead.unwrap_or_default();
+        self
+    }
+
+    /// Sets the time window the journal is filtered by
+    pub fn with_window(mut self, window: TimeWindow) -> DisputeFinder<S> {
+        self.window = window;
+        self
+    }
+
+    /// Sets dispute eligibility window, disputes filed more than `days` after the disputed transaction are rejected.
+    /// Applies only to journals with timestamps
+    pub fn with_max_dispute_age(mut self, days: Option<u32>) -> DisputeFinder<S> {
+        self.max_dispute_age = days.map(|days| i64::from(days) * 24 * 60 * 60);
+        self
+    }
+
+    /// Lets resolves of charged back transactions through, so they can unfreeze the account
+    pub fn with_unfreeze_on_resolve(mut self, unfreeze_on_resolve: bool) -> DisputeFinder<S> {
+        self.unfreeze_on_resolve = unfreeze_on_resolve;
+        self
+    }
+
+    /// Continues disputes of accounts recovered from write-ahead log, so their resolves and chargebacks are applied.
+    /// Has to be called after [DisputeFinder::with_unfreeze_on_resolve]
+    pub fn with_recovered(mut self, accounts: &Accounts) -> DisputeFinder<S> {
+        for (client_id, transaction_id, held) in accounts.open_disputes() {
+            self.start_dispute(client_id, transaction_id, held);
+        }
+        for (client_id, transaction_id) in accounts.charged_back() {
+            self.record_chargeback(client_id, transaction_id);
+        }
+        self
+    }
+
+    pub fn with_dead_letter(mut self, dead_letter: Option<DeadLetter>) -> DisputeFinder<S> {
+        self.dead_letter = dead_letter;
+        self
+    }
+
+    /// Sets what happens with disputes of transactions which belong to another client
+    pub fn with_mismatched_disputes(
+        mut self,
+        mismatched_disputes: MismatchedDisputePolicy,
+    ) -> DisputeFinder<S> {
+        self.mismatched_disputes = mismatched_disputes;
+        self
+    }
+
+    /// Disputes of transactions which were not found wait to be looked up again instead of being dropped.
+    /// Retried disputes are applied after the records which arrived in between, write-ahead log and checkpoints
+    /// don't know about the disputes still waiting
+    pub fn with_pending_disputes(
+        mut self,
+        pending_retry: Option<PendingRetry>,
+    ) -> DisputeFinder<S> {
+        self.pending_retry = pending_retry;
+        self
+    }
+
+    /// Checks whether dispute was filed after the eligibility window. If any of the timestamps is missing
+    /// the dispute can't be late
+    fn is_late(
+        &self,
+        transaction_timestamp: Option<Timestamp>,
+        dispute_timestamp: Option<Timestamp>,
+    ) -> bool {
+        match (
+            self.max_dispute_age,
+            transaction_timestamp,
+            dispute_timestamp,
+        ) {
+            (Some(max_age), Some(transaction), Some(dispute)) => dispute - transaction > max_age,
+            _ => false,
+        }
+    }
+
+    /// Marks `amount` of the transaction as disputed, returns `false` if it already is under dispute
+    fn start_dispute(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+        amount: Amount,
+    ) -> bool {
+        match self.disputed.contains_key(&transaction_id) {
+            true => false,
+            false => {
+                self.disputed.insert(transaction_id, (client_id, amount));
+                true
+            }
+        }
+    }
+
+    /// Remembers charged back transaction if its resolve can unfreeze the account
+    fn record_chargeback(&mut self, client_id: ClientID, transaction_id: TransactionID) {
+        if self.unfreeze_on_resolve {
+            self.charged_back.insert(transaction_id, client_id);
+        }
+    }
+
+    /// Forgets charged back transaction, returns `false` if the client has no such transaction
+    fn end_chargeback(&mut self, client_id: ClientID, transaction_id: TransactionID) -> bool {
+        match self.charged_back.get(&transaction_id) {
+            Some(charged_back_by) if *charged_back_by == client_id => {
+                self.charged_back.remove(&transaction_id);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the client has active dispute of the transaction
+    fn is_disputed(&self, client_id: ClientID, transaction_id: TransactionID) -> bool {
+        self.disputed
+            .get(&transaction_id)
+            .is_some_and(|(disputed_by, _)| *disputed_by == client_id)
+    }
+
+    /// Ends the dispute of the transaction, returns the disputed amount not resolved yet or `None` if the client
+    /// has no active dispute for it
+    fn end_dispute(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+    ) -> Option<Amount> {
+        match self.is_disputed(client_id, transaction_id) {
+            true => self.disputed.remove(&transaction_id).map(|(_, open)| open),
+            false => None,
+        }
+    }
+
+    /// Counters of ignored requests collected so far, they are reset
+    pub fn take_summary(&mut self) -> Summary {
+        std::mem::take(&mut self.summary)
     }
 }
 
-impl DisputeFinder<File> {
+impl<S: DisputeResolver> DisputeFinder<S> {
+    /// Remembers deposit or withdrawal, so later disputes can refer to it, see [DisputeResolver::record]
+    pub fn index(&mut self, message: &TransactionMessage, timestamp: Option<Timestamp>) {
+        self.source.record(message, timestamp);
+        self.count_operation();
+    }
+
+    fn count_operation(&mut self) {
+        if self.memory.is_some() && self.operations.is_multiple_of(memory::CHECK_INTERVAL) {
+            self.check_memory();
+        }
+        self.operations += 1;
+    }
+
+    /// Drops the cached transactions and lets the resolver free its memory if the estimate is over the budget.
+    /// Dropped transactions are looked up again once they are resolved or charged back
+    fn check_memory(&mut self) {
+        let Some(memory) = self.memory.as_ref() else {
+            return;
+        };
+        memory.record(Component::Disputes, self.estimated_memory());
+        if !memory.is_over() {
+            return;
+        }
+
+        let dropped = self.cache.len();
+        self.cache = HashMap::new();
+        let scanning = self.source.shrink_memory();
+        memory.record(Component::Disputes, self.estimated_memory());
+        if (dropped > 0 || scanning) && self.warnings.should_log("memory_disputes") {
+            warn!(
+                dropped,
+                scanning,
+                usage = memory.usage(),
+                limit = memory.limit(),
+                "estimated memory is over the limit, dropped cached disputed transactions"
+            );
+        }
+    }
+
+    fn estimated_memory(&self) -> u64 {
+        memory::map_bytes::<TransactionID, (ClientID, Amount, Option<Timestamp>, TransactionKind)>(
+            self.cache.len(),
+        )
+        .saturating_add(memory::map_bytes::<TransactionID, (ClientID, Amount)>(
+            self.disputed.len(),
+        ))
+        .saturating_add(memory::map_bytes::<TransactionID, ClientID>(
+            self.charged_back.len(),
+        ))
+        .saturating_add(self.source.memory_usage())
+    }
+
     #[tracing::instrument(skip(self))]
     pub fn find_dispute_amount(
         &mut self,
         client_id: ClientID,
         transaction_id: TransactionID,
-    ) -> Result<Decimal> {
-        if let Some(amount) = self.cache.get(&transaction_id) {
+    ) -> Result<(Amount, Option<Timestamp>, TransactionKind)> {
+        self.find_disputed(client_id, transaction_id)?
+            .ok_or_else(|| {
+                eyre!("disputed transaction is outside of the time window, it was not applied")
+            })
+    }
+
+    /// Same as [DisputeFinder::find_dispute_amount], only the transaction outside of the time window is `None`
+    /// and errors are left for transactions which were not found
+    fn find_disputed(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+    ) -> Result<Option<(Amount, Option<Timestamp>, TransactionKind)>> {
+        if let Some((_, amount, timestamp, kind)) = self
+            .cache
+            .get(&transaction_id)
+            .filter(|(owner, ..)| *owner == client_id)
+        {
             debug!(%amount, "found disputed transaction in cache");
-            return Ok(amount.clone());
+            return Ok(Some((*amount, *timestamp, *kind)));
         }
 
         debug!("dispute transaction not found in cache, will search in file");
-        let amount = self
-            .parser
-            .find_transaction(client_id, transaction_id)
-            .map(|r| r.2)?;
+        let found = match self.prefetched.remove(&(client_id, transaction_id)) {
+            Some(found) => found,
+            None => self.source.find_transaction(client_id, transaction_id),
+        };
+        let (_, _, amount, timestamp, kind) = found?;
+        if !self.window.contains(timestamp) {
+            self.summary.disputes_outside_window += 1;
+            return Ok(None);
+        }
 
         trace!("disputed transaction found");
-        self.cache.insert(transaction_id, amount.clone());
-        Ok(amount)
+        self.cache
+            .insert(transaction_id, (client_id, amount, timestamp, kind));
+        Ok(Some((amount, timestamp, kind)))
     }
 
-    pub fn remove_from_cache(&mut self, transaction_id: TransactionID) -> Result<Amount> {
+    /// Amount and kind of the reversed transaction, looked up the same way as disputed ones. It is not cached,
+    /// every transaction is reversed at most once
+    fn find_reversed(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+    ) -> Result<(Amount, TransactionKind)> {
+        if let Some((_, amount, _, kind)) = self
+            .cache
+            .get(&transaction_id)
+            .filter(|(owner, ..)| *owner == client_id)
+        {
+            return Ok((*amount, *kind));
+        }
+
+        let found = match self.prefetched.remove(&(client_id, transaction_id)) {
+            Some(found) => found,
+            None => self.source.find_transaction(client_id, transaction_id),
+        };
+        let (_, _, amount, timestamp, kind) = found?;
+        match self.window.contains(timestamp) {
+            true => Ok((amount, kind)),
+            false => Err(eyre!(
+                "reversed transaction is outside of the time window, it was not applied"
+            )),
+        }
+    }
+
+    pub fn remove_from_cache(
+        &mut self,
+        transaction_id: TransactionID,
+    ) -> Result<(Amount, Option<Timestamp>)> {
         self.cache
             .remove(&transaction_id)
+            .map(|(_, amount, timestamp, _)| (amount, timestamp))
             .ok_or(eyre!("value not found in cache, failed to remove"))
     }
 
+    /// Processes look-up requests until all senders are dropped. Returns [Summary] with counts of ignored requests
     #[tracing::instrument(skip(self, sender, receiver))]
     pub fn run_dispute_look_up_loop(
         mut self,
-        sender: Sender<TransactionMessage>,
-        receiver: Receiver<DisputeLookUpMessage>,
+        sender: Sender<Indexed<TransactionMessage>>,
+        receiver: Receiver<Indexed<DisputeLookUpMessage>>,
+    ) -> Summary {
+        // requests taken from the receiver ahead of their batch, passed to the resolver to read ahead
+        let mut queued = VecDeque::new();
+        let batch_size = self.batch_size.max(1);
+        loop {
+            let waiting = Instant::now();
+            if queued.is_empty() {
+                let Ok(first) = receiver.recv() else {
+                    break;
+                };
+                queued.push_back(first);
+            }
+            let start = Instant::now();
+            self.summary
+                .timings
+                .dispute_queue_wait
+                .record(start - waiting);
+
+            let taken = queued.len();
+            let wanted = batch_size + self.read_ahead;
+            queued.extend(receiver.try_iter().take(wanted.saturating_sub(taken)));
+            let batch: Vec<_> = queued.drain(..batch_size.min(queued.len())).collect();
+            if self.read_ahead > 0 {
+                // requests taken before were already passed
+                let ahead: Vec<_> = queued
+                    .iter()
+                    .skip(taken.saturating_sub(batch.len()))
+                    .map(|request| request.message.transaction_id())
+                    .collect();
+                if !ahead.is_empty() {
+                    self.source.read_ahead(&ahead);
+                }
+            }
+            self.prefetch(&batch);
+            for request in batch {
+                self.retry_pending(request.index, &sender);
+                self.look_up(request, &sender);
+            }
+            self.prefetched.clear();
+            self.summary.timings.dispute_look_up.record(start.elapsed());
+        }
+
+        self.flush_pending(&sender);
+        self.save_cache();
+        self.summary
+    }
+
+    fn log_not_found(&mut self, err: &eyre::Report) {
+        if self.warnings.should_log("transaction_not_found") {
+            error!(%err, "failed to find disputed transaction");
+        }
+    }
+
+    /// Partial dispute, resolve or chargeback can't be for more than the transaction, it is rejected
+    fn reject_excessive_amount(
+        &mut self,
+        index: u64,
+        operation: &'static str,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+        amount: Amount,
+        original: Amount,
     ) {
-        while let Ok(look_up_request) = receiver.recv() {
-            let span = tracing::trace_span!(
-                "look_up_request",
-                client_id = look_up_request.client_id(),
-                transaction_id = look_up_request.transaction_id()
+        if self.warnings.should_log("excessive_dispute_amount") {
+            warn!(%amount, %original, "{operation} amount exceeds the transaction, rejecting");
+        }
+        self.summary.excessive_dispute_amounts += 1;
+        if let Some(dead_letter) = self.dead_letter.as_ref() {
+            dead_letter.write(
+                index,
+                operation,
+                client_id,
+                Some(transaction_id),
+                Some(amount),
+                "dispute_amount_exceeds_transaction",
+                &format_args!("transaction amount is {original}"),
             );
+        }
+    }
 
-            let _enter = span.enter();
-            debug!(?look_up_request, "received dispute look-up request");
+    /// Disputed transaction was not found for the client, if it belongs to another one
+    /// the [MismatchedDisputePolicy] is applied
+    fn dispute_not_found(
+        &mut self,
+        index: u64,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+        err: &eyre::Report,
+        sender: &Sender<Indexed<TransactionMessage>>,
+    ) {
+        let owner = self
+            .source
+            .find_owner(transaction_id)
+            .unwrap_or_else(|err| {
+                debug!(%err, "failed to find owner of disputed transaction");
+                None
+            });
+        let Some(owner) = owner.filter(|owner| *owner != client_id) else {
+            return self.log_not_found(err);
+        };
 
-            match look_up_request {
-                DisputeLookUpMessage::Dispute(client_id, transaction_id) => {
-                    match self.find_dispute_amount(client_id, transaction_id) {
-                        Ok(amount) => {
-                            sender.send(TransactionMessage::dispute(client_id, amount));
+        self.summary.mismatched_disputes += 1;
+        match (self.mismatched_disputes, self.dead_letter.as_ref()) {
+            (MismatchedDisputePolicy::Ignore, _) => {
+                debug!(%owner, "disputed transaction belongs to another client, ignoring")
+            }
+            (MismatchedDisputePolicy::Freeze, _) => {
+                warn!(%owner, "disputed transaction belongs to another client, freezing the account");
+                sender.send(Indexed::new(index, TransactionMessage::Lock(client_id)));
+            }
+            (MismatchedDisputePolicy::DeadLetter, Some(dead_letter)) => dead_letter.write(
+                index,
+                "dispute",
+                client_id,
+                Some(transaction_id),
+                None,
+                "mismatched_dispute",
+                &format_args!("disputed transaction belongs to client {owner}"),
+            ),
+            (MismatchedDisputePolicy::Warn | MismatchedDisputePolicy::DeadLetter, _) => {
+                if self.warnings.should_log("mismatched_dispute") {
+                    warn!(%owner, "disputed transaction belongs to another client, ignoring");
+                }
+            }
+        }
+    }
+
+    /// Handles single look-up request, resolved disputes, resolves and chargebacks are sent for processing
+    pub fn look_up(
+        &mut self,
+        Indexed {
+            index,
+            message: look_up_request,
+        }: Indexed<DisputeLookUpMessage>,
+        sender: &Sender<Indexed<TransactionMessage>>,
+    ) {
+        let span = tracing::trace_span!(
+            "look_up_request",
+            index,
+            client_id = look_up_request.client_id(),
+            transaction_id = look_up_request.transaction_id()
+        );
+
+        let _enter = span.enter();
+        debug!(?look_up_request, "received dispute look-up request");
+        self.count_operation();
+
+        match look_up_request {
+            DisputeLookUpMessage::Dispute(
+                client_id,
+                transaction_id,
+                dispute_timestamp,
+                disputed,
+            ) => {
+                match self.find_disputed(client_id, transaction_id) {
+                    Ok(None) => self.log_not_found(&eyre!(
+                        "disputed transaction is outside of the time window, it was not applied"
+                    )),
+                    Ok(Some((amount, timestamp, _)))
+                        if self.is_late(timestamp, dispute_timestamp) =>
+                    {
+                        if self.warnings.should_log("late_dispute") {
+                            warn!("dispute was filed after the eligibility window, rejecting");
+                        }
+                        self.summary.late_disputes += 1;
+                        if let Some(dead_letter) = self.dead_letter.as_ref() {
+                            dead_letter.write(
+                                index,
+                                "dispute",
+                                client_id,
+                                Some(transaction_id),
+                                Some(amount),
+                                "late_dispute",
+                                &"dispute filed after the eligibility window",
+                            );
                         }
-                        Err(err) => error!(%err, "failed to find disputed transaction"),
-                    };
+                    }
+                    Ok(Some((original, ..)))
+                        if disputed.is_some_and(|disputed| disputed > original) =>
+                    {
+                        self.reject_excessive_amount(
+                            index,
+                            "dispute",
+                            client_id,
+                            transaction_id,
+                            disputed.unwrap_or(original),
+                            original,
+                        )
+                    }
+                    Ok(Some((original, ..)))
+                        if !self.start_dispute(
+                            client_id,
+                            transaction_id,
+                            disputed.unwrap_or(original),
+                        ) =>
+                    {
+                        if self.warnings.should_log("duplicate_dispute") {
+                            warn!("transaction is already under dispute, ignoring");
+                        }
+                        self.summary.duplicate_disputes += 1;
+                    }
+                    Ok(Some((original, _, kind))) => {
+                        let amount = disputed.unwrap_or(original);
+                        let dispute =
+                            Dispute::new(client_id, transaction_id, amount).with_kind(kind);
+                        sender.send(Indexed::new(index, TransactionMessage::Dispute(dispute)));
+                    }
+                    Err(err)
+                        if !self.park(Indexed::new(
+                            index,
+                            DisputeLookUpMessage::Dispute(
+                                client_id,
+                                transaction_id,
+                                dispute_timestamp,
+                                disputed,
+                            ),
+                        )) =>
+                    {
+                        self.dispute_not_found(index, client_id, transaction_id, &err, sender)
+                    }
+                    Err(_) => debug!("disputed transaction was not seen yet, looking it up later"),
+                };
+            }
+            DisputeLookUpMessage::Resolve(client_id, transaction_id, disputed)
+                if self.end_chargeback(client_id, transaction_id) =>
+            {
+                debug!("resolve of charged back transaction, account may be unfrozen");
+                match self.find_dispute_amount(client_id, transaction_id) {
+                    Ok((original, ..)) if disputed.is_some_and(|disputed| disputed > original) => {
+                        self.reject_excessive_amount(
+                            index,
+                            "resolve",
+                            client_id,
+                            transaction_id,
+                            disputed.unwrap_or(original),
+                            original,
+                        );
+                        self.record_chargeback(client_id, transaction_id);
+                    }
+                    Ok((original, ..)) => sender.send(Indexed::new(
+                        index,
+                        TransactionMessage::resolve(
+                            client_id,
+                            transaction_id,
+                            disputed.unwrap_or(original),
+                        ),
+                    )),
+                    Err(err) => self.log_not_found(&err),
                 }
-                DisputeLookUpMessage::Resolve(client_id, transaction_id) => {
-                    match self.find_dispute_amount(client_id, transaction_id) {
-                        Ok(amount) => {
-                            sender.send(TransactionMessage::resolve(client_id, amount));
-                            self.remove_from_cache(transaction_id);
+            }
+            DisputeLookUpMessage::Resolve(client_id, transaction_id, _)
+            | DisputeLookUpMessage::Chargeback(client_id, transaction_id, _)
+                if !self.is_disputed(client_id, transaction_id) =>
+            {
+                if self.warnings.should_log("not_disputed") {
+                    warn!("transaction is not under dispute, ignoring");
+                }
+                self.summary.ignored_without_dispute += 1;
+            }
+            DisputeLookUpMessage::Resolve(client_id, transaction_id, disputed) => {
+                let open = self
+                    .end_dispute(client_id, transaction_id)
+                    .unwrap_or(Amount::ZERO);
+                match self.find_dispute_amount(client_id, transaction_id) {
+                    // rejected resolve leaves the dispute open
+                    Ok((original, ..)) if disputed.is_some_and(|disputed| disputed > original) => {
+                        self.reject_excessive_amount(
+                            index,
+                            "resolve",
+                            client_id,
+                            transaction_id,
+                            disputed.unwrap_or(original),
+                            original,
+                        );
+                        self.start_dispute(client_id, transaction_id, open);
+                    }
+                    Ok((original, ..)) => {
+                        let released = disputed.unwrap_or(original);
+                        sender.send(Indexed::new(
+                            index,
+                            TransactionMessage::resolve(client_id, transaction_id, released),
+                        ));
+                        // partial resolve leaves the rest of the dispute open
+                        if released < open {
+                            self.start_dispute(
+                                client_id,
+                                transaction_id,
+                                open.saturating_sub(released),
+                            );
+                        } else if let Err(err) = self.remove_from_cache(transaction_id) {
+                            debug!(%err, "disputed transaction was not cached");
                         }
-                        Err(err) => error!(%err, "failed to find disputed transaction"),
                     }
+                    Err(err) => self.log_not_found(&err),
                 }
-                DisputeLookUpMessage::Chargeback(client_id, transaction_id) => {
-                    match self.find_dispute_amount(client_id, transaction_id) {
-                        Ok(amount) => {
-                            sender.send(TransactionMessage::chargeback(client_id, amount));
-                            self.remove_from_cache(transaction_id);
+            }
+            DisputeLookUpMessage::Chargeback(client_id, transaction_id, disputed) => {
+                let open = self
+                    .end_dispute(client_id, transaction_id)
+                    .unwrap_or(Amount::ZERO);
+                match self.find_dispute_amount(client_id, transaction_id) {
+                    Ok((original, ..)) if disputed.is_some_and(|disputed| disputed > original) => {
+                        self.reject_excessive_amount(
+                            index,
+                            "chargeback",
+                            client_id,
+                            transaction_id,
+                            disputed.unwrap_or(original),
+                            original,
+                        );
+                        self.start_dispute(client_id, transaction_id, open);
+                    }
+                    Ok((original, ..)) => {
+                        sender.send(Indexed::new(
+                            index,
+                            TransactionMessage::chargeback(
+                                client_id,
+                                transaction_id,
+                                disputed.unwrap_or(original),
+                            ),
+                        ));
+                        self.record_chargeback(client_id, transaction_id);
+                        if let Err(err) = self.remove_from_cache(transaction_id) {
+                            debug!(%err, "disputed transaction was not cached");
                         }
-                        Err(err) => error!(%err, "failed to find disputed transaction"),
                     }
+                    Err(err) => self.log_not_found(&err),
+                }
+            }
+            DisputeLookUpMessage::Reversal(client_id, transaction_id) => {
+                match self.find_reversed(client_id, transaction_id) {
+                    Ok((amount, kind)) => sender.send(Indexed::new(
+                        index,
+                        TransactionMessage::reversal(client_id, transaction_id, amount, kind),
+                    )),
+                    Err(err) => self.log_not_found(&err),
                 }
+            }
+        };
+    }
+
+    /// Keeps the dispute to be looked up again, see [PendingRetry]. Returns `false` if it can't wait, because
+    /// pending disputes are disabled or it is already being looked up again
+    fn park(&mut self, request: Indexed<DisputeLookUpMessage>) -> bool {
+        if self.pending_retry.is_none() || self.retrying {
+            return false;
+        }
+        self.pending.push_back(request);
+        true
+    }
+
+    /// Looks up again the pending disputes which waited long enough by the time record `index` is read,
+    /// see [PendingRetry::AfterRecords]
+    pub fn retry_pending(&mut self, index: u64, sender: &Sender<Indexed<TransactionMessage>>) {
+        let Some(PendingRetry::AfterRecords(records)) = self.pending_retry else {
+            return;
+        };
+        while self
+            .pending
+            .front()
+            .is_some_and(|pending| index >= pending.index.saturating_add(records))
+        {
+            let Some(pending) = self.pending.pop_front() else {
+                break;
             };
+            self.retry(pending, sender);
+        }
+    }
+
+    /// Looks up again all pending disputes, called once no more records come
+    pub fn flush_pending(&mut self, sender: &Sender<Indexed<TransactionMessage>>) {
+        while let Some(pending) = self.pending.pop_front() {
+            self.retry(pending, sender);
+        }
+    }
+
+    fn retry(
+        &mut self,
+        pending: Indexed<DisputeLookUpMessage>,
+        sender: &Sender<Indexed<TransactionMessage>>,
+    ) {
+        self.summary.retried_disputes += 1;
+        self.retrying = true;
+        self.look_up(pending, sender);
+        self.retrying = false;
+    }
+
+    /// Looks up all not yet cached transactions of the batch in a single pass over the journal
+    fn prefetch(&mut self, batch: &[Indexed<DisputeLookUpMessage>]) {
+        let mut requests: Vec<_> = batch
+            .iter()
+            .map(|request| {
+                (
+                    request.message.client_id(),
+                    request.message.transaction_id(),
+                )
+            })
+            .filter(|(client_id, transaction_id)| {
+                !matches!(self.cache.get(transaction_id), Some((owner, ..)) if owner == client_id)
+            })
+            .collect();
+        requests.sort_unstable_by_key(|(client_id, transaction_id)| (*transaction_id, *client_id));
+        requests.dedup();
+        // single transaction is looked up the usual way
+        if requests.len() < 2 {
+            return;
+        }
+
+        debug!(
+            requests = requests.len(),
+            "looking up batch of disputed transactions"
+        );
+        let found = self.source.find_transactions(&requests);
+        self.prefetched = requests.into_iter().zip(found).collect();
+    }
+}
+
+/// Deposits and withdrawals seen so far, kept in memory so disputes can be resolved by the parser itself
+/// without reading the journal again, see [DisputeFinder::index]. Holds every transaction of the journal,
+/// unless it is dropped to save memory and the transactions are looked up by its scan fallback
+#[derive(Default)]
+pub struct TransactionIndex {
+    transactions: HashMap<TransactionID, (ClientID, Amount, Option<Timestamp>, TransactionKind)>,
+    /// Looks transactions up once the index is dropped, see [TransactionIndex::with_scan_fallback]
+    fallback: Option<BoxedResolver>,
+    /// The index was dropped, transactions are looked up by `fallback`
+    scanning: bool,
+}
+
+impl TransactionIndex {
+    /// Lets the index be dropped while the estimated memory is over the limit, see [crate::memory]. Transactions
+    /// are then looked up by `fallback`, e.g. by a parser re-scanning the journal
+    pub fn with_scan_fallback(mut self, fallback: Option<BoxedResolver>) -> TransactionIndex {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Resolver transactions are looked up in once the index was dropped
+    fn scanned(&mut self) -> Option<&mut BoxedResolver> {
+        self.fallback.as_mut().filter(|_| self.scanning)
+    }
+}
+
+impl DisputeResolver for TransactionIndex {
+    fn find_transaction(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+    ) -> Result<Found> {
+        if let Some(fallback) = self.scanned() {
+            return fallback.find_transaction(client_id, transaction_id);
+        }
+        match self.transactions.get(&transaction_id) {
+            Some((found_client_id, amount, timestamp, kind)) if *found_client_id == client_id => {
+                Ok((client_id, transaction_id, *amount, *timestamp, *kind))
+            }
+            _ => Err(eyre!(
+                "transaction for requested client id and transaction id not found"
+            )),
+        }
+    }
+
+    fn find_transactions(&mut self, requests: &[(ClientID, TransactionID)]) -> Vec<Result<Found>> {
+        match self.scanned() {
+            Some(fallback) => fallback.find_transactions(requests),
+            None => requests
+                .iter()
+                .map(|(client_id, transaction_id)| {
+                    self.find_transaction(*client_id, *transaction_id)
+                })
+                .collect(),
+        }
+    }
+
+    fn find_owner(&mut self, transaction_id: TransactionID) -> Result<Option<ClientID>> {
+        if let Some(fallback) = self.scanned() {
+            return fallback.find_owner(transaction_id);
+        }
+        Ok(self
+            .transactions
+            .get(&transaction_id)
+            .map(|(client_id, ..)| *client_id))
+    }
+
+    /// Other messages than deposits and withdrawals are ignored, nothing is recorded once the index was dropped
+    fn record(&mut self, message: &TransactionMessage, timestamp: Option<Timestamp>) {
+        if self.scanning {
+            return;
+        }
+        if let Some((transaction, kind)) = message.disputable() {
+            self.transactions.insert(
+                transaction.transaction_id,
+                (transaction.client_id, transaction.amount, timestamp, kind),
+            );
         }
     }
+
+    fn memory_usage(&self) -> u64 {
+        memory::map_bytes::<TransactionID, (ClientID, Amount, Option<Timestamp>, TransactionKind)>(
+            self.transactions.len(),
+        )
+    }
+
+    /// Drops the index if it has the scan fallback, it is never rebuilt
+    fn shrink_memory(&mut self) -> bool {
+        if self.scanning || self.fallback.is_none() {
+            return false;
+        }
+        self.transactions = HashMap::new();
+        self.scanning = true;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dispute_state() {
+        let mut finder = DisputeFinder::new(std::io::empty());
+
+        assert_eq!(finder.end_dispute(1, 1), None, "resolve without dispute");
+        assert!(finder.start_dispute(1, 1, amount!(5)), "first dispute");
+        assert!(!finder.start_dispute(1, 1, amount!(5)), "duplicate dispute");
+        assert_eq!(finder.end_dispute(2, 1), None, "resolve by other client");
+        assert_eq!(
+            finder.end_dispute(1, 1),
+            Some(amount!(5)),
+            "resolve of disputed transaction"
+        );
+        assert_eq!(finder.end_dispute(1, 1), None, "second resolve");
+    }
+
+    #[test]
+    fn test_is_late() {
+        let finder = DisputeFinder::new(std::io::empty()).with_max_dispute_age(Some(1));
+
+        assert!(!finder.is_late(Some(0), Some(86_400)), "exactly one day");
+        assert!(finder.is_late(Some(0), Some(86_401)), "over one day");
+        assert!(!finder.is_late(None, Some(86_401)), "missing timestamp");
+        assert!(
+            !DisputeFinder::new(std::io::empty()).is_late(Some(0), Some(86_401)),
+            "no eligibility window"
+        );
+    }
+
+    #[test]
+    fn test_mismatched_dispute() {
+        let tests = vec![
+            ("ignore", MismatchedDisputePolicy::Ignore, vec![]),
+            ("warn", MismatchedDisputePolicy::Warn, vec![]),
+            (
+                "freeze",
+                MismatchedDisputePolicy::Freeze,
+                vec![Indexed::new(2, TransactionMessage::Lock(2))],
+            ),
+            ("dead letter", MismatchedDisputePolicy::DeadLetter, vec![]),
+        ];
+
+        for (name, policy, want) in tests {
+            let (sender, receiver) = crossbeam_channel::unbounded();
+            let sender = Sender::new(sender);
+            let mut finder =
+                DisputeFinder::new(TransactionIndex::default()).with_mismatched_disputes(policy);
+            finder.index(&TransactionMessage::deposit(1, 1, amount!(10)), None);
+
+            finder.look_up(
+                Indexed::new(1, DisputeLookUpMessage::Dispute(1, 1, None, None)),
+                &sender,
+            );
+            let _ = receiver.try_recv();
+            finder.look_up(
+                Indexed::new(2, DisputeLookUpMessage::Dispute(2, 1, None, None)),
+                &sender,
+            );
+            finder.look_up(
+                Indexed::new(3, DisputeLookUpMessage::Dispute(2, 9, None, None)),
+                &sender,
+            );
+
+            let got: Vec<_> = receiver.try_iter().flatten().collect();
+            assert_eq!(got, want, "failed test {name}");
+            assert_eq!(finder.summary.mismatched_disputes, 1, "failed test {name}");
+        }
+    }
+
+    #[test]
+    fn test_partial_dispute() {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let sender = Sender::new(sender);
+        let mut finder = DisputeFinder::new(TransactionIndex::default());
+        finder.index(&TransactionMessage::deposit(1, 1, amount!(10)), None);
+
+        let requests = vec![
+            DisputeLookUpMessage::Dispute(1, 1, None, Some(amount!(11))),
+            DisputeLookUpMessage::Dispute(1, 1, None, Some(amount!(4))),
+            DisputeLookUpMessage::Chargeback(1, 1, Some(amount!(12))),
+            DisputeLookUpMessage::Chargeback(1, 1, Some(amount!(3))),
+        ];
+        for (index, request) in requests.into_iter().enumerate() {
+            finder.look_up(Indexed::new(index as u64, request), &sender);
+        }
+
+        let got: Vec<_> = receiver.try_iter().flatten().collect();
+        assert_eq!(
+            got,
+            vec![
+                Indexed::new(
+                    1,
+                    TransactionMessage::Dispute(Dispute::new(1, 1, amount!(4)))
+                ),
+                Indexed::new(3, TransactionMessage::chargeback(1, 1, amount!(3))),
+            ],
+            "amounts over the transaction are rejected and the dispute stays open"
+        );
+        assert_eq!(finder.summary.excessive_dispute_amounts, 2);
+    }
+
+    #[test]
+    fn test_warm_cache() {
+        let path =
+            std::env::temp_dir().join(format!("tren-test-warm-cache-{}.csv", std::process::id()));
+        // the empty index stands for a journal without the transactions of the previous run
+        let mut finder = DisputeFinder::new(TransactionIndex::default())
+            .with_warm_cache(vec![
+                (1, 1, amount!(10), None, TransactionKind::Deposit),
+                (2, 1, amount!(5), None, TransactionKind::Deposit),
+                (3, 2, amount!(1), None, TransactionKind::Withdrawal),
+            ])
+            .with_cache_file(Some(path.clone()));
+        finder.start_dispute(1, 1, amount!(10));
+        finder.start_dispute(1, 2, amount!(5));
+
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        finder.look_up(
+            Indexed::new(1, DisputeLookUpMessage::Chargeback(1, 1, None)),
+            &Sender::new(sender),
+        );
+        let got: Vec<_> = receiver.try_iter().flatten().collect();
+        assert_eq!(
+            got,
+            vec![Indexed::new(
+                1,
+                TransactionMessage::chargeback(1, 1, amount!(10))
+            )],
+            "charged back from the warm cache"
+        );
+
+        finder.save_cache();
+        let saved = dispute_cache::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            saved,
+            vec![(2, 1, amount!(5), None, TransactionKind::Deposit)],
+            "only open disputes are saved"
+        );
+    }
+
+    /// Records read-ahead hints, transactions are looked up in the inner index
+    struct HintRecorder {
+        index: TransactionIndex,
+        hints: std::rc::Rc<std::cell::RefCell<Vec<Vec<TransactionID>>>>,
+    }
+
+    impl DisputeResolver for HintRecorder {
+        fn find_transaction(
+            &mut self,
+            client_id: ClientID,
+            transaction_id: TransactionID,
+        ) -> Result<Found> {
+            self.index.find_transaction(client_id, transaction_id)
+        }
+
+        fn record(&mut self, message: &TransactionMessage, timestamp: Option<Timestamp>) {
+            self.index.record(message, timestamp)
+        }
+
+        fn read_ahead(&mut self, transaction_ids: &[TransactionID]) {
+            self.hints.borrow_mut().push(transaction_ids.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_read_ahead() {
+        let hints = std::rc::Rc::default();
+        let mut finder = DisputeFinder::new(HintRecorder {
+            index: TransactionIndex::default(),
+            hints: std::rc::Rc::clone(&hints),
+        })
+        .with_batch_size(2)
+        .with_read_ahead(Some(2));
+        let (request_sender, request_receiver) = crossbeam_channel::unbounded();
+        for (index, transaction_id) in (1..=5).enumerate() {
+            finder.index(
+                &TransactionMessage::deposit(1, transaction_id, amount!(1)),
+                None,
+            );
+            request_sender
+                .send(Indexed::new(
+                    index as u64,
+                    DisputeLookUpMessage::Dispute(1, transaction_id, None, None),
+                ))
+                .unwrap();
+        }
+        drop(request_sender);
+
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        finder.run_dispute_look_up_loop(Sender::new(sender), request_receiver);
+        assert_eq!(
+            receiver.try_iter().flatten().count(),
+            5,
+            "all disputes resolved in order"
+        );
+        assert_eq!(*hints.borrow(), vec![vec![3, 4], vec![5]]);
+    }
+
+    #[test]
+    fn test_scan_fallback() {
+        // the fallback stands for the parser re-scanning the journal
+        let mut journal = TransactionIndex::default();
+        let mut index = TransactionIndex::default().with_scan_fallback(None);
+        for transaction_id in 1..=2 {
+            let deposit = TransactionMessage::deposit(1, transaction_id, amount!(1));
+            journal.record(&deposit, None);
+            index.record(&deposit, None);
+        }
+        assert!(!index.shrink_memory(), "index without fallback is kept");
+
+        let mut index = index.with_scan_fallback(Some(Box::new(journal)));
+        assert!(index.memory_usage() > 0);
+        assert!(index.shrink_memory(), "index with fallback is dropped");
+        assert!(!index.shrink_memory(), "index is dropped once");
+        assert_eq!(index.memory_usage(), 0);
+        index.record(&TransactionMessage::deposit(1, 3, amount!(1)), None);
+        assert_eq!(index.memory_usage(), 0, "nothing is indexed once dropped");
+        assert!(
+            index.find_transaction(1, 2).is_ok(),
+            "found by the fallback"
+        );
+        assert_eq!(index.find_owner(1).unwrap(), Some(1));
+    }
 }
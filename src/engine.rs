@@ -0,0 +1,867 @@
+//! Single-threaded engine applying records as they are submitted. It doesn't open files nor spawn threads,
+//! so it can be embedded where those are not available, e.g. a journal validator compiled to WebAssembly
+//! or the C ABI in [crate::ffi]. Disputes are resolved from the deposits and withdrawals submitted before them,
+//! the same rules as in the single-pass pipeline. Services which already have the transactions in memory can run it
+//! on its own thread through [EngineHandle]. Accounts stay owned by that thread, other threads read them through
+//! [AccountQuery] which the thread answers between records, so reads never lock the accounts nor race with updates.
+//! For high query volume the thread can publish copies of the accounts into a [Replica] instead, read without
+//! involving the thread at all. Services submitting from many threads can spread the clients over several engine
+//! threads with [ShardedEngineHandle], whose [Submitter::snapshot] copies the accounts of all engines at the same
+//! point of the submitted records
+use crate::accounts::{AccountView, Accounts};
+use crate::aliases::*;
+use crate::channel::{DisputeLookUpMessage, Indexed, Sender, TransactionMessage};
+use crate::dispute_look_up::{BoxedResolver, DisputeFinder, PendingRetry, TransactionIndex};
+use crate::parser::{parse_record_with, CsvParser, JournalEntry};
+use crate::processor::Processor;
+use crate::record_types::RecordTypes;
+use crate::replica::{AccountsSnapshot, Replica};
+use crate::report::Partition;
+use crate::summary::Summary;
+use crossbeam_channel::{select, Receiver};
+use eyre::{eyre, Result};
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+pub struct Engine {
+    processor: Processor,
+    finder: DisputeFinder<BoxedResolver>,
+    /// Disputes resolved by the finder, applied right after the look-up
+    sender: Sender<Indexed<TransactionMessage>>,
+    receiver: Receiver<Vec<Indexed<TransactionMessage>>>,
+    /// Index of the next submitted record
+    index: u64,
+    /// Handlers of custom types of records submitted by [Engine::submit_record]
+    record_types: RecordTypes,
+}
+
+impl Engine {
+    pub fn new(accounts: Accounts) -> Engine {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        Engine {
+            finder: DisputeFinder::<BoxedResolver>::new(Box::new(TransactionIndex::default()))
+                .with_recovered(&accounts),
+            processor: Processor::new(accounts, None, None),
+            sender: Sender::new(sender),
+            receiver,
+            index: 0,
+            record_types: RecordTypes::default(),
+        }
+    }
+
+    /// Replaces the in-memory [TransactionIndex] the disputed transactions are looked up in, e.g. with
+    /// an external store. The resolver is given every submitted deposit and withdrawal first, see
+    /// [crate::dispute_look_up::DisputeResolver::record]
+    pub fn with_resolver(mut self, resolver: BoxedResolver) -> Engine {
+        self.finder = DisputeFinder::new(resolver).with_recovered(self.processor.accounts());
+        self
+    }
+
+    /// Skips redelivered transactions, for engines fed by at-least-once sources, see [crate::dedup::DedupWindow]
+    pub fn with_dedup_window(mut self, capacity: Option<usize>) -> Engine {
+        self.processor = self.processor.with_dedup_window(capacity);
+        self
+    }
+
+    /// Disputes submitted before their transaction wait to be looked up again, see [PendingRetry].
+    /// Has to be called after [Engine::with_resolver]
+    pub fn with_pending_disputes(mut self, pending_retry: Option<PendingRetry>) -> Engine {
+        self.finder = self.finder.with_pending_disputes(pending_retry);
+        self
+    }
+
+    /// Records of custom types submitted by [Engine::submit_record] are parsed by their handlers
+    pub fn with_record_types(mut self, record_types: RecordTypes) -> Engine {
+        self.record_types = record_types;
+        self
+    }
+
+    /// Applies the entry, rejected operations are counted in the [Summary] returned by [Engine::finish]
+    pub fn submit(&mut self, entry: JournalEntry, timestamp: Option<Timestamp>) {
+        let index = self.index;
+        self.index += 1;
+        match entry {
+            JournalEntry::Transaction(message) => {
+                self.finder.index(&message, timestamp);
+                apply(&mut self.processor, Indexed::new(index, message));
+            }
+            JournalEntry::DisputeLookUp(request) => {
+                self.finder
+                    .look_up(Indexed::new(index, request), &self.sender);
+            }
+        }
+        self.finder.retry_pending(index, &self.sender);
+        self.apply_found();
+    }
+
+    /// Applies disputes, resolves, chargebacks and reversals the finder has found
+    fn apply_found(&mut self) {
+        for message in self.receiver.try_iter().flatten() {
+            apply(&mut self.processor, message);
+        }
+    }
+
+    /// Parses single CSV record in the default column order and applies it, see [crate::parser::parse_record]
+    pub fn submit_record(&mut self, record: &[u8]) -> Result<()> {
+        self.submit(parse_record_with(record, &self.record_types)?, None);
+        Ok(())
+    }
+
+    pub fn accounts(&self) -> &Accounts {
+        self.processor.accounts()
+    }
+
+    /// Returns final state of the accounts and counters of rejected operations and ignored disputes
+    pub fn finish(mut self) -> (Accounts, Summary) {
+        self.finder.flush_pending(&self.sender);
+        self.apply_found();
+        let finder_summary = self.finder.take_summary();
+        let (accounts, mut summary) = self.processor.finish();
+        summary.merge(finder_summary);
+        (accounts, summary)
+    }
+}
+
+/// Transaction a service has in memory, submitted through [EngineHandle::submit] without any journal
+#[derive(Debug, PartialEq, Eq)]
+pub struct TransactionRecord {
+    entry: JournalEntry,
+    timestamp: Option<Timestamp>,
+}
+
+impl TransactionRecord {
+    pub fn deposit(client_id: ClientID, transaction_id: TransactionID, amount: Amount) -> Self {
+        TransactionMessage::deposit(client_id, transaction_id, amount).into()
+    }
+
+    pub fn withdrawal(client_id: ClientID, transaction_id: TransactionID, amount: Amount) -> Self {
+        TransactionMessage::withdrawal(client_id, transaction_id, amount).into()
+    }
+
+    /// Dispute of the client's deposit or withdrawal submitted before
+    pub fn dispute(client_id: ClientID, transaction_id: TransactionID) -> Self {
+        DisputeLookUpMessage::Dispute(client_id, transaction_id, None, None).into()
+    }
+
+    pub fn resolve(client_id: ClientID, transaction_id: TransactionID) -> Self {
+        DisputeLookUpMessage::Resolve(client_id, transaction_id, None).into()
+    }
+
+    pub fn chargeback(client_id: ClientID, transaction_id: TransactionID) -> Self {
+        DisputeLookUpMessage::Chargeback(client_id, transaction_id, None).into()
+    }
+
+    /// Reversal of the client's deposit or withdrawal submitted before, see [crate::accounts::Accounts::reverse]
+    pub fn reversal(client_id: ClientID, transaction_id: TransactionID) -> Self {
+        DisputeLookUpMessage::Reversal(client_id, transaction_id).into()
+    }
+
+    /// Time of the transaction, used by dispute eligibility the same way as the timestamp column of the journal
+    pub fn with_timestamp(mut self, timestamp: Option<Timestamp>) -> Self {
+        self.timestamp = timestamp;
+        if let JournalEntry::DisputeLookUp(DisputeLookUpMessage::Dispute(
+            _,
+            _,
+            dispute_timestamp,
+            _,
+        )) = &mut self.entry
+        {
+            *dispute_timestamp = timestamp;
+        }
+        self
+    }
+
+    /// Amount of partial dispute, resolve or chargeback, used instead of the whole amount of the transaction.
+    /// Other records are left as they are
+    pub fn with_amount(mut self, amount: Amount) -> Self {
+        if let JournalEntry::DisputeLookUp(
+            DisputeLookUpMessage::Dispute(.., disputed)
+            | DisputeLookUpMessage::Resolve(.., disputed)
+            | DisputeLookUpMessage::Chargeback(.., disputed),
+        ) = &mut self.entry
+        {
+            *disputed = Some(amount);
+        }
+        self
+    }
+}
+
+/// Transfers, adjustments and other operations without their own constructor
+impl From<TransactionMessage> for TransactionRecord {
+    fn from(message: TransactionMessage) -> Self {
+        JournalEntry::Transaction(message).into()
+    }
+}
+
+impl From<DisputeLookUpMessage> for TransactionRecord {
+    fn from(request: DisputeLookUpMessage) -> Self {
+        JournalEntry::DisputeLookUp(request).into()
+    }
+}
+
+impl From<JournalEntry> for TransactionRecord {
+    fn from(entry: JournalEntry) -> Self {
+        TransactionRecord {
+            entry,
+            timestamp: None,
+        }
+    }
+}
+
+/// Final state of the accounts returned by [EngineHandle::finish]
+pub struct Report {
+    pub accounts: Accounts,
+    /// Counters of rejected operations and ignored disputes
+    pub summary: Summary,
+}
+
+/// [Engine] running on its own thread, driven through a channel by services which already have the transactions
+/// in memory. Records are applied in the order they are submitted, submitting blocks once [SUBMIT_QUEUE_SIZE]
+/// records are waiting
+pub struct EngineHandle {
+    sender: crossbeam_channel::Sender<Submission>,
+    queries: AccountQuery,
+    replica: Option<Replica>,
+    handle: JoinHandle<Report>,
+}
+
+/// Client whose account is asked for with the channel the answer is sent to
+type Query = (ClientID, crossbeam_channel::Sender<Option<AccountView>>);
+
+/// Sent to the engine thread in the order of the records
+enum Submission {
+    Record(TransactionRecord),
+    /// Copy of the accounts with all records submitted before is sent to the channel
+    Snapshot(crossbeam_channel::Sender<AccountsSnapshot>),
+}
+
+/// Reads accounts of the engine thread, see [EngineHandle::query]. Can be cloned and shared, e.g. by request
+/// handlers of a service. Queries of [ShardedEngineHandle] are sent to the engine of the client
+#[derive(Clone)]
+pub struct AccountQuery {
+    senders: Vec<crossbeam_channel::Sender<Query>>,
+}
+
+impl AccountQuery {
+    /// Current state of client's account, `None` if the client has no account. The engine thread answers before
+    /// it applies the next record, records submitted before the query may not be applied yet. Fails once the
+    /// engine thread stopped
+    pub fn get(&self, client_id: ClientID) -> Result<Option<AccountView>> {
+        let (reply, answer) = crossbeam_channel::bounded(1);
+        self.senders[shard_of(client_id, self.senders.len())]
+            .send((client_id, reply))
+            .map_err(|_| eyre!("engine thread stopped, account can't be queried"))?;
+        answer
+            .recv()
+            .map_err(|_| eyre!("engine thread stopped before answering the query"))
+    }
+}
+
+/// Records submitted to [EngineHandle] and not yet applied
+pub const SUBMIT_QUEUE_SIZE: usize = 1024;
+
+impl EngineHandle {
+    /// Starts the engine thread, the engine can be configured before, e.g. [Engine::with_resolver]
+    pub fn spawn(engine: Engine) -> Result<EngineHandle> {
+        EngineHandle::start(engine, None, 0)
+    }
+
+    /// Starts the engine thread which also publishes a copy of the accounts into [EngineHandle::replica] every
+    /// interval, unless no record was applied since the last copy. Taking the copy pauses applying of records,
+    /// with many accounts the interval shouldn't be too short
+    pub fn spawn_with_replica(engine: Engine, interval: Duration) -> Result<EngineHandle> {
+        EngineHandle::start(engine, Some(interval), 0)
+    }
+
+    fn start(engine: Engine, interval: Option<Duration>, shard: usize) -> Result<EngineHandle> {
+        let (sender, receiver) = crossbeam_channel::bounded::<Submission>(SUBMIT_QUEUE_SIZE);
+        let (query_sender, queries) = crossbeam_channel::unbounded::<Query>();
+        let replica = interval.map(|_| Replica::new(AccountsSnapshot::of(engine.accounts(), 0)));
+        let publisher = replica.clone();
+        let ticks = match interval {
+            Some(interval) => crossbeam_channel::tick(interval),
+            None => crossbeam_channel::never(),
+        };
+        let handle = std::thread::Builder::new()
+            .name(format!("tren-engine-{shard}"))
+            .spawn(move || {
+                let mut engine = engine;
+                let (mut applied, mut published) = (0u64, 0u64);
+                let answer = |engine: &Engine, (client_id, reply): Query| {
+                    // asking thread may have given up waiting
+                    let _ = reply.send(engine.accounts().get(client_id));
+                };
+                loop {
+                    // waiting queries are answered first, they don't wait behind the queued records
+                    for query in queries.try_iter() {
+                        answer(&engine, query);
+                    }
+                    select! {
+                        recv(receiver) -> submission => match submission {
+                            Ok(Submission::Record(record)) => {
+                                engine.submit(record.entry, record.timestamp);
+                                applied += 1;
+                            }
+                            Ok(Submission::Snapshot(reply)) => {
+                                let _ = reply.send(AccountsSnapshot::of(engine.accounts(), applied));
+                            }
+                            Err(_) => break,
+                        },
+                        recv(queries) -> query => {
+                            if let Ok(query) = query {
+                                answer(&engine, query);
+                            }
+                        },
+                        recv(ticks) -> _ => {
+                            if let Some(replica) = publisher.as_ref().filter(|_| applied > published) {
+                                replica.publish(AccountsSnapshot::of(engine.accounts(), applied));
+                                published = applied;
+                            }
+                        },
+                    }
+                }
+                let (accounts, summary) = engine.finish();
+                Report { accounts, summary }
+            })?;
+        Ok(EngineHandle {
+            sender,
+            queries: AccountQuery {
+                senders: vec![query_sender],
+            },
+            replica,
+            handle,
+        })
+    }
+
+    /// Shared reader of the accounts, queries are answered until the engine is finished
+    pub fn query(&self) -> AccountQuery {
+        self.queries.clone()
+    }
+
+    /// Copies of the accounts published by the thread started with [EngineHandle::spawn_with_replica]. The last
+    /// copy stays readable after the engine is finished, it may miss the records applied after it was taken
+    pub fn replica(&self) -> Option<Replica> {
+        self.replica.clone()
+    }
+
+    /// Queues the record to be applied, fails only if the engine thread stopped
+    pub fn submit(&self, record: TransactionRecord) -> Result<()> {
+        self.sender
+            .send(Submission::Record(record))
+            .map_err(|_| eyre!("engine thread stopped, record was not submitted"))
+    }
+
+    /// Copy of the accounts with all records submitted before and none submitted after, waits until the records
+    /// queued before it are applied. [AccountsSnapshot::applied] is the number of the records
+    pub fn snapshot(&self) -> Result<AccountsSnapshot> {
+        request_snapshot(&self.sender)?
+            .recv()
+            .map_err(|_| eyre!("engine thread stopped before taking the snapshot"))
+    }
+
+    /// Waits until all submitted records are applied and returns the final state of the accounts
+    pub fn finish(self) -> Result<Report> {
+        drop(self.sender);
+        self.handle
+            .join()
+            .map_err(|_| eyre!("engine thread panicked"))
+    }
+}
+
+/// Shard of the client among `shards` engines of [ShardedEngineHandle], the same hash as
+/// `--partition-output by-hash=<n>`
+pub fn shard_of(client_id: ClientID, shards: usize) -> usize {
+    Partition::ByHash(shards as u64).of(client_id) as usize
+}
+
+/// Engine threads each applying records of its own share of the clients, so services with many producer threads
+/// don't have to pass all records through a single submitting thread. Records are routed to the engine of their
+/// client by [Submitter], which every producer thread can have its own clone of. Records of a client are applied
+/// in the order they were submitted as long as they are submitted by the same thread.
+/// Disputes are looked up only among transactions of their engine, so dispute of a transaction of another client
+/// is not found instead of being counted as mismatched. Transfers between clients of different engines are
+/// rejected by [Submitter::submit]
+pub struct ShardedEngineHandle {
+    submitter: Submitter,
+    queries: AccountQuery,
+    handles: Vec<JoinHandle<Report>>,
+}
+
+impl ShardedEngineHandle {
+    /// Starts `shards` engine threads with engines created by `engine` for every shard. Accounts the engines start
+    /// with can only have clients of their shard, see [shard_of]. With fees every engine credits its own house
+    /// account, so they can't be merged by [ShardedEngineHandle::finish]
+    pub fn spawn(
+        shards: usize,
+        mut engine: impl FnMut(usize) -> Engine,
+    ) -> Result<ShardedEngineHandle> {
+        if shards == 0 {
+            return Err(eyre!("sharded engine needs at least one shard"));
+        }
+        let mut sharded = ShardedEngineHandle {
+            submitter: Submitter {
+                senders: Vec::with_capacity(shards),
+                barrier: Arc::default(),
+                submitted: Arc::default(),
+            },
+            queries: AccountQuery {
+                senders: Vec::with_capacity(shards),
+            },
+            handles: Vec::with_capacity(shards),
+        };
+        for shard in 0..shards {
+            let handle = EngineHandle::start(engine(shard), None, shard)?;
+            sharded.submitter.senders.push(handle.sender);
+            sharded.queries.senders.extend(handle.queries.senders);
+            sharded.handles.push(handle.handle);
+        }
+        Ok(sharded)
+    }
+
+    /// Submitter which can be moved to a producer thread
+    pub fn submitter(&self) -> Submitter {
+        self.submitter.clone()
+    }
+
+    /// Shared reader of the accounts of all engines, queries are answered until the engines are finished
+    pub fn query(&self) -> AccountQuery {
+        self.queries.clone()
+    }
+
+    /// Queues the record to be applied by the engine of its client, see [Submitter::submit]
+    pub fn submit(&self, record: TransactionRecord) -> Result<()> {
+        self.submitter.submit(record)
+    }
+
+    /// Consistent copy of the accounts of all engines, see [Submitter::snapshot]
+    pub fn snapshot(&self) -> Result<AccountsSnapshot> {
+        self.submitter.snapshot()
+    }
+
+    /// Waits until all submitted records are applied and returns the accounts of all engines together. Engines
+    /// finish only once all [Submitter]s are dropped
+    pub fn finish(self) -> Result<Report> {
+        drop(self.submitter);
+        let mut reports = Vec::with_capacity(self.handles.len());
+        for handle in self.handles {
+            reports.push(handle.join().map_err(|_| eyre!("engine thread panicked"))?);
+        }
+        let mut reports = reports.into_iter();
+        let mut merged = reports
+            .next()
+            .expect("sharded engine has at least one shard");
+        for Report { accounts, summary } in reports {
+            merged.accounts.absorb(accounts)?;
+            merged.summary.merge(summary);
+        }
+        Ok(merged)
+    }
+}
+
+/// Submits records to the engines of [ShardedEngineHandle], can be cloned and moved to other threads.
+/// Records are numbered from 1 in the order they are submitted by all clones together
+#[derive(Clone)]
+pub struct Submitter {
+    senders: Vec<crossbeam_channel::Sender<Submission>>,
+    /// Held for reading while a record is submitted and for writing while snapshot requests are queued,
+    /// so no record is submitted to some engines before the requests and to others after them
+    barrier: Arc<RwLock<()>>,
+    /// Number of submitted records
+    submitted: Arc<AtomicU64>,
+}
+
+impl Submitter {
+    /// Queues the record to be applied by the engine of its client, blocks while the engine has
+    /// [SUBMIT_QUEUE_SIZE] records waiting. Fails if the engine thread stopped or for transfer between clients
+    /// of different engines
+    pub fn submit(&self, record: TransactionRecord) -> Result<()> {
+        let shards = self.senders.len();
+        let shard = |client_id| shard_of(client_id, shards);
+        let target = match &record.entry {
+            JournalEntry::Transaction(message) => {
+                let target = shard(message.client_id());
+                if message
+                    .client_ids()
+                    .any(|client_id| shard(client_id) != target)
+                {
+                    return Err(eyre!(
+                        "transfer between clients of different shards can't be submitted"
+                    ));
+                }
+                target
+            }
+            JournalEntry::DisputeLookUp(request) => shard(request.client_id()),
+        };
+        let _barrier = self.barrier.read().unwrap_or_else(|err| err.into_inner());
+        self.senders[target]
+            .send(Submission::Record(record))
+            .map_err(|_| eyre!("engine thread stopped, record was not submitted"))?;
+        self.submitted.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Copy of the accounts of all engines with all records submitted before and none submitted after, a single
+    /// cut of the submitted records. Submitting is paused only while the requests are queued behind the submitted
+    /// records, engines take their copy once they get to the request and go on applying records.
+    /// [AccountsSnapshot::applied] is the number of the last record included in the copy
+    pub fn snapshot(&self) -> Result<AccountsSnapshot> {
+        let barrier = self.barrier.write().unwrap_or_else(|err| err.into_inner());
+        let submitted = self.submitted.load(Ordering::Relaxed);
+        let requests = self
+            .senders
+            .iter()
+            .map(request_snapshot)
+            .collect::<Result<Vec<_>>>()?;
+        drop(barrier);
+
+        let mut merged: Option<AccountsSnapshot> = None;
+        for request in requests {
+            let snapshot = request
+                .recv()
+                .map_err(|_| eyre!("engine thread stopped before taking the snapshot"))?;
+            match merged.as_mut() {
+                Some(merged) => merged.absorb(snapshot),
+                None => merged = Some(snapshot),
+            }
+        }
+        let mut merged = merged.expect("sharded engine has at least one shard");
+        merged.applied = submitted;
+        Ok(merged)
+    }
+}
+
+/// Queues the snapshot request behind the submitted records, returns the channel the snapshot is sent to
+fn request_snapshot(
+    sender: &crossbeam_channel::Sender<Submission>,
+) -> Result<crossbeam_channel::Receiver<AccountsSnapshot>> {
+    let (reply, snapshot) = crossbeam_channel::bounded(1);
+    sender
+        .send(Submission::Snapshot(reply))
+        .map_err(|_| eyre!("engine thread stopped, snapshot can't be taken"))?;
+    Ok(snapshot)
+}
+
+/// Engine's processor has no write-ahead log, so the message is always applied
+fn apply(processor: &mut Processor, message: Indexed<TransactionMessage>) {
+    processor
+        .apply(message)
+        .expect("engine processor has no write-ahead log to fail on");
+}
+
+/// Processes the whole CSV journal with header from any reader, e.g. file uploaded into the browser
+pub fn process_journal(journal: impl Read, accounts: Accounts) -> Result<(Accounts, Summary)> {
+    let mut engine = Engine::new(accounts);
+    CsvParser::new(journal).read_entries(|entry, timestamp| {
+        engine.submit(entry, timestamp);
+        Ok(())
+    })?;
+    Ok(engine.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::DisputePolicy;
+    use crate::channel::TransactionKind;
+    use crate::dispute_look_up::DisputeResolver;
+    use crate::parser::Found;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_process_journal() {
+        let journal = "type,client,tx,amount\n\
+                       deposit,1,1,10\n\
+                       withdrawal,1,2,15\n\
+                       deposit,2,3,4\n\
+                       dispute,2,3,\n\
+                       dispute,2,9,\n";
+        let (accounts, summary) =
+            process_journal(journal.as_bytes(), Accounts::new(DisputePolicy::default())).unwrap();
+
+        let client = accounts.get(1).unwrap();
+        assert_eq!((client.available, client.total), (amount!(10), amount!(10)));
+        let client = accounts.get(2).unwrap();
+        assert_eq!((client.available, client.held), (amount!(0), amount!(4)));
+        assert_eq!(summary.rejected_withdrawals, 1);
+    }
+
+    #[test]
+    fn test_pending_disputes() {
+        let records = [
+            "dispute,1,2,",
+            "deposit,1,1,10",
+            "deposit,1,2,5",
+            "deposit,1,3,1",
+        ];
+        let tests = vec![
+            ("dropped", None, amount!(0), 0),
+            (
+                "retried too early",
+                Some(PendingRetry::AfterRecords(1)),
+                amount!(0),
+                1,
+            ),
+            (
+                "retried",
+                Some(PendingRetry::AfterRecords(2)),
+                amount!(5),
+                1,
+            ),
+            (
+                "retried on flush",
+                Some(PendingRetry::OnFlush),
+                amount!(5),
+                1,
+            ),
+        ];
+
+        for (name, pending_retry, want_held, want_retried) in tests {
+            let mut engine = Engine::new(Accounts::default()).with_pending_disputes(pending_retry);
+            for record in records {
+                engine.submit_record(record.as_bytes()).unwrap();
+            }
+            let (accounts, summary) = engine.finish();
+            assert_eq!(
+                accounts.get(1).unwrap().held,
+                want_held,
+                "failed test {name}"
+            );
+            assert_eq!(summary.retried_disputes, want_retried, "failed test {name}");
+            assert_eq!(accounts.integrity_mismatch(), None, "failed test {name}");
+        }
+    }
+
+    /// Resolver backed by a store of transactions submitted before the engine was started
+    struct Store(HashMap<TransactionID, (ClientID, Amount)>);
+
+    impl DisputeResolver for Store {
+        fn find_transaction(
+            &mut self,
+            client_id: ClientID,
+            transaction_id: TransactionID,
+        ) -> Result<Found> {
+            match self.0.get(&transaction_id) {
+                Some((found, amount)) if *found == client_id => Ok((
+                    client_id,
+                    transaction_id,
+                    *amount,
+                    None,
+                    TransactionKind::Deposit,
+                )),
+                _ => Err(eyre::eyre!("transaction not found")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_with_resolver() {
+        let mut accounts = Accounts::new(DisputePolicy::default());
+        accounts.deposit(1, amount!(10)).unwrap();
+        let store = Store(HashMap::from([(1, (1, amount!(10)))]));
+        let mut engine = Engine::new(accounts).with_resolver(Box::new(store));
+
+        engine.submit_record(b"dispute,1,1,").unwrap();
+        engine.submit_record(b"dispute,1,2,").unwrap();
+
+        let (accounts, _) = engine.finish();
+        let client = accounts.get(1).unwrap();
+        assert_eq!((client.available, client.held), (amount!(0), amount!(10)));
+    }
+
+    #[test]
+    fn test_engine_handle() {
+        let engine =
+            EngineHandle::spawn(Engine::new(Accounts::new(DisputePolicy::default()))).unwrap();
+        let records = vec![
+            TransactionRecord::deposit(1, 1, amount!(10)),
+            TransactionRecord::deposit(2, 2, amount!(4)),
+            TransactionRecord::withdrawal(1, 3, amount!(15)),
+            TransactionRecord::dispute(2, 2),
+            TransactionRecord::chargeback(2, 2),
+            TransactionMessage::Transfer(crate::channel::Transfer {
+                from_client_id: 1,
+                to_client_id: 3,
+                transaction_id: 4,
+                amount: amount!(3),
+            })
+            .into(),
+        ];
+        for record in records {
+            engine.submit(record).unwrap();
+        }
+
+        let query = engine.query();
+        assert_eq!(query.get(9).unwrap(), None, "client without account");
+        // queries may be answered before the queued records are applied
+        let is_frozen = || {
+            query
+                .get(2)
+                .unwrap()
+                .is_some_and(|client| client.status.is_frozen())
+        };
+        for _ in 0..1000 {
+            if is_frozen() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert!(is_frozen(), "charged back client");
+
+        let Report { accounts, summary } = engine.finish().unwrap();
+        assert!(query.get(1).is_err(), "engine finished");
+        assert_eq!(accounts.get(1).unwrap().available, amount!(7));
+        assert_eq!(accounts.get(3).unwrap().available, amount!(3));
+        let client = accounts.get(2).unwrap();
+        assert_eq!(
+            (client.total, client.status.is_frozen()),
+            (amount!(0), true)
+        );
+        assert_eq!(summary.rejected_withdrawals, 1);
+    }
+
+    #[test]
+    fn test_engine_replica() {
+        let engine = EngineHandle::spawn_with_replica(
+            Engine::new(Accounts::new(DisputePolicy::default())),
+            std::time::Duration::from_millis(1),
+        )
+        .unwrap();
+        let replica = engine.replica().unwrap();
+        assert!(replica.latest().is_empty(), "nothing applied yet");
+        engine
+            .submit(TransactionRecord::deposit(1, 1, amount!(10)))
+            .unwrap();
+        engine
+            .submit(TransactionRecord::withdrawal(1, 2, amount!(4)))
+            .unwrap();
+
+        for _ in 0..1000 {
+            if replica.latest().applied == 2 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let snapshot = replica.latest();
+        assert_eq!(snapshot.applied, 2);
+        assert_eq!(snapshot.get(1).unwrap().available, amount!(6));
+        engine.finish().unwrap();
+        assert_eq!(replica.latest().get(1).unwrap().available, amount!(6));
+        assert!(
+            EngineHandle::spawn(Engine::new(Accounts::new(DisputePolicy::default())))
+                .unwrap()
+                .replica()
+                .is_none(),
+            "spawned without replica"
+        );
+    }
+
+    #[test]
+    fn test_sharded_engine() {
+        let engine =
+            ShardedEngineHandle::spawn(4, |_| Engine::new(Accounts::new(DisputePolicy::default())))
+                .unwrap();
+        let producers: Vec<_> = (0..4 as ClientID)
+            .map(|producer| {
+                let submitter = engine.submitter();
+                std::thread::spawn(move || {
+                    for client_id in (1..=100).filter(|client_id| client_id % 4 == producer) {
+                        let transaction_id = TransactionID::from(client_id) * 10;
+                        for record in [
+                            TransactionRecord::deposit(client_id, transaction_id, amount!(10)),
+                            TransactionRecord::withdrawal(
+                                client_id,
+                                transaction_id + 1,
+                                amount!(3),
+                            ),
+                            TransactionRecord::dispute(client_id, transaction_id),
+                        ] {
+                            submitter.submit(record).unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        let same_shard = (2..100).find(|client| shard_of(*client, 4) == shard_of(1, 4));
+        let other_shard = (2..100).find(|client| shard_of(*client, 4) != shard_of(1, 4));
+        let transfer = |to_client_id: ClientID| {
+            TransactionRecord::from(TransactionMessage::Transfer(crate::channel::Transfer {
+                from_client_id: 1,
+                to_client_id,
+                transaction_id: 2000,
+                amount: amount!(1),
+            }))
+        };
+        assert!(engine.submit(transfer(other_shard.unwrap())).is_err());
+        engine.submit(transfer(same_shard.unwrap())).unwrap();
+        // snapshot waits until the engines applied the records submitted before it
+        let snapshot = engine.snapshot().unwrap();
+        assert_eq!(snapshot.applied, 301);
+        assert_eq!(snapshot.get(100).unwrap().held, amount!(10));
+        let query = engine.query();
+        assert_eq!(query.get(100).unwrap().unwrap().held, amount!(10));
+
+        let Report { accounts, summary } = engine.finish().unwrap();
+        assert_eq!(accounts.len(), 100);
+        assert_eq!(accounts.get(1).unwrap().available, amount!(-3));
+        assert_eq!(accounts.totals().total, amount!(700));
+        assert_eq!(accounts.integrity_mismatch(), None);
+        // routed to the engine of both clients, which rejects it because the dispute held the funds
+        assert_eq!(summary.rejected_transfers, 1);
+        assert!(
+            ShardedEngineHandle::spawn(0, |_| Engine::new(Accounts::default())).is_err(),
+            "no shards"
+        );
+    }
+
+    #[test]
+    fn test_sharded_snapshot() {
+        let engine =
+            ShardedEngineHandle::spawn(4, |_| Engine::new(Accounts::new(DisputePolicy::default())))
+                .unwrap();
+        let producers: Vec<_> = (0..4 as ClientID)
+            .map(|producer| {
+                let submitter = engine.submitter();
+                std::thread::spawn(move || {
+                    for transaction_id in 0..2000 {
+                        let client_id = producer * 16 + (transaction_id % 16) as ClientID;
+                        submitter
+                            .submit(TransactionRecord::deposit(
+                                client_id,
+                                transaction_id + TransactionID::from(producer) * 10_000,
+                                amount!(1),
+                            ))
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        // every record deposits 1, so the copy is a single cut if it holds as much as records it includes
+        let mut previous = 0;
+        for _ in 0..20 {
+            let snapshot = engine.snapshot().unwrap();
+            let total = snapshot.iter().fold(amount!(0), |total, account| {
+                total.saturating_add(account.total)
+            });
+            assert_eq!(
+                total.to_string(),
+                snapshot.applied.to_string(),
+                "consistent cut"
+            );
+            assert!(snapshot.applied >= previous, "later snapshot includes more");
+            previous = snapshot.applied;
+        }
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        assert_eq!(engine.snapshot().unwrap().applied, 8000);
+        engine.finish().unwrap();
+    }
+}
@@ -0,0 +1,275 @@
+//! Fault injection for tests of the pipeline, enabled by the `fault-injection` feature and never built into
+//! releases. Faults are injected in three places:
+//! - messages sent over [crate::channel::Sender] are dropped, duplicated or delayed, see [ChannelFaults] and
+//!   [crate::pipeline::PipelineBuilder::with_faults]
+//! - reads of the journal fail past an offset, see [FaultyReader]
+//! - look-ups of disputed transactions fail, see [FaultyResolver]
+//!
+//! Faults are deterministic, every nth message or look-up counted from 1 is hit, so tests can tell exactly
+//! which records were affected
+use crate::aliases::*;
+use crate::channel::TransactionMessage;
+use crate::dispute_look_up::DisputeResolver;
+use crate::parser::Found;
+use eyre::{eyre, Result};
+use std::cell::Cell;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::time::Duration;
+
+/// Faults injected into the messages of a sender, every nth message is hit
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChannelFaults {
+    drop_every: Option<u64>,
+    duplicate_every: Option<u64>,
+    delay_every: Option<(u64, Duration)>,
+}
+
+impl ChannelFaults {
+    /// Every nth message is never sent, it takes precedence over the other faults
+    pub fn with_drop_every(mut self, n: u64) -> ChannelFaults {
+        self.drop_every = Some(n);
+        self
+    }
+
+    /// Every nth message is sent twice
+    pub fn with_duplicate_every(mut self, n: u64) -> ChannelFaults {
+        self.duplicate_every = Some(n);
+        self
+    }
+
+    /// Sender blocks for `delay` before every nth message, as if the channel was full
+    pub fn with_delay_every(mut self, n: u64, delay: Duration) -> ChannelFaults {
+        self.delay_every = Some((n, delay));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == ChannelFaults::default()
+    }
+
+    /// Copies of the `count`th message which are sent, none if it is dropped
+    pub fn apply<T>(&self, count: u64, message: T, duplicate: impl Fn(&T) -> T) -> Vec<T> {
+        let hits = |every: Option<u64>| every.is_some_and(|n| n > 0 && count.is_multiple_of(n));
+        if hits(self.drop_every) {
+            return Vec::new();
+        }
+        if let Some((_, delay)) = self.delay_every.filter(|(n, _)| hits(Some(*n))) {
+            std::thread::sleep(delay);
+        }
+        match hits(self.duplicate_every) {
+            true => vec![duplicate(&message), message],
+            false => vec![message],
+        }
+    }
+}
+
+/// Faults of a sender together with the number of messages it was asked to send
+pub(crate) struct InjectedFaults<T> {
+    faults: ChannelFaults,
+    duplicate: fn(&T) -> T,
+    count: Cell<u64>,
+}
+
+impl<T: Clone> InjectedFaults<T> {
+    pub(crate) fn new(faults: ChannelFaults) -> InjectedFaults<T> {
+        InjectedFaults {
+            faults,
+            duplicate: T::clone,
+            count: Cell::new(0),
+        }
+    }
+}
+
+impl<T> InjectedFaults<T> {
+    pub(crate) fn inject(&self, message: T) -> Vec<T> {
+        self.count.set(self.count.get() + 1);
+        self.faults.apply(self.count.get(), message, self.duplicate)
+    }
+}
+
+/// Faults injected into the pipeline, see [crate::pipeline::PipelineBuilder::with_faults]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Faults {
+    /// Transactions sent by the parser to the processing
+    pub parsed: ChannelFaults,
+    /// Disputes, resolves and chargebacks sent by the dispute look-up to the processing
+    pub looked_up: ChannelFaults,
+}
+
+/// Reader whose reads fail once they get past `fail_at` bytes, as if the disk or the network mount failed.
+/// Seeking back before the offset reads fine again, so look-ups re-scanning the start of the journal succeed
+#[derive(Debug)]
+pub struct FaultyReader<R> {
+    inner: R,
+    position: u64,
+    fail_at: u64,
+}
+
+impl<R> FaultyReader<R> {
+    pub fn new(inner: R, fail_at: u64) -> FaultyReader<R> {
+        FaultyReader {
+            inner,
+            position: 0,
+            fail_at,
+        }
+    }
+}
+
+impl<R: Read> Read for FaultyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.fail_at.saturating_sub(self.position);
+        if remaining == 0 && !buf.is_empty() {
+            return Err(io::Error::other(format!(
+                "injected read error at offset {}",
+                self.position
+            )));
+        }
+        let len = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+        let read = self.inner.read(&mut buf[..len])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for FaultyReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = self.inner.seek(pos)?;
+        Ok(self.position)
+    }
+}
+
+/// Resolver whose every nth look-up of a transaction fails, as if the store behind it was unavailable
+pub struct FaultyResolver<S> {
+    inner: S,
+    fail_every: u64,
+    count: u64,
+}
+
+impl<S> FaultyResolver<S> {
+    pub fn new(inner: S, fail_every: u64) -> FaultyResolver<S> {
+        FaultyResolver {
+            inner,
+            fail_every,
+            count: 0,
+        }
+    }
+
+    /// `true` if the next look-up fails
+    fn fails(&mut self) -> bool {
+        self.count += 1;
+        self.fail_every > 0 && self.count.is_multiple_of(self.fail_every)
+    }
+}
+
+fn injected(transaction_id: TransactionID) -> eyre::Report {
+    eyre!("injected look-up failure of transaction {transaction_id}")
+}
+
+impl<S: DisputeResolver> DisputeResolver for FaultyResolver<S> {
+    fn find_transaction(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+    ) -> Result<Found> {
+        match self.fails() {
+            true => Err(injected(transaction_id)),
+            false => self.inner.find_transaction(client_id, transaction_id),
+        }
+    }
+
+    fn find_transactions(&mut self, requests: &[(ClientID, TransactionID)]) -> Vec<Result<Found>> {
+        self.inner
+            .find_transactions(requests)
+            .into_iter()
+            .zip(requests)
+            .map(|(found, (_, transaction_id))| match self.fails() {
+                true => Err(injected(*transaction_id)),
+                false => found,
+            })
+            .collect()
+    }
+
+    fn record(&mut self, message: &TransactionMessage, timestamp: Option<Timestamp>) {
+        self.inner.record(message, timestamp)
+    }
+
+    fn find_owner(&mut self, transaction_id: TransactionID) -> Result<Option<ClientID>> {
+        self.inner.find_owner(transaction_id)
+    }
+
+    fn read_ahead(&mut self, transaction_ids: &[TransactionID]) {
+        self.inner.read_ahead(transaction_ids)
+    }
+
+    fn memory_usage(&self) -> u64 {
+        self.inner.memory_usage()
+    }
+
+    fn shrink_memory(&mut self) -> bool {
+        self.inner.shrink_memory()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::Sender;
+
+    #[test]
+    fn test_channel_faults() {
+        let tests = vec![
+            (
+                "no faults",
+                ChannelFaults::default(),
+                vec![1, 2, 3, 4, 5, 6],
+            ),
+            (
+                "dropped",
+                ChannelFaults::default().with_drop_every(2),
+                vec![1, 3, 5],
+            ),
+            (
+                "duplicated",
+                ChannelFaults::default().with_duplicate_every(3),
+                vec![1, 2, 3, 3, 4, 5, 6, 6],
+            ),
+            (
+                "dropped before duplicated",
+                ChannelFaults::default()
+                    .with_drop_every(3)
+                    .with_duplicate_every(2),
+                vec![1, 2, 2, 4, 4, 5],
+            ),
+            (
+                "delayed",
+                ChannelFaults::default().with_delay_every(2, Duration::from_millis(1)),
+                vec![1, 2, 3, 4, 5, 6],
+            ),
+        ];
+
+        for (name, faults, want) in tests {
+            let (sender, receiver) = crossbeam_channel::unbounded();
+            let sender = Sender::new(sender).with_batch_size(4).with_faults(faults);
+            for message in 1..=6 {
+                sender.send(message);
+            }
+            drop(sender);
+            let got: Vec<_> = receiver.try_iter().flatten().collect();
+            assert_eq!(got, want, "failed test {name}");
+        }
+    }
+
+    #[test]
+    fn test_faulty_reader() {
+        let mut reader = FaultyReader::new(io::Cursor::new(b"abcdef".to_vec()), 4);
+        let mut read = Vec::new();
+        let err = reader.read_to_end(&mut read).unwrap_err();
+        assert_eq!(read, b"abcd");
+        assert_eq!(err.to_string(), "injected read error at offset 4");
+
+        reader.seek(SeekFrom::Start(2)).unwrap();
+        let mut buf = [0; 2];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"cd", "read before the offset after seeking back");
+    }
+}
@@ -0,0 +1,116 @@
+//! Optional fees charged on withdrawals and chargebacks. Every fee is moved from the client's account to the house
+//! account, so the grand total of all accounts doesn't change. Charged fees are itemized in the audit trail as `fee`
+//! operations and summed up in the [crate::summary::Summary]
+use crate::aliases::*;
+use crate::amount::{from_decimal, into_decimal, PRECISION};
+use eyre::{eyre, Context};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Flat amount or percentage of the operation's amount
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fee {
+    Flat(Decimal),
+    Percent(Decimal),
+}
+
+impl FromStr for Fee {
+    type Err = eyre::Report;
+
+    /// Parses flat amount, e.g. `0.5`, or percentage, e.g. `1.5%`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let fee = match s.strip_suffix('%') {
+            Some(percent) => Fee::Percent(
+                percent
+                    .trim()
+                    .parse()
+                    .wrap_err_with(|| format!("invalid fee percentage '{s}'"))?,
+            ),
+            None => {
+                let flat = s.parse().wrap_err_with(|| {
+                    format!("invalid fee '{s}', expected amount or percentage")
+                })?;
+                from_decimal(flat).wrap_err_with(|| format!("invalid fee '{s}'"))?;
+                Fee::Flat(flat)
+            }
+        };
+        match fee {
+            Fee::Flat(value) | Fee::Percent(value) if value.is_sign_negative() => {
+                Err(eyre!("invalid fee '{s}', it can't be negative"))
+            }
+            fee => Ok(fee),
+        }
+    }
+}
+
+impl Fee {
+    /// Fee of the operation with given amount, percentages are rounded half up to [PRECISION] decimal places.
+    /// `None` if the fee doesn't fit into [Amount]
+    pub fn of(self, amount: Amount) -> Option<Amount> {
+        let fee = match self {
+            Fee::Flat(flat) => flat,
+            Fee::Percent(percent) => into_decimal(amount)
+                .checked_mul(percent)?
+                .checked_div(Decimal::ONE_HUNDRED)?
+                .round_dp_with_strategy(PRECISION, RoundingStrategy::MidpointAwayFromZero)
+                .normalize(),
+        };
+        from_decimal(fee).ok()
+    }
+}
+
+/// Fees of the operations and the account they are credited to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeeSchedule {
+    /// Charged on top of every applied withdrawal, the client needs available funds for both
+    pub withdrawal: Option<Fee>,
+    /// Charged from the available funds of the client after every chargeback, at most what is available
+    pub chargeback: Option<Fee>,
+    /// Client whose account is credited with the fees, operations of this client are not charged
+    pub house_account: Option<ClientID>,
+}
+
+impl FeeSchedule {
+    pub fn is_empty(&self) -> bool {
+        self.withdrawal.is_none() && self.chargeback.is_none()
+    }
+
+    /// House account the fees of the client go to, `None` if the client is not charged
+    pub fn house_for(&self, client_id: ClientID) -> Option<ClientID> {
+        self.house_account
+            .filter(|house_account| *house_account != client_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fee() {
+        let tests = vec![
+            ("flat", "0.5", amount!(20), Some(amount!(0.5))),
+            ("percentage", "1.5%", amount!(20), Some(amount!(0.3))),
+            (
+                "rounded half up",
+                "1%",
+                amount!(0.0150),
+                Some(amount!(0.0002)),
+            ),
+            ("negative", "-1", amount!(20), None),
+            ("not a number", "1.5 %x", amount!(20), None),
+        ];
+
+        for (name, fee, amount, want) in tests {
+            assert_eq!(
+                fee.parse::<Fee>().ok().and_then(|fee| fee.of(amount)),
+                want,
+                "failed test {name}"
+            );
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! C ABI of the engine for services which embed it instead of running the binary, built with `cdylib` feature.
+//! Records are submitted one by one as CSV lines in the default column order, see [crate::parser::parse_record].
+//! It wraps [Engine], see `include/tren.h`. The shared library is not built by default, build it with
+//! `cargo rustc --lib --release --features cdylib --crate-type cdylib`
+use crate::accounts::{Accounts, DisputePolicy};
+use crate::engine::Engine;
+use crate::parser::parse_record;
+use std::ffi::{c_char, c_int, CStr};
+use std::fmt::Write;
+use std::panic::AssertUnwindSafe;
+use tracing::warn;
+
+/// Record was submitted, it can still be rejected by the accounts
+pub const TREN_OK: c_int = 0;
+/// Engine or record pointer is null
+pub const TREN_NULL: c_int = -1;
+/// Record is not valid UTF-8 or can't be parsed
+pub const TREN_INVALID_RECORD: c_int = -2;
+/// Engine panicked while processing the record, it shouldn't be used anymore
+pub const TREN_PANIC: c_int = -3;
+
+/// Accounts sorted by client as JSON array, amounts are strings so they keep their precision
+fn report_json(engine: &Engine) -> String {
+    let mut accounts: Vec<_> = engine.accounts().iter().collect();
+    accounts.sort_unstable_by_key(|account| account.client_id);
+
+    let mut json = String::from("[");
+    for (i, account) in accounts.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        // writing into String can't fail
+        let _ = write!(
+            json,
+            r#"{{"client":{},"available":"{}","held":"{}","total":"{}","locked":{},"closed":{},"flagged":{}}}"#,
+            account.client_id,
+            account.available,
+            account.held,
+            account.total,
+            account.status.is_frozen(),
+            account.status.is_closed(),
+            account.flagged
+        );
+    }
+    json.push(']');
+    json
+}
+
+/// Creates new engine with empty accounts, it has to be released by [tren_engine_free]. Returns null if the engine
+/// panicked while it was created
+#[no_mangle]
+pub extern "C" fn tren_engine_new() -> *mut Engine {
+    // unwinding into C is undefined behaviour, the whole body of each exported function runs inside catch_unwind
+    std::panic::catch_unwind(|| {
+        Box::into_raw(Box::new(Engine::new(Accounts::new(
+            DisputePolicy::default(),
+        ))))
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+/// Parses and applies single record, e.g. `deposit,1,1,10.5`. Returns [TREN_OK] or one of the negative error codes
+///
+/// # Safety
+/// `engine` has to be returned by [tren_engine_new] and not yet freed, `record` has to be NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn tren_engine_submit(engine: *mut Engine, record: *const c_char) -> c_int {
+    std::panic::catch_unwind(AssertUnwindSafe(|| {
+        let (Some(engine), false) = (engine.as_mut(), record.is_null()) else {
+            return TREN_NULL;
+        };
+        let entry = match parse_record(CStr::from_ptr(record).to_bytes()) {
+            Ok(entry) => entry,
+            Err(err) => {
+                warn!(%err, "rejecting malformed record");
+                return TREN_INVALID_RECORD;
+            }
+        };
+        engine.submit(entry, None);
+        TREN_OK
+    }))
+    .unwrap_or(TREN_PANIC)
+}
+
+/// Writes the report as NUL-terminated JSON array into `buffer` of `len` bytes. Returns size of the report
+/// including the NUL, nothing is written if it is larger than `len`, so the call can be repeated with larger buffer.
+/// Returns 0 if `engine` is null or the engine panicked while writing the report
+///
+/// # Safety
+/// `engine` has to be returned by [tren_engine_new] and not yet freed, `buffer` has to be valid for `len` bytes
+/// or null
+#[no_mangle]
+pub unsafe extern "C" fn tren_engine_report_json(
+    engine: *const Engine,
+    buffer: *mut c_char,
+    len: usize,
+) -> usize {
+    std::panic::catch_unwind(AssertUnwindSafe(|| {
+        let Some(engine) = engine.as_ref() else {
+            return 0;
+        };
+        let report = report_json(engine);
+        let size = report.len() + 1;
+        if !buffer.is_null() && size <= len {
+            std::ptr::copy_nonoverlapping(report.as_ptr(), buffer.cast(), report.len());
+            *buffer.add(report.len()) = 0;
+        }
+        size
+    }))
+    .unwrap_or(0)
+}
+
+/// Releases the engine, null is ignored. Panic while the engine is dropped is swallowed
+///
+/// # Safety
+/// `engine` has to be returned by [tren_engine_new] and must not be used after this call
+#[no_mangle]
+pub unsafe extern "C" fn tren_engine_free(engine: *mut Engine) {
+    let _ = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        if !engine.is_null() {
+            drop(Box::from_raw(engine));
+        }
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_engine() {
+        let records: [&CStr; 5] = [
+            c"deposit,1,1,10",
+            c"deposit,2,2,2.5",
+            c"withdrawal,1,3,20",
+            c"dispute,1,1,",
+            c"chargeback,1,1,",
+        ];
+        unsafe {
+            let engine = tren_engine_new();
+            for record in records {
+                assert_eq!(tren_engine_submit(engine, record.as_ptr()), TREN_OK);
+            }
+            assert_eq!(
+                tren_engine_submit(engine, c"deposit,x".as_ptr()),
+                TREN_INVALID_RECORD
+            );
+            assert_eq!(tren_engine_submit(engine, std::ptr::null()), TREN_NULL);
+
+            let size = tren_engine_report_json(engine, std::ptr::null_mut(), 0);
+            let mut buffer = vec![0 as c_char; size];
+            assert_eq!(
+                tren_engine_report_json(engine, buffer.as_mut_ptr(), buffer.len()),
+                size
+            );
+            tren_engine_free(engine);
+
+            assert_eq!(
+                CStr::from_ptr(buffer.as_ptr()).to_str().unwrap(),
+                r#"[{"client":1,"available":"0","held":"0","total":"0","locked":true,"closed":false,"flagged":false},{"client":2,"available":"2.5","held":"0","total":"2.5","locked":false,"closed":false,"flagged":false}]"#
+            );
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Compact amount with [SCALE] implied decimal places stored in `i64`, used as [crate::aliases::Amount] with
+//! `fixed-amount` feature. It is half the size of [Decimal] and much cheaper to parse and add, but amounts
+//! with more decimal places are rejected and balances are limited to about ±922 trillion.
+//! Build without the feature when amounts have to be kept exactly as written in the journal
+use eyre::{eyre, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// Number of implied decimal places
+pub const SCALE: u32 = 4;
+const FACTOR: i64 = 10i64.pow(SCALE);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FixedAmount(i64);
+
+impl FixedAmount {
+    pub const ZERO: FixedAmount = FixedAmount(0);
+    pub const ONE: FixedAmount = FixedAmount(FACTOR);
+    pub const MAX: FixedAmount = FixedAmount(i64::MAX);
+    pub const MIN: FixedAmount = FixedAmount(i64::MIN);
+
+    /// Amount of `scaled` ten-thousandths, e.g. `12500` is `1.25`
+    pub const fn from_scaled(scaled: i64) -> FixedAmount {
+        FixedAmount(scaled)
+    }
+
+    pub const fn scaled(self) -> i64 {
+        self.0
+    }
+
+    /// Parses plain decimal number, e.g. `-12.5`. Fails if it has more than [SCALE] decimal places
+    /// which are not zero or it doesn't fit
+    pub fn from_str_exact(s: &str) -> Result<FixedAmount> {
+        let (negative, digits) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if integer.is_empty() && fraction.is_empty() {
+            return Err(eyre!("invalid amount '{s}'"));
+        }
+
+        let mut scaled: i64 = 0;
+        for (position, digit) in integer.bytes().chain(fraction.bytes()).enumerate() {
+            if !digit.is_ascii_digit() {
+                return Err(eyre!("invalid amount '{s}'"));
+            }
+            let digit = i64::from(digit - b'0');
+            if position >= integer.len() + SCALE as usize {
+                if digit != 0 {
+                    return Err(eyre!("amount '{s}' has more than {SCALE} decimal places"));
+                }
+                continue;
+            }
+            scaled = scaled
+                .checked_mul(10)
+                .and_then(|scaled| match negative {
+                    true => scaled.checked_sub(digit),
+                    false => scaled.checked_add(digit),
+                })
+                .ok_or_else(|| eyre!("amount '{s}' is too large"))?;
+        }
+        let missing = SCALE.saturating_sub(fraction.len() as u32);
+        scaled
+            .checked_mul(10i64.pow(missing))
+            .map(FixedAmount)
+            .ok_or_else(|| eyre!("amount '{s}' is too large"))
+    }
+
+    pub fn checked_add(self, other: FixedAmount) -> Option<FixedAmount> {
+        self.0.checked_add(other.0).map(FixedAmount)
+    }
+
+    pub fn checked_sub(self, other: FixedAmount) -> Option<FixedAmount> {
+        self.0.checked_sub(other.0).map(FixedAmount)
+    }
+
+    pub fn saturating_add(self, other: FixedAmount) -> FixedAmount {
+        FixedAmount(self.0.saturating_add(other.0))
+    }
+
+    pub fn saturating_sub(self, other: FixedAmount) -> FixedAmount {
+        FixedAmount(self.0.saturating_sub(other.0))
+    }
+
+    /// Saturates at [FixedAmount::MAX] for [FixedAmount::MIN]
+    pub fn abs(self) -> FixedAmount {
+        FixedAmount(self.0.saturating_abs())
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_sign_negative(self) -> bool {
+        self.0 < 0
+    }
+}
+
+impl From<FixedAmount> for Decimal {
+    fn from(amount: FixedAmount) -> Decimal {
+        Decimal::new(amount.0, SCALE).normalize()
+    }
+}
+
+impl TryFrom<Decimal> for FixedAmount {
+    type Error = eyre::Report;
+
+    fn try_from(amount: Decimal) -> Result<FixedAmount> {
+        let scaled = amount
+            .checked_mul(Decimal::from(FACTOR))
+            .ok_or_else(|| eyre!("amount {amount} is too large"))?;
+        if !scaled.fract().is_zero() {
+            return Err(eyre!(
+                "amount {amount} has more than {SCALE} decimal places"
+            ));
+        }
+        i64::try_from(scaled)
+            .map(FixedAmount)
+            .map_err(|_| eyre!("amount {amount} is too large"))
+    }
+}
+
+impl FromStr for FixedAmount {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<FixedAmount> {
+        FixedAmount::from_str_exact(s)
+    }
+}
+
+/// Written without trailing zeros, e.g. `1.5` and `-3`
+impl fmt::Display for FixedAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let factor = FACTOR.unsigned_abs();
+        let (integer, fraction) = (
+            self.0.unsigned_abs() / factor,
+            self.0.unsigned_abs() % factor,
+        );
+        match fraction {
+            0 => write!(f, "{sign}{integer}"),
+            _ => {
+                let fraction = format!("{fraction:0width$}", width = SCALE as usize);
+                write!(f, "{sign}{integer}.{}", fraction.trim_end_matches('0'))
+            }
+        }
+    }
+}
+
+/// Serialized as string, same as [Decimal]
+impl Serialize for FixedAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for FixedAmount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<FixedAmount, D::Error> {
+        FixedAmount::try_from(<Decimal as Deserialize>::deserialize(deserializer)?)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str_exact() {
+        let tests = vec![
+            ("integer", "12", Some(120000)),
+            ("decimal", "1.25", Some(12500)),
+            ("negative", "-0.0001", Some(-1)),
+            ("plus sign", "+3.", Some(30000)),
+            ("leading dot", ".5", Some(5000)),
+            ("trailing zeros", "2.500000", Some(25000)),
+            ("too precise", "0.00001", None),
+            ("max", "922337203685477.5807", Some(i64::MAX)),
+            ("min", "-922337203685477.5808", Some(i64::MIN)),
+            ("too large", "922337203685477.5808", None),
+            ("empty", "", None),
+            ("only sign", "-", None),
+            ("letter", "1a", None),
+            ("two dots", "1.2.3", None),
+        ];
+
+        for (name, s, want) in tests {
+            let got = FixedAmount::from_str_exact(s).ok().map(FixedAmount::scaled);
+            assert_eq!(got, want, "failed test {name}");
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let tests = vec![
+            ("zero", 0, "0"),
+            ("integer", 120000, "12"),
+            ("trailing zeros", 12500, "1.25"),
+            ("leading zeros", -1, "-0.0001"),
+            ("min", i64::MIN, "-922337203685477.5808"),
+        ];
+
+        for (name, scaled, want) in tests {
+            let got = FixedAmount::from_scaled(scaled).to_string();
+            assert_eq!(got, want, "failed test {name}");
+        }
+    }
+
+    #[test]
+    fn test_decimal_conversion() {
+        let amount = FixedAmount::try_from(Decimal::new(-12345, 3)).unwrap();
+        assert_eq!(amount.scaled(), -123450);
+        assert_eq!(Decimal::from(amount), Decimal::new(-12345, 3));
+        assert!(FixedAmount::try_from(Decimal::new(1, 5)).is_err());
+        assert!(FixedAmount::try_from(Decimal::MAX).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use crate::config::Config;
+use crate::pipeline;
+use eyre::{eyre, Context, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Golden-file fixture: journal `<name>.csv` is processed and its report is compared with `<name>.expected`.
+/// Optional `<name>.ron` holds [Config] the journal is processed with. Journals which have to fail the run
+/// have `error: <message>` in `<name>.expected`, the error has to contain the message.
+#[derive(Debug)]
+pub struct Fixture {
+    pub name: String,
+    journal: PathBuf,
+    expected: PathBuf,
+    config: Option<PathBuf>,
+}
+
+/// Report which didn't match the expected one, rows are compared regardless of their order
+#[derive(Debug)]
+pub struct Mismatch {
+    pub name: String,
+    /// Expected rows missing in the report
+    pub missing: Vec<String>,
+    /// Rows in the report which were not expected
+    pub unexpected: Vec<String>,
+}
+
+/// Finds all fixtures in the directory, journals without `.expected` file are ignored
+pub fn discover(dir: &Path) -> Result<Vec<Fixture>> {
+    let mut fixtures = Vec::new();
+    for entry in fs::read_dir(dir)
+        .wrap_err_with(|| format!("failed to read fixtures directory {}", dir.display()))?
+    {
+        let journal = entry?.path();
+        if journal.extension().is_none_or(|ext| ext != "csv") {
+            continue;
+        }
+        let expected = journal.with_extension("expected");
+        if !expected.exists() {
+            continue;
+        }
+        let config = Some(journal.with_extension("ron")).filter(|path| path.exists());
+        let name = journal
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        fixtures.push(Fixture {
+            name,
+            journal,
+            expected,
+            config,
+        });
+    }
+
+    fixtures.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(fixtures)
+}
+
+impl Fixture {
+    /// Runs the full pipeline over the journal, returns `Ok(None)` if the report matches
+    pub fn run(&self) -> Result<Option<Mismatch>> {
+        let mut config = match self.config.as_ref() {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+        config.progress = false;
+        let expected = fs::read_to_string(&self.expected)
+            .wrap_err_with(|| format!("failed to read {}", self.expected.display()))?;
+        let processed = pipeline::run(&self.journal, config);
+
+        if let Some(message) = expected.trim().strip_prefix("error:") {
+            let got = match processed {
+                Ok(_) => "report".to_string(),
+                Err(err) if format!("{err:#}").contains(message.trim()) => return Ok(None),
+                Err(err) => format!("error: {err:#}"),
+            };
+            return Ok(Some(Mismatch {
+                name: self.name.clone(),
+                missing: vec![expected.trim().to_string()],
+                unexpected: vec![got],
+            }));
+        }
+
+        let (accounts, _) = processed?;
+        let mut report = Vec::new();
+        accounts.write_report(&mut report)?;
+        let got = rows(&String::from_utf8(report)?);
+        let expected = rows(&expected);
+
+        if got == expected {
+            return Ok(None);
+        }
+        Ok(Some(Mismatch {
+            name: self.name.clone(),
+            missing: expected.difference(&got).cloned().collect(),
+            unexpected: got.difference(&expected).cloned().collect(),
+        }))
+    }
+}
+
+fn rows(report: &str) -> BTreeSet<String> {
+    report
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(normalize)
+        .collect()
+}
+
+#[cfg(not(feature = "fixed-amount"))]
+fn normalize(row: &str) -> String {
+    row.to_string()
+}
+
+/// Fixed amounts are written without trailing zeros, so amounts of both reports are compared in that form
+#[cfg(feature = "fixed-amount")]
+fn normalize(row: &str) -> String {
+    row.split(',')
+        .map(|cell| match rust_decimal::Decimal::from_str_exact(cell) {
+            Ok(amount) => amount.normalize().to_string(),
+            Err(_) => cell.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Runs all fixtures in the directory and prints the outcome of each, fails if any of them doesn't match
+pub fn run_all(dir: &Path) -> Result<()> {
+    let fixtures = discover(dir)?;
+    let mut failed = 0;
+    for fixture in fixtures.iter() {
+        match fixture.run() {
+            Ok(None) => println!("ok      {}", fixture.name),
+            Ok(Some(mismatch)) => {
+                failed += 1;
+                println!("FAILED  {}", mismatch.name);
+                for row in mismatch.missing {
+                    println!("  - {row}");
+                }
+                for row in mismatch.unexpected {
+                    println!("  + {row}");
+                }
+            }
+            Err(err) => {
+                failed += 1;
+                println!("ERROR   {}: {err:#}", fixture.name);
+            }
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        _ => Err(eyre!("{failed} of {} fixtures failed", fixtures.len())),
+    }
+}
@@ -0,0 +1,428 @@
+use crate::binary::Binary;
+use eyre::{eyre, Context, Result};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::{debug, error, info};
+
+/// Number of bytes read from the start of the journal to detect its format
+const HEAD_SIZE: usize = 4096;
+
+/// Columns of the journal produced by formats which have to be transcoded
+const COLUMNS: [&str; 6] = ["type", "client", "tx", "amount", "to_client", "timestamp"];
+
+/// Format of the input journal. Every format is turned into delimited records with header,
+/// which are then read by [crate::parser::CsvParser], or into [crate::binary] journal
+pub trait InputFormat: Send + Sync {
+    /// Name used by `--input-format`
+    fn name(&self) -> &'static str;
+
+    /// Returns `true` if the first bytes of the journal look like this format
+    fn detect(&self, head: &[u8]) -> bool;
+
+    /// Prepares the journal for parsing
+    fn open(&self, input: &Path) -> Result<Journal>;
+}
+
+/// Journal ready to be read by [crate::parser::CsvParser] or [crate::binary::BinaryParser]
+pub struct Journal {
+    pub path: PathBuf,
+    /// Separator of the fields, `None` for binary journal
+    delimiter: Option<u8>,
+    /// Transcoded copy of the input, removed once the journal is dropped
+    transcoded: bool,
+}
+
+impl Journal {
+    /// Journal which can be read as it is
+    pub fn delimited(path: &Path, delimiter: u8) -> Journal {
+        Journal {
+            path: path.to_path_buf(),
+            delimiter: Some(delimiter),
+            transcoded: false,
+        }
+    }
+
+    /// Journal of fixed-width binary records
+    pub fn binary(path: &Path) -> Journal {
+        Journal {
+            path: path.to_path_buf(),
+            delimiter: None,
+            transcoded: false,
+        }
+    }
+
+    /// Temporary comma separated copy of the input, removed once the journal is dropped
+    pub fn transcoded(path: PathBuf) -> Journal {
+        Journal {
+            path,
+            delimiter: Some(b','),
+            transcoded: true,
+        }
+    }
+
+    /// Separator of the fields, `None` for binary journal
+    pub fn delimiter(&self) -> Option<u8> {
+        self.delimiter
+    }
+
+    /// `true` if the path is a temporary copy of the input, offsets in it don't match the input
+    pub fn is_transcoded(&self) -> bool {
+        self.transcoded
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        if self.transcoded {
+            if let Err(err) = std::fs::remove_file(&self.path) {
+                error!(%err, "failed to remove transcoded journal");
+            }
+        }
+    }
+}
+
+/// Known input formats, detection tries them in the order they were registered
+pub struct FormatRegistry {
+    formats: Vec<Box<dyn InputFormat>>,
+}
+
+impl Default for FormatRegistry {
+    /// Registry with all built-in formats
+    fn default() -> Self {
+        let mut registry = FormatRegistry {
+            formats: Vec::new(),
+        };
+        registry.register(Box::new(Binary));
+        registry.register(Box::new(JsonLines));
+        registry.register(Box::new(Tsv));
+        registry.register(Box::new(Csv));
+        registry
+    }
+}
+
+impl FormatRegistry {
+    /// Adds the format, it replaces already registered format with the same name
+    pub fn register(&mut self, format: Box<dyn InputFormat>) {
+        self.formats.retain(|known| known.name() != format.name());
+        self.formats.push(format);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn InputFormat> {
+        self.formats
+            .iter()
+            .find(|format| format.name() == name)
+            .map(|format| format.as_ref())
+    }
+
+    /// Returns the first format which recognizes the start of the journal
+    pub fn detect(&self, head: &[u8]) -> Option<&dyn InputFormat> {
+        self.formats
+            .iter()
+            .find(|format| format.detect(head))
+            .map(|format| format.as_ref())
+    }
+
+    /// Opens the journal in the format given by name, or in the detected one if no name is given
+    pub fn open(&self, input: &Path, name: Option<&str>) -> Result<Journal> {
+        let format = match name {
+            Some(name) => self.get(name).ok_or_else(|| {
+                eyre!(
+                    "unknown input format '{name}', expected one of {}",
+                    self.names()
+                )
+            })?,
+            None => self.detect(&head(input)?).ok_or_else(|| {
+                eyre!(
+                    "failed to detect format of journal {}, set it by --input-format, one of {}",
+                    input.display(),
+                    self.names()
+                )
+            })?,
+        };
+
+        info!(format = format.name(), "opening journal");
+        format.open(input)
+    }
+
+    fn names(&self) -> String {
+        self.formats
+            .iter()
+            .map(|format| format.name())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+fn head(input: &Path) -> Result<Vec<u8>> {
+    let file = File::open(input)
+        .wrap_err_with(|| format!("failed to open journal {}", input.display()))?;
+    let mut head = Vec::with_capacity(HEAD_SIZE);
+    file.take(HEAD_SIZE as u64)
+        .read_to_end(&mut head)
+        .wrap_err("failed to read start of the journal")?;
+    Ok(head)
+}
+
+/// First line of the head which is not blank nor `#` comment, without the line ending
+fn first_line(head: &[u8]) -> &[u8] {
+    head.split(|b| *b == b'\n')
+        .find(|line| {
+            let line = line.trim_ascii();
+            !line.is_empty() && !line.starts_with(b"#")
+        })
+        .unwrap_or_default()
+}
+
+/// Comma separated values with header, the default format
+pub struct Csv;
+
+impl InputFormat for Csv {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn detect(&self, head: &[u8]) -> bool {
+        first_line(head).contains(&b',')
+    }
+
+    fn open(&self, input: &Path) -> Result<Journal> {
+        Ok(Journal::delimited(input, b','))
+    }
+}
+
+/// Tab separated values with the same header as [Csv]
+pub struct Tsv;
+
+impl InputFormat for Tsv {
+    fn name(&self) -> &'static str {
+        "tsv"
+    }
+
+    fn detect(&self, head: &[u8]) -> bool {
+        let line = first_line(head);
+        line.contains(&b'\t') && !line.contains(&b',')
+    }
+
+    fn open(&self, input: &Path) -> Result<Journal> {
+        Ok(Journal::delimited(input, b'\t'))
+    }
+}
+
+/// One flat JSON object per line with the same keys as the [Csv] columns, for example
+/// `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`. Numbers are kept as written, so amounts
+/// don't lose precision. Journal is transcoded into temporary CSV file before parsing
+pub struct JsonLines;
+
+impl InputFormat for JsonLines {
+    fn name(&self) -> &'static str {
+        "jsonl"
+    }
+
+    fn detect(&self, head: &[u8]) -> bool {
+        head.trim_ascii_start().first() == Some(&b'{')
+    }
+
+    fn open(&self, input: &Path) -> Result<Journal> {
+        static TRANSCODED: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "{}-journal-{}-{}.csv",
+            env!("CARGO_PKG_NAME"),
+            std::process::id(),
+            TRANSCODED.fetch_add(1, Ordering::Relaxed)
+        ));
+        let reader = BufReader::new(
+            File::open(input)
+                .wrap_err_with(|| format!("failed to open journal {}", input.display()))?,
+        );
+        let mut writer = csv::WriterBuilder::new()
+            .flexible(true)
+            .from_path(&path)
+            .wrap_err_with(|| format!("failed to create {}", path.display()))?;
+        // created right away so the file is removed even if transcoding fails
+        let journal = Journal::transcoded(path);
+
+        writer.write_record(COLUMNS)?;
+        for (index, line) in reader.lines().enumerate() {
+            let line = line.wrap_err("failed to read journal")?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let object = parse_object(&line)
+                .wrap_err_with(|| format!("malformed JSON record on line {}", index + 1))?;
+            writer.write_record(COLUMNS.iter().map(|column| {
+                object
+                    .iter()
+                    .find(|(key, _)| key == column)
+                    .map(|(_, value)| value.as_str())
+                    .unwrap_or_default()
+            }))?;
+        }
+        writer
+            .flush()
+            .wrap_err("failed to write transcoded journal")?;
+        debug!(path = %journal.path.display(), "transcoded JSON lines journal");
+
+        Ok(journal)
+    }
+}
+
+/// Parses flat JSON object into its keys and values. Strings are unescaped, numbers and literals are kept
+/// as written and `null` is turned into empty value. Nested objects and arrays are not supported
+fn parse_object(line: &str) -> Result<Vec<(String, String)>> {
+    let mut chars = line.trim().chars().peekable();
+    let mut object = Vec::new();
+    if chars.next() != Some('{') {
+        return Err(eyre!("expected '{{'"));
+    }
+
+    loop {
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            Some('}') if object.is_empty() => break,
+            Some('"') => (),
+            _ => return Err(eyre!("expected key")),
+        }
+        let key = parse_string(&mut chars)?;
+
+        skip_whitespace(&mut chars);
+        if chars.next() != Some(':') {
+            return Err(eyre!("expected ':' after key '{key}'"));
+        }
+        skip_whitespace(&mut chars);
+
+        let value = match chars.peek() {
+            Some('"') => {
+                chars.next();
+                parse_string(&mut chars)?
+            }
+            Some('{' | '[') => return Err(eyre!("nested value of key '{key}' is not supported")),
+            _ => {
+                let mut literal = String::new();
+                while let Some(c) = chars.next_if(|c| !matches!(c, ',' | '}')) {
+                    literal.push(c);
+                }
+                match literal.trim() {
+                    "" => return Err(eyre!("missing value of key '{key}'")),
+                    "null" => String::new(),
+                    literal => literal.to_string(),
+                }
+            }
+        };
+        object.push((key, value));
+
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            Some(',') => continue,
+            Some('}') => break,
+            _ => return Err(eyre!("expected ',' or '}}'")),
+        }
+    }
+
+    skip_whitespace(&mut chars);
+    match chars.next() {
+        None => Ok(object),
+        Some(_) => Err(eyre!("unexpected characters after the object")),
+    }
+}
+
+fn skip_whitespace(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+/// Parses string after its opening quote
+fn parse_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String> {
+    let mut string = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(string),
+            Some('\\') => match chars.next() {
+                Some('n') => string.push('\n'),
+                Some('t') => string.push('\t'),
+                Some('r') => string.push('\r'),
+                Some('u') => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    let c = u32::from_str_radix(&code, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| eyre!("invalid escape '\\u{code}'"))?;
+                    string.push(c);
+                }
+                Some(c @ ('"' | '\\' | '/')) => string.push(c),
+                _ => return Err(eyre!("invalid escape in string")),
+            },
+            Some(c) => string.push(c),
+            None => return Err(eyre!("unterminated string")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let registry = FormatRegistry::default();
+        let tests = vec![
+            (
+                "csv",
+                &b"type,client,tx,amount\ndeposit,1,1,1"[..],
+                Some("csv"),
+            ),
+            ("tsv", b"type\tclient\ttx\tamount\n", Some("tsv")),
+            (
+                "tsv after comment",
+                b"# note, with comma\n\ntype\tclient\ttx\tamount\n",
+                Some("tsv"),
+            ),
+            (
+                "crlf",
+                b"# exported on Windows\r\n\r\ntype,client,tx,amount\r\n",
+                Some("csv"),
+            ),
+            ("tsv crlf", b"type\tclient\ttx\tamount\r\n", Some("tsv")),
+            ("jsonl", b"  {\"type\": \"deposit\"}\n", Some("jsonl")),
+            ("unknown", b"type client tx amount\n", None),
+        ];
+
+        for (name, head, want) in tests {
+            assert_eq!(
+                registry.detect(head).map(|format| format.name()),
+                want,
+                "failed test {name}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_object() {
+        let pair = |key: &str, value: &str| (key.to_string(), value.to_string());
+        let tests = vec![
+            (
+                "numbers kept as written",
+                r#"{"type": "deposit", "client": 1, "tx": 2, "amount": 1.00010}"#,
+                Some(vec![
+                    pair("type", "deposit"),
+                    pair("client", "1"),
+                    pair("tx", "2"),
+                    pair("amount", "1.00010"),
+                ]),
+            ),
+            (
+                "null and escapes",
+                r#"{"type":"dis\u0070ute","amount":null}"#,
+                Some(vec![pair("type", "dispute"), pair("amount", "")]),
+            ),
+            ("empty", "{}", Some(vec![])),
+            ("nested", r#"{"type": {"a": 1}}"#, None),
+            ("unterminated", r#"{"type": "deposit"#, None),
+            ("trailing", r#"{"type": "deposit"} x"#, None),
+        ];
+
+        for (name, line, want) in tests {
+            assert_eq!(parse_object(line).ok(), want, "failed test {name}");
+        }
+    }
+}
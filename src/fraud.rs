@@ -0,0 +1,159 @@
+use eyre::{eyre, Context, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+/// Optional fraud-screening rules, client breaking any of them is flagged in the report.
+/// Flagging doesn't block any operations, it only marks the account for review.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FraudRules {
+    pub deposit_velocity: Option<DepositVelocity>,
+    /// Flag client whose chargebacks exceed this percentage of their deposits
+    pub max_chargeback_ratio: Option<Decimal>,
+}
+
+/// Flag client who makes more than `max_deposits` deposits within `within_records` processed records
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepositVelocity {
+    pub max_deposits: usize,
+    pub within_records: u64,
+}
+
+impl FromStr for DepositVelocity {
+    type Err = eyre::Report;
+
+    /// Parses `<max_deposits>:<within_records>`, for example `5:100`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (max_deposits, within_records) = s.split_once(':').ok_or(eyre!(
+            "invalid deposit velocity '{s}', expected <max_deposits>:<within_records>"
+        ))?;
+
+        Ok(DepositVelocity {
+            max_deposits: max_deposits
+                .trim()
+                .parse()
+                .wrap_err("failed to parse max deposits")?,
+            within_records: within_records
+                .trim()
+                .parse()
+                .wrap_err("failed to parse records window")?,
+        })
+    }
+}
+
+/// Per-client rolling counters the [FraudRules] are evaluated against
+#[derive(Clone, Debug, Default)]
+pub struct FraudCounters {
+    /// Sequence numbers of client's deposits within the velocity window
+    recent_deposits: VecDeque<u64>,
+    deposits: u64,
+    chargebacks: u64,
+}
+
+impl FraudCounters {
+    /// Counters of account restored from a snapshot, see [crate::state], the velocity window starts empty
+    pub fn restored(deposits: u64, chargebacks: u64) -> FraudCounters {
+        FraudCounters {
+            deposits,
+            chargebacks,
+            ..FraudCounters::default()
+        }
+    }
+
+    /// Number of deposits since the account was opened
+    pub fn deposits(&self) -> u64 {
+        self.deposits
+    }
+
+    /// Records deposit made at given sequence number, returns name of the broken rule if any
+    pub fn record_deposit(&mut self, sequence: u64, rules: &FraudRules) -> Option<&'static str> {
+        self.deposits += 1;
+
+        let velocity = rules.deposit_velocity?;
+        self.recent_deposits.push_back(sequence);
+        while let Some(oldest) = self.recent_deposits.front() {
+            if sequence - oldest < velocity.within_records {
+                break;
+            }
+            self.recent_deposits.pop_front();
+        }
+
+        (self.recent_deposits.len() > velocity.max_deposits).then_some("deposit velocity")
+    }
+
+    /// Number of chargebacks since the account was opened
+    pub fn chargebacks(&self) -> u64 {
+        self.chargebacks
+    }
+
+    /// Records chargeback, returns name of the broken rule if any
+    pub fn record_chargeback(&mut self, rules: &FraudRules) -> Option<&'static str> {
+        self.chargebacks += 1;
+
+        let max_ratio = rules.max_chargeback_ratio?;
+        let ratio = match self.deposits {
+            0 => Decimal::ONE_HUNDRED,
+            deposits => {
+                Decimal::from(self.chargebacks) * Decimal::ONE_HUNDRED / Decimal::from(deposits)
+            }
+        };
+        (ratio > max_ratio).then_some("chargeback ratio")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_deposit_velocity() {
+        let rules = FraudRules {
+            deposit_velocity: Some(DepositVelocity {
+                max_deposits: 2,
+                within_records: 10,
+            }),
+            ..Default::default()
+        };
+        let mut counters = FraudCounters::default();
+
+        assert_eq!(counters.record_deposit(0, &rules), None);
+        assert_eq!(counters.record_deposit(5, &rules), None);
+        assert_eq!(counters.record_deposit(9, &rules), Some("deposit velocity"));
+        assert_eq!(
+            counters.record_deposit(16, &rules),
+            None,
+            "first two left the window"
+        );
+    }
+
+    #[test]
+    fn test_chargeback_ratio() {
+        let rules = FraudRules {
+            max_chargeback_ratio: Some(dec!(50)),
+            ..Default::default()
+        };
+        let mut counters = FraudCounters::default();
+        for sequence in 0..4 {
+            counters.record_deposit(sequence, &rules);
+        }
+
+        assert_eq!(counters.record_chargeback(&rules), None);
+        assert_eq!(counters.record_chargeback(&rules), None, "exactly 50%");
+        assert_eq!(counters.record_chargeback(&rules), Some("chargeback ratio"));
+    }
+
+    #[test]
+    fn test_parse_deposit_velocity() {
+        assert_eq!(
+            "5:100".parse::<DepositVelocity>().unwrap(),
+            DepositVelocity {
+                max_deposits: 5,
+                within_records: 100
+            }
+        );
+        assert!("5".parse::<DepositVelocity>().is_err());
+    }
+}
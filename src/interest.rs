@@ -0,0 +1,172 @@
+//! Interest accrued once the journal is processed, so the report already has the balances which were otherwise
+//! adjusted in a spreadsheet after the run. Simple interest of `rate` percent per period is credited to positive
+//! available balances of active accounts. The number of periods is either given, or counted as whole periods
+//! between `--from` and `--to` of the time window. Interest is credited like an administrative adjustment, it is
+//! in the report but not in the write-ahead log, the audit trail nor the decision log
+use crate::accounts::{AccountStatus, Accounts};
+use crate::aliases::*;
+use crate::amount::{from_decimal, into_decimal, PRECISION};
+use crate::timestamp::TimeWindow;
+use eyre::{eyre, Result};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InterestConfig {
+    /// Percentage of the available balance credited per period, no interest is accrued if not set
+    pub rate: Option<Decimal>,
+    /// Number of periods to accrue
+    pub periods: Option<u32>,
+    /// Length of the period in seconds, periods are counted over the time window if their number is not set
+    pub period: Option<u64>,
+}
+
+impl InterestConfig {
+    /// Accrual of the configured rate, `None` if no rate is set. Fails if the number of periods can't be found out
+    pub fn accrual(&self, window: TimeWindow) -> Result<Option<InterestAccrual>> {
+        let Some(rate) = self.rate else {
+            return Ok(None);
+        };
+        if rate.is_sign_negative() {
+            return Err(eyre!("invalid interest rate {rate}, it can't be negative"));
+        }
+        let periods = match (self.periods, self.period, window.from, window.to) {
+            (Some(periods), _, _, _) => periods,
+            (None, Some(period), Some(from), Some(to)) if period > 0 => {
+                u32::try_from(to.saturating_sub(from).max(0) as u64 / period)?
+            }
+            _ => {
+                return Err(eyre!(
+                    "interest needs the number of periods, set it by --periods or by --interest-period with --from and --to"
+                ))
+            }
+        };
+        Ok(Some(InterestAccrual { rate, periods }))
+    }
+}
+
+/// Simple interest of `rate` percent per period over `periods` periods
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InterestAccrual {
+    pub rate: Decimal,
+    pub periods: u32,
+}
+
+impl InterestAccrual {
+    /// Interest of the balance, rounded half to even to [PRECISION] decimal places. `None` if it doesn't fit
+    pub fn of(&self, balance: Amount) -> Option<Amount> {
+        let interest = into_decimal(balance)
+            .checked_mul(self.rate)?
+            .checked_mul(Decimal::from(self.periods))?
+            .checked_div(Decimal::ONE_HUNDRED)?
+            .round_dp_with_strategy(PRECISION, RoundingStrategy::MidpointNearestEven)
+            .normalize();
+        from_decimal(interest).ok()
+    }
+
+    /// Credits the interest to active accounts with positive available balance. Returns number of credited
+    /// accounts and the interest credited in total
+    pub fn apply(&self, accounts: &mut Accounts) -> (u64, Amount) {
+        let balances: Vec<_> = accounts
+            .iter()
+            .filter(|account| account.status == AccountStatus::Active)
+            .filter(|account| account.available > Amount::ZERO)
+            .map(|account| (account.client_id, account.available))
+            .collect();
+
+        let (mut credited, mut total) = (0, Amount::ZERO);
+        for (client_id, available) in balances {
+            let Some(interest) = self.of(available).filter(|interest| !interest.is_zero()) else {
+                continue;
+            };
+            match accounts.adjust_credit(client_id, interest) {
+                Ok(()) => {
+                    credited += 1;
+                    total = total.saturating_add(interest);
+                }
+                Err(err) => warn!(%err, client_id, %interest, "failed to credit interest"),
+            }
+        }
+        info!(
+            rate = %self.rate,
+            periods = self.periods,
+            credited,
+            %total,
+            "accrued interest"
+        );
+        (credited, total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accrual_periods() {
+        let config = |periods, period| InterestConfig {
+            rate: Some(Decimal::ONE),
+            periods,
+            period,
+        };
+        let window = TimeWindow {
+            from: Some(0),
+            to: Some(10 * 86_400 + 5),
+        };
+        let tests = vec![
+            ("periods", config(Some(3), None), window, Some(3)),
+            (
+                "periods win over window",
+                config(Some(3), Some(86_400)),
+                window,
+                Some(3),
+            ),
+            (
+                "days of window",
+                config(None, Some(86_400)),
+                window,
+                Some(10),
+            ),
+            (
+                "unbounded window",
+                config(None, Some(86_400)),
+                TimeWindow::default(),
+                None,
+            ),
+            ("nothing", config(None, None), window, None),
+        ];
+
+        for (name, config, window, want) in tests {
+            assert_eq!(
+                config
+                    .accrual(window)
+                    .ok()
+                    .flatten()
+                    .map(|accrual| accrual.periods),
+                want,
+                "failed test {name}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut accounts = Accounts::default();
+        accounts.deposit(1, amount!(100)).unwrap();
+        accounts.deposit(2, amount!(50)).unwrap();
+        accounts.lock(2).unwrap();
+        accounts.deposit(3, amount!(0.01)).unwrap();
+        accounts.withdraw(3, amount!(0.01)).unwrap();
+
+        let accrual = InterestAccrual {
+            rate: rust_decimal_macros::dec!(0.5),
+            periods: 3,
+        };
+        assert_eq!(accrual.apply(&mut accounts), (1, amount!(1.5)));
+        assert_eq!(accounts.get(1).unwrap().available, amount!(101.5));
+        assert_eq!(accounts.get(2).unwrap().available, amount!(50), "frozen");
+        assert_eq!(accounts.integrity_mismatch(), None);
+    }
+}
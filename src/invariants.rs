@@ -0,0 +1,196 @@
+use crate::accounts::{AccountView, Accounts};
+use crate::aliases::*;
+use eyre::eyre;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use tracing::error;
+
+/// What to do when an invariant is broken
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InvariantMode {
+    Log,
+    Panic,
+}
+
+impl FromStr for InvariantMode {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "log" => Ok(InvariantMode::Log),
+            "panic" => Ok(InvariantMode::Panic),
+            _ => Err(eyre!(
+                "invalid invariant mode '{s}', expected one of log, panic"
+            )),
+        }
+    }
+}
+
+/// Broken invariant of single account
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvariantViolation {
+    /// `total` is not equal to `available + held`, or the sum overflows
+    Unbalanced(AccountView),
+    /// Balances of frozen account were changed
+    FrozenChanged {
+        before: Box<AccountView>,
+        after: Box<AccountView>,
+    },
+}
+
+impl Display for InvariantViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvariantViolation::Unbalanced(view) => write!(
+                f,
+                "client {} total {} is not available {} + held {}",
+                view.client_id, view.total, view.available, view.held
+            ),
+            InvariantViolation::FrozenChanged { before, after } => write!(
+                f,
+                "frozen account of client {} changed from {}/{}/{} to {}/{}/{}",
+                before.client_id,
+                before.available,
+                before.held,
+                before.total,
+                after.available,
+                after.held,
+                after.total
+            ),
+        }
+    }
+}
+
+/// Checks single account after an operation. `before` is the state before the operation, `None` for new accounts.
+/// Unlock is the only operation allowed to touch frozen account, so it skips the frozen check
+pub fn check(
+    before: Option<&AccountView>,
+    after: &AccountView,
+    unlock: bool,
+) -> Result<(), InvariantViolation> {
+    if after.available.checked_add(after.held) != Some(after.total) {
+        return Err(InvariantViolation::Unbalanced(*after));
+    }
+
+    match before {
+        Some(before)
+            if !unlock
+                && before.status.is_frozen()
+                && (before.available, before.held, before.total)
+                    != (after.available, after.held, after.total) =>
+        {
+            Err(InvariantViolation::FrozenChanged {
+                before: Box::new(*before),
+                after: Box::new(*after),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Debug aid checking accounts touched by every operation, see [check]. Breaks are logged or panic,
+/// depending on [InvariantMode], with index of the offending operation
+pub struct InvariantChecker {
+    mode: InvariantMode,
+    /// States of the accounts taken before the current operation
+    before: Vec<(ClientID, Option<AccountView>)>,
+    /// Number of checked operations
+    operations: u64,
+    violations: u64,
+}
+
+impl InvariantChecker {
+    pub fn new(mode: InvariantMode) -> Self {
+        InvariantChecker {
+            mode,
+            before: Vec::with_capacity(2),
+            operations: 0,
+            violations: 0,
+        }
+    }
+
+    /// Takes state of the accounts which are about to be changed by the next operation
+    pub fn before(&mut self, accounts: &Accounts, clients: impl Iterator<Item = ClientID>) {
+        self.before.clear();
+        self.before
+            .extend(clients.map(|client_id| (client_id, accounts.get(client_id))));
+    }
+
+    /// Checks the accounts captured by [InvariantChecker::before]
+    pub fn after(&mut self, accounts: &Accounts, operation: &str, unlock: bool) {
+        self.operations += 1;
+        for (client_id, before) in self.before.drain(..) {
+            let Some(after) = accounts.get(client_id) else {
+                continue;
+            };
+            if let Err(violation) = check(before.as_ref(), &after, unlock) {
+                self.violations += 1;
+                match self.mode {
+                    InvariantMode::Log => {
+                        error!(%violation, operation, index = self.operations, "invariant broken")
+                    }
+                    InvariantMode::Panic => panic!(
+                        "invariant broken by {operation} #{}: {violation}",
+                        self.operations
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Number of broken invariants found so far
+    pub fn violations(&self) -> u64 {
+        self.violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::AccountStatus;
+
+    fn view(available: Amount, held: Amount, total: Amount, status: AccountStatus) -> AccountView {
+        AccountView {
+            client_id: 1,
+            available,
+            held,
+            total,
+            status,
+            flagged: false,
+            open_disputes: 0,
+            chargebacks: 0,
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let active = view(amount!(5), amount!(5), amount!(10), AccountStatus::Active);
+        let frozen = view(amount!(5), amount!(5), amount!(10), AccountStatus::Frozen);
+        let changed = view(amount!(6), amount!(5), amount!(11), AccountStatus::Frozen);
+        let unbalanced = view(amount!(6), amount!(5), amount!(10), AccountStatus::Active);
+
+        let tests = vec![
+            ("new account", None, active, false, true),
+            ("balanced", Some(active), active, false, true),
+            ("unbalanced", Some(active), unbalanced, false, false),
+            ("frozen unchanged", Some(frozen), frozen, false, true),
+            ("frozen changed", Some(frozen), changed, false, false),
+            (
+                "frozen changed by unlock",
+                Some(frozen),
+                changed,
+                true,
+                true,
+            ),
+        ];
+
+        for (name, before, after, unlock, want) in tests {
+            assert_eq!(
+                check(before.as_ref(), &after, unlock).is_ok(),
+                want,
+                "failed test {name}"
+            );
+        }
+    }
+}
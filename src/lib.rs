@@ -0,0 +1,81 @@
+//! Payment engine processing journal of client transactions into the final state of their accounts.
+//! The binary wires the modules into a pipeline of parser, dispute look-up and processing threads,
+//! library users can use [accounts::Accounts] directly.
+
+/// [aliases::Amount] literal in tests, e.g. `amount!(-1.5)`, the same with and without `fixed-amount` feature
+#[cfg(test)]
+macro_rules! amount {
+    ($($value:tt)+) => {
+        $crate::amount::from_decimal(rust_decimal_macros::dec!($($value)+)).unwrap()
+    };
+}
+
+pub mod accounts;
+pub mod affinity;
+pub mod alerts;
+pub mod aliases;
+pub mod amount;
+pub mod audit;
+pub mod binary;
+pub mod channel;
+pub mod checkpoint;
+pub mod cli;
+pub mod client_filter;
+pub mod client_remap;
+pub mod compress;
+pub mod config;
+pub mod currency;
+pub mod dead_letter;
+pub mod decisions;
+pub mod dedup;
+pub mod dispute_cache;
+pub mod dispute_look_up;
+pub mod engine;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+pub mod fees;
+#[cfg(feature = "cdylib")]
+pub mod ffi;
+pub mod fixed;
+pub mod fixtures;
+pub mod format;
+pub mod fraud;
+pub mod interest;
+pub mod invariants;
+pub mod limits;
+pub mod logger;
+pub mod manifest;
+pub mod memory;
+pub mod offsets;
+pub mod order;
+pub mod parser;
+pub mod pipeline;
+pub mod processor;
+pub mod progress;
+pub mod provenance;
+pub mod read_ahead;
+pub mod record_types;
+pub mod replica;
+pub mod report;
+pub mod rules;
+pub mod run_guard;
+pub mod run_id;
+pub mod run_manifest;
+pub mod sample;
+pub mod seen;
+pub mod segments;
+pub mod simulate;
+pub mod sink;
+pub mod sort;
+pub mod spill;
+pub mod split;
+pub mod state;
+pub mod summary;
+pub mod timestamp;
+pub mod timings;
+pub mod wal;
+pub mod warnings;
+pub mod webhook;
+
+use aliases::*;
+use channel::{DisputeLookUpMessage, TransactionMessage};
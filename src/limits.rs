@@ -0,0 +1,203 @@
+use crate::accounts::AccountError;
+use crate::aliases::*;
+use eyre::{eyre, Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Limits applied to single client, unset limit means the operation is not limited
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub max_deposit: Option<Amount>,
+    pub max_withdrawal: Option<Amount>,
+    /// Maximum `total` the account can reach by deposits or incoming transfers
+    pub max_balance: Option<Amount>,
+}
+
+impl Limits {
+    /// Checks deposit of `amount` to the account which currently has `total` funds, the amount has to be positive
+    pub fn check_deposit(&self, amount: Amount, total: Amount) -> Result<(), AccountError> {
+        check_positive(amount)?;
+        check("max_deposit", self.max_deposit, amount)?;
+        check(
+            "max_balance",
+            self.max_balance,
+            total.saturating_add(amount),
+        )
+    }
+
+    /// Checks withdrawal of `amount`, negative one would credit the account past the limits
+    pub fn check_withdrawal(&self, amount: Amount) -> Result<(), AccountError> {
+        check_positive(amount)?;
+        check("max_withdrawal", self.max_withdrawal, amount)
+    }
+
+    /// Limits set in `self` take precedence over the ones in `fallback`
+    fn or(self, fallback: Limits) -> Limits {
+        Limits {
+            max_deposit: self.max_deposit.or(fallback.max_deposit),
+            max_withdrawal: self.max_withdrawal.or(fallback.max_withdrawal),
+            max_balance: self.max_balance.or(fallback.max_balance),
+        }
+    }
+}
+
+fn check_positive(amount: Amount) -> Result<(), AccountError> {
+    match amount > Amount::ZERO {
+        true => Ok(()),
+        false => Err(AccountError::NonPositiveAmount(amount)),
+    }
+}
+
+fn check(limit: &'static str, max: Option<Amount>, requested: Amount) -> Result<(), AccountError> {
+    match max {
+        Some(max) if requested > max => Err(AccountError::LimitExceeded {
+            limit,
+            max,
+            requested,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Global and per-client limits consulted by [crate::accounts::Accounts] before applying deposits and withdrawals.
+/// Loaded from CSV file with `client,max_deposit,max_withdrawal,max_balance` columns, where `*` in the client column
+/// sets the global limits. Empty value means no limit, per-client limits override the global ones.
+#[derive(Debug, Default)]
+pub struct LimitPolicy {
+    global: Limits,
+    clients: HashMap<ClientID, Limits>,
+}
+
+impl LimitPolicy {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .wrap_err_with(|| format!("failed to open limits file {}", path.display()))?;
+
+        let mut policy = LimitPolicy::default();
+        for (index, record) in reader.records().enumerate() {
+            let record = record.wrap_err("failed to read limits file")?;
+            let limits = Limits {
+                max_deposit: parse_limit(record.get(1))?,
+                max_withdrawal: parse_limit(record.get(2))?,
+                max_balance: parse_limit(record.get(3))?,
+            };
+
+            match record.get(0) {
+                Some("*") => policy.global = limits,
+                Some(client_id) => {
+                    let client_id = client_id.parse::<ClientID>().wrap_err_with(|| {
+                        eyre!("invalid client id on line {} of limits file", index + 2)
+                    })?;
+                    policy.clients.insert(client_id, limits);
+                }
+                None => return Err(eyre!("missing client on line {} of limits file", index + 2)),
+            }
+        }
+
+        Ok(policy)
+    }
+
+    /// Returns effective limits of the client
+    pub fn for_client(&self, client_id: ClientID) -> Limits {
+        match self.clients.get(&client_id) {
+            Some(limits) => limits.or(self.global),
+            None => self.global,
+        }
+    }
+}
+
+fn parse_limit(value: Option<&str>) -> Result<Option<Amount>> {
+    match value {
+        Some(value) if !value.is_empty() => Ok(Some(
+            Amount::from_str_exact(value).wrap_err_with(|| eyre!("invalid limit '{value}'"))?,
+        )),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_client() {
+        let policy = LimitPolicy {
+            global: Limits {
+                max_deposit: Some(amount!(100)),
+                max_withdrawal: Some(amount!(50)),
+                max_balance: None,
+            },
+            clients: HashMap::from([(
+                1,
+                Limits {
+                    max_deposit: Some(amount!(1000)),
+                    ..Default::default()
+                },
+            )]),
+        };
+
+        assert_eq!(
+            policy.for_client(1),
+            Limits {
+                max_deposit: Some(amount!(1000)),
+                max_withdrawal: Some(amount!(50)),
+                max_balance: None,
+            }
+        );
+        assert_eq!(policy.for_client(2), policy.global);
+    }
+
+    #[test]
+    fn test_check_deposit() {
+        let limits = Limits {
+            max_deposit: Some(amount!(10)),
+            max_balance: Some(amount!(15)),
+            ..Default::default()
+        };
+
+        assert_eq!(limits.check_deposit(amount!(10), amount!(5)), Ok(()));
+        assert_eq!(
+            limits.check_deposit(amount!(11), amount!(0)),
+            Err(AccountError::LimitExceeded {
+                limit: "max_deposit",
+                max: amount!(10),
+                requested: amount!(11)
+            })
+        );
+        assert_eq!(
+            limits.check_deposit(amount!(10), amount!(6)),
+            Err(AccountError::LimitExceeded {
+                limit: "max_balance",
+                max: amount!(15),
+                requested: amount!(16)
+            })
+        );
+        assert_eq!(
+            limits.check_deposit(Amount::ZERO, amount!(0)),
+            Err(AccountError::NonPositiveAmount(Amount::ZERO))
+        );
+    }
+
+    #[test]
+    fn test_check_withdrawal() {
+        let limits = Limits {
+            max_withdrawal: Some(amount!(10)),
+            ..Default::default()
+        };
+
+        assert_eq!(limits.check_withdrawal(amount!(10)), Ok(()));
+        let negative = Amount::ZERO.saturating_sub(amount!(100));
+        assert_eq!(
+            limits.check_withdrawal(negative),
+            Err(AccountError::NonPositiveAmount(negative)),
+            "negative withdrawal doesn't slip under the limit"
+        );
+        assert_eq!(
+            Limits::default().check_withdrawal(Amount::ZERO),
+            Err(AccountError::NonPositiveAmount(Amount::ZERO)),
+            "checked without any limit set"
+        );
+    }
+}
@@ -1,18 +1,397 @@
+use crate::run_id::RunId;
+use eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
 use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::{fmt::layer, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-pub fn init() -> WorkerGuard {
-    let (file_appender, file_guard) = tracing_appender::non_blocking(
-        tracing_appender::rolling::daily(".", format!("{}.log", env!("CARGO_PKG_NAME"))),
-    );
+/// Format of the log file, the console log is always human readable
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Plain,
+    /// One JSON object per line with the fields of the event and of all its spans, for log shippers
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(LogFormat::Plain),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(eyre!(
+                "invalid log format '{s}', expected one of plain, json"
+            )),
+        }
+    }
+}
+
+/// Logs into daily rolling file and to stderr, so the logs don't get mixed with the report on stdout.
+/// Every line of the file starts with the run ID. With `per_run` the file is named after the run instead,
+/// e.g. `tren.01ARYZ6S410000000000000000.log`, and is not rolled. The file is written into `dir`, or into
+/// [default_log_dir] if it is not set. If the directory can't be written, the log goes into the temp directory
+pub fn init(
+    filter: &str,
+    format: LogFormat,
+    run_id: RunId,
+    per_run: bool,
+    dir: Option<&Path>,
+) -> WorkerGuard {
+    let dir = writable_log_dir(dir.map(Path::to_path_buf).unwrap_or_else(default_log_dir));
+    let file = match per_run {
+        true => tracing_appender::rolling::never(
+            &dir,
+            format!("{}.{run_id}.log", env!("CARGO_PKG_NAME")),
+        ),
+        false => tracing_appender::rolling::daily(&dir, format!("{}.log", env!("CARGO_PKG_NAME"))),
+    };
+    let (file_appender, file_guard) = tracing_appender::non_blocking(file);
+    let (plain_appender, json_appender) = match format {
+        LogFormat::Plain => (Some(file_appender), None),
+        LogFormat::Json => (None, Some(file_appender)),
+    };
 
     tracing_subscriber::Registry::default()
-        .with(EnvFilter::new(format!("{}=debug", env!("CARGO_PKG_NAME"))))
+        .with(EnvFilter::new(filter))
         // file logger settings
-        .with(layer().with_ansi(false).with_writer(file_appender))
+        .with(plain_appender.map(|appender| {
+            layer()
+                .with_ansi(false)
+                .event_format(WithRunId {
+                    run_id,
+                    inner: tracing_subscriber::fmt::format().with_ansi(false),
+                })
+                .with_writer(appender)
+        }))
+        .with(json_appender.map(|appender| {
+            layer()
+                .with_ansi(false)
+                .fmt_fields(JsonFields)
+                .event_format(JsonLines {
+                    run_id: Some(run_id),
+                })
+                .with_writer(appender)
+        }))
         // console logger settings
-        .with(tracing_subscriber::fmt::layer().with_ansi(true).pretty())
+        .with(
+            layer()
+                .with_ansi(true)
+                .pretty()
+                .with_writer(std::io::stderr),
+        )
         .init();
 
     file_guard
 }
+
+/// Directory of the log file unless `--log-dir` is set. On Unix it is the working directory. Windows batch jobs
+/// usually run in a directory they can't write, e.g. `C:\Windows\System32`, so the log goes next to the
+/// executable instead, or into the temp directory if that one is read-only too, e.g. under `Program Files`
+pub fn default_log_dir() -> PathBuf {
+    if cfg!(windows) {
+        std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(Path::to_path_buf))
+            .filter(|dir| is_writable(dir))
+            .unwrap_or_else(std::env::temp_dir)
+    } else {
+        PathBuf::from(".")
+    }
+}
+
+/// The directory if it can be created and written, the appender panics otherwise. Falls back to the temp
+/// directory. Long paths need no special care, std adds the `\\?\` prefix on Windows where it is needed
+fn writable_log_dir(dir: PathBuf) -> PathBuf {
+    // the appender also needs the directory as UTF-8
+    if dir.to_str().is_some() && is_writable(&dir) {
+        return dir;
+    }
+    let temp = std::env::temp_dir();
+    eprintln!(
+        "log directory {} is not writable, logging into {}",
+        dir.display(),
+        temp.display()
+    );
+    temp
+}
+
+fn is_writable(dir: &Path) -> bool {
+    let probe = dir.join(format!(
+        ".{}-{}.probe",
+        env!("CARGO_PKG_NAME"),
+        std::process::id()
+    ));
+    let writable = std::fs::create_dir_all(dir)
+        .and_then(|_| std::fs::File::create(&probe))
+        .is_ok();
+    let _ = std::fs::remove_file(&probe);
+    writable
+}
+
+/// Prefixes every line formatted by the inner formatter with the run ID
+struct WithRunId<F> {
+    run_id: RunId,
+    inner: F,
+}
+
+impl<S, N, F> FormatEvent<S, N> for WithRunId<F>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    F: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        write!(writer, "{} ", self.run_id)?;
+        self.inner.format_event(ctx, writer, event)
+    }
+}
+
+/// Formats event as JSON object on a single line, e.g.
+/// `{"timestamp":"2022-09-01T10:00:00Z","run_id":"01ARYZ6S41...","level":"DEBUG","target":"tren::dispute_look_up","fields":{"message":"..."},"spans":[{"name":"look_up_request","client_id":1}]}`
+pub struct JsonLines {
+    pub run_id: Option<RunId>,
+}
+
+impl<S> FormatEvent<S, JsonFields> for JsonLines
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, JsonFields>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let timestamp = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .map_err(|_| fmt::Error)?;
+        let metadata = event.metadata();
+        write!(writer, r#"{{"timestamp":"{timestamp}","#)?;
+        if let Some(run_id) = self.run_id {
+            write!(writer, r#""run_id":"{run_id}","#)?;
+        }
+        write!(writer, r#""level":"{}","target":"#, metadata.level())?;
+        write_string(&mut writer, metadata.target())?;
+
+        writer.write_str(r#","fields":{"#)?;
+        let mut visitor = JsonVisitor::new(writer.by_ref());
+        event.record(&mut visitor);
+        visitor.result?;
+        writer.write_char('}')?;
+
+        if let Some(scope) = ctx.event_scope() {
+            writer.write_str(r#","spans":["#)?;
+            for (position, span) in scope.from_root().enumerate() {
+                if position > 0 {
+                    writer.write_char(',')?;
+                }
+                writer.write_str(r#"{"name":"#)?;
+                write_string(&mut writer, span.name())?;
+                match span.extensions().get::<FormattedFields<JsonFields>>() {
+                    Some(fields) if !fields.fields.is_empty() => {
+                        write!(writer, ",{}}}", fields.fields)?
+                    }
+                    _ => writer.write_char('}')?,
+                }
+            }
+            writer.write_char(']')?;
+        }
+        writeln!(writer, "}}")
+    }
+}
+
+/// Formats fields of spans as members of JSON object without the braces, so [JsonLines] can add them to the span
+pub struct JsonFields;
+
+impl<'writer> FormatFields<'writer> for JsonFields {
+    fn format_fields<R: tracing_subscriber::field::RecordFields>(
+        &self,
+        writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        let mut visitor = JsonVisitor::new(writer);
+        fields.record(&mut visitor);
+        visitor.result
+    }
+
+    fn add_fields(
+        &self,
+        current: &'writer mut FormattedFields<Self>,
+        fields: &tracing::span::Record<'_>,
+    ) -> fmt::Result {
+        if !current.fields.is_empty() {
+            current.fields.push(',');
+        }
+        self.format_fields(current.as_writer(), fields)
+    }
+}
+
+/// Writes recorded fields as `"name":value` separated by commas. Numbers and booleans are kept as JSON values,
+/// everything else is written as string
+struct JsonVisitor<'writer> {
+    writer: Writer<'writer>,
+    empty: bool,
+    result: fmt::Result,
+}
+
+impl<'writer> JsonVisitor<'writer> {
+    fn new(writer: Writer<'writer>) -> Self {
+        JsonVisitor {
+            writer,
+            empty: true,
+            result: Ok(()),
+        }
+    }
+
+    fn record(&mut self, field: &Field, value: impl FnOnce(&mut Writer<'writer>) -> fmt::Result) {
+        if self.result.is_err() {
+            return;
+        }
+        let separator = if self.empty { "" } else { "," };
+        self.empty = false;
+        self.result = self
+            .writer
+            .write_str(separator)
+            .and_then(|_| write_string(&mut self.writer, field.name()))
+            .and_then(|_| self.writer.write_char(':'))
+            .and_then(|_| value(&mut self.writer));
+    }
+}
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        match value.is_finite() {
+            true => self.record(field, |writer| write!(writer, "{value}")),
+            false => self.record(field, |writer| write_string(writer, &value.to_string())),
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.record(field, |writer| write!(writer, "{value}"));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.record(field, |writer| write!(writer, "{value}"));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.record(field, |writer| write!(writer, "{value}"));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, |writer| write_string(writer, value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record(field, |writer| write_string(writer, &format!("{value:?}")));
+    }
+}
+
+/// Writes JSON string literal, quotes, backslashes and control characters are escaped
+fn write_string(writer: &mut impl Write, s: &str) -> fmt::Result {
+    writer.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => writer.write_str(r#"\""#)?,
+            '\\' => writer.write_str(r"\\")?,
+            '\n' => writer.write_str(r"\n")?,
+            '\r' => writer.write_str(r"\r")?,
+            '\t' => writer.write_str(r"\t")?,
+            c if c.is_control() => write!(writer, "\\u{:04x}", c as u32)?,
+            c => writer.write_char(c)?,
+        }
+    }
+    writer.write_char('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Collects everything written by the logger
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_writable_log_dir() {
+        let temp = std::env::temp_dir();
+        let file = temp.join(format!("tren-test-log-dir-{}", std::process::id()));
+        std::fs::write(&file, "").unwrap();
+        let nested = temp.join(format!("tren-test-log-dir-{}.d/logs", std::process::id()));
+        let tests = vec![
+            ("created", nested.clone(), nested.clone()),
+            ("existing", temp.clone(), temp.clone()),
+            ("under a file", file.join("logs"), temp.clone()),
+        ];
+
+        for (name, dir, want) in tests {
+            assert_eq!(writable_log_dir(dir), want, "failed test {name}");
+        }
+        assert_eq!(
+            std::fs::read_dir(&nested).unwrap().count(),
+            0,
+            "probe is removed"
+        );
+        std::fs::remove_file(&file).unwrap();
+        std::fs::remove_dir_all(nested.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::Registry::default().with(
+            layer()
+                .fmt_fields(JsonFields)
+                .event_format(JsonLines {
+                    run_id: Some(RunId::from_parts(0, 1)),
+                })
+                .with_writer(move || writer.clone()),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("look_up_request", client_id = 1u16, tx = 7u32);
+            let _enter = span.enter();
+            tracing::warn!(frozen = true, reason = ?"a \"quoted\"\nline", "dispute ignored");
+        });
+
+        let log = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let (_, rest) = log
+            .split_once(r#""run_id":"00000000000000000000000001","level":"WARN","#)
+            .unwrap();
+        assert_eq!(
+            rest,
+            r#""target":"tren::logger::tests","fields":{"message":"dispute ignored","frozen":true,"reason":"\"a \\\"quoted\\\"\\nline\""},"spans":[{"name":"look_up_request","client_id":1,"tx":7}]}
+"#
+        );
+    }
+}
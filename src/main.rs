@@ -1,118 +1,317 @@
-use std::fs::OpenOptions;
-use std::path::PathBuf;
-
-use aliases::*;
-use channel::{Dispute, DisputeLookUpMessage, TransactionMessage};
-use tracing::{error, info, trace};
-
-use crate::channel::Transaction;
-
-mod accounts;
-mod aliases;
-mod channel;
-mod dispute_look_up;
-mod logger;
-mod parser;
-// mod transaction;
+use tracing::{error, info};
+use tren::cli::Command;
+use tren::compress::{CompressedWriter, Compression};
+use tren::format::FormatRegistry;
+use tren::manifest::{self, Manifest};
+use tren::run_id::RunId;
+use tren::run_manifest::RunManifest;
+use tren::{
+    binary, cli, dead_letter, decisions, fixtures, logger, order, pipeline, report, simulate, sink,
+    sort, split, state,
+};
 
 fn main() {
-    //let _guard = logger::init();
+    let args = match cli::Args::parse() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err:?}");
+            eprintln!("\n{}", cli::USAGE);
+            std::process::exit(1);
+        }
+    };
+    let run_id = RunId::generate();
+    let (log_format, log_per_run) = (args.config.log_format, args.config.log_per_run);
+    let log_dir = args.config.log_dir.as_deref();
+    let _guard = args
+        .config
+        .log_filter
+        .as_deref()
+        .map(|filter| logger::init(filter, log_format, run_id, log_per_run, log_dir));
 
     info!(
         app_name = env!("CARGO_PKG_NAME"),
         version = env!("CARGO_PKG_VERSION"),
+        %run_id,
         "started journal parser"
     );
 
-    let file_path: PathBuf = std::env::args()
-        .nth(1)
-        .expect("expected path to file to parse as and first argument, but got nothing")
-        .into();
-
-    let file_path_2 = file_path.clone();
-
-    let start = std::time::Instant::now();
-
-    let (transaction_sender, tx_receiver) =
-        crossbeam_channel::bounded::<TransactionMessage>(10_000);
-
-    let (dispute_look_up_sender, dispute_look_up_receiver) =
-        crossbeam_channel::unbounded::<DisputeLookUpMessage>();
-
-    let (transaction_sender, transaction_sender_2) = (
-        channel::Sender::new(transaction_sender.clone()),
-        channel::Sender::new(transaction_sender),
-    );
-
-    // parser thread
-    std::thread::spawn(move || {
-        parser::CsvParser::new(
-            OpenOptions::new()
-                .read(true)
-                .open(file_path)
-                .expect("failed to open file"),
-        )
-        .parse_journal(
-            transaction_sender,
-            channel::Sender::new(dispute_look_up_sender),
-        )
-    });
-
-    // dispute look-up thread
-    std::thread::spawn(move || {
-        //  let mut dispute_cache: HashMap<TransactionID, Amount> = HashMap::new();
-        dispute_look_up::DisputeFinder::new(
-            OpenOptions::new()
-                .read(true)
-                .open(file_path_2)
-                .expect("failed to open file"),
-        )
-        .run_dispute_look_up_loop(transaction_sender_2, dispute_look_up_receiver);
-    });
-
-    // transaction processing thread
-    let handle = std::thread::spawn(move || {
-        let mut accounts = accounts::Accounts::default();
-        while let Ok(message) = tx_receiver.recv() {
-            trace!(?message, "received ProcessTransactionMessage");
-            match message {
-                TransactionMessage::Deposit(Transaction { client_id, amount }) => {
-                    accounts.deposit(client_id, amount)
-                }
-                TransactionMessage::Withdrawal(Transaction { client_id, amount }) => {
-                    accounts.withdraw(client_id, amount)
+    match args.command {
+        Command::Help => println!("{}", cli::USAGE),
+        Command::Process => {
+            let partition = args.config.partition_output;
+            let report_file = args.config.report_file.clone();
+            let compress = args.config.compress;
+            let aging_report = args.config.aging_report.clone();
+            let aging_min_records = args.config.aging_min_records;
+            let segment_totals = args.config.segment_totals.clone();
+            let save_state = args.config.save_state.clone();
+            let (outputs, output_errors) = (args.config.outputs.clone(), args.config.output_errors);
+            let mut run_manifest = args
+                .config
+                .run_manifest
+                .clone()
+                .map(|path| (RunManifest::new(run_id, &args.input, &args.config), path));
+            match pipeline::run(&args.input, args.config) {
+                Ok((accounts, mut summary)) => {
+                    summary.run_id = Some(run_id);
+                    if let Some(path) = aging_report {
+                        let written = std::fs::File::create(&path).and_then(|file| {
+                            report::write_aging_report(
+                                &accounts,
+                                &mut std::io::BufWriter::new(file),
+                                aging_min_records,
+                            )
+                        });
+                        if let Err(err) = written {
+                            error!(%err, "failed to write aging report");
+                        }
+                    }
+                    if let Some(path) = segment_totals {
+                        let written = std::fs::File::create(&path).and_then(|file| {
+                            report::write_segment_totals(
+                                &accounts,
+                                &mut std::io::BufWriter::new(file),
+                            )
+                        });
+                        if let Err(err) = written {
+                            error!(%err, "failed to write segment totals");
+                        }
+                    }
+                    if let Some(path) = save_state {
+                        if let Err(err) = state::save(&accounts, &path) {
+                            error!(%err, "failed to save state snapshot");
+                        }
+                    }
+                    if let Some(partition) = partition {
+                        let path = report_file.unwrap_or_else(|| "report.csv".into());
+                        match report::write_partitioned(&accounts, &path, partition, compress) {
+                            Ok(written) => {
+                                run_manifest = run_manifest.map(|(manifest, path)| {
+                                    (
+                                        written
+                                            .into_iter()
+                                            .fold(manifest, RunManifest::with_output),
+                                        path,
+                                    )
+                                });
+                            }
+                            Err(err) => error!(%err, "failed to write partitioned report"),
+                        }
+                    }
+                    if !outputs.is_empty() {
+                        match sink::write_outputs(&accounts, &outputs, compress, output_errors) {
+                            Ok(written) => {
+                                run_manifest = run_manifest.map(|(manifest, path)| {
+                                    (
+                                        written
+                                            .into_iter()
+                                            .fold(manifest, RunManifest::with_output),
+                                        path,
+                                    )
+                                });
+                            }
+                            Err(err) => {
+                                error!(%err, "failed to write outputs");
+                                eprintln!("{err:?}");
+                                summary.print();
+                                if let Some((manifest, path)) = run_manifest {
+                                    if let Err(err) = manifest.write(&path, Err(&err)) {
+                                        error!(%err, "failed to write run manifest");
+                                    }
+                                }
+                                std::process::exit(1);
+                            }
+                        }
+                    } else if partition.is_none() {
+                        match compress {
+                            Some(compression) => {
+                                let written =
+                                    CompressedWriter::stdout(compression).and_then(|mut writer| {
+                                        accounts.write_report(&mut writer)?;
+                                        writer.finish()
+                                    });
+                                if let Err(err) = written {
+                                    error!(%err, "failed to print compressed report");
+                                }
+                            }
+                            None => accounts.print_report(),
+                        }
+                    }
+                    summary.print();
+                    if let Some((manifest, path)) = run_manifest {
+                        if let Err(err) = manifest.write(&path, Ok(&summary)) {
+                            error!(%err, "failed to write run manifest");
+                        }
+                    }
                 }
-                TransactionMessage::Dispute(Dispute { client_id, amount }) => {
-                    if let Err(err) = accounts.dispute(client_id, amount) {
-                        error!(%err, "failed to do dispute");
+                Err(err) => {
+                    error!(%err, "failed to process transaction journal");
+                    eprintln!("{err:?}");
+                    if let Some((manifest, path)) = run_manifest {
+                        if let Err(err) = manifest.write(&path, Err(&err)) {
+                            error!(%err, "failed to write run manifest");
+                        }
                     }
+                    std::process::exit(1);
                 }
-                TransactionMessage::Resolve(Dispute { client_id, amount }) => {
-                    if let Err(err) = accounts.resolve(client_id, amount) {
-                        error!(%err, "failed to do resolve");
+            }
+        }
+        Command::Convert(output) => {
+            if let Err(err) = binary::convert(
+                &args.input,
+                &output,
+                &FormatRegistry::default(),
+                args.config.input_format.as_deref(),
+                args.config.parse_errors,
+                &args.config.journal_amount_format(),
+            ) {
+                eprintln!("{err:?}");
+                std::process::exit(1);
+            }
+        }
+        Command::Sort(output) => {
+            if let Err(err) = sort::sort(
+                &args.input,
+                &output,
+                &FormatRegistry::default(),
+                args.config.input_format.as_deref(),
+                args.config.sort_by,
+                args.config.sort_buffer,
+            ) {
+                eprintln!("{err:?}");
+                std::process::exit(1);
+            }
+        }
+        Command::Split { shards, output } => {
+            match split::split(
+                &args.input,
+                &output,
+                &FormatRegistry::default(),
+                args.config.input_format.as_deref(),
+                shards,
+            ) {
+                Ok(counts) => {
+                    for (path, count) in split::shard_paths(&args.input, &output, shards)
+                        .iter()
+                        .zip(counts)
+                    {
+                        eprintln!("{}: {count}", path.display());
                     }
                 }
-                TransactionMessage::Chargeback(Dispute { client_id, amount }) => {
-                    if let Err(err) = accounts.chargeback(client_id, amount) {
-                        error!(%err, "failed to do chargeback");
+                Err(err) => {
+                    eprintln!("{err:?}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Merge { reports, output } => {
+            let reports: Vec<_> = std::iter::once(args.input).chain(reports).collect();
+            let writer = match &output {
+                Some(output) => CompressedWriter::create(
+                    output,
+                    Compression::resolve(args.config.compress, output),
+                ),
+                None => CompressedWriter::stdout(args.config.compress.unwrap_or_default()),
+            };
+            let merged = writer.and_then(|mut writer| {
+                let conflicts = report::merge_reports(
+                    &reports,
+                    &mut writer,
+                    args.config.merge_conflicts,
+                    args.config.totals_row,
+                    args.config.report_version,
+                )?;
+                writer.finish()?;
+                Ok(conflicts)
+            });
+            match merged {
+                Ok(conflicts) => eprintln!("merge_conflicts: {conflicts}"),
+                Err(err) => {
+                    eprintln!("{err:?}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Simulate(disputes) => {
+            match simulate::simulate(&args.input, &disputes, args.config) {
+                Ok((impacts, mut summary)) => {
+                    summary.run_id = Some(run_id);
+                    if let Err(err) =
+                        simulate::write_impacts(&mut std::io::stdout().lock(), &impacts)
+                    {
+                        error!(%err, "failed to print simulated impact");
                     }
+                    summary.print();
+                }
+                Err(err) => {
+                    eprintln!("{err:?}");
+                    std::process::exit(1);
                 }
             }
         }
-
-        accounts
-    });
-
-    let result = handle.join();
-
-    match result {
-        Ok(accounts) => {
-            info!(
-                took_s = start.elapsed().as_secs(),
-                "successfully finished processing journal"
-            );
-            accounts.print_report();
+        Command::PreviewDispute {
+            client_id,
+            transaction_id,
+        } => match simulate::preview_dispute(&args.input, client_id, transaction_id, args.config) {
+            Ok((amount, preview)) => {
+                if let Err(err) =
+                    simulate::write_preview(&mut std::io::stdout().lock(), amount, &preview)
+                {
+                    error!(%err, "failed to print dispute preview");
+                }
+            }
+            Err(err) => {
+                eprintln!("{err:?}");
+                std::process::exit(1);
+            }
+        },
+        Command::Validate => match order::validate(&args.input, &args.config) {
+            Ok(report) => {
+                report.print();
+                if !report.is_valid() {
+                    std::process::exit(1);
+                }
+            }
+            Err(err) => {
+                eprintln!("{err:?}");
+                std::process::exit(1);
+            }
+        },
+        Command::Checksum => {
+            let written = Manifest::of_journal(&args.input, manifest::CHUNK_SIZE)
+                .and_then(|manifest| Ok(manifest.write(&mut std::io::stdout().lock())?));
+            if let Err(err) = written {
+                eprintln!("{err:?}");
+                std::process::exit(1);
+            }
+        }
+        Command::ReplayDecisions => match decisions::replay(&args.input, &args.config) {
+            Ok((accounts, mut summary)) => {
+                summary.run_id = Some(run_id);
+                accounts.print_report();
+                summary.print();
+            }
+            Err(err) => {
+                eprintln!("{err:?}");
+                std::process::exit(1);
+            }
+        },
+        Command::ReplayDeadLetter => match dead_letter::replay(&args.input, &args.config) {
+            Ok((accounts, mut summary)) => {
+                summary.run_id = Some(run_id);
+                accounts.print_report();
+                summary.print();
+            }
+            Err(err) => {
+                eprintln!("{err:?}");
+                std::process::exit(1);
+            }
+        },
+        Command::TestFixtures => {
+            if let Err(err) = fixtures::run_all(&args.input) {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
         }
-        Err(err) => error!(?err, "failed to process transaction journal"),
     }
 }
@@ -0,0 +1,225 @@
+//! Checksum manifest of the journal written by `tren checksum`. Processing with `--verify-manifest` refuses
+//! journals which don't match it, so accounts are never settled against truncated or modified journal.
+//! Chunks are hashed with 64-bit FNV-1a, it catches accidental changes but doesn't protect against deliberate ones
+use crate::binary;
+use eyre::{eyre, Context, Result};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+/// Default size of hashed chunks, the first mismatching chunk tells roughly where the journal changed
+pub const CHUNK_SIZE: u64 = 1 << 20;
+
+const HEADER: &str = "# tren journal manifest";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Manifest {
+    /// Size of the journal in bytes
+    pub size: u64,
+    /// Lines after the header, or records of binary journal
+    pub records: u64,
+    pub chunk_size: u64,
+    /// Hash of every chunk of the journal, the last one can be shorter
+    pub chunks: Vec<u64>,
+}
+
+impl Manifest {
+    /// Computes manifest of the journal file
+    pub fn of_journal(journal: &Path, chunk_size: u64) -> Result<Manifest> {
+        let file = File::open(journal)
+            .wrap_err_with(|| format!("failed to open journal {}", journal.display()))?;
+        Manifest::compute(file, chunk_size)
+            .wrap_err_with(|| format!("failed to read journal {}", journal.display()))
+    }
+
+    pub fn compute(mut reader: impl Read, chunk_size: u64) -> Result<Manifest> {
+        if chunk_size == 0 {
+            return Err(eyre!("chunk size of manifest must be positive"));
+        }
+        let mut manifest = Manifest {
+            chunk_size,
+            ..Default::default()
+        };
+        let (mut lines, mut last, mut is_binary) = (0, b'\n', false);
+        let mut chunk = vec![0; chunk_size as usize];
+        loop {
+            let len = read_chunk(&mut reader, &mut chunk)?;
+            if len == 0 {
+                break;
+            }
+            let chunk = &chunk[..len];
+            if manifest.size == 0 {
+                is_binary = binary::is_binary(chunk);
+            }
+            manifest.chunks.push(fnv1a(chunk));
+            manifest.size += len as u64;
+            lines += chunk.iter().filter(|byte| **byte == b'\n').count() as u64;
+            last = chunk[len - 1];
+        }
+
+        if last != b'\n' {
+            lines += 1;
+        }
+        manifest.records = match is_binary {
+            true => binary::record_count(manifest.size),
+            false => lines.saturating_sub(1),
+        };
+        Ok(manifest)
+    }
+
+    pub fn load(path: &Path) -> Result<Manifest> {
+        let file = File::open(path)
+            .wrap_err_with(|| format!("failed to open manifest {}", path.display()))?;
+        Manifest::parse(BufReader::new(file))
+            .wrap_err_with(|| format!("failed to parse manifest {}", path.display()))
+    }
+
+    /// Parses manifest written by [Manifest::write]
+    pub fn parse(reader: impl BufRead) -> Result<Manifest> {
+        let mut lines = reader.lines();
+        if lines.next().transpose()?.as_deref() != Some(HEADER) {
+            return Err(eyre!("missing '{HEADER}' header"));
+        }
+
+        let mut manifest = Manifest::default();
+        for line in lines {
+            let line = line?;
+            let mut fields = line.split_whitespace();
+            let (Some(key), Some(value)) = (fields.next(), fields.last()) else {
+                continue;
+            };
+            let invalid = || format!("invalid line '{line}'");
+            match key {
+                "size" => manifest.size = value.parse().wrap_err_with(invalid)?,
+                "records" => manifest.records = value.parse().wrap_err_with(invalid)?,
+                "chunk_size" => manifest.chunk_size = value.parse().wrap_err_with(invalid)?,
+                "chunk" => manifest
+                    .chunks
+                    .push(u64::from_str_radix(value, 16).wrap_err_with(invalid)?),
+                _ => return Err(eyre!("unknown key '{key}'")),
+            }
+        }
+        Ok(manifest)
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writeln!(writer, "{HEADER}")?;
+        writeln!(writer, "size {}", self.size)?;
+        writeln!(writer, "records {}", self.records)?;
+        writeln!(writer, "chunk_size {}", self.chunk_size)?;
+        for (index, hash) in self.chunks.iter().enumerate() {
+            writeln!(writer, "chunk {index} {hash:016x}")?;
+        }
+        Ok(())
+    }
+
+    /// Single hash of the whole journal, FNV-1a of the chunk hashes, e.g. for [crate::run_manifest]
+    pub fn digest(&self) -> u64 {
+        let hashes: Vec<_> = self
+            .chunks
+            .iter()
+            .flat_map(|hash| hash.to_le_bytes())
+            .collect();
+        fnv1a(&hashes)
+    }
+
+    /// Fails if the journal doesn't match the manifest, the error tells where it differs
+    pub fn verify(&self, journal: &Path) -> Result<()> {
+        self.matches(&Manifest::of_journal(journal, self.chunk_size)?)
+            .wrap_err_with(|| format!("journal {} doesn't match manifest", journal.display()))
+    }
+
+    /// Compares manifest of the actual journal with this expected one
+    fn matches(&self, actual: &Manifest) -> Result<()> {
+        if actual.size != self.size {
+            return Err(eyre!(
+                "journal has {} bytes but {} are expected, it was truncated or appended to",
+                actual.size,
+                self.size
+            ));
+        }
+        if actual.chunks.len() != self.chunks.len() {
+            return Err(eyre!(
+                "manifest has {} chunks but the journal {}",
+                self.chunks.len(),
+                actual.chunks.len()
+            ));
+        }
+        let mismatch = self
+            .chunks
+            .iter()
+            .zip(&actual.chunks)
+            .position(|(a, b)| a != b);
+        if let Some(index) = mismatch {
+            let start = index as u64 * self.chunk_size;
+            let end = (start + self.chunk_size).min(self.size);
+            return Err(eyre!("bytes {start}..{end} of the journal were modified"));
+        }
+        if actual.records != self.records {
+            return Err(eyre!(
+                "journal has {} records but {} are expected",
+                actual.records,
+                self.records
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Fills the chunk unless the reader ends first, returns the number of bytes read
+fn read_chunk(reader: &mut impl Read, chunk: &mut [u8]) -> std::io::Result<usize> {
+    let mut len = 0;
+    while len < chunk.len() {
+        match reader.read(&mut chunk[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(len)
+}
+
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let journal = "type,client,tx,amount\ndeposit,1,1,10\ndeposit,1,2,5\n";
+        let manifest = Manifest::compute(journal.as_bytes(), 16).unwrap();
+        assert_eq!((manifest.size, manifest.records), (51, 2));
+        assert_eq!(manifest.chunks.len(), 4);
+
+        let mut written = Vec::new();
+        manifest.write(&mut written).unwrap();
+        assert_eq!(Manifest::parse(written.as_slice()).unwrap(), manifest);
+
+        let tests = vec![
+            ("unchanged", journal.to_string(), true),
+            ("truncated", journal[..36].to_string(), false),
+            ("appended", format!("{journal}withdrawal,1,3,1\n"), false),
+            ("modified", journal.replace("10", "90"), false),
+        ];
+        for (name, actual, want) in tests {
+            let actual = Manifest::compute(actual.as_bytes(), 16).unwrap();
+            assert_eq!(
+                manifest.matches(&actual).is_ok(),
+                want,
+                "failed test {name}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    }
+}
@@ -0,0 +1,108 @@
+//! Soft memory limit set by `--max-memory`, for shared batch hosts where going over the limit gets the run killed.
+//! Parts of the pipeline which grow with the journal record estimates of their memory into [MemoryBudget] and
+//! degrade once the total is over the limit, instead of failing:
+//! - settled accounts are evicted, see [crate::accounts::Accounts::evict_settled]
+//! - cached disputed transactions are dropped and looked up again when they are resolved or charged back
+//! - single-pass look-ups stop indexing transactions in memory and scan the journal instead, see
+//!   [crate::dispute_look_up::TransactionIndex::with_scan_fallback]
+//! - queued dispute look-up requests are spilled to disk early, see [crate::spill::SpillingSender]
+//!
+//! Estimates count the entries of the maps and queues, not what the allocator really holds, so the limit should
+//! leave some headroom. Accounts, open disputes and everything else which can't be dropped still grows
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Number of records or operations between two checks of the memory estimate
+pub const CHECK_INTERVAL: u64 = 4096;
+
+/// Part of the pipeline whose memory is estimated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Component {
+    /// Accounts map and evicted clients
+    Accounts,
+    /// Cached disputed transactions, disputes state and transactions indexed for single-pass look-ups
+    Disputes,
+    /// Queue of transactions between the parser and the processing, fixed by its capacity
+    TransactionQueue,
+    /// Queue of dispute look-up requests
+    DisputeQueue,
+}
+
+/// Memory estimates of the components shared by all threads of the pipeline, clones share the same estimates
+#[derive(Clone, Debug)]
+pub struct MemoryBudget {
+    limit: u64,
+    usage: Arc<[AtomicU64; 4]>,
+}
+
+impl MemoryBudget {
+    /// Budget of `limit` bytes
+    pub fn new(limit: u64) -> MemoryBudget {
+        MemoryBudget {
+            limit,
+            usage: Arc::new(Default::default()),
+        }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Replaces the estimate of the component
+    pub fn record(&self, component: Component, bytes: u64) {
+        self.usage[component as usize].store(bytes, Ordering::Relaxed);
+    }
+
+    /// Estimated memory of all components together
+    pub fn usage(&self) -> u64 {
+        self.usage
+            .iter()
+            .map(|usage| usage.load(Ordering::Relaxed))
+            .fold(0, u64::saturating_add)
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.usage() > self.limit
+    }
+}
+
+/// `true` if the budget is set and over its limit
+pub fn is_over(budget: Option<&MemoryBudget>) -> bool {
+    budget.is_some_and(MemoryBudget::is_over)
+}
+
+/// Estimated memory of hash map with `len` entries, hashbrown keeps one control byte per entry and is at most
+/// 7/8 full
+pub fn map_bytes<K, V>(len: usize) -> u64 {
+    (len as u64).saturating_mul((std::mem::size_of::<(K, V)>() as u64 + 1) * 8 / 7)
+}
+
+/// Estimated memory of queue with `len` messages
+pub fn queue_bytes<T>(len: usize) -> u64 {
+    (len as u64).saturating_mul(std::mem::size_of::<T>() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget() {
+        let budget = MemoryBudget::new(1000);
+        let shared = budget.clone();
+        let tests = vec![
+            ("empty", Component::Accounts, 0, false),
+            ("under", Component::Accounts, 600, false),
+            ("at the limit", Component::DisputeQueue, 400, false),
+            ("over", Component::Disputes, 1, true),
+            ("replaced estimate", Component::Accounts, 100, false),
+        ];
+
+        for (name, component, bytes, want) in tests {
+            budget.record(component, bytes);
+            assert_eq!(shared.is_over(), want, "failed test {name}");
+        }
+        assert_eq!(shared.usage(), 501);
+        assert!(!is_over(None), "no budget");
+    }
+}
@@ -0,0 +1,113 @@
+use crate::aliases::*;
+use crate::parser::Found;
+use std::sync::{Arc, RwLock};
+
+/// Byte offsets of deposits and withdrawals in the journal, recorded by the parser while it streams the journal
+/// and read by the dispute look-up, so it can seek right to the disputed transaction instead of scanning.
+/// Clones share the same index. IDs and offsets are kept in separate vectors, so every transaction costs
+/// 12 bytes. Only increasing transaction IDs can be looked up, once the journal goes out of order the index
+/// returns `None` and the look-up falls back to the scan
+#[derive(Clone, Debug, Default)]
+pub struct OffsetIndex(Arc<RwLock<Offsets>>);
+
+#[derive(Debug)]
+struct Offsets {
+    transaction_ids: Vec<TransactionID>,
+    offsets: Vec<u64>,
+    /// Transaction IDs were recorded in increasing order, so they can be binary searched
+    sorted: bool,
+}
+
+impl Default for Offsets {
+    fn default() -> Self {
+        Offsets {
+            transaction_ids: Vec::new(),
+            offsets: Vec::new(),
+            sorted: true,
+        }
+    }
+}
+
+impl OffsetIndex {
+    pub fn record(&self, transaction_id: TransactionID, offset: u64) {
+        let mut index = self.0.write().unwrap_or_else(|err| err.into_inner());
+        if !index.sorted {
+            return;
+        }
+        if index
+            .transaction_ids
+            .last()
+            .is_some_and(|last| *last > transaction_id)
+        {
+            index.sorted = false;
+            index.transaction_ids = Vec::new();
+            index.offsets = Vec::new();
+            return;
+        }
+        index.transaction_ids.push(transaction_id);
+        index.offsets.push(offset);
+    }
+
+    /// Offset of the transaction, `None` if it wasn't recorded yet or the index is not usable
+    pub fn get(&self, transaction_id: TransactionID) -> Option<u64> {
+        let index = self.0.read().unwrap_or_else(|err| err.into_inner());
+        index
+            .transaction_ids
+            .binary_search(&transaction_id)
+            .ok()
+            .map(|position| index.offsets[position])
+    }
+}
+
+/// Combines transactions found through the index with the ones found by `scan`, which is called only
+/// with the requests missing from the index and only if there are any. Results are in the order of `requests`
+pub(crate) fn merge_indexed(
+    requests: &[(ClientID, TransactionID)],
+    indexed: Vec<Option<Found>>,
+    scan: impl FnOnce(&[(ClientID, TransactionID)]) -> Vec<Result<Found, eyre::Report>>,
+) -> Vec<Result<Found, eyre::Report>> {
+    let missing: Vec<_> = requests
+        .iter()
+        .zip(indexed.iter())
+        .filter(|(_, found)| found.is_none())
+        .map(|(request, _)| *request)
+        .collect();
+    let mut scanned = match missing.is_empty() {
+        true => Vec::new(),
+        false => scan(&missing),
+    }
+    .into_iter();
+
+    indexed
+        .into_iter()
+        .map(|found| match found {
+            Some(found) => Ok(found),
+            None => scanned
+                .next()
+                .unwrap_or_else(|| Err(eyre::eyre!("transaction was not looked up"))),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset_index() {
+        let index = OffsetIndex::default();
+        let shared = index.clone();
+        index.record(1, 10);
+        index.record(3, 30);
+        index.record(3, 40);
+
+        assert_eq!(shared.get(1), Some(10));
+        assert!(matches!(shared.get(3), Some(30 | 40)));
+        assert_eq!(shared.get(2), None, "not recorded");
+
+        index.record(2, 50);
+        assert_eq!(shared.get(1), None, "out of order journal");
+        index.record(4, 60);
+        assert_eq!(shared.get(4), None, "stays unusable");
+    }
+}
@@ -0,0 +1,193 @@
+//! Checks that deposits and withdrawals come with increasing transaction IDs. The dispute look-up stops
+//! scanning the journal once it passes the disputed ID, so disputes of transactions which are out of order
+//! are not found unless the journal is processed with `--unordered-input`
+use crate::aliases::*;
+use crate::channel::TransactionMessage;
+use crate::config::Config;
+use crate::format::FormatRegistry;
+use crate::parser::{CsvParser, JournalEntry};
+use crate::summary::Summary;
+use eyre::{eyre, Context, Result};
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::Path;
+use tracing::warn;
+
+/// Checks the order of transaction IDs in the whole journal, only delimited journals are supported
+pub fn validate(journal: &Path, config: &Config) -> Result<OrderReport> {
+    let prepared = FormatRegistry::default().open(journal, config.input_format.as_deref())?;
+    let delimiter = prepared.delimiter().ok_or(eyre!(
+        "only delimited journals can be validated, binary journal has no lines"
+    ))?;
+    let file = File::open(&prepared.path)
+        .wrap_err_with(|| format!("failed to open journal {}", prepared.path.display()))?;
+    CsvParser::new(file)
+        .with_delimiter(delimiter)
+        .with_parse_errors(config.parse_errors)
+        .with_amount_format(config.journal_amount_format())
+        .validate_order()
+}
+
+/// At most this many violations are kept with their line, the rest is only counted
+pub const MAX_REPORTED: usize = 100;
+
+/// Transaction ID breaking the order, returned by [TransactionOrder::check]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// ID is lower than `max`, the highest ID seen before it
+    OutOfOrder { max: TransactionID },
+    /// ID was already used by another deposit or withdrawal
+    Reused,
+}
+
+/// Highest transaction ID seen so far and all the IDs, so reused ones are recognized.
+/// Holds every transaction ID of the journal
+#[derive(Debug, Default)]
+pub struct TransactionOrder {
+    max: Option<TransactionID>,
+    seen: HashSet<TransactionID>,
+}
+
+impl TransactionOrder {
+    pub fn check(&mut self, transaction_id: TransactionID) -> Option<Violation> {
+        if !self.seen.insert(transaction_id) {
+            return Some(Violation::Reused);
+        }
+        match self.max {
+            Some(max) if transaction_id < max => Some(Violation::OutOfOrder { max }),
+            _ => {
+                self.max = Some(transaction_id);
+                None
+            }
+        }
+    }
+
+    /// Checks deposit or withdrawal while the journal is parsed, violations are logged and counted
+    /// in the summary. Other entries are ignored
+    pub fn check_entry(&mut self, entry: &JournalEntry, index: u64, summary: &mut Summary) {
+        let JournalEntry::Transaction(
+            TransactionMessage::Deposit(transaction) | TransactionMessage::Withdrawal(transaction),
+        ) = entry
+        else {
+            return;
+        };
+        let transaction_id = transaction.transaction_id;
+        match self.check(transaction_id) {
+            Some(Violation::OutOfOrder { max }) => {
+                warn!(%transaction_id, %max, %index, "transaction is out of order, its disputes need --unordered-input");
+                summary.out_of_order_transactions += 1;
+            }
+            Some(Violation::Reused) => {
+                warn!(%transaction_id, %index, "transaction ID was already used");
+                summary.reused_transaction_ids += 1;
+            }
+            None => (),
+        }
+    }
+}
+
+/// Result of `tren validate`, violations with the line of the offending record
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct OrderReport {
+    /// Checked deposits and withdrawals
+    pub transactions: u64,
+    pub out_of_order: u64,
+    pub reused: u64,
+    /// First [MAX_REPORTED] violations with the line and the transaction ID
+    pub violations: Vec<(u64, TransactionID, Violation)>,
+}
+
+impl OrderReport {
+    pub fn record(&mut self, line: u64, transaction_id: TransactionID, violation: Violation) {
+        match violation {
+            Violation::OutOfOrder { .. } => self.out_of_order += 1,
+            Violation::Reused => self.reused += 1,
+        }
+        if self.violations.len() < MAX_REPORTED {
+            self.violations.push((line, transaction_id, violation));
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.out_of_order == 0 && self.reused == 0
+    }
+
+    /// What to do about the journal before it is processed
+    pub fn suggestion(&self) -> &'static str {
+        match (self.out_of_order, self.reused) {
+            (0, 0) => "transaction IDs are increasing, the journal can be processed as it is",
+            (_, 0) => "process the journal with --unordered-input or sort it with `tren sort`, otherwise disputes of out of order transactions are not found",
+            _ => "fix the reused transaction IDs, disputes of them refer to the first deposit or withdrawal of the client with the ID",
+        }
+    }
+
+    pub fn print(&self) {
+        for (line, transaction_id, violation) in &self.violations {
+            match violation {
+                Violation::OutOfOrder { max } => {
+                    println!("line {line}: transaction {transaction_id} is out of order, it follows transaction {max}")
+                }
+                Violation::Reused => {
+                    println!("line {line}: transaction {transaction_id} reuses ID of earlier transaction")
+                }
+            }
+        }
+        let omitted =
+            (self.out_of_order + self.reused).saturating_sub(self.violations.len() as u64);
+        if omitted > 0 {
+            println!("{omitted} more violations omitted");
+        }
+        eprintln!("checked_transactions: {}", self.transactions);
+        eprintln!("out_of_order_transactions: {}", self.out_of_order);
+        eprintln!("reused_transaction_ids: {}", self.reused);
+        eprintln!("suggestion: {}", self.suggestion());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let mut order = TransactionOrder::default();
+        let tests = vec![
+            ("first", 2, None),
+            ("increasing", 5, None),
+            ("gap is fine", 9, None),
+            ("out of order", 7, Some(Violation::OutOfOrder { max: 9 })),
+            ("reused", 5, Some(Violation::Reused)),
+            ("after out of order", 10, None),
+        ];
+
+        for (name, transaction_id, want) in tests {
+            assert_eq!(order.check(transaction_id), want, "failed test {name}");
+        }
+    }
+
+    #[test]
+    fn test_validate_order() {
+        let journal = "type,client,tx,amount
+                       deposit,1,1,10
+                       deposit,1,3,10
+                       dispute,1,1,
+                       withdrawal,1,2,5
+                       deposit,2,3,1
+";
+        let report = CsvParser::new(journal.as_bytes()).validate_order().unwrap();
+
+        assert_eq!(
+            report,
+            OrderReport {
+                transactions: 4,
+                out_of_order: 1,
+                reused: 1,
+                violations: vec![
+                    (5, 2, Violation::OutOfOrder { max: 3 }),
+                    (6, 3, Violation::Reused)
+                ],
+            }
+        );
+        assert!(report.suggestion().starts_with("fix the reused"));
+    }
+}
@@ -1,13 +1,30 @@
-use std::fs::File;
-
+use crate::amount::{parse_amount, parse_untrimmed_amount, AmountFormat};
 use crate::channel::Sender;
+use crate::checkpoint::{Checkpoint, ResumePoint};
+use crate::client_filter::ClientFilter;
+use crate::client_remap::{remapped, ClientRemap};
+use crate::dispute_look_up::{DisputeFinder, DisputeResolver, TransactionIndex};
+use crate::offsets::{merge_indexed, OffsetIndex};
+use crate::order::{OrderReport, TransactionOrder};
+use crate::progress::Progress;
+use crate::provenance::Provenance;
+use crate::read_ahead::ReadAhead;
+use crate::record_types::{Record, RecordTypes};
+use crate::rules::Validator;
+use crate::run_guard::{self, RunGuard};
+use crate::sample::Sample;
+use crate::seen::{is_unseen, look_up_seen, unseen_error, SeenTransactions};
+use crate::spill::SpillingSender;
+use crate::summary::Summary;
+use crate::timestamp::{parse_timestamp, TimeWindow};
+use crate::timings::TIMING_BATCH;
 use crate::{aliases::*, channel::*};
 use csv::ByteRecord;
 use eyre::{eyre, Context, Result};
-use rust_decimal::Decimal;
-use std::ops::Deref;
+use std::collections::HashMap;
 use std::str::from_utf8;
-use tracing::{debug, info};
+use std::str::FromStr;
+use tracing::{debug, info, warn};
 
 enum RecordType {
     Deposit,
@@ -15,112 +32,1138 @@ enum RecordType {
     Dispute,
     Resolve,
     Chargeback,
+    Reversal,
+    Transfer,
+    AdjustmentCredit,
+    AdjustmentDebit,
+    Lock,
+    Unlock,
+    Close,
+}
+
+/// Number of records whitespace is looked for in when it is not known whether the journal has it
+pub const WHITESPACE_SAMPLE: usize = 1000;
+
+/// Positions of the optional columns, looked up by their name in the header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Columns {
+    /// Receiving client of the transfer, if it is not named in the header it is expected right after the amount
+    pub(crate) to_client: usize,
+    timestamp: Option<usize>,
+}
+
+impl Columns {
+    /// Layout of journal without optional columns
+    const DEFAULT: Columns = Columns {
+        to_client: 4,
+        timestamp: None,
+    };
+
+    pub(crate) fn from_headers(headers: &ByteRecord) -> Columns {
+        let position = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header.trim_ascii() == name.as_bytes())
+        };
+
+        Columns {
+            to_client: position("to_client").unwrap_or(Self::DEFAULT.to_client),
+            timestamp: position("timestamp"),
+        }
+    }
 }
 
-pub struct CsvParser<T>(csv::Reader<T>);
+/// What to do with records which can't be parsed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParseErrorPolicy {
+    /// Stop parsing the journal at the first malformed record and fail the run, no report is written
+    #[default]
+    Strict,
+    /// Skip malformed records, they are logged and counted in the summary
+    Lenient,
+}
+
+impl FromStr for ParseErrorPolicy {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(ParseErrorPolicy::Strict),
+            "lenient" => Ok(ParseErrorPolicy::Lenient),
+            _ => Err(eyre!(
+                "invalid parse error policy '{s}', expected one of strict, lenient"
+            )),
+        }
+    }
+}
+
+pub struct CsvParser<T> {
+    reader: csv::Reader<T>,
+    delimiter: u8,
+    /// Capacity of the read buffer, `None` keeps the default of the csv reader
+    read_buffer: Option<usize>,
+    /// Only records inside of this window are processed
+    window: TimeWindow,
+    parse_errors: ParseErrorPolicy,
+    progress: Option<Progress>,
+    /// Parsing stops after this many records
+    limit: Option<u64>,
+    /// Parsing stops past the max runtime or once the run was stopped by other thread
+    guard: Option<RunGuard>,
+    /// Only records of sampled clients are processed
+    sample: Option<Sample>,
+    /// Only records of clients allowed by the filter are processed
+    client_filter: Option<ClientFilter>,
+    /// Clients of records are replaced by their new IDs
+    client_remap: Option<ClientRemap>,
+    /// Records are checked against the validation rules, rejected ones are skipped
+    validator: Option<Validator>,
+    /// Records which were already applied are skipped
+    checkpoint: Checkpoint,
+    /// Parsing starts at this position instead of the first record
+    resume: Option<ResumePoint>,
+    /// Transaction IDs are not increasing, so look-ups can't stop at higher ID
+    unordered: bool,
+    /// Disputes are resolved while parsing instead of being sent to the dispute look-up
+    inline: Option<DisputeFinder<TransactionIndex>>,
+    /// Offsets of deposits and withdrawals, recorded while parsing and used by the look-ups
+    offsets: Option<OffsetIndex>,
+    /// IDs of deposits and withdrawals, recorded while parsing so look-ups of unseen transactions fail right away
+    seen: Option<SeenTransactions>,
+    /// Reads blocks of indexed transactions before they are looked up
+    read_ahead: Option<ReadAhead>,
+    /// Transaction IDs of deposits and withdrawals are checked to be increasing while parsing
+    order: Option<TransactionOrder>,
+    amount_format: AmountFormat,
+    /// Whitespace in types and amounts is ignored, `None` detects it from the first records of the journal
+    trim_whitespace: Option<bool>,
+    columns: Option<Columns>,
+    /// Handlers of record types which are not built in
+    record_types: RecordTypes,
+    /// Lines of the records, recorded while parsing, see [crate::provenance]
+    provenance: Option<Provenance>,
+    /// Counters of skipped records, returned once the journal is parsed
+    summary: Summary,
+}
 
 impl<T: std::io::Read> CsvParser<T> {
     pub fn new(reader: T) -> CsvParser<T> {
-        CsvParser(csv::Reader::from_reader(reader))
+        CsvParser {
+            // transfers have one extra column, so we can't require all records to have the same length
+            reader: csv::ReaderBuilder::new().flexible(true).from_reader(reader),
+            delimiter: b',',
+            read_buffer: None,
+            window: TimeWindow::default(),
+            parse_errors: ParseErrorPolicy::default(),
+            progress: None,
+            limit: None,
+            guard: None,
+            sample: None,
+            client_filter: None,
+            client_remap: None,
+            validator: None,
+            checkpoint: Checkpoint::default(),
+            resume: None,
+            unordered: false,
+            inline: None,
+            offsets: None,
+            seen: None,
+            read_ahead: None,
+            order: None,
+            amount_format: AmountFormat::default(),
+            trim_whitespace: None,
+            columns: None,
+            record_types: RecordTypes::default(),
+            provenance: None,
+            summary: Summary::default(),
+        }
+    }
+
+    /// Sets separator of the fields, has to be called before anything is read
+    pub fn with_delimiter(mut self, delimiter: u8) -> CsvParser<T> {
+        self.delimiter = delimiter;
+        self.rebuild_reader()
+    }
+
+    /// Sets capacity of the read buffer, larger buffer means fewer reads on fast disks.
+    /// Has to be called before anything is read
+    pub fn with_read_buffer(mut self, read_buffer: Option<usize>) -> CsvParser<T> {
+        self.read_buffer = read_buffer;
+        self.rebuild_reader()
+    }
+
+    fn rebuild_reader(mut self) -> CsvParser<T> {
+        let mut builder = csv::ReaderBuilder::new();
+        builder.flexible(true).delimiter(self.delimiter);
+        if let Some(read_buffer) = self.read_buffer {
+            builder.buffer_capacity(read_buffer);
+        }
+        self.reader = builder.from_reader(self.reader.into_inner());
+        self
+    }
+
+    pub fn with_parse_errors(mut self, parse_errors: ParseErrorPolicy) -> CsvParser<T> {
+        self.parse_errors = parse_errors;
+        self
+    }
+
+    /// Stops [CsvParser::parse_journal] after `limit` records
+    pub fn with_limit(mut self, limit: Option<u64>) -> CsvParser<T> {
+        self.limit = limit;
+        self
+    }
+
+    /// Stops [CsvParser::parse_journal] once the guard trips, see [crate::run_guard]
+    pub fn with_run_guard(mut self, guard: Option<RunGuard>) -> CsvParser<T> {
+        self.guard = guard;
+        self
+    }
+
+    /// Skips records of clients which are not in the sample
+    pub fn with_sample(mut self, sample: Option<Sample>) -> CsvParser<T> {
+        self.sample = sample;
+        self
+    }
+
+    /// Skips records of clients excluded by the filter
+    pub fn with_client_filter(mut self, client_filter: Option<ClientFilter>) -> CsvParser<T> {
+        self.client_filter = client_filter;
+        self
+    }
+
+    /// Replaces clients of records by their new IDs before they are sampled, filtered or applied
+    pub fn with_client_remap(mut self, client_remap: Option<ClientRemap>) -> CsvParser<T> {
+        self.client_remap = client_remap;
+        self
+    }
+
+    /// Checks records which pass the sample and client filter against the rules, see [crate::rules]
+    pub fn with_validator(mut self, validator: Option<Validator>) -> CsvParser<T> {
+        self.validator = validator;
+        self
+    }
+
+    /// Skips records which were already applied
+    pub fn with_checkpoint(mut self, checkpoint: Checkpoint) -> CsvParser<T> {
+        self.checkpoint = checkpoint;
+        self
+    }
+
+    /// Starts [JournalSource::parse_journal] at the resume point instead of the first record, records keep
+    /// their indices as if the whole journal was parsed
+    pub fn with_resume_point(mut self, resume: Option<ResumePoint>) -> CsvParser<T> {
+        self.resume = resume;
+        self
+    }
+
+    /// Makes [DisputeResolver::find_transaction] search the whole journal, for journals whose transaction IDs
+    /// are not increasing
+    pub fn with_unordered_input(mut self, unordered: bool) -> CsvParser<T> {
+        self.unordered = unordered;
+        self
+    }
+
+    /// Resolves disputes, resolves and chargebacks while parsing with the finder, so the journal is read only once
+    /// and the dispute look-up thread is not needed. Deposits and withdrawals are kept in memory for it
+    pub fn with_inline_disputes(
+        mut self,
+        inline: Option<DisputeFinder<TransactionIndex>>,
+    ) -> CsvParser<T> {
+        self.inline = inline;
+        self
+    }
+
+    /// Shares the index of transaction offsets, [JournalSource::parse_journal] fills it and
+    /// [DisputeResolver::find_transaction] seeks to the indexed offset before falling back to scanning
+    pub fn with_offset_index(mut self, offsets: Option<OffsetIndex>) -> CsvParser<T> {
+        self.offsets = offsets;
+        self
+    }
+
+    /// Shares the filter of seen transactions, [JournalSource::parse_journal] fills it and
+    /// [DisputeResolver::find_transaction] fails without scanning for transactions which didn't pass it
+    pub fn with_seen_transactions(mut self, seen: Option<SeenTransactions>) -> CsvParser<T> {
+        self.seen = seen;
+        self
+    }
+
+    /// Reads blocks of indexed transactions in the background once they are passed to
+    /// [DisputeResolver::read_ahead], needs [CsvParser::with_offset_index]
+    pub fn with_read_ahead(mut self, read_ahead: Option<ReadAhead>) -> CsvParser<T> {
+        self.read_ahead = read_ahead;
+        self
+    }
+
+    /// Sets how amounts are written in the journal, e.g. with decimal comma
+    /// Counts and warns about deposits and withdrawals with out of order or reused transaction IDs,
+    /// see [crate::order]
+    pub fn with_order_check(mut self, check: bool) -> CsvParser<T> {
+        self.order = check.then(TransactionOrder::default);
+        self
+    }
+
+    pub fn with_amount_format(mut self, amount_format: AmountFormat) -> CsvParser<T> {
+        self.amount_format = amount_format;
+        self
+    }
+
+    /// Sets whether whitespace in types and amounts is ignored. Without it records with whitespace are malformed
+    /// and clean journals skip the checks. `None` trims only if the first [WHITESPACE_SAMPLE] records of
+    /// [JournalSource::parse_journal] have whitespace, the other readers always trim
+    pub fn with_trim_whitespace(mut self, trim_whitespace: Option<bool>) -> CsvParser<T> {
+        self.trim_whitespace = trim_whitespace;
+        self
+    }
+
+    /// Records the line of every record parsed by [JournalSource::parse_journal], so rejected records can be
+    /// located in the journal
+    pub fn with_provenance(mut self, provenance: Option<Provenance>) -> CsvParser<T> {
+        self.provenance = provenance;
+        self
+    }
+
+    /// Shows progress of [CsvParser::parse_journal]
+    pub fn with_progress(mut self, progress: Option<Progress>) -> CsvParser<T> {
+        self.progress = progress;
+        self
+    }
+
+    /// Sets the time window, records with timestamp outside of it are skipped
+    /// Rows of types which are not built in are parsed by their handlers instead of being skipped
+    pub fn with_record_types(mut self, record_types: RecordTypes) -> CsvParser<T> {
+        self.record_types = record_types;
+        self
+    }
+
+    pub fn with_window(mut self, window: TimeWindow) -> CsvParser<T> {
+        self.window = window;
+        self
+    }
+
+    /// Reads the header, comment and blank lines before it are skipped
+    fn columns(&mut self) -> Result<Columns> {
+        if let Some(columns) = self.columns {
+            return Ok(columns);
+        }
+
+        let mut headers = self
+            .reader
+            .byte_headers()
+            .wrap_err("failed to read journal header")?
+            .clone();
+        while is_comment_or_blank(&headers) {
+            self.summary.comment_lines += 1;
+            if !self
+                .reader
+                .read_byte_record(&mut headers)
+                .wrap_err("failed to read journal header")?
+            {
+                break;
+            }
+        }
+        let columns = Columns::from_headers(&headers);
+        self.columns = Some(columns);
+        Ok(columns)
+    }
+}
+
+/// Source of journal records, either delimited text read by [CsvParser] or [crate::binary::BinaryParser].
+/// Disputed transactions can be looked up in the journal it reads by scanning it again
+pub trait JournalSource: DisputeResolver {
+    /// Parses the whole journal, transactions are sent for processing and disputes to the dispute look-up.
+    /// Returns [Summary] with counts of skipped records
+    fn parse_journal(
+        &mut self,
+        transaction_sender: Sender<Indexed<TransactionMessage>>,
+        dispute_look_up_sender: SpillingSender,
+    ) -> Result<Summary>;
+}
+
+/// Transaction found by [DisputeResolver::find_transaction]
+pub type Found = (
+    ClientID,
+    TransactionID,
+    Amount,
+    Option<Timestamp>,
+    TransactionKind,
+);
+
+/// Look-ups of [DisputeResolver::find_transactions] done in a single pass over the journal
+pub(crate) struct BatchLookUp {
+    /// Positions in the requests of every requested transaction which wasn't found yet
+    pending: HashMap<(ClientID, TransactionID), Vec<usize>>,
+    /// Ordered journal can't contain any requested transaction after this ID
+    max_transaction_id: Option<TransactionID>,
+    found: Vec<Option<Found>>,
+}
+
+impl BatchLookUp {
+    pub(crate) fn new(requests: &[(ClientID, TransactionID)]) -> BatchLookUp {
+        let mut pending: HashMap<_, Vec<usize>> = HashMap::new();
+        for (position, request) in requests.iter().enumerate() {
+            pending.entry(*request).or_default().push(position);
+        }
+
+        BatchLookUp {
+            pending,
+            max_transaction_id: requests
+                .iter()
+                .map(|(_, transaction_id)| *transaction_id)
+                .max(),
+            found: vec![None; requests.len()],
+        }
+    }
+
+    /// Returns `true` if the transaction was requested, it has to be completed by [BatchLookUp::found]
+    pub(crate) fn is_requested(&self, client_id: ClientID, transaction_id: TransactionID) -> bool {
+        self.pending.contains_key(&(client_id, transaction_id))
+    }
+
+    pub(crate) fn found(&mut self, found: Found) {
+        for position in self.pending.remove(&(found.0, found.1)).unwrap_or_default() {
+            self.found[position] = Some(found);
+        }
+    }
+
+    /// Returns `true` once the rest of the journal can't contain any requested transaction
+    pub(crate) fn is_done(&self, transaction_id: TransactionID, unordered: bool) -> bool {
+        self.pending.is_empty() || (!unordered && Some(transaction_id) > self.max_transaction_id)
+    }
+
+    pub(crate) fn finish(self) -> Vec<Result<Found>> {
+        self.found
+            .into_iter()
+            .map(|found| {
+                found.ok_or_else(|| {
+                    eyre!("transaction for requested client id and transaction id not found")
+                })
+            })
+            .collect()
+    }
+}
+
+impl<S: JournalSource + ?Sized> JournalSource for Box<S> {
+    fn parse_journal(
+        &mut self,
+        transaction_sender: Sender<Indexed<TransactionMessage>>,
+        dispute_look_up_sender: SpillingSender,
+    ) -> Result<Summary> {
+        (**self).parse_journal(transaction_sender, dispute_look_up_sender)
     }
 }
 
-impl CsvParser<File> {
-    /// We will read file and parse each line. We assume spaces can be present in type and amount,
-    /// other fields are assumed to be valid u16 and u32 for client and tx respectively
-    /// Checking for whitespaces and their removal worsens the performance by roughly 1s per 10_000_000 records
+impl<T: std::io::Read> CsvParser<T> {
+    /// Calls `f` with every entry of the journal and its timestamp, ignoring the time window, sample and limit.
+    /// Records of unknown type are skipped, malformed ones depending on [ParseErrorPolicy]. Returns number of entries
+    pub fn read_entries(
+        &mut self,
+        mut f: impl FnMut(JournalEntry, Option<Timestamp>) -> Result<()>,
+    ) -> Result<u64> {
+        let columns = self.columns()?;
+        let mut count = 0;
+        for (index, record) in self.reader.byte_records().enumerate() {
+            let record = record?;
+            if is_comment_or_blank(&record) {
+                self.summary.comment_lines += 1;
+                continue;
+            }
+            let trim = self.trim_whitespace.unwrap_or(true);
+            let parsed = parse_entry(
+                &record,
+                columns,
+                &self.amount_format,
+                trim,
+                &self.record_types,
+            )
+            .and_then(|entry| Ok((entry, parse_record_timestamp(&record, columns)?)));
+            match parsed {
+                Ok((Some(entry), timestamp)) => {
+                    f(entry, timestamp)?;
+                    count += 1;
+                }
+                Ok((None, _)) => (),
+                Err(err) if self.parse_errors == ParseErrorPolicy::Lenient => {
+                    warn!(%err, %index, "skipping malformed record");
+                    self.summary.malformed_records += 1;
+                }
+                Err(err) => return Err(err.wrap_err(format!("malformed record {index}"))),
+            }
+        }
+        Ok(count)
+    }
+
+    /// Checks the order of transaction IDs of all deposits and withdrawals, violations are reported
+    /// with their line in the journal. Malformed records are skipped depending on [ParseErrorPolicy]
+    pub fn validate_order(&mut self) -> Result<OrderReport> {
+        let columns = self.columns()?;
+        let (mut order, mut report) = (TransactionOrder::default(), OrderReport::default());
+        for (index, record) in self.reader.byte_records().enumerate() {
+            let record = record?;
+            if is_comment_or_blank(&record) {
+                continue;
+            }
+            let trim = self.trim_whitespace.unwrap_or(true);
+            let transaction_id = match parse_entry(
+                &record,
+                columns,
+                &self.amount_format,
+                trim,
+                &self.record_types,
+            ) {
+                Ok(Some(JournalEntry::Transaction(
+                    TransactionMessage::Deposit(transaction)
+                    | TransactionMessage::Withdrawal(transaction),
+                ))) => transaction.transaction_id,
+                Ok(_) => continue,
+                Err(err) if self.parse_errors == ParseErrorPolicy::Lenient => {
+                    warn!(%err, %index, "skipping malformed record");
+                    continue;
+                }
+                Err(err) => return Err(err.wrap_err(format!("malformed record {index}"))),
+            };
+            report.transactions += 1;
+            if let Some(violation) = order.check(transaction_id) {
+                let line = record.position().map_or(0, |position| position.line());
+                report.record(line, transaction_id, violation);
+            }
+        }
+        Ok(report)
+    }
+}
+
+impl<T: std::io::Read + std::io::Seek> JournalSource for CsvParser<T> {
+    /// We will read file and parse each line. Spaces can be present in type and amount if whitespace is trimmed,
+    /// other fields are assumed to be valid [ClientID] and [TransactionID] for client and tx respectively
+    /// Checking for whitespaces and their removal worsens the performance by roughly 1s per 10_000_000 records,
+    /// so it is skipped for journals without them, see [CsvParser::with_trim_whitespace]
     #[tracing::instrument(skip(self, transaction_sender, dispute_look_up_sender))]
-    pub fn parse_journal(
+    fn parse_journal(
         &mut self,
-        transaction_sender: Sender<TransactionMessage>,
-        dispute_look_up_sender: Sender<DisputeLookUpMessage>,
-    ) -> Result<()> {
+        transaction_sender: Sender<Indexed<TransactionMessage>>,
+        mut dispute_look_up_sender: SpillingSender,
+    ) -> Result<Summary> {
         info!("starting to parse transaction journal");
         let mut count = 0;
+        let columns = self.columns()?;
+        let first = match self.resume {
+            Some(resume) => {
+                info!(
+                    offset = resume.offset,
+                    index = resume.index,
+                    "resuming journal"
+                );
+                let mut position = csv::Position::new();
+                position.set_byte(resume.offset).set_record(resume.index);
+                self.reader
+                    .seek(position)
+                    .wrap_err("failed to seek to the resume point")?;
+                resume.index as usize
+            }
+            None => 0,
+        };
+        let trim = match self.trim_whitespace {
+            Some(trim) => trim,
+            None => self.detect_whitespace()?,
+        };
+        let (mut next, mut reached_limit, mut stopped) = (first, false, None);
+        // lines of the records before the resume point are not counted
+        let lines = self.provenance.as_ref().filter(|_| self.resume.is_none());
 
         let mut record_timer = std::time::Instant::now();
-        for (index, record) in self.0.byte_records().enumerate() {
+        let mut batch_timer = std::time::Instant::now();
+        for (index, record) in (first..).zip(self.reader.byte_records()) {
             if index % 10_000_000 == 0 {
                 debug!(elapsed_seconds = record_timer.elapsed().as_secs(), %index, "processed 10_000_000 records");
                 record_timer = std::time::Instant::now();
             }
+            if index > 0 && (index as u64).is_multiple_of(TIMING_BATCH) {
+                self.summary.timings.parse.record(batch_timer.elapsed());
+                batch_timer = std::time::Instant::now();
+            }
+
+            if self
+                .limit
+                .is_some_and(|limit| (index - first) as u64 >= limit)
+            {
+                info!(%index, "reached record limit, stopping");
+                reached_limit = true;
+                break;
+            }
 
             count = index;
+            next = index + 1;
 
             let record = record?;
 
-            match parse_type(&record[0]) {
-                // once we do not need to handle spaces, we can just match against bytes like record[0] == b"deposit"
-                Ok(RecordType::Deposit) => {
-                    let (client_id, _, amount) = parse_deposit_or_withdrawal(&record)?;
-                    transaction_sender.send(TransactionMessage::deposit(client_id, amount));
+            if run_guard::should_stop(self.guard.as_ref(), index as u64) {
+                info!(%index, "run was stopped, stopping");
+                // the next run with `--since-offset` continues with this record
+                stopped = record.position().map(|position| ResumePoint {
+                    offset: position.byte(),
+                    index: index as u64,
+                });
+                break;
+            }
+
+            if let (Some(progress), Some(position)) = (self.progress.as_mut(), record.position()) {
+                progress.update(position.byte(), index as u64);
+            }
+
+            if let (Some(lines), Some(position)) = (lines, record.position()) {
+                lines.record(index as u64, position.line());
+            }
+
+            if is_comment_or_blank(&record) {
+                self.summary.comment_lines += 1;
+                continue;
+            }
+
+            // timestamp of the transactions indexed for the disputes, a bad one makes the record malformed
+            let parsed = parse_windowed(
+                &record,
+                columns,
+                &self.amount_format,
+                self.window,
+                trim,
+                &self.record_types,
+                &mut self.summary,
+            )
+            .and_then(|entry| {
+                let timestamp = match (self.inline.is_some(), entry.as_ref()) {
+                    (true, Some(JournalEntry::Transaction(_))) => {
+                        parse_record_timestamp(&record, columns)?
+                    }
+                    _ => None,
+                };
+                Ok((entry, timestamp))
+            });
+            let (entry, timestamp) = match parsed {
+                Ok(parsed) => parsed,
+                Err(err) if self.parse_errors == ParseErrorPolicy::Lenient => {
+                    let source = self
+                        .provenance
+                        .as_ref()
+                        .map(|provenance| tracing::field::display(provenance.locate(index as u64)));
+                    warn!(%err, %index, source, "skipping malformed record");
+                    self.summary.malformed_records += 1;
+                    continue;
                 }
-                Ok(RecordType::Withdrawal) => {
-                    let (client_id, _, amount) = parse_deposit_or_withdrawal(&record)?;
-                    transaction_sender.send(TransactionMessage::withdrawal(client_id, amount));
+                Err(err) => {
+                    return Err(err.wrap_err(match self.provenance.as_ref() {
+                        Some(provenance) => format!(
+                            "malformed record {index} at {}",
+                            provenance.locate(index as u64)
+                        ),
+                        None => format!("malformed record {index}"),
+                    }))
                 }
-                Ok(RecordType::Dispute) => {
-                    let (client_id, transaction_id) = parse_dispute_data(&record)?;
+            };
+            let entry = match self.client_remap.as_ref() {
+                Some(remap) => entry.map(|entry| remap.apply(entry)),
+                None => entry,
+            };
 
-                    debug!(%client_id, %transaction_id, %index, "found dispute transaction!");
+            if let (Some(order), Some(entry)) = (self.order.as_mut(), entry.as_ref()) {
+                order.check_entry(entry, index as u64, &mut self.summary);
+            }
+
+            if let (Some(sample), Some(entry)) = (self.sample, entry.as_ref()) {
+                if !sample.contains(entry.client_id()) {
+                    self.summary.sampled_out += 1;
+                    continue;
+                }
+            }
+
+            if let (Some(filter), Some(entry)) = (self.client_filter.as_ref(), entry.as_ref()) {
+                if !filter.allows(entry) {
+                    self.summary.filtered_clients += 1;
+                    continue;
+                }
+            }
 
-                    dispute_look_up_sender
-                        .send(DisputeLookUpMessage::Dispute(client_id, transaction_id));
+            if let (Some(validator), Some(entry)) = (self.validator.as_ref(), entry.as_ref()) {
+                if !validator.check(entry, index as u64, &mut self.summary) {
+                    continue;
                 }
-                Ok(RecordType::Resolve) => {
-                    let (client_id, transaction_id) = parse_dispute_data(&record)?;
-                    dispute_look_up_sender
-                        .send(DisputeLookUpMessage::Resolve(client_id, transaction_id));
+            }
+
+            // indexed even when already applied, later disputes can still refer to it
+            if let (Some(inline), Some(JournalEntry::Transaction(message))) =
+                (self.inline.as_mut(), entry.as_ref())
+            {
+                inline.index(message, timestamp);
+            }
+
+            if let (
+                Some(offsets),
+                Some(JournalEntry::Transaction(
+                    TransactionMessage::Deposit(transaction)
+                    | TransactionMessage::Withdrawal(transaction),
+                )),
+                Some(position),
+            ) = (self.offsets.as_ref(), entry.as_ref(), record.position())
+            {
+                offsets.record(transaction.transaction_id, position.byte());
+            }
+
+            if let (Some(seen), Some(JournalEntry::Transaction(message))) =
+                (self.seen.as_ref(), entry.as_ref())
+            {
+                if let Some((transaction, _)) = message.disputable() {
+                    seen.record(transaction.transaction_id);
                 }
-                Ok(RecordType::Chargeback) => {
-                    let (client_id, transaction_id) = parse_dispute_data(&record)?;
-                    dispute_look_up_sender
-                        .send(DisputeLookUpMessage::Chargeback(client_id, transaction_id));
+            }
+
+            if let Some(entry) = entry.as_ref() {
+                if self
+                    .checkpoint
+                    .is_applied(index as u64, entry.is_dispute_look_up())
+                {
+                    self.summary.already_applied += 1;
+                    continue;
                 }
-                _ => (),
+            }
+
+            match entry {
+                Some(JournalEntry::Transaction(message)) => transaction_sender.send_timed(
+                    Indexed::new(index as u64, message),
+                    &mut self.summary.timings.parse_queue_wait,
+                ),
+                Some(JournalEntry::DisputeLookUp(message)) => {
+                    debug!(?message, %index, "found dispute look-up request");
+                    let request = Indexed::new(index as u64, message);
+                    match self.inline.as_mut() {
+                        Some(inline) => inline.look_up(request, &transaction_sender),
+                        None => {
+                            // transactions parsed before the dispute have to reach processing before it
+                            transaction_sender.flush();
+                            dispute_look_up_sender.send(request)
+                        }
+                    }
+                }
+                None => (),
+            }
+            if let Some(inline) = self.inline.as_mut() {
+                inline.retry_pending(index as u64, &transaction_sender);
             }
         }
         info!(%count, "finished parsing transaction journal");
-        Ok(())
+        if let Some(progress) = self.progress.as_mut() {
+            progress.finish(count as u64);
+        }
+        if stopped.is_some() {
+            self.summary.journal_end = stopped;
+        } else if !reached_limit {
+            self.summary.journal_end = Some(ResumePoint {
+                offset: self.reader.position().byte(),
+                index: next as u64,
+            });
+        }
+        self.summary.spilled_disputes = dispute_look_up_sender.finish();
+        if let Some(inline) = self.inline.as_mut() {
+            inline.flush_pending(&transaction_sender);
+            inline.save_cache();
+            self.summary.merge(inline.take_summary());
+        }
+        Ok(std::mem::take(&mut self.summary))
     }
+}
 
+impl<T: std::io::Read + std::io::Seek> DisputeResolver for CsvParser<T> {
     /// Goes through the file from the start and looks for requested transaction
-    /// Stops when we reach transaction with ID higher than requested one or EOF or we find the requested transaction
+    /// Stops when we reach transaction with ID higher than requested one or EOF or we find the requested transaction.
+    /// With unordered input only EOF or the requested transaction stop the search
     /// We check `client_id` and `transaction_id` to make sure we have correct transaction
-    pub fn find_transaction(
+    /// Returned timestamp is `None` if the journal doesn't have timestamps
+    fn find_transaction(
         &mut self,
         client_id: ClientID,
         transaction_id: TransactionID,
-    ) -> Result<(ClientID, TransactionID, Amount)> {
-        // we should implement some logic to move to the closest position to the record we try to find
-        // and not to start from the start everytime
-        self.0.seek(csv::Position::new())?;
-        for record in self.0.byte_records() {
+    ) -> Result<Found> {
+        if is_unseen(self.seen.as_ref(), transaction_id) {
+            return Err(unseen_error(transaction_id));
+        }
+        if let Some(found) = self.find_indexed(client_id, transaction_id)? {
+            return Ok(found);
+        }
+
+        let columns = self.columns()?;
+        let trim = self.trim_whitespace.unwrap_or(true);
+        self.reader.seek(csv::Position::new())?;
+        for record in self.reader.byte_records() {
             let record = record?;
-            match &record[0] {
-                b"withdrawal" | b"deposit" => {
-                    let (found_client_id, found_transaction_id, amount) =
-                        parse_deposit_or_withdrawal(&record)?;
+            let Some(kind) = disputable_kind(&record, trim) else {
+                continue;
+            };
 
-                    if found_client_id == client_id && transaction_id == found_transaction_id {
-                        return Ok((found_client_id, found_transaction_id, amount));
-                    }
+            let (found_client_id, found_transaction_id, amount) =
+                match parse_deposit_or_withdrawal(&record, &self.amount_format, true) {
+                    Ok(parsed) => parsed,
+                    Err(_) if self.parse_errors == ParseErrorPolicy::Lenient => continue,
+                    Err(err) => return Err(err),
+                };
+            let found_client_id = remapped(self.client_remap.as_ref(), found_client_id);
 
-                    if found_transaction_id > transaction_id {
-                        return Err(eyre!("Transaction for given dispute not found"));
-                    }
-                }
-                _ => (),
+            if found_client_id == client_id && transaction_id == found_transaction_id {
+                let timestamp = parse_record_timestamp(&record, columns)?;
+                return Ok((
+                    found_client_id,
+                    found_transaction_id,
+                    amount,
+                    timestamp,
+                    kind,
+                ));
+            }
+
+            if !self.unordered && found_transaction_id > transaction_id {
+                return Err(eyre!("Transaction for given dispute not found"));
             }
         }
         Err(eyre!(
             "transaction for requested client id and transaction id not found"
         ))
     }
+
+    /// Looks up all requested transactions in a single pass over the file, stops once all of them are found
+    /// or the journal is past the highest requested ID
+    fn find_transactions(&mut self, requests: &[(ClientID, TransactionID)]) -> Vec<Result<Found>> {
+        let seen = self.seen.clone();
+        look_up_seen(seen.as_ref(), requests, |requests| {
+            let indexed = requests
+                .iter()
+                .map(|(client_id, transaction_id)| {
+                    self.find_indexed(*client_id, *transaction_id)
+                        .ok()
+                        .flatten()
+                })
+                .collect();
+            merge_indexed(requests, indexed, |missing| {
+                let mut batch = BatchLookUp::new(missing);
+                if let Err(err) = self.scan_batch(&mut batch) {
+                    return missing.iter().map(|_| Err(eyre!("{err:#}"))).collect();
+                }
+                batch.finish()
+            })
+        })
+    }
+
+    /// Scans the file from the start the same way as [DisputeResolver::find_transaction] does
+    fn find_owner(&mut self, transaction_id: TransactionID) -> Result<Option<ClientID>> {
+        if is_unseen(self.seen.as_ref(), transaction_id) {
+            return Ok(None);
+        }
+        self.columns()?;
+        let trim = self.trim_whitespace.unwrap_or(true);
+        self.reader.seek(csv::Position::new())?;
+        for record in self.reader.byte_records() {
+            let record = record?;
+            if disputable_kind(&record, trim).is_none() {
+                continue;
+            }
+
+            let (found_client_id, found_transaction_id, _) =
+                match parse_deposit_or_withdrawal(&record, &self.amount_format, true) {
+                    Ok(parsed) => parsed,
+                    Err(_) if self.parse_errors == ParseErrorPolicy::Lenient => continue,
+                    Err(err) => return Err(err),
+                };
+            if found_transaction_id == transaction_id {
+                return Ok(Some(remapped(self.client_remap.as_ref(), found_client_id)));
+            }
+            if !self.unordered && found_transaction_id > transaction_id {
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads blocks of the indexed transactions in the background, see [ReadAhead]
+    fn read_ahead(&mut self, transaction_ids: &[TransactionID]) {
+        if let (Some(offsets), Some(read_ahead)) = (self.offsets.as_ref(), self.read_ahead.as_ref())
+        {
+            for offset in transaction_ids.iter().filter_map(|tx| offsets.get(*tx)) {
+                read_ahead.request(offset);
+            }
+        }
+    }
+}
+
+impl<T: std::io::Read + std::io::Seek> CsvParser<T> {
+    /// Looks for whitespace in types and amounts of the next [WHITESPACE_SAMPLE] records, then seeks back
+    fn detect_whitespace(&mut self) -> Result<bool> {
+        let start = self.reader.position().clone();
+        let mut record = ByteRecord::new();
+        let mut found = false;
+        for _ in 0..WHITESPACE_SAMPLE {
+            if !self.reader.read_byte_record(&mut record)? {
+                break;
+            }
+            found = !is_comment_or_blank(&record)
+                && [0, 3]
+                    .into_iter()
+                    .filter_map(|column| record.get(column))
+                    .any(|field| field.iter().any(u8::is_ascii_whitespace));
+            if found {
+                break;
+            }
+        }
+        self.reader.seek(start)?;
+        debug!(trim = found, "detected whitespace in the journal");
+        Ok(found)
+    }
+
+    /// Reads the record at the offset from the index, `None` if the transaction is not indexed
+    /// or the record doesn't match
+    fn find_indexed(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+    ) -> Result<Option<Found>> {
+        let Some(offset) = self
+            .offsets
+            .as_ref()
+            .and_then(|offsets| offsets.get(transaction_id))
+        else {
+            return Ok(None);
+        };
+
+        let columns = self.columns()?;
+        let mut position = csv::Position::new();
+        position.set_byte(offset);
+        self.reader.seek(position)?;
+        let mut record = ByteRecord::new();
+        if !self.reader.read_byte_record(&mut record)? {
+            return Ok(None);
+        }
+        let Some(kind) = disputable_kind(&record, self.trim_whitespace.unwrap_or(true)) else {
+            return Ok(None);
+        };
+        match parse_deposit_or_withdrawal(&record, &self.amount_format, true) {
+            Ok((found_client_id, found_transaction_id, amount))
+                if remapped(self.client_remap.as_ref(), found_client_id) == client_id
+                    && found_transaction_id == transaction_id =>
+            {
+                let timestamp = parse_record_timestamp(&record, columns)?;
+                Ok(Some((client_id, transaction_id, amount, timestamp, kind)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn scan_batch(&mut self, batch: &mut BatchLookUp) -> Result<()> {
+        let columns = self.columns()?;
+        let trim = self.trim_whitespace.unwrap_or(true);
+        self.reader.seek(csv::Position::new())?;
+        for record in self.reader.byte_records() {
+            let record = record?;
+            let Some(kind) = disputable_kind(&record, trim) else {
+                continue;
+            };
+
+            let (client_id, transaction_id, amount) =
+                match parse_deposit_or_withdrawal(&record, &self.amount_format, true) {
+                    Ok(parsed) => parsed,
+                    Err(_) if self.parse_errors == ParseErrorPolicy::Lenient => continue,
+                    Err(err) => return Err(err),
+                };
+            let client_id = remapped(self.client_remap.as_ref(), client_id);
+            if batch.is_requested(client_id, transaction_id) {
+                let timestamp = parse_record_timestamp(&record, columns)?;
+                batch.found((client_id, transaction_id, amount, timestamp, kind));
+            }
+            if batch.is_done(transaction_id, self.unordered) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Single parsed journal record, either applied to the accounts directly or sent to the dispute look-up first
+#[derive(Debug, PartialEq, Eq)]
+pub enum JournalEntry {
+    Transaction(TransactionMessage),
+    DisputeLookUp(DisputeLookUpMessage),
+}
+
+impl JournalEntry {
+    pub fn is_dispute_look_up(&self) -> bool {
+        matches!(self, JournalEntry::DisputeLookUp(_))
+    }
+
+    /// Client the record belongs to, for transfers the sending client
+    pub fn client_id(&self) -> ClientID {
+        match self {
+            JournalEntry::Transaction(message) => message.client_id(),
+            JournalEntry::DisputeLookUp(message) => message.client_id(),
+        }
+    }
 }
 
-fn parse_type(record: &[u8]) -> Result<RecordType> {
-    if record.contains(&b' ') {
+/// Parses single CSV line without the header, columns are expected in the default order
+/// `type,client,tx,amount[,to_client]`. Entry point for fuzzing, see `fuzz/`
+pub fn parse_record(line: &[u8]) -> Result<JournalEntry> {
+    parse_record_with(line, &RecordTypes::default())
+}
+
+/// Same as [parse_record], rows of types which are not built in are parsed by their handlers
+pub fn parse_record_with(line: &[u8], record_types: &RecordTypes) -> Result<JournalEntry> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(line);
+    let mut record = ByteRecord::new();
+    if !reader.read_byte_record(&mut record)? {
+        return Err(eyre!("empty record"));
+    }
+
+    parse_entry(
+        &record,
+        Columns::DEFAULT,
+        &AmountFormat::default(),
+        true,
+        record_types,
+    )?
+    .ok_or(eyre!("invalid record type"))
+}
+
+/// Parses the record, returns `None` if it is outside of the time window or of unknown type
+fn parse_windowed(
+    record: &ByteRecord,
+    columns: Columns,
+    amount_format: &AmountFormat,
+    window: TimeWindow,
+    trim: bool,
+    record_types: &RecordTypes,
+    summary: &mut Summary,
+) -> Result<Option<JournalEntry>> {
+    if !window.is_unbounded() && !window.contains(parse_record_timestamp(record, columns)?) {
+        summary.outside_window += 1;
+        return Ok(None);
+    }
+
+    parse_entry(record, columns, amount_format, trim, record_types)
+}
+
+/// Parses the record by its type, types which are not built in are parsed by their handler in `record_types`
+/// and the rest is skipped, returning `None`. Without `trim` whitespace in the type or the amount makes the record malformed
+fn parse_entry(
+    record: &ByteRecord,
+    columns: Columns,
+    amount_format: &AmountFormat,
+    trim: bool,
+    record_types: &RecordTypes,
+) -> Result<Option<JournalEntry>> {
+    let record_type = field(record, 0, "type")?;
+    let entry = match parse_type(record_type, trim) {
+        Ok(RecordType::Deposit) => {
+            let (client_id, transaction_id, amount) =
+                parse_deposit_or_withdrawal(record, amount_format, trim)?;
+            JournalEntry::Transaction(TransactionMessage::deposit(
+                client_id,
+                transaction_id,
+                amount,
+            ))
+        }
+        Ok(RecordType::Withdrawal) => {
+            let (client_id, transaction_id, amount) =
+                parse_deposit_or_withdrawal(record, amount_format, trim)?;
+            JournalEntry::Transaction(TransactionMessage::withdrawal(
+                client_id,
+                transaction_id,
+                amount,
+            ))
+        }
+        Ok(RecordType::Dispute) => {
+            let (client_id, transaction_id) = parse_dispute_data(record)?;
+            let timestamp = parse_record_timestamp(record, columns)?;
+            let amount = parse_dispute_amount(record, amount_format, trim)?;
+            JournalEntry::DisputeLookUp(DisputeLookUpMessage::Dispute(
+                client_id,
+                transaction_id,
+                timestamp,
+                amount,
+            ))
+        }
+        Ok(RecordType::Resolve) => {
+            let (client_id, transaction_id) = parse_dispute_data(record)?;
+            let amount = parse_dispute_amount(record, amount_format, trim)?;
+            JournalEntry::DisputeLookUp(DisputeLookUpMessage::Resolve(
+                client_id,
+                transaction_id,
+                amount,
+            ))
+        }
+        Ok(RecordType::Chargeback) => {
+            let (client_id, transaction_id) = parse_dispute_data(record)?;
+            let amount = parse_dispute_amount(record, amount_format, trim)?;
+            JournalEntry::DisputeLookUp(DisputeLookUpMessage::Chargeback(
+                client_id,
+                transaction_id,
+                amount,
+            ))
+        }
+        Ok(RecordType::Reversal) => {
+            let (client_id, transaction_id) = parse_dispute_data(record)?;
+            JournalEntry::DisputeLookUp(DisputeLookUpMessage::Reversal(client_id, transaction_id))
+        }
+        Ok(RecordType::Transfer) => {
+            let (from_client_id, transaction_id, amount, to_client_id) =
+                parse_transfer(record, columns.to_client, amount_format, trim)?;
+            JournalEntry::Transaction(TransactionMessage::transfer(
+                from_client_id,
+                to_client_id,
+                transaction_id,
+                amount,
+            ))
+        }
+        Ok(RecordType::AdjustmentCredit) => {
+            let (client_id, transaction_id, amount) =
+                parse_deposit_or_withdrawal(record, amount_format, trim)?;
+            JournalEntry::Transaction(TransactionMessage::adjustment_credit(
+                client_id,
+                transaction_id,
+                amount,
+            ))
+        }
+        Ok(RecordType::AdjustmentDebit) => {
+            let (client_id, transaction_id, amount) =
+                parse_deposit_or_withdrawal(record, amount_format, trim)?;
+            JournalEntry::Transaction(TransactionMessage::adjustment_debit(
+                client_id,
+                transaction_id,
+                amount,
+            ))
+        }
+        Ok(RecordType::Lock) => {
+            JournalEntry::Transaction(TransactionMessage::Lock(parse_client_id(record)?))
+        }
+        Ok(RecordType::Unlock) => {
+            JournalEntry::Transaction(TransactionMessage::Unlock(parse_client_id(record)?))
+        }
+        Ok(RecordType::Close) => {
+            JournalEntry::Transaction(TransactionMessage::Close(parse_client_id(record)?))
+        }
+        Err(_) => {
+            if let Some(handler) = record_types.get(record_type, trim) {
+                return handler.parse(&Record::new(record, columns, amount_format, trim));
+            }
+            if !trim && record_type.iter().any(u8::is_ascii_whitespace) {
+                return Err(eyre!(
+                    "unexpected whitespace in record type, journal with whitespace needs --trim-whitespace"
+                ));
+            }
+            return Ok(None);
+        }
+    };
+
+    Ok(Some(entry))
+}
+
+/// Without `trim` only the exact type names are recognized
+fn parse_type(record: &[u8], trim: bool) -> Result<RecordType> {
+    if trim && record.contains(&b' ') {
         let mut s = String::from(from_utf8(record).wrap_err("failed to read utf-8 from bytes")?);
         s.retain(|c| !c.is_ascii_whitespace());
         match s.as_str() {
@@ -129,6 +1172,13 @@ fn parse_type(record: &[u8]) -> Result<RecordType> {
             "dispute" => Ok(RecordType::Dispute),
             "resolve" => Ok(RecordType::Resolve),
             "chargeback" => Ok(RecordType::Chargeback),
+            "reversal" => Ok(RecordType::Reversal),
+            "transfer" => Ok(RecordType::Transfer),
+            "adjustment_credit" => Ok(RecordType::AdjustmentCredit),
+            "adjustment_debit" => Ok(RecordType::AdjustmentDebit),
+            "lock" => Ok(RecordType::Lock),
+            "unlock" => Ok(RecordType::Unlock),
+            "close" => Ok(RecordType::Close),
             _ => Err(eyre!("invalid record type")),
         }
     } else {
@@ -138,103 +1188,462 @@ fn parse_type(record: &[u8]) -> Result<RecordType> {
             b"dispute" => Ok(RecordType::Dispute),
             b"resolve" => Ok(RecordType::Resolve),
             b"chargeback" => Ok(RecordType::Chargeback),
+            b"reversal" => Ok(RecordType::Reversal),
+            b"transfer" => Ok(RecordType::Transfer),
+            b"adjustment_credit" => Ok(RecordType::AdjustmentCredit),
+            b"adjustment_debit" => Ok(RecordType::AdjustmentDebit),
+            b"lock" => Ok(RecordType::Lock),
+            b"unlock" => Ok(RecordType::Unlock),
+            b"close" => Ok(RecordType::Close),
             _ => Err(eyre!("invalid record type")),
         }
     }
 }
 
-fn parse_deposit_or_withdrawal(record: &ByteRecord) -> Result<(ClientID, TransactionID, Amount)> {
-    let amount = match record[3].contains(&b' ') {
-        true => Decimal::from_str_exact(
-            from_utf8(
-                &record[3]
-                    .iter()
-                    .filter_map(|b| {
-                        if !b.is_ascii_whitespace() {
-                            Some(b.clone())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect::<Vec<u8>>()
-                    .deref(),
-            )
-            .wrap_err("failed to parse amount to string")?,
-        )
-        .wrap_err("failed to convert str to decimal")?,
-        false => Decimal::from_str_exact(
-            from_utf8(&record[3]).wrap_err("failed to parse amount to string")?,
-        )
-        .wrap_err("failed to convert str to decimal")?,
+/// Returns `true` for lines starting with `#` and lines with only whitespace, e.g. annotations
+/// of hand-crafted correction journals
+fn is_comment_or_blank(record: &ByteRecord) -> bool {
+    match record.get(0) {
+        Some(first) if first.trim_ascii_start().starts_with(b"#") => true,
+        _ => record.iter().all(|field| field.trim_ascii().is_empty()),
+    }
+}
+
+/// Returns field of the record, fails instead of panicking if the record is too short
+pub(crate) fn field<'r>(record: &'r ByteRecord, index: usize, name: &str) -> Result<&'r [u8]> {
+    record
+        .get(index)
+        .ok_or_else(|| eyre!("record is missing {name} column"))
+}
+
+pub(crate) fn parse_deposit_or_withdrawal(
+    record: &ByteRecord,
+    amount_format: &AmountFormat,
+    trim: bool,
+) -> Result<(ClientID, TransactionID, Amount)> {
+    let amount = field(record, 3, "amount")?;
+    let amount = match trim {
+        true => parse_amount(amount, amount_format)?,
+        false => parse_untrimmed_amount(amount, amount_format)?,
     };
 
     Ok((
-        from_utf8(&record[1])
-            .expect("failed to parse client ID")
-            .parse::<u16>()
+        from_utf8(field(record, 1, "client")?)
+            .wrap_err("failed to parse client ID")?
+            .parse::<ClientID>()
             .wrap_err("failed to parse client id")?,
-        from_utf8(&record[2])
-            .expect("failed to parse transaction ID")
-            .parse::<u32>()
+        from_utf8(field(record, 2, "tx")?)
+            .wrap_err("failed to parse transaction ID")?
+            .parse::<TransactionID>()
             .wrap_err("failed to parse transaction id")?,
         amount,
     ))
 }
 
+/// Kind of the deposit or withdrawal record, `None` for other records. The type is trimmed the same way
+/// as by [parse_entry], so padded records applied by the parser are found by the disputes
+fn disputable_kind(record: &ByteRecord, trim: bool) -> Option<TransactionKind> {
+    match parse_type(record.get(0)?, trim) {
+        Ok(RecordType::Deposit) => Some(TransactionKind::Deposit),
+        Ok(RecordType::Withdrawal) => Some(TransactionKind::Withdrawal),
+        _ => None,
+    }
+}
+
+/// Returns `None` if the journal doesn't have timestamp column or the record has it empty
+pub(crate) fn parse_record_timestamp(
+    record: &ByteRecord,
+    columns: Columns,
+) -> Result<Option<Timestamp>> {
+    match columns.timestamp.and_then(|column| record.get(column)) {
+        Some(timestamp) if !timestamp.trim_ascii().is_empty() => Ok(Some(parse_timestamp(
+            from_utf8(timestamp).wrap_err("failed to parse timestamp")?,
+        )?)),
+        _ => Ok(None),
+    }
+}
+
+/// Transfer has the same layout as deposit or withdrawal with extra column for the receiving client
+pub(crate) fn parse_transfer(
+    record: &ByteRecord,
+    to_client_column: usize,
+    amount_format: &AmountFormat,
+    trim: bool,
+) -> Result<(ClientID, TransactionID, Amount, ClientID)> {
+    let (from_client_id, transaction_id, amount) =
+        parse_deposit_or_withdrawal(record, amount_format, trim)?;
+    let to_client_id = from_utf8(
+        record
+            .get(to_client_column)
+            .ok_or(eyre!("transfer is missing to_client"))?,
+    )
+    .wrap_err("failed to parse to_client ID")?
+    .trim()
+    .parse::<ClientID>()
+    .wrap_err("failed to parse to_client id")?;
+
+    Ok((from_client_id, transaction_id, amount, to_client_id))
+}
+
+/// Lock, unlock and close rows only need the client, the rest of the columns is ignored
+pub(crate) fn parse_client_id(record: &ByteRecord) -> Result<ClientID> {
+    from_utf8(field(record, 1, "client")?)
+        .wrap_err("failed to parse client ID")?
+        .parse::<ClientID>()
+        .wrap_err("failed to parse client id")
+}
+
+/// Dispute, resolve and chargeback rows don't need the amount, so both `dispute,1,5,` and `dispute,1,5` are accepted,
+/// amount of partial disputes is parsed by [parse_dispute_amount]
 fn parse_dispute_data(record: &ByteRecord) -> Result<(ClientID, TransactionID)> {
     Ok((
-        from_utf8(&record[1])
+        from_utf8(field(record, 1, "client")?)
             .wrap_err("failed to parse client ID")?
-            .parse::<u16>()
-            .wrap_err("failed to parse u16")?,
-        from_utf8(&record[2])
+            .parse::<ClientID>()
+            .wrap_err("failed to parse client id")?,
+        from_utf8(field(record, 2, "tx")?)
             .wrap_err("failed to parse transaction ID")?
-            .parse::<u32>()
-            .wrap_err("failed to parse u32")?,
+            .parse::<TransactionID>()
+            .wrap_err("failed to parse transaction id")?,
     ))
 }
 
+/// Amount of partial-dispute row, e.g. `dispute,1,5,30.0`, `None` if the row has no or empty amount column
+fn parse_dispute_amount(
+    record: &ByteRecord,
+    amount_format: &AmountFormat,
+    trim: bool,
+) -> Result<Option<Amount>> {
+    let Some(amount) = record
+        .get(3)
+        .filter(|amount| !amount.trim_ascii().is_empty())
+    else {
+        return Ok(None);
+    };
+    let amount = match trim {
+        true => parse_amount(amount, amount_format)?,
+        false => parse_untrimmed_amount(amount, amount_format)?,
+    };
+    if amount <= Amount::ZERO {
+        return Err(eyre!("disputed amount {amount} is not positive"));
+    }
+    Ok(Some(amount))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rust_decimal_macros::dec;
+    use std::fs::File;
+
+    #[test]
+    fn test_columns_from_headers() {
+        let tests = vec![
+            (
+                "default",
+                vec!["type", "client", "tx", "amount"],
+                Columns {
+                    to_client: 4,
+                    timestamp: None,
+                },
+            ),
+            (
+                "timestamp and named to_client",
+                vec!["type", "client", "tx", "amount", "timestamp", " to_client"],
+                Columns {
+                    to_client: 5,
+                    timestamp: Some(4),
+                },
+            ),
+        ];
+
+        for (name, headers, want) in tests {
+            let got = Columns::from_headers(&csv::ByteRecord::from(headers));
+            assert_eq!(got, want, "failed test {name}");
+        }
+    }
+
+    #[test]
+    fn test_parse_transfer() {
+        let got = parse_transfer(
+            &csv::ByteRecord::from(vec!["transfer", "1", "5", "2.5", "2"]),
+            4,
+            &AmountFormat::default(),
+            true,
+        )
+        .expect("failed to parse valid transfer");
+        assert_eq!(got, (1, 5, amount!(2.5), 2));
+
+        assert!(
+            parse_transfer(
+                &csv::ByteRecord::from(vec!["transfer", "1", "5", "2.5"]),
+                4,
+                &AmountFormat::default(),
+                true
+            )
+            .is_err(),
+            "transfer without to_client"
+        );
+    }
 
     #[test]
     fn test_parse_deposit_or_withdrawal() {
-        let tests: Vec<(&str, ByteRecord, (u16, u32, Decimal))> = vec![
+        let tests: Vec<(&str, ByteRecord, (ClientID, TransactionID, Amount))> = vec![
             (
                 "simple deposit",
                 csv::ByteRecord::from(vec!["deposit", "1", "1", "1.0"]),
-                (1, 1, dec!(1.0)),
+                (1, 1, amount!(1.0)),
             ),
             (
                 "simple withdrawal",
                 csv::ByteRecord::from(vec!["withdrawal", "1", "2", "1.0"]),
-                (1, 2, dec!(1.0)),
+                (1, 2, amount!(1.0)),
             ),
             (
                 "amount with space",
                 csv::ByteRecord::from(vec!["deposit", "1", "3", "1. 0"]),
-                (1, 3, dec!(1.0)),
+                (1, 3, amount!(1.0)),
             ),
             (
                 "amount with multiple spaces",
                 csv::ByteRecord::from(vec!["deposit", "1", "4", "10 . 0"]),
-                (1, 4, dec!(10.0)),
+                (1, 4, amount!(10.0)),
             ),
             (
                 "type with multiple spaces",
                 csv::ByteRecord::from(vec!["d e p osit", "1", "4", "10.0"]),
-                (1, 4, dec!(10.0)),
+                (1, 4, amount!(10.0)),
             ),
         ];
 
         for (i, (name, test_data, want)) in tests.into_iter().enumerate() {
-            let got = parse_deposit_or_withdrawal(&test_data).expect(&format!(
-                "failed to parse data from ByteRecord for test {} - {name}",
-                i + 1
-            ));
+            let got = parse_deposit_or_withdrawal(&test_data, &AmountFormat::default(), true)
+                .unwrap_or_else(|err| {
+                    panic!(
+                        "failed to parse data from ByteRecord for test {} - {name}: {err}",
+                        i + 1
+                    )
+                });
             assert_eq!(got, want, "failed test {} - {name}", i + 1)
         }
     }
+
+    #[test]
+    fn test_parse_entry_untrimmed() {
+        let tests = vec![
+            ("clean", vec!["deposit", "1", "1", "1.5"], Some(true)),
+            ("type with space", vec![" deposit", "1", "1", "1.5"], None),
+            ("amount with space", vec!["deposit", "1", "1", "1. 5"], None),
+            ("unknown type", vec!["bonus", "1", "1", "1.5"], Some(false)),
+        ];
+
+        for (name, fields, want) in tests {
+            let got = parse_entry(
+                &ByteRecord::from(fields.clone()),
+                Columns::DEFAULT,
+                &AmountFormat::default(),
+                false,
+                &RecordTypes::default(),
+            );
+            assert_eq!(
+                got.ok().map(|entry| entry.is_some()),
+                want,
+                "failed test {name}"
+            );
+            let trimmed = parse_entry(
+                &ByteRecord::from(fields),
+                Columns::DEFAULT,
+                &AmountFormat::default(),
+                true,
+                &RecordTypes::default(),
+            );
+            assert!(trimmed.is_ok(), "failed test {name} with trimming");
+        }
+    }
+
+    #[test]
+    fn test_detect_whitespace() {
+        let path = std::env::temp_dir().join(format!("tren-whitespace-{}.csv", std::process::id()));
+        let tests = vec![
+            ("clean", "type,client,tx,amount\ndeposit,1,1,1.5\n", false),
+            (
+                "spaced amount",
+                "type,client,tx,amount\ndeposit,1,1, 1.5\n",
+                true,
+            ),
+            (
+                "spaced comment",
+                "type,client,tx,amount\n# a note\ndeposit,1,1,1\n",
+                false,
+            ),
+        ];
+
+        for (name, journal, want) in tests {
+            std::fs::write(&path, journal).unwrap();
+            let mut parser = CsvParser::new(File::open(&path).unwrap());
+            parser.columns().unwrap();
+            let got = parser.detect_whitespace().unwrap();
+            assert_eq!(got, want, "failed test {name}");
+            let first = parser.reader.byte_records().next().unwrap().unwrap();
+            assert_eq!(
+                first.as_slice(),
+                journal.lines().nth(1).unwrap().replace(',', "").as_bytes(),
+                "failed test {name}, reader didn't seek back"
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_is_comment_or_blank() {
+        let tests = vec![
+            ("comment", vec!["# note, with comma"], true),
+            ("indented comment", vec!["  #note"], true),
+            ("whitespace", vec!["  "], true),
+            ("empty fields", vec!["", " ", ""], true),
+            ("record", vec!["deposit", "1", "1", "#1"], false),
+        ];
+
+        for (name, fields, want) in tests {
+            let got = is_comment_or_blank(&ByteRecord::from(fields));
+            assert_eq!(got, want, "failed test {name}");
+        }
+    }
+
+    #[test]
+    fn test_parse_record() {
+        let tests = vec![
+            (
+                "deposit",
+                "deposit,1,2,1.5",
+                JournalEntry::Transaction(TransactionMessage::deposit(1, 2, amount!(1.5))),
+            ),
+            (
+                "type with spaces",
+                " with drawal ,1,2,1.5",
+                JournalEntry::Transaction(TransactionMessage::withdrawal(1, 2, amount!(1.5))),
+            ),
+            (
+                "dispute without amount",
+                "dispute,1,2,",
+                JournalEntry::DisputeLookUp(DisputeLookUpMessage::Dispute(1, 2, None, None)),
+            ),
+            (
+                "dispute without trailing comma",
+                "dispute,1,2",
+                JournalEntry::DisputeLookUp(DisputeLookUpMessage::Dispute(1, 2, None, None)),
+            ),
+            (
+                "partial dispute",
+                "dispute,1,2, 0.5",
+                JournalEntry::DisputeLookUp(DisputeLookUpMessage::Dispute(
+                    1,
+                    2,
+                    None,
+                    Some(amount!(0.5)),
+                )),
+            ),
+            (
+                "chargeback without trailing comma",
+                "chargeback,1,2",
+                JournalEntry::DisputeLookUp(DisputeLookUpMessage::Chargeback(1, 2, None)),
+            ),
+            (
+                "transfer",
+                "transfer,1,2,1.5,3",
+                JournalEntry::Transaction(TransactionMessage::transfer(1, 3, 2, amount!(1.5))),
+            ),
+        ];
+
+        for (name, input, want) in tests {
+            let got = parse_record(input.as_bytes())
+                .unwrap_or_else(|err| panic!("failed test {name}: {err}"));
+            assert_eq!(got, want, "failed test {name}");
+        }
+
+        assert!(parse_record(b"").is_err(), "empty record");
+        assert!(parse_record(b"unknown,1,2,1.5").is_err(), "unknown type");
+        assert!(parse_record(b"deposit,1,2,abc").is_err(), "invalid amount");
+        assert!(
+            parse_record(b"resolve,1,2,-1").is_err(),
+            "negative dispute amount"
+        );
+        assert!(parse_record(b"deposit,1").is_err(), "missing columns");
+        assert!(parse_record(b"deposit,\xff,2,1").is_err(), "invalid utf-8");
+    }
+
+    #[test]
+    fn test_find_transactions() {
+        let path = std::env::temp_dir().join(format!("tren-test-batch-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "type,client,tx,amount\ndeposit,1,1,1.5\ndeposit,2,2,2\nwithdrawal,1,3,0.5\ndeposit,1,5,1\n",
+        )
+        .unwrap();
+        let mut parser = CsvParser::new(File::open(&path).unwrap());
+        let requests = [(1, 3), (2, 2), (1, 4), (2, 1), (1, 3)];
+
+        let got: Vec<Option<Amount>> = parser
+            .find_transactions(&requests)
+            .into_iter()
+            .map(|found| found.ok().map(|(_, _, amount, ..)| amount))
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            got,
+            vec![
+                Some(amount!(0.5)),
+                Some(amount!(2)),
+                None,
+                None,
+                Some(amount!(0.5))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_padded_dispute() {
+        let path =
+            std::env::temp_dir().join(format!("tren-test-padded-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "type,client,tx,amount\n deposit,1,1,10\nwithdrawal ,1,2,4\ndispute,1,1,\n",
+        )
+        .unwrap();
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let sender = Sender::new(sender);
+        let mut finder = DisputeFinder::new(CsvParser::new(File::open(&path).unwrap()));
+        for (index, transaction_id) in [1, 2].into_iter().enumerate() {
+            finder.look_up(
+                Indexed::new(
+                    index as u64,
+                    DisputeLookUpMessage::Dispute(1, transaction_id, None, None),
+                ),
+                &sender,
+            );
+        }
+        // without trimming the padded records are malformed, they were never applied
+        let mut untrimmed =
+            CsvParser::new(File::open(&path).unwrap()).with_trim_whitespace(Some(false));
+        let untrimmed_found = untrimmed.find_transaction(1, 1);
+        std::fs::remove_file(&path).unwrap();
+
+        let got: Vec<_> = receiver.try_iter().flatten().collect();
+        assert_eq!(
+            got,
+            vec![
+                Indexed::new(
+                    0,
+                    TransactionMessage::Dispute(Dispute::new(1, 1, amount!(10)))
+                ),
+                Indexed::new(
+                    1,
+                    TransactionMessage::Dispute(
+                        Dispute::new(1, 2, amount!(4)).with_kind(TransactionKind::Withdrawal)
+                    )
+                ),
+            ]
+        );
+        assert!(untrimmed_found.is_err());
+    }
 }
@@ -0,0 +1,824 @@
+use crate::accounts::Accounts;
+use crate::affinity::{pin_current_thread, CpuAffinity};
+use crate::alerts::{self, Threshold};
+use crate::channel::Indexed;
+use crate::checkpoint::{Checkpoint, ResumePoint};
+use crate::client_filter::ClientFilter;
+use crate::client_remap::ClientRemap;
+use crate::config::Config;
+use crate::dead_letter::DeadLetter;
+use crate::decisions::DecisionLog;
+use crate::dispute_look_up::{BoxedResolver, DisputeFinder, TransactionIndex};
+#[cfg(feature = "fault-injection")]
+use crate::fault::Faults;
+use crate::format::{FormatRegistry, Journal};
+use crate::interest::InterestAccrual;
+use crate::manifest::Manifest;
+use crate::memory::{self, Component, MemoryBudget};
+use crate::offsets::OffsetIndex;
+use crate::parser::JournalSource;
+use crate::processor::Hook;
+use crate::progress::Progress;
+use crate::provenance::Provenance;
+use crate::read_ahead::ReadAhead;
+use crate::record_types::RecordTypes;
+use crate::rules::Validator;
+use crate::run_guard::{self, RunGuard};
+use crate::sample::Sample;
+use crate::seen::SeenTransactions;
+use crate::summary::Summary;
+use crate::wal::WriteAheadLog;
+use crate::webhook::Webhook;
+use crate::{
+    audit, binary, channel, dispute_cache, limits, parser, processor, report, segments, spill,
+    state, DisputeLookUpMessage, TransactionMessage,
+};
+use eyre::{eyre, Context, Result};
+use std::any::Any;
+use std::fs::File;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+/// Processes the whole journal with parser, dispute look-up and processing each running in its own thread.
+/// Returns final state of the accounts and counters collected by all three threads
+pub fn run(input: &Path, config: Config) -> Result<(Accounts, Summary)> {
+    PipelineBuilder::new(config)
+        .with_input(input)
+        .build()?
+        .run()
+}
+
+/// Same as [run], the journal format is looked up in the given registry
+pub fn run_with_formats(
+    input: &Path,
+    config: Config,
+    formats: &FormatRegistry,
+) -> Result<(Accounts, Summary)> {
+    PipelineBuilder::new(config)
+        .with_input(input)
+        .with_formats(formats)
+        .build()?
+        .run()
+}
+
+/// Source of the journal records, boxed so custom sources can be plugged into [PipelineBuilder]
+pub type BoxedSource = Box<dyn JournalSource + Send>;
+
+/// Assembles [Pipeline] from the [Config] and optional components replacing the default ones.
+/// By default the journal at the input path is read by [parser::CsvParser] or [binary::BinaryParser]
+/// depending on its format, disputed transactions are looked up by re-scanning the same file
+/// and [Accounts] are configured from the [Config]
+pub struct PipelineBuilder<'f> {
+    config: Config,
+    input: Option<PathBuf>,
+    formats: Option<&'f FormatRegistry>,
+    sources: Option<(BoxedSource, BoxedResolver)>,
+    accounts: Option<Accounts>,
+    hooks: Vec<Hook>,
+    record_types: RecordTypes,
+    #[cfg(feature = "fault-injection")]
+    faults: Faults,
+}
+
+impl<'f> PipelineBuilder<'f> {
+    pub fn new(config: Config) -> PipelineBuilder<'f> {
+        PipelineBuilder {
+            config,
+            input: None,
+            formats: None,
+            sources: None,
+            accounts: None,
+            hooks: Vec::new(),
+            record_types: RecordTypes::default(),
+            #[cfg(feature = "fault-injection")]
+            faults: Faults::default(),
+        }
+    }
+
+    /// Path to the journal, not needed with [PipelineBuilder::with_sources]
+    pub fn with_input(mut self, input: &Path) -> PipelineBuilder<'f> {
+        self.input = Some(input.to_path_buf());
+        self
+    }
+
+    /// Registry the format of the input is looked up in, [FormatRegistry::default] if not set
+    pub fn with_formats(mut self, formats: &'f FormatRegistry) -> PipelineBuilder<'f> {
+        self.formats = Some(formats);
+        self
+    }
+
+    /// Replaces the parsers of the input. `journal` is parsed and `disputes` answers look-ups of disputed
+    /// transactions, so it can be backed by an index, an external store or a remote service instead of
+    /// re-scanning the journal, see [crate::dispute_look_up::DisputeResolver].
+    /// Time window, limit, sample, client filter and checkpoint of the [Config] are not applied to custom sources
+    pub fn with_sources(
+        mut self,
+        journal: BoxedSource,
+        disputes: BoxedResolver,
+    ) -> PipelineBuilder<'f> {
+        self.sources = Some((journal, disputes));
+        self
+    }
+
+    /// Replaces the accounts configured from the [Config], for example with pre-loaded balances
+    pub fn with_accounts(mut self, accounts: Accounts) -> PipelineBuilder<'f> {
+        self.accounts = Some(accounts);
+        self
+    }
+
+    /// Adds hook called with every applied or rejected operation, see [processor::Processor::with_hook]
+    pub fn with_hook(mut self, hook: Hook) -> PipelineBuilder<'f> {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Drops, duplicates or delays messages sent to the processing, for tests of the pipeline under failure.
+    /// Faults of the look-ups apply only to the separate dispute look-up, not to disputes resolved by the parser
+    #[cfg(feature = "fault-injection")]
+    pub fn with_faults(mut self, faults: Faults) -> PipelineBuilder<'f> {
+        self.faults = faults;
+        self
+    }
+
+    /// Handlers of custom record types of delimited journals, see [crate::record_types]. Not used by binary
+    /// journals nor custom sources
+    pub fn with_record_types(mut self, record_types: RecordTypes) -> PipelineBuilder<'f> {
+        self.record_types = record_types;
+        self
+    }
+
+    /// Opens all files of the pipeline and recovers the accounts if requested, nothing is processed yet
+    pub fn build(self) -> Result<Pipeline> {
+        let config = self.config;
+        let default_formats;
+        let formats = match self.formats {
+            Some(formats) => formats,
+            None => {
+                default_formats = FormatRegistry::default();
+                &default_formats
+            }
+        };
+        // kept until the end of processing, transcoded journal is removed once it is dropped
+        let prepared = match (&self.sources, &self.input) {
+            (Some(_), _) => None,
+            (None, Some(input)) => {
+                if let Some(manifest) = &config.verify_manifest {
+                    Manifest::load(manifest)?.verify(input)?;
+                }
+                Some(formats.open(input, config.input_format.as_deref())?)
+            }
+            (None, None) => return Err(eyre!("pipeline needs path to the journal or its sources")),
+        };
+        // offsets only make sense in the journal itself, custom sources are parsed from their start
+        let offset_file = config.since_offset.clone().filter(|_| prepared.is_some());
+        let resume = offset_file
+            .as_deref()
+            .map(ResumePoint::load)
+            .transpose()?
+            .flatten();
+        if resume.is_some() {
+            if config.recover.is_none() && self.accounts.is_none() {
+                return Err(eyre!(
+                    "--since-offset needs the accounts of the previous runs, recover them with --recover"
+                ));
+            }
+            if config.single_pass {
+                return Err(eyre!(
+                    "--since-offset can't be used with --single-pass, disputes can refer to records before the offset"
+                ));
+            }
+            if prepared.as_ref().is_some_and(Journal::is_transcoded) {
+                return Err(eyre!(
+                    "--since-offset needs comma or tab separated or binary journal, offsets of transcoded journal don't match it"
+                ));
+            }
+        }
+
+        let interest = config.interest.accrual(config.window)?;
+        let warning_interval = config.warning_interval.map(std::time::Duration::from_secs);
+        // custom sources don't have an input file to point to
+        let provenance = match (config.provenance, &self.input, prepared.is_some()) {
+            (true, Some(input), true) => Some(Provenance::new(input.clone())),
+            _ => None,
+        };
+        let dead_letter = config
+            .dead_letter
+            .as_deref()
+            .map(|path| DeadLetter::create(path, provenance.clone()))
+            .transpose()?;
+        let dispute_dead_letter = dead_letter.clone();
+        let validator = match config.rules.is_empty() {
+            true => None,
+            false => {
+                let segments = config
+                    .segments
+                    .as_deref()
+                    .map(segments::Segments::load)
+                    .transpose()?;
+                Some(
+                    Validator::new(config.rules.clone(), segments)?
+                        .with_dead_letter(dead_letter.clone()),
+                )
+            }
+        };
+        let audit = config
+            .audit
+            .as_deref()
+            .map(|path| audit::AuditLog::create(path, provenance.clone()))
+            .transpose()?;
+        let snapshots = config.report_interval.map(|interval| {
+            report::ReportSnapshots::new(
+                config
+                    .report_file
+                    .clone()
+                    .unwrap_or_else(|| "report.csv".into()),
+                std::time::Duration::from_secs(interval),
+            )
+            .with_changes(config.report_changes.clone())
+            .with_keep(config.report_keep)
+            .with_link(config.report_link.clone())
+            .with_compression(config.compress)
+        });
+        let accounts = match self.accounts {
+            Some(accounts) => accounts,
+            None => configured_accounts(&config)?,
+        };
+        let memory = config
+            .max_memory
+            .map(|limit| MemoryBudget::new(limit as u64));
+        let guard = RunGuard::new(
+            config.max_runtime.map(Duration::from_secs),
+            config.idle_timeout.map(Duration::from_secs),
+        );
+        let webhook = Webhook::start(&config.webhook)?;
+        let hooks = self
+            .hooks
+            .into_iter()
+            .chain(webhook.as_ref().map(Webhook::hook));
+        let mut processor = hooks.fold(
+            processor::Processor::new(accounts, dead_letter, audit)
+                .with_snapshots(snapshots)
+                .with_invariant_checks(config.check_invariants)
+                .with_dedup_window(config.dedup_window)
+                .with_warning_interval(warning_interval)
+                .with_memory_budget(memory.clone())
+                .with_run_guard(guard.clone())
+                .with_provenance(provenance.clone())
+                .with_rule_flags(validator.as_ref().map(Validator::flags)),
+            |processor, hook| processor.with_hook(hook),
+        );
+        let mut checkpoint = config
+            .skip_until
+            .map(Checkpoint::skip_until)
+            .unwrap_or_default();
+        if let Some(path) = config.recover.as_ref() {
+            checkpoint = checkpoint.merge(processor.recover(path)?);
+        }
+
+        // taken by the finder which looks the disputes up, either the inline one or the separate one
+        let mut warm_cache = config
+            .dispute_cache_file
+            .as_deref()
+            .map(dispute_cache::load)
+            .transpose()?
+            .unwrap_or_default();
+        let (journal, dispute_journal) = match (self.sources, prepared.as_ref()) {
+            (Some((journal, disputes)), _) => (journal, Some(disputes)),
+            (None, Some(prepared)) => {
+                let inline = config.single_pass.then(|| {
+                    dispute_finder(
+                        TransactionIndex::default(),
+                        &config,
+                        processor.accounts(),
+                        dispute_dead_letter.clone(),
+                    )
+                    .with_warm_cache(std::mem::take(&mut warm_cache))
+                    .with_memory_budget(memory.clone())
+                });
+                open_sources(
+                    prepared,
+                    &config,
+                    checkpoint,
+                    resume,
+                    inline,
+                    self.record_types,
+                    guard.clone(),
+                    provenance,
+                    validator,
+                )?
+            }
+            (None, None) => unreachable!("journal is prepared when sources are not set"),
+        };
+
+        let dispute_finder = dispute_journal.map(|source| {
+            dispute_finder(source, &config, processor.accounts(), dispute_dead_letter)
+                .with_warm_cache(warm_cache)
+                .with_batch_size(config.dispute_batch_size)
+                .with_read_ahead(config.dispute_read_ahead)
+                .with_memory_budget(memory.clone())
+        });
+        // opened after recovery, so the log can be recovered from and appended to in the same run
+        let processor = processor
+            .with_wal(config.wal.as_deref().map(WriteAheadLog::open).transpose()?)
+            .with_decision_log(
+                config
+                    .record_decisions
+                    .as_deref()
+                    .map(DecisionLog::create)
+                    .transpose()?,
+            );
+
+        Ok(Pipeline {
+            journal,
+            dispute_finder,
+            processor,
+            channel_size: config.channel_size,
+            channel_batch_size: config.channel_batch_size,
+            dispute_spill_threshold: config.dispute_spill_threshold,
+            cpu_affinity: config.cpu_affinity.clone(),
+            webhook,
+            offset_file,
+            interest,
+            memory,
+            alerts: config.alerts.clone(),
+            guard,
+            #[cfg(feature = "fault-injection")]
+            faults: self.faults,
+            _prepared: prepared,
+        })
+    }
+}
+
+/// Accounts configured from the [Config], with the initial state or the state snapshot loaded if either is set
+pub fn configured_accounts(config: &Config) -> Result<Accounts> {
+    let limits = config
+        .limits
+        .as_deref()
+        .map(limits::LimitPolicy::load)
+        .transpose()?
+        .unwrap_or_default();
+    if !config.fees.is_empty() && config.fees.house_account.is_none() {
+        return Err(eyre!(
+            "fees need the house account they are credited to, set it by --fee-account"
+        ));
+    }
+    if config.segment_totals.is_some() && config.segments.is_none() {
+        return Err(eyre!(
+            "segment totals need segments of the clients, set them by --segments"
+        ));
+    }
+    let segments = config
+        .segments
+        .as_deref()
+        .map(segments::Segments::load)
+        .transpose()?;
+    let mut accounts = Accounts::new(config.dispute_policy)
+        .with_withdrawal_disputes(config.withdrawal_disputes)
+        .with_max_open_disputes(config.max_open_disputes)
+        .with_fraud_rules(config.fraud_rules)
+        .with_limits(limits)
+        .with_fees(config.fees)
+        .with_unfreeze_on_resolve(config.unfreeze_on_resolve)
+        .with_extended_report(config.extended_report)
+        .with_totals_row(config.totals_row)
+        .with_report_version(config.report_version)
+        .with_segments(segments)
+        .with_report_threads(config.report_threads)
+        .with_rounding(config.rounding)
+        .with_report_scale(config.currency_scale())
+        .with_eviction_interval(config.eviction_interval);
+    match (
+        config.initial_state.as_deref(),
+        config.load_state.as_deref(),
+    ) {
+        (Some(_), Some(_)) => {
+            return Err(eyre!(
+            "--initial-state and --load-state can't be used together, both set the starting state"
+        ))
+        }
+        (Some(path), None) => {
+            let loaded = accounts.load_initial_state(path)?;
+            info!(loaded, path = %path.display(), "loaded initial state of the accounts");
+        }
+        (None, Some(path)) => {
+            let loaded = state::load(&mut accounts, path)?;
+            info!(loaded, path = %path.display(), "loaded state snapshot of the accounts");
+        }
+        (None, None) => (),
+    }
+    Ok(accounts)
+}
+
+/// Dispute look-up configured from the [Config], it continues from the state of the recovered accounts
+fn dispute_finder<S>(
+    source: S,
+    config: &Config,
+    accounts: &Accounts,
+    dead_letter: Option<DeadLetter>,
+) -> DisputeFinder<S> {
+    DisputeFinder::new(source)
+        .with_window(config.window)
+        .with_max_dispute_age(config.dispute_max_age_days)
+        .with_unfreeze_on_resolve(config.unfreeze_on_resolve)
+        .with_recovered(accounts)
+        .with_dead_letter(dead_letter)
+        .with_mismatched_disputes(config.mismatched_disputes)
+        .with_pending_disputes(config.pending_disputes)
+        .with_warning_interval(config.warning_interval.map(std::time::Duration::from_secs))
+        .with_cache_file(config.dispute_cache_file.clone())
+}
+
+/// Parsers of the prepared journal, one parses the journal and the other looks up disputed transactions.
+/// With `inline` disputes are resolved by the parser itself and the journal is opened only once, unless the index
+/// of `inline` can fall back to scanning the journal, see [Config::max_memory]
+#[allow(clippy::too_many_arguments)]
+fn open_sources(
+    prepared: &Journal,
+    config: &Config,
+    checkpoint: Checkpoint,
+    resume: Option<ResumePoint>,
+    inline: Option<DisputeFinder<TransactionIndex>>,
+    record_types: RecordTypes,
+    guard: Option<RunGuard>,
+    provenance: Option<Provenance>,
+    validator: Option<Validator>,
+) -> Result<(BoxedSource, Option<BoxedResolver>)> {
+    let open = || {
+        File::open(&prepared.path)
+            .wrap_err_with(|| format!("failed to open journal {}", prepared.path.display()))
+    };
+    let journal = open()?;
+    // with memory limit, inline look-ups can fall back to scanning the journal once their index is dropped
+    let separate = inline.is_none();
+    let scan_fallback = !separate && config.max_memory.is_some();
+    let dispute_journal = (separate || scan_fallback).then(open).transpose()?;
+    for file in std::iter::once(&journal).chain(dispute_journal.as_ref()) {
+        advise_sequential(file);
+    }
+    let progress = match config.progress {
+        true => Progress::for_terminal(journal.metadata().map(|m| m.len()).unwrap_or_default()),
+        false => None,
+    };
+    let (window, parse_errors) = (config.window, config.parse_errors);
+    let (limit, sample) = (config.limit, config.sample.map(Sample::new).transpose()?);
+    let client_filter = ClientFilter::load(
+        config.only_clients.as_deref(),
+        config.ignore_clients.as_deref(),
+    )?;
+    let client_remap = config
+        .client_remap
+        .as_deref()
+        .map(ClientRemap::load)
+        .transpose()?;
+    // only the second reader looks transactions up
+    let offsets = (config.offset_index && separate).then(OffsetIndex::default);
+    // transactions before the resume point are never parsed, so they would never pass the filter
+    let seen = match (config.transaction_filter, separate, resume.is_some()) {
+        (Some(_), true, true) => {
+            warn!("--transaction-filter can't be used with --since-offset, not filtering look-ups");
+            None
+        }
+        (Some(expected), true, false) => Some(SeenTransactions::new(expected)),
+        _ => None,
+    };
+    // only indexed look-ups jump around the journal, scans are read ahead by the kernel
+    let read_ahead = match (config.dispute_read_ahead, offsets.is_some()) {
+        (Some(_), true) => Some(ReadAhead::start(open()?)?),
+        (Some(_), false) => {
+            warn!("--dispute-read-ahead needs --offset-index and separate dispute look-up, not reading ahead");
+            None
+        }
+        (None, _) => None,
+    };
+
+    let resolver = match (prepared.delimiter(), dispute_journal) {
+        (_, None) => None,
+        (Some(delimiter), Some(dispute_journal)) => Some(Box::new(
+            parser::CsvParser::new(dispute_journal)
+                .with_delimiter(delimiter)
+                .with_read_buffer(config.read_buffer)
+                .with_parse_errors(parse_errors)
+                .with_amount_format(config.journal_amount_format())
+                .with_offset_index(offsets.clone())
+                .with_seen_transactions(seen.clone())
+                .with_read_ahead(read_ahead)
+                .with_unordered_input(config.unordered_input)
+                .with_client_remap(client_remap.clone()),
+        ) as BoxedResolver),
+        (None, Some(dispute_journal)) => Some(Box::new(
+            binary::BinaryParser::with_capacity(dispute_journal, config.read_buffer)?
+                .with_offset_index(offsets.clone())
+                .with_seen_transactions(seen.clone())
+                .with_read_ahead(read_ahead)
+                .with_unordered_input(config.unordered_input)
+                .with_client_remap(client_remap.clone()),
+        ) as BoxedResolver),
+    };
+    let (inline, resolver) = match inline {
+        Some(mut inline) => {
+            let index = std::mem::take(inline.source_mut());
+            *inline.source_mut() = index.with_scan_fallback(resolver);
+            (Some(inline), None)
+        }
+        None => (None, resolver),
+    };
+
+    let journal: BoxedSource = match prepared.delimiter() {
+        Some(delimiter) => Box::new(
+            parser::CsvParser::new(journal)
+                .with_delimiter(delimiter)
+                .with_read_buffer(config.read_buffer)
+                .with_window(window)
+                .with_parse_errors(parse_errors)
+                .with_progress(progress)
+                .with_limit(limit)
+                .with_run_guard(guard)
+                .with_sample(sample)
+                .with_client_filter(client_filter)
+                .with_client_remap(client_remap)
+                .with_validator(validator)
+                .with_checkpoint(checkpoint)
+                .with_resume_point(resume)
+                .with_amount_format(config.journal_amount_format())
+                .with_trim_whitespace(config.trim_whitespace)
+                .with_offset_index(offsets)
+                .with_seen_transactions(seen)
+                .with_order_check(config.check_order)
+                .with_inline_disputes(inline)
+                .with_record_types(record_types)
+                // lines of the transcoded copy don't match the input
+                .with_provenance(provenance.filter(|_| !prepared.is_transcoded())),
+        ),
+        None => Box::new(
+            binary::BinaryParser::with_capacity(journal, config.read_buffer)?
+                .with_window(window)
+                .with_progress(progress)
+                .with_limit(limit)
+                .with_run_guard(guard)
+                .with_sample(sample)
+                .with_client_filter(client_filter)
+                .with_client_remap(client_remap)
+                .with_validator(validator)
+                .with_checkpoint(checkpoint)
+                .with_resume_point(resume)
+                .with_offset_index(offsets)
+                .with_seen_transactions(seen)
+                .with_order_check(config.check_order)
+                .with_inline_disputes(inline),
+        ),
+    };
+    Ok((journal, resolver))
+}
+
+/// Tells the kernel the journal is read sequentially, so it reads ahead more aggressively.
+/// It is only a hint, failure is logged and ignored
+#[cfg(target_os = "linux")]
+fn advise_sequential(file: &File) {
+    use std::os::fd::AsRawFd;
+    // SAFETY: the descriptor is owned by the file for the whole call
+    let result =
+        unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_SEQUENTIAL) };
+    if result != 0 {
+        debug!(
+            err = %std::io::Error::from_raw_os_error(result),
+            "failed to advise sequential read of the journal"
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn advise_sequential(_file: &File) {}
+
+/// Parser, dispute look-up and processing ready to be run, see [PipelineBuilder]
+pub struct Pipeline {
+    journal: BoxedSource,
+    /// `None` when disputes are resolved by the parser, see [Config::single_pass]
+    dispute_finder: Option<DisputeFinder<BoxedResolver>>,
+    processor: processor::Processor,
+    channel_size: usize,
+    channel_batch_size: usize,
+    dispute_spill_threshold: Option<usize>,
+    cpu_affinity: CpuAffinity,
+    /// Delivers notifications queued by its processor hook, see [crate::webhook]
+    webhook: Option<Webhook>,
+    /// Updated with the end of the journal once it is parsed, see [Config::since_offset]
+    offset_file: Option<PathBuf>,
+    /// Accrued once the journal is processed, see [crate::interest]
+    interest: Option<InterestAccrual>,
+    /// Estimates of the parts of the pipeline, see [Config::max_memory]
+    memory: Option<MemoryBudget>,
+    /// Balance thresholds the accounts are checked against once the journal is processed, see [Config::alerts]
+    alerts: Vec<Threshold>,
+    /// Max runtime and idle timeout of the run, see [Config::max_runtime]
+    guard: Option<RunGuard>,
+    /// Faults injected into the messages sent to the processing, see [crate::fault]
+    #[cfg(feature = "fault-injection")]
+    faults: Faults,
+    /// Transcoded journal is removed once it is dropped
+    _prepared: Option<Journal>,
+}
+
+impl Pipeline {
+    /// Processes the whole journal with parser, dispute look-up and processing each running in its own thread.
+    /// Returns final state of the accounts and counters collected by all three threads
+    pub fn run(self) -> Result<(Accounts, Summary)> {
+        let Pipeline {
+            mut journal,
+            dispute_finder,
+            processor,
+            channel_size,
+            channel_batch_size,
+            dispute_spill_threshold,
+            cpu_affinity,
+            webhook,
+            offset_file,
+            interest,
+            memory,
+            alerts,
+            guard,
+            #[cfg(feature = "fault-injection")]
+            faults,
+            _prepared,
+        } = self;
+        let start = std::time::Instant::now();
+
+        let (transaction_sender, tx_receiver) =
+            crossbeam_channel::bounded::<Vec<Indexed<TransactionMessage>>>(
+                channel_size.div_ceil(channel_batch_size.max(1)),
+            );
+
+        if let Some(memory) = memory.as_ref() {
+            memory.record(
+                Component::TransactionQueue,
+                memory::queue_bytes::<Indexed<TransactionMessage>>(channel_size),
+            );
+        }
+
+        let (dispute_look_up_sender, dispute_look_up_receiver) =
+            crossbeam_channel::unbounded::<Indexed<DisputeLookUpMessage>>();
+
+        let channel_batch_size = match guard.as_ref().is_some_and(RunGuard::has_idle_timeout) {
+            true => 1,
+            false => channel_batch_size,
+        };
+        let (transaction_sender, transaction_sender_2) = (
+            channel::Sender::new(transaction_sender.clone()).with_batch_size(channel_batch_size),
+            channel::Sender::new(transaction_sender),
+        );
+        #[cfg(feature = "fault-injection")]
+        let (transaction_sender, transaction_sender_2) = (
+            transaction_sender.with_faults(faults.parsed),
+            transaction_sender_2.with_faults(faults.looked_up),
+        );
+
+        // parser thread
+        let parser_handle =
+            spawn_worker("parser", "tren-parser", cpu_affinity.parser, move || {
+                journal.parse_journal(
+                    transaction_sender,
+                    spill::SpillingSender::new(dispute_look_up_sender, dispute_spill_threshold)
+                        .with_memory_budget(memory),
+                )
+            })?;
+
+        // dispute look-up thread
+        let dispute_handle = dispute_finder
+            .map(|dispute_finder| {
+                spawn_worker(
+                    "dispute look-up",
+                    "tren-disputes",
+                    cpu_affinity.disputes,
+                    move || {
+                        dispute_finder.run_dispute_look_up_loop(
+                            transaction_sender_2,
+                            dispute_look_up_receiver,
+                        )
+                    },
+                )
+            })
+            .transpose()?;
+
+        // transaction processing thread
+        let handle = spawn_worker(
+            "processing",
+            "tren-accounts-0",
+            cpu_affinity.accounts,
+            move || processor.run(tx_receiver),
+        )?;
+
+        // all threads are joined before failing, so the panic of one of them doesn't leave the others running
+        // failed write-ahead log append stops the processing, the parser fails once the channel is closed
+        let processed = join_worker(handle).and_then(|processed| processed);
+        // once the processing gave up on the records, the parser may be stuck reading them and is left behind
+        let gave_up = processed
+            .as_ref()
+            .is_ok_and(|(_, summary)| summary.stopped_early > 0);
+        let abandon_at = gave_up.then(|| Instant::now() + run_guard::STOP_GRACE);
+        let parsed = join_or_abandon(parser_handle, abandon_at);
+        let looked_up = dispute_handle
+            .and_then(|handle| join_or_abandon(handle, abandon_at))
+            .transpose();
+        // the processor with the webhook hook is dropped by now, so all notifications are queued
+        let undelivered = webhook.map(Webhook::finish);
+
+        let (mut accounts, mut summary) = processed?;
+        summary.undelivered_notifications = undelivered.unwrap_or_default();
+        match parsed.transpose()? {
+            Some(Ok(parser_summary)) => summary.merge(parser_summary),
+            // strict parse error stops the parser, the accounts are missing the rest of the journal
+            Some(Err(err)) => return Err(err.wrap_err("failed to parse transaction journal")),
+            None => warn!("parser didn't stop in time, its counters are missing"),
+        }
+        if let Some(dispute_summary) = looked_up? {
+            summary.merge(dispute_summary);
+        }
+        if let Some(stop) = guard.as_ref().and_then(RunGuard::stopped) {
+            if gave_up {
+                // records the parser sent after the processing gave up were never applied
+                summary.journal_end = None;
+            }
+            summary.stopped_early = 1;
+            warn!(%stop, "run was stopped before the end of the journal");
+        }
+        if let Some(interest) = interest {
+            (summary.interest_accounts, summary.interest_credited) = interest.apply(&mut accounts);
+        }
+        summary.alerts = alerts::check(&accounts, &alerts);
+        if let Some(offset_file) = offset_file {
+            match summary.journal_end {
+                Some(end) => end.save(&offset_file)?,
+                None => warn!(
+                    offset_file = %offset_file.display(),
+                    "journal was not parsed to the end, offset file is not updated"
+                ),
+            }
+        }
+        info!(
+            took_s = start.elapsed().as_secs(),
+            ?summary,
+            "successfully finished processing journal"
+        );
+
+        Ok((accounts, summary))
+    }
+}
+
+/// Runs the worker in its own thread named `thread_name`, pinned to `cpus` if there are any. `name` is used
+/// in the errors. Panic of the worker is logged as soon as it happens and returned as an error once the thread
+/// is joined, so the run doesn't end with partially applied journal reported as success
+fn spawn_worker<T: Send + 'static>(
+    name: &'static str,
+    thread_name: &str,
+    cpus: Vec<usize>,
+    worker: impl FnOnce() -> T + Send + 'static,
+) -> Result<JoinHandle<Result<T>>> {
+    std::thread::Builder::new()
+        .name(thread_name.to_string())
+        .spawn(move || {
+            pin_current_thread(&cpus);
+            std::panic::catch_unwind(AssertUnwindSafe(worker)).map_err(|payload| {
+                let message = panic_message(payload.as_ref());
+                error!(thread = name, %message, "worker thread panicked, aborting the run");
+                eyre!("{name} thread panicked: {message}")
+            })
+        })
+        .wrap_err_with(|| format!("failed to start {name} thread"))
+}
+
+/// Joins the worker, or if it has to finish by `abandon_at` returns `None` once it doesn't. A worker left behind,
+/// e.g. blocked on a read of a hung network mount, ends with the process
+fn join_or_abandon<T>(
+    handle: JoinHandle<Result<T>>,
+    abandon_at: Option<Instant>,
+) -> Option<Result<T>> {
+    if let Some(abandon_at) = abandon_at {
+        while !handle.is_finished() && Instant::now() < abandon_at {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        if !handle.is_finished() {
+            return None;
+        }
+    }
+    Some(join_worker(handle))
+}
+
+fn join_worker<T>(handle: JoinHandle<Result<T>>) -> Result<T> {
+    handle.join().unwrap_or_else(|payload| {
+        Err(eyre!(
+            "thread panicked: {}",
+            panic_message(payload.as_ref())
+        ))
+    })
+}
+
+/// Panics carry either `&str` or `String` unless they were raised with [std::panic::panic_any]
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
@@ -0,0 +1,725 @@
+use crate::accounts::{AccountError, Accounts};
+use crate::aliases::*;
+use crate::audit::AuditLog;
+use crate::channel::{
+    Dispute, Indexed, Transaction, TransactionKind, TransactionMessage, Transfer,
+};
+use crate::checkpoint::Checkpoint;
+use crate::dead_letter::DeadLetter;
+use crate::decisions::DecisionLog;
+use crate::dedup::DedupWindow;
+use crate::invariants::{InvariantChecker, InvariantMode};
+use crate::memory::{self, Component, MemoryBudget};
+use crate::provenance::Provenance;
+use crate::report::ReportSnapshots;
+use crate::rules::RuleFlags;
+use crate::run_guard::RunGuard;
+use crate::summary::Summary;
+use crate::timings::TIMING_BATCH;
+use crate::wal::{self, WriteAheadLog};
+use crate::warnings::WarningAggregator;
+use crossbeam_channel::{Receiver, RecvTimeoutError, TryRecvError};
+use eyre::{Context, Result};
+use std::fmt::Display;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::{error, info, trace, warn};
+
+/// Applies received [TransactionMessage]s to the [Accounts], keeps track of rejected operations in [Summary]
+/// and optionally writes them into [DeadLetter] file. If [AuditLog] is provided, every operation and its outcome is recorded.
+/// With [ReportSnapshots] the current report is periodically written out while processing.
+/// With [WriteAheadLog] every message is logged before it is applied, see [Processor::recover].
+/// Every [Hook] is called with each applied or rejected operation.
+/// With [DedupWindow] redelivered transactions are skipped before they are logged or applied.
+/// With [DecisionLog] every applied message is recorded in the order it was applied.
+/// With [MemoryBudget] settled accounts are evicted while the estimated memory is over the limit.
+/// With [RunGuard] processing gives up waiting for records which don't arrive in time
+pub struct Processor {
+    accounts: Accounts,
+    summary: Summary,
+    dead_letter: Option<DeadLetter>,
+    audit: Option<AuditLog>,
+    snapshots: Option<ReportSnapshots>,
+    invariants: Option<InvariantChecker>,
+    wal: Option<WriteAheadLog>,
+    decisions: Option<DecisionLog>,
+    hooks: Vec<Hook>,
+    warnings: WarningAggregator,
+    dedup: Option<DedupWindow>,
+    memory: Option<MemoryBudget>,
+    guard: Option<RunGuard>,
+    /// Number of accounts settled accounts are evicted again at, evicting scans all of them
+    evict_at: usize,
+    /// Index of the journal record of the message being processed
+    index: u64,
+    /// Locates the record of rejected operations in the logs
+    provenance: Option<Provenance>,
+    /// Clients flagged by the validation rules while parsing
+    rule_flags: Option<RuleFlags>,
+}
+
+/// Called by the processing thread with every operation once it was applied or rejected
+pub type Hook = Box<dyn FnMut(&Completed) + Send>;
+
+/// Operation passed to [Hook]s, the same details as recorded in the audit trail
+#[derive(Debug)]
+pub struct Completed<'a> {
+    pub operation: &'a str,
+    pub client_id: ClientID,
+    pub transaction_id: Option<TransactionID>,
+    pub amount: Option<Amount>,
+    /// Why the operation was rejected, `None` if it was applied
+    pub rejection: Option<&'a AccountError>,
+}
+
+impl Processor {
+    pub fn new(
+        accounts: Accounts,
+        dead_letter: Option<DeadLetter>,
+        audit: Option<AuditLog>,
+    ) -> Self {
+        Processor {
+            accounts,
+            summary: Summary::default(),
+            dead_letter,
+            audit,
+            snapshots: None,
+            invariants: None,
+            wal: None,
+            decisions: None,
+            hooks: Vec::new(),
+            warnings: WarningAggregator::new(None),
+            dedup: None,
+            memory: None,
+            guard: None,
+            evict_at: 0,
+            index: 0,
+            provenance: None,
+            rule_flags: None,
+        }
+    }
+
+    pub fn with_snapshots(mut self, snapshots: Option<ReportSnapshots>) -> Self {
+        self.snapshots = snapshots;
+        self
+    }
+
+    /// Checks invariants of the accounts after every operation, see [InvariantChecker]
+    pub fn with_invariant_checks(mut self, mode: Option<InvariantMode>) -> Self {
+        self.invariants = mode.map(InvariantChecker::new);
+        self
+    }
+
+    pub fn with_wal(mut self, wal: Option<WriteAheadLog>) -> Self {
+        self.wal = wal;
+        self
+    }
+
+    /// Logs rejected operations with the line of their record, see [crate::provenance]
+    pub fn with_provenance(mut self, provenance: Option<Provenance>) -> Self {
+        self.provenance = provenance;
+        self
+    }
+
+    pub fn with_decision_log(mut self, decisions: Option<DecisionLog>) -> Self {
+        self.decisions = decisions;
+        self
+    }
+
+    /// Skips transactions already applied among the last `capacity` ones, see [DedupWindow]
+    pub fn with_dedup_window(mut self, capacity: Option<usize>) -> Self {
+        self.dedup = capacity.map(DedupWindow::new);
+        self
+    }
+
+    /// Estimates of the accounts are recorded into the budget, see [crate::memory]
+    pub fn with_memory_budget(mut self, memory: Option<MemoryBudget>) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    /// Stops [Processor::run] once no record arrives for the idle timeout or past the max runtime, see
+    /// [crate::run_guard]
+    pub fn with_run_guard(mut self, guard: Option<RunGuard>) -> Self {
+        self.guard = guard;
+        self
+    }
+
+    /// Rejections of the same kind are logged at most once per interval, see [WarningAggregator]
+    pub fn with_warning_interval(mut self, interval: Option<Duration>) -> Self {
+        self.warnings = WarningAggregator::new(interval);
+        self
+    }
+
+    /// Accounts of clients flagged by the validation rules are flagged right after the record which flagged them
+    /// is applied, see [crate::rules]
+    pub fn with_rule_flags(mut self, rule_flags: Option<RuleFlags>) -> Self {
+        self.rule_flags = rule_flags;
+        self
+    }
+
+    /// Hooks are called in the order they were added, they are not called for operations replayed by [Processor::recover]
+    pub fn with_hook(mut self, hook: Hook) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Rebuilds the accounts by applying all operations from the write-ahead log. Has to be called before [Processor::run].
+    /// Replayed operations are not written into audit trail nor dead-letter file and their rejections are not counted,
+    /// they were already recorded by the run which crashed. Returns [Checkpoint] of the journal records applied so far
+    pub fn recover(&mut self, path: &Path) -> Result<Checkpoint> {
+        let (audit, dead_letter, hooks) = (
+            self.audit.take(),
+            self.dead_letter.take(),
+            std::mem::take(&mut self.hooks),
+        );
+        let mut checkpoint = Checkpoint::default();
+        let recovered = wal::replay(path, |applied| {
+            checkpoint.record(&applied);
+            if let Some(dedup) = self.dedup.as_mut() {
+                dedup.insert(&applied.message);
+            }
+            self.process(applied.message);
+        });
+        (self.audit, self.dead_letter, self.hooks) = (audit, dead_letter, hooks);
+        self.summary = Summary {
+            recovered_operations: recovered.as_ref().copied().unwrap_or_default(),
+            ..Default::default()
+        };
+
+        let recovered = recovered?;
+        info!(%recovered, ?checkpoint, "recovered accounts from write-ahead log");
+        Ok(checkpoint)
+    }
+
+    pub fn accounts(&self) -> &Accounts {
+        &self.accounts
+    }
+
+    /// Processes batches of messages until all senders are dropped, then returns final state of the accounts.
+    /// Fails as soon as a message can't be appended to the write-ahead log
+    pub fn run(
+        mut self,
+        receiver: Receiver<Vec<Indexed<TransactionMessage>>>,
+    ) -> Result<(Accounts, Summary)> {
+        // time spent waiting is left out of the apply batch
+        let (mut batch_start, mut batch_wait, mut applied) = (Instant::now(), Duration::ZERO, 0u64);
+        let mut last_batch = Instant::now();
+        loop {
+            let batch = match receiver.try_recv() {
+                Ok(batch) => Ok(batch),
+                Err(TryRecvError::Disconnected) => Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {
+                    let waiting = Instant::now();
+                    let snapshot_due = self
+                        .snapshots
+                        .as_ref()
+                        .map(|snapshots| waiting + snapshots.remaining());
+                    let give_up_at = self
+                        .guard
+                        .as_ref()
+                        .and_then(|guard| guard.give_up_at(last_batch));
+                    let batch = match snapshot_due.into_iter().chain(give_up_at).min() {
+                        Some(due) => receiver.recv_deadline(due),
+                        None => receiver.recv().map_err(Into::into),
+                    };
+                    let wait = waiting.elapsed();
+                    self.summary.timings.apply_queue_wait.record(wait);
+                    batch_wait += wait;
+                    batch
+                }
+            };
+            match batch {
+                Ok(batch) => {
+                    last_batch = Instant::now();
+                    for message in batch {
+                        trace!(?message, "received ProcessTransactionMessage");
+                        self.apply(message)?;
+                        applied += 1;
+                        if applied.is_multiple_of(TIMING_BATCH) {
+                            self.summary
+                                .timings
+                                .apply
+                                .record(batch_start.elapsed().saturating_sub(batch_wait));
+                            (batch_start, batch_wait) = (Instant::now(), Duration::ZERO);
+                        }
+                        if applied.is_multiple_of(memory::CHECK_INTERVAL) {
+                            self.check_memory();
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Some(guard) = self.guard.as_ref() {
+                        if let Some(stop) = guard.expired(last_batch, Instant::now()) {
+                            guard.stop(stop);
+                            warn!(%stop, applied, "no record arrived in time, stopping the run");
+                            self.summary.stopped_early = 1;
+                            break;
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            if let Some(snapshots) = self.snapshots.as_mut() {
+                if let Err(err) = snapshots.write_if_due(&self.accounts) {
+                    error!(%err, "failed to write report snapshot");
+                }
+            }
+        }
+
+        Ok(self.finish())
+    }
+
+    /// Evicts settled accounts if the estimated memory is over the budget
+    fn check_memory(&mut self) {
+        let Some(memory) = self.memory.as_ref() else {
+            return;
+        };
+        memory.record(Component::Accounts, self.accounts.estimated_memory());
+        if !memory.is_over() || self.accounts.len() < self.evict_at {
+            return;
+        }
+
+        let evicted = self.accounts.evict_settled();
+        self.accounts.shrink_to_fit();
+        memory.record(Component::Accounts, self.accounts.estimated_memory());
+        let resident = self.accounts.len() - self.accounts.evicted_count();
+        self.evict_at = self.accounts.len() + (resident / 8).max(memory::CHECK_INTERVAL as usize);
+        if self.warnings.should_log("memory_eviction") {
+            warn!(
+                evicted,
+                usage = memory.usage(),
+                limit = memory.limit(),
+                "estimated memory is over the limit, evicted settled accounts"
+            );
+        }
+    }
+
+    /// Runs the end of processing checks and returns final state of the accounts, called by [Processor::run]
+    /// or by embedders once they [Processor::apply] all messages
+    pub fn finish(mut self) -> (Accounts, Summary) {
+        // flags of the last records which were not applied, e.g. disputes of unknown transactions
+        self.apply_rule_flags(u64::MAX);
+        self.summary.flagged_accounts = self.accounts.flagged_count() as u64;
+        self.summary.evicted_accounts = self.accounts.evicted_count() as u64;
+        if let Some(difference) = self.accounts.integrity_mismatch() {
+            warn!(
+                %difference,
+                "integrity check failed, grand total doesn't match deposits minus withdrawals and chargebacks"
+            );
+            self.summary.integrity_mismatches += 1;
+        }
+        if let Some(invariants) = self.invariants.as_ref() {
+            self.summary.invariant_violations = invariants.violations();
+        }
+        (self.accounts, self.summary)
+    }
+
+    /// Applies single message right away, for embedders driving the processor without [Processor::run].
+    /// The message is appended to the write-ahead log first if there is one, it is not applied if it can't be
+    /// appended, the accounts would be ahead of the log they are recovered from
+    pub fn apply(&mut self, message: Indexed<TransactionMessage>) -> Result<()> {
+        if let Some(dedup) = self.dedup.as_mut() {
+            if !dedup.insert(&message.message) {
+                if self.warnings.should_log("duplicate_transaction") {
+                    warn!(?message, "skipping transaction delivered again");
+                }
+                self.summary.duplicate_transactions += 1;
+                return Ok(());
+            }
+        }
+        if let Some(wal) = self.wal.as_mut() {
+            wal.append(&message)
+                .wrap_err_with(|| format!("failed to apply journal record {}", message.index))?;
+        }
+        if let Some(decisions) = self.decisions.as_mut() {
+            decisions.record(&message);
+        }
+        self.index = message.index;
+        self.process_checked(message.message);
+        self.apply_rule_flags(self.index);
+        Ok(())
+    }
+
+    /// Flags accounts of the clients flagged by the validation rules up to the record `index`. The record itself is
+    /// applied first, so the flag raised by client's first deposit finds the account
+    fn apply_rule_flags(&mut self, index: u64) {
+        let Some(rule_flags) = self.rule_flags.as_ref() else {
+            return;
+        };
+        for (client_id, rule) in rule_flags.take_up_to(index) {
+            if !self.accounts.flag(client_id, &rule) {
+                if self.warnings.should_log("rule_flag_without_account") {
+                    warn!(
+                        client_id,
+                        rule, "client flagged by rule has no account, dropping the flag"
+                    );
+                }
+                self.summary.rule_flags_without_account += 1;
+            }
+        }
+    }
+
+    /// Processes the message, checking the invariants around it if enabled
+    fn process_checked(&mut self, message: TransactionMessage) {
+        let Some(invariants) = self.invariants.as_mut() else {
+            return self.process(message);
+        };
+
+        invariants.before(&self.accounts, message.client_ids());
+        let (operation, unlock) = (
+            message.name(),
+            matches!(message, TransactionMessage::Unlock(_)),
+        );
+        self.process(message);
+        if let Some(invariants) = self.invariants.as_mut() {
+            invariants.after(&self.accounts, operation, unlock);
+        }
+    }
+
+    fn process(&mut self, message: TransactionMessage) {
+        self.accounts.advance_sequence();
+        match message {
+            TransactionMessage::Deposit(Transaction {
+                client_id,
+                transaction_id,
+                amount,
+            }) => {
+                let result = self.accounts.deposit(client_id, amount);
+                self.complete(
+                    "deposit",
+                    client_id,
+                    Some(transaction_id),
+                    Some(amount),
+                    result.map(|_| "applied"),
+                );
+            }
+            TransactionMessage::Withdrawal(Transaction {
+                client_id,
+                transaction_id,
+                amount,
+            }) => {
+                let result = self.accounts.withdraw(client_id, amount);
+                let fee = result.as_ref().ok().copied().flatten();
+                self.complete(
+                    "withdrawal",
+                    client_id,
+                    Some(transaction_id),
+                    Some(amount),
+                    result.map(|_| "applied"),
+                );
+                self.charged_fee(client_id, transaction_id, fee);
+            }
+            TransactionMessage::Dispute(Dispute {
+                client_id,
+                transaction_id,
+                amount,
+                kind,
+            }) => {
+                let result = match kind {
+                    TransactionKind::Deposit => {
+                        self.accounts.dispute(client_id, transaction_id, amount)
+                    }
+                    TransactionKind::Withdrawal => {
+                        self.accounts
+                            .dispute_withdrawal(client_id, transaction_id, amount)
+                    }
+                };
+                self.complete(
+                    "dispute",
+                    client_id,
+                    Some(transaction_id),
+                    Some(amount),
+                    result,
+                );
+            }
+            TransactionMessage::Resolve(Dispute {
+                client_id,
+                transaction_id,
+                amount,
+                ..
+            }) => {
+                let result = self
+                    .accounts
+                    .resolve_up_to(client_id, transaction_id, amount);
+                self.complete(
+                    "resolve",
+                    client_id,
+                    Some(transaction_id),
+                    Some(amount),
+                    result,
+                );
+            }
+            TransactionMessage::Chargeback(Dispute {
+                client_id,
+                transaction_id,
+                amount,
+                ..
+            }) => {
+                let result = self
+                    .accounts
+                    .chargeback_up_to(client_id, transaction_id, amount);
+                let fee = result.as_ref().ok().copied().flatten();
+                self.complete(
+                    "chargeback",
+                    client_id,
+                    Some(transaction_id),
+                    Some(amount),
+                    result.map(|_| "applied"),
+                );
+                self.charged_fee(client_id, transaction_id, fee);
+            }
+            TransactionMessage::Reversal(Dispute {
+                client_id,
+                transaction_id,
+                amount,
+                kind,
+            }) => {
+                let result = self
+                    .accounts
+                    .reverse(client_id, transaction_id, amount, kind);
+                self.complete(
+                    "reversal",
+                    client_id,
+                    Some(transaction_id),
+                    Some(amount),
+                    result.map(|_| "applied"),
+                );
+            }
+            TransactionMessage::Transfer(Transfer {
+                from_client_id,
+                to_client_id,
+                transaction_id,
+                amount,
+            }) => {
+                let result = self
+                    .accounts
+                    .transfer(from_client_id, to_client_id, amount)
+                    .map(|_| format!("transferred to client {to_client_id}"));
+                self.complete(
+                    "transfer",
+                    from_client_id,
+                    Some(transaction_id),
+                    Some(amount),
+                    result,
+                );
+            }
+            TransactionMessage::AdjustmentCredit(Transaction {
+                client_id,
+                transaction_id,
+                amount,
+            }) => {
+                let result = self.accounts.adjust_credit(client_id, amount);
+                let outcome = result.map(|_| "applied");
+                self.complete(
+                    "adjustment_credit",
+                    client_id,
+                    Some(transaction_id),
+                    Some(amount),
+                    outcome,
+                );
+            }
+            TransactionMessage::AdjustmentDebit(Transaction {
+                client_id,
+                transaction_id,
+                amount,
+            }) => {
+                let result = self.accounts.adjust_debit(client_id, amount);
+                let outcome = result.map(|_| "applied");
+                self.complete(
+                    "adjustment_debit",
+                    client_id,
+                    Some(transaction_id),
+                    Some(amount),
+                    outcome,
+                );
+            }
+            TransactionMessage::Lock(client_id) => {
+                let result = self.accounts.lock(client_id);
+                self.complete("lock", client_id, None, None, result.map(|_| "applied"));
+            }
+            TransactionMessage::Unlock(client_id) => {
+                let result = self.accounts.unlock(client_id);
+                self.complete("unlock", client_id, None, None, result.map(|_| "applied"));
+            }
+            TransactionMessage::Close(client_id) => {
+                let result = self.accounts.close(client_id);
+                self.complete("close", client_id, None, None, result.map(|_| "applied"));
+            }
+        }
+    }
+
+    /// Itemizes the fee charged by the operation in the audit trail and counts it in the summary
+    fn charged_fee(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+        fee: Option<Amount>,
+    ) {
+        let Some(fee) = fee else {
+            return;
+        };
+        self.summary.fees_charged += 1;
+        self.summary.fees_collected = self.summary.fees_collected.saturating_add(fee);
+        let house_account = self.accounts.fees().house_account.unwrap_or_default();
+        self.complete(
+            "fee",
+            client_id,
+            Some(transaction_id),
+            Some(fee),
+            Ok(format!("credited to client {house_account}")),
+        );
+    }
+
+    /// Records the outcome of the operation into the audit trail, rejected operations are also counted
+    /// and written into dead-letter file
+    fn complete(
+        &mut self,
+        operation: &str,
+        client_id: ClientID,
+        transaction_id: Option<TransactionID>,
+        amount: Option<Amount>,
+        result: Result<impl Display, AccountError>,
+    ) {
+        let adjustment = is_adjustment(operation);
+        if !self.hooks.is_empty() {
+            let completed = Completed {
+                operation,
+                client_id,
+                transaction_id,
+                amount,
+                rejection: result.as_ref().err(),
+            };
+            for hook in self.hooks.iter_mut() {
+                hook(&completed);
+            }
+        }
+        match result {
+            Ok(outcome) => {
+                if let Some(audit) = self.audit.as_mut() {
+                    audit.record(
+                        self.index,
+                        operation,
+                        client_id,
+                        transaction_id,
+                        amount,
+                        &outcome,
+                        adjustment,
+                    );
+                }
+            }
+            Err(err) => {
+                if self.warnings.should_log(err.kind()) {
+                    let source = self
+                        .provenance
+                        .as_ref()
+                        .map(|provenance| tracing::field::display(provenance.locate(self.index)));
+                    error!(%err, operation, client_id, ?transaction_id, source, "failed to process transaction");
+                }
+                self.summary.record_rejection(operation, &err);
+                if let Some(audit) = self.audit.as_mut() {
+                    audit.record(
+                        self.index,
+                        operation,
+                        client_id,
+                        transaction_id,
+                        amount,
+                        &format_args!("rejected: {err}"),
+                        adjustment,
+                    );
+                }
+                if let Some(dead_letter) = self.dead_letter.as_ref() {
+                    dead_letter.write(
+                        self.index,
+                        operation,
+                        client_id,
+                        transaction_id,
+                        amount,
+                        err.kind(),
+                        &err,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Administrative operations done by back-office, these are flagged in the audit trail
+fn is_adjustment(operation: &str) -> bool {
+    matches!(
+        operation,
+        "adjustment_credit" | "adjustment_debit" | "lock" | "unlock"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::JournalEntry;
+    use crate::rules::Validator;
+    use crate::wal::LogFile;
+    use std::io::{self, Write};
+
+    /// Disk without space left
+    struct FullDisk;
+
+    impl Write for FullDisk {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("no space left on device"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl LogFile for FullDisk {
+        fn sync_data(&self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_failed_wal_append() {
+        let deposit = |index| Indexed::new(index, TransactionMessage::deposit(1, 1, amount!(10)));
+        let processor = || {
+            Processor::new(Accounts::default(), None, None)
+                .with_wal(Some(WriteAheadLog::unbuffered(FullDisk)))
+        };
+
+        let mut applying = processor();
+        assert!(applying.apply(deposit(0)).is_err());
+        assert!(applying.accounts().get(1).is_none());
+
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        sender.send(vec![deposit(0)]).unwrap();
+        drop(sender);
+        assert!(processor().run(receiver).is_err());
+    }
+
+    #[test]
+    fn test_rule_flags() {
+        let validator =
+            Validator::new(vec!["watched:flag:client=1|3".parse().unwrap()], None).unwrap();
+        let mut processor = Processor::new(Accounts::default(), None, None)
+            .with_rule_flags(Some(validator.flags()));
+        let mut apply = |index, message: TransactionMessage| {
+            let entry = JournalEntry::Transaction(message.clone());
+            validator.check(&entry, index, &mut Summary::default());
+            processor.apply(Indexed::new(index, message)).unwrap();
+            processor.accounts().get(1).map(|account| account.flagged)
+        };
+
+        assert_eq!(
+            apply(0, TransactionMessage::deposit(1, 1, amount!(10))),
+            Some(true),
+            "flagged as soon as the deposit opening the account is applied"
+        );
+        // client 3 has no account to flag
+        apply(1, TransactionMessage::resolve(3, 2, amount!(1)));
+
+        let (_, summary) = processor.finish();
+        assert_eq!(summary.rule_flags_without_account, 1);
+    }
+}
@@ -0,0 +1,71 @@
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+/// How often the progress line is redrawn
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Progress line drawn on stderr while the journal is parsed, based on bytes consumed vs the file size,
+/// with records per second and estimated time left
+pub struct Progress {
+    total_bytes: u64,
+    start: Instant,
+    last_draw: Instant,
+}
+
+impl Progress {
+    /// Returns `None` if stderr is not a terminal, so redirected output is not filled with progress lines
+    pub fn for_terminal(total_bytes: u64) -> Option<Progress> {
+        std::io::stderr()
+            .is_terminal()
+            .then(|| Progress::new(total_bytes))
+    }
+
+    pub fn new(total_bytes: u64) -> Progress {
+        let now = Instant::now();
+        Progress {
+            total_bytes,
+            start: now,
+            last_draw: now,
+        }
+    }
+
+    /// Redraws the progress line if enough time passed since the last redraw
+    pub fn update(&mut self, bytes: u64, records: u64) {
+        if self.last_draw.elapsed() >= REDRAW_INTERVAL {
+            self.last_draw = Instant::now();
+            self.draw(bytes, records);
+        }
+    }
+
+    /// Draws the final state and moves to the next line
+    pub fn finish(&mut self, records: u64) {
+        self.draw(self.total_bytes, records);
+        eprintln!();
+    }
+
+    fn draw(&self, bytes: u64, records: u64) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let rate = match elapsed > 0.0 {
+            true => records as f64 / elapsed,
+            false => 0.0,
+        };
+        let done = match self.total_bytes {
+            0 => 1.0,
+            total => (bytes as f64 / total as f64).min(1.0),
+        };
+        let eta = match done > 0.0 {
+            true => elapsed / done - elapsed,
+            false => 0.0,
+        };
+
+        let mut stderr = std::io::stderr().lock();
+        // progress is best effort, failing to draw it must not stop the parsing
+        let _ = write!(
+            stderr,
+            "\r{:5.1}% {records} records {rate:.0} records/s ETA {}s   ",
+            done * 100.0,
+            eta.round() as u64
+        );
+        let _ = stderr.flush();
+    }
+}
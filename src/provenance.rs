@@ -0,0 +1,115 @@
+//! Provenance of records enabled by `--provenance`, so rejections in the logs, the dead-letter file and the
+//! audit trail point back to the line of the input file the record came from. Messages already carry the index
+//! of their record through the pipeline, see [crate::channel::Indexed], [Provenance] turns the index into
+//! `<file>:<line>` only when something is reported about the record.
+//!
+//! Lines are counted by the parser of delimited journals. Line of a record is its index plus a fixed offset,
+//! the header, unless blank lines or quoted fields spanning several lines shift it, so only the records where
+//! the offset changes are remembered and clean journals cost a single entry. The csv reader skips blank lines
+//! as part of the record after them, so such a record is located at the first of the blank lines. Lines are not
+//! known for binary and transcoded journals nor for journals resumed by `--since-offset`, their records are
+//! located by index only
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// Input file and lines of its records, clones share the same lines
+#[derive(Clone, Debug)]
+pub struct Provenance(Arc<Lines>);
+
+#[derive(Debug)]
+struct Lines {
+    source: PathBuf,
+    /// `(index, line)` of records which don't follow on the line after the previous record, records after
+    /// them follow line by line
+    starts: RwLock<Vec<(u64, u64)>>,
+    /// `line - index` of the last recorded record, lets most records skip the lock
+    last_offset: AtomicU64,
+}
+
+impl Provenance {
+    pub fn new(source: PathBuf) -> Provenance {
+        Provenance(Arc::new(Lines {
+            source,
+            starts: RwLock::new(Vec::new()),
+            last_offset: AtomicU64::new(u64::MAX),
+        }))
+    }
+
+    /// Records the line the record with the index starts at, records have to be recorded in increasing order
+    pub fn record(&self, index: u64, line: u64) {
+        let offset = line.wrapping_sub(index);
+        if self.0.last_offset.load(Ordering::Relaxed) == offset {
+            return;
+        }
+        let mut starts = self.0.starts.write().unwrap_or_else(|err| err.into_inner());
+        starts.push((index, line));
+        self.0.last_offset.store(offset, Ordering::Relaxed);
+    }
+
+    /// Where the record with the index comes from
+    pub fn locate(&self, index: u64) -> Location<'_> {
+        let starts = self.0.starts.read().unwrap_or_else(|err| err.into_inner());
+        let start = starts.partition_point(|(start, _)| *start <= index);
+        let line = start
+            .checked_sub(1)
+            .map(|start| starts[start])
+            .map(|(start, line)| line + (index - start));
+        Location {
+            source: &self.0.source,
+            index,
+            line,
+        }
+    }
+}
+
+/// Record of the input file, displayed as `<file>:<line>` or `<file> record <index>` if the line is not known
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Location<'p> {
+    pub source: &'p Path,
+    pub index: u64,
+    pub line: Option<u64>,
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{line}", self.source.display()),
+            None => write!(f, "{} record {}", self.source.display(), self.index),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate() {
+        let provenance = Provenance::new("journal.csv".into());
+        // header on line 1, blank line 4, record 3 spans lines 6 and 7
+        for (index, line) in [(0, 2), (1, 3), (2, 5), (3, 6), (4, 8), (5, 9)] {
+            provenance.record(index, line);
+        }
+        assert_eq!(provenance.0.starts.read().unwrap().len(), 3);
+
+        let tests = vec![
+            ("first record", 0, "journal.csv:2"),
+            ("before blank line", 1, "journal.csv:3"),
+            ("after blank line", 3, "journal.csv:6"),
+            ("after multi-line record", 5, "journal.csv:9"),
+            ("not yet parsed", 8, "journal.csv:12"),
+        ];
+        for (name, index, want) in tests {
+            assert_eq!(
+                provenance.locate(index).to_string(),
+                want,
+                "failed test {name}"
+            );
+        }
+
+        let resumed = Provenance::new("journal.csv".into());
+        assert_eq!(resumed.locate(7).to_string(), "journal.csv record 7");
+    }
+}
@@ -0,0 +1,128 @@
+//! Background reads of journal blocks which the dispute look-up is going to read soon. Look-ups through the
+//! [crate::offsets::OffsetIndex] jump to transactions far apart, on spinning disks every one of them can stall
+//! on a cold read. The dispute look-up passes offsets of queued requests to [ReadAhead], its thread reads their
+//! blocks so they are already in the page cache once the look-up gets to them
+use crossbeam_channel::{Sender, TrySendError};
+use std::collections::{HashSet, VecDeque};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::thread::JoinHandle;
+use tracing::debug;
+
+/// Size of the block read around every requested offset, records longer than that are read only partially
+pub const BLOCK_SIZE: u64 = 64 * 1024;
+
+/// Requested offsets waiting for the thread, requests over it are dropped instead of blocking the look-up
+const QUEUE_SIZE: usize = 1024;
+
+/// Number of recently read blocks which are not read again
+const RECENT_BLOCKS: usize = 4096;
+
+pub struct ReadAhead {
+    sender: Option<Sender<u64>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ReadAhead {
+    /// Starts the reading thread, the file should be a separate handle of the journal since the thread seeks in it
+    pub fn start(file: File) -> std::io::Result<ReadAhead> {
+        let (sender, receiver) = crossbeam_channel::bounded::<u64>(QUEUE_SIZE);
+        let handle = std::thread::Builder::new()
+            .name("tren-read-ahead-0".into())
+            .spawn(move || {
+                let (mut file, mut block) = (file, vec![0; BLOCK_SIZE as usize]);
+                let mut recent = RecentBlocks::default();
+                let mut read = 0u64;
+                for offset in receiver {
+                    let start = offset - offset % BLOCK_SIZE;
+                    if !recent.insert(start) {
+                        continue;
+                    }
+                    // only the side effect of filling the page cache is wanted, errors are left to the look-up
+                    let filled = file
+                        .seek(SeekFrom::Start(start))
+                        .and_then(|_| fill(&mut file, &mut block));
+                    if let Err(err) = filled {
+                        debug!(%err, offset, "failed to read ahead journal block");
+                    }
+                    read += 1;
+                }
+                debug!(blocks = read, "finished reading ahead");
+            })?;
+        Ok(ReadAhead {
+            sender: Some(sender),
+            handle: Some(handle),
+        })
+    }
+
+    /// Asks for the block containing the offset to be read, the request is dropped if the thread is behind
+    pub fn request(&self, offset: u64) {
+        if let Some(sender) = self.sender.as_ref() {
+            if let Err(TrySendError::Full(_)) = sender.try_send(offset) {
+                debug!(offset, "read-ahead queue is full, dropping request");
+            }
+        }
+    }
+}
+
+impl Drop for ReadAhead {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Reads until the block is full or the file ends
+fn fill(file: &mut File, block: &mut [u8]) -> std::io::Result<()> {
+    let mut len = 0;
+    while len < block.len() {
+        match file.read(&mut block[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Starts of the last [RECENT_BLOCKS] read blocks, the oldest one is forgotten first
+#[derive(Default)]
+struct RecentBlocks {
+    order: VecDeque<u64>,
+    blocks: HashSet<u64>,
+}
+
+impl RecentBlocks {
+    /// Returns `false` if the block was read recently
+    fn insert(&mut self, start: u64) -> bool {
+        if !self.blocks.insert(start) {
+            return false;
+        }
+        self.order.push_back(start);
+        if self.order.len() > RECENT_BLOCKS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.blocks.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_blocks() {
+        let mut recent = RecentBlocks::default();
+        assert!(recent.insert(0));
+        assert!(!recent.insert(0), "read recently");
+        for block in 1..=RECENT_BLOCKS as u64 {
+            assert!(recent.insert(block * BLOCK_SIZE));
+        }
+        assert!(recent.insert(0), "forgotten after other blocks were read");
+    }
+}
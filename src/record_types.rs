@@ -0,0 +1,185 @@
+//! Record types of delimited journals beyond the built-in ones. Forks with their own rows, e.g. `fee` or `interest`,
+//! register a handler for the type in [RecordTypes] and pass it to [crate::pipeline::PipelineBuilder::with_record_types]
+//! or [crate::engine::Engine::with_record_types]. Handlers turn the row into one of the existing journal entries,
+//! the built-in types are matched first and can't be replaced. Dispute look-ups re-scanning the journal only find
+//! the built-in deposits and withdrawals, rows of custom types can't be disputed that way
+use crate::amount::AmountFormat;
+use crate::channel::{Transaction, Transfer};
+use crate::parser::{self, Columns, JournalEntry};
+use crate::{aliases::*, TransactionMessage};
+use csv::ByteRecord;
+use eyre::Result;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Row of the journal given to [RecordHandler], fields are parsed with the settings of the journal,
+/// e.g. its amount format and whitespace trimming
+pub struct Record<'r> {
+    record: &'r ByteRecord,
+    columns: Columns,
+    amount_format: &'r AmountFormat,
+    trim: bool,
+}
+
+impl<'r> Record<'r> {
+    pub(crate) fn new(
+        record: &'r ByteRecord,
+        columns: Columns,
+        amount_format: &'r AmountFormat,
+        trim: bool,
+    ) -> Record<'r> {
+        Record {
+            record,
+            columns,
+            amount_format,
+            trim,
+        }
+    }
+
+    /// Raw value of the column, fails if the record is too short
+    pub fn field(&self, index: usize) -> Result<&'r [u8]> {
+        parser::field(self.record, index, "requested")
+    }
+
+    pub fn client_id(&self) -> Result<ClientID> {
+        parser::parse_client_id(self.record)
+    }
+
+    /// Client, transaction and amount in the layout of deposit `type,client,tx,amount`
+    pub fn transaction(&self) -> Result<Transaction> {
+        let (client_id, transaction_id, amount) =
+            parser::parse_deposit_or_withdrawal(self.record, self.amount_format, self.trim)?;
+        Ok(Transaction::new(client_id, transaction_id, amount))
+    }
+
+    /// Transaction with the receiving client in the layout of transfer
+    pub fn transfer(&self) -> Result<Transfer> {
+        let (from_client_id, transaction_id, amount, to_client_id) = parser::parse_transfer(
+            self.record,
+            self.columns.to_client,
+            self.amount_format,
+            self.trim,
+        )?;
+        Ok(Transfer {
+            from_client_id,
+            to_client_id,
+            transaction_id,
+            amount,
+        })
+    }
+
+    /// `None` if the journal doesn't have timestamp column or the record has it empty
+    pub fn timestamp(&self) -> Result<Option<Timestamp>> {
+        parser::parse_record_timestamp(self.record, self.columns)
+    }
+}
+
+/// Turns rows of a custom type into journal entries, returning `None` skips the row like a row of unknown type.
+/// Errors make the row malformed, see [crate::parser::ParseErrorPolicy]
+pub trait RecordHandler: Send + Sync {
+    fn parse(&self, record: &Record) -> Result<Option<JournalEntry>>;
+}
+
+impl<F> RecordHandler for F
+where
+    F: Fn(&Record) -> Result<Option<JournalEntry>> + Send + Sync,
+{
+    fn parse(&self, record: &Record) -> Result<Option<JournalEntry>> {
+        self(record)
+    }
+}
+
+/// Handlers of custom record types by their name, empty by default
+#[derive(Clone, Default)]
+pub struct RecordTypes {
+    handlers: HashMap<Vec<u8>, Arc<dyn RecordHandler>>,
+}
+
+impl RecordTypes {
+    /// Adds handler of rows of the type, it replaces already registered handler of the same type.
+    /// Handlers of the built-in types are never called
+    pub fn with_type(mut self, name: &str, handler: impl RecordHandler + 'static) -> RecordTypes {
+        self.handlers
+            .insert(name.as_bytes().to_vec(), Arc::new(handler));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+
+    /// Handler of the type, with `trim` whitespace in the type is ignored
+    pub(crate) fn get(&self, record_type: &[u8], trim: bool) -> Option<&dyn RecordHandler> {
+        if self.handlers.is_empty() {
+            return None;
+        }
+        let handler = match trim && record_type.iter().any(u8::is_ascii_whitespace) {
+            true => {
+                let mut trimmed = record_type.to_vec();
+                trimmed.retain(|b| !b.is_ascii_whitespace());
+                self.handlers.get(&trimmed)
+            }
+            false => self.handlers.get(record_type),
+        };
+        handler.map(|handler| handler.as_ref())
+    }
+}
+
+/// Handler of rows applied as [TransactionMessage::AdjustmentDebit], e.g. fees charged to the client
+pub fn debit(record: &Record) -> Result<Option<JournalEntry>> {
+    Ok(Some(JournalEntry::Transaction(
+        TransactionMessage::AdjustmentDebit(record.transaction()?),
+    )))
+}
+
+/// Handler of rows applied as [TransactionMessage::AdjustmentCredit], e.g. interest paid to the client
+pub fn credit(record: &Record) -> Result<Option<JournalEntry>> {
+    Ok(Some(JournalEntry::Transaction(
+        TransactionMessage::AdjustmentCredit(record.transaction()?),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CsvParser;
+
+    #[test]
+    fn test_custom_types() {
+        let record_types = RecordTypes::default()
+            .with_type("fee", debit)
+            .with_type("interest", credit)
+            .with_type("note", |_: &Record| Ok(None))
+            .with_type("deposit", |_: &Record| Err(eyre::eyre!("never called")));
+        let journal = "type,client,tx,amount\n\
+                       deposit,1,1,10\n\
+                       fee,1,2,0.5\n\
+                       inter est,1,3,0.25\n\
+                       note,1,,\n\
+                       bonus,1,4,1\n";
+        let mut parser = CsvParser::new(journal.as_bytes()).with_record_types(record_types);
+        let mut entries = Vec::new();
+        parser
+            .read_entries(|entry, _| {
+                entries.push(entry);
+                Ok(())
+            })
+            .unwrap();
+
+        let transaction = |tx, amount| Transaction::new(1, tx, amount);
+        assert_eq!(
+            entries,
+            vec![
+                JournalEntry::Transaction(TransactionMessage::Deposit(transaction(1, amount!(10)))),
+                JournalEntry::Transaction(TransactionMessage::AdjustmentDebit(transaction(
+                    2,
+                    amount!(0.5)
+                ))),
+                JournalEntry::Transaction(TransactionMessage::AdjustmentCredit(transaction(
+                    3,
+                    amount!(0.25)
+                ))),
+            ]
+        );
+    }
+}
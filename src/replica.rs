@@ -0,0 +1,105 @@
+//! Eventually consistent read replica of the accounts applied by [crate::engine::EngineHandle]. The engine thread
+//! publishes an immutable copy of the accounts every interval and readers take the latest copy, so high query volume
+//! doesn't compete with the engine for the accounts. The lock around the latest copy is held only to swap or clone
+//! the pointer, never while the copy is taken or read. Copies lag behind the accounts by up to the interval
+use crate::accounts::{AccountView, Accounts};
+use crate::aliases::*;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+/// Immutable copy of the accounts
+#[derive(Debug)]
+pub struct AccountsSnapshot {
+    accounts: HashMap<ClientID, AccountView>,
+    /// Number of records applied before the copy was taken
+    pub applied: u64,
+    pub taken_at: Instant,
+}
+
+impl AccountsSnapshot {
+    pub fn of(accounts: &Accounts, applied: u64) -> AccountsSnapshot {
+        AccountsSnapshot {
+            accounts: accounts
+                .iter()
+                .map(|account| (account.client_id, account))
+                .collect(),
+            applied,
+            taken_at: Instant::now(),
+        }
+    }
+
+    /// State of client's account when the copy was taken, `None` if the client had no account
+    pub fn get(&self, client_id: ClientID) -> Option<AccountView> {
+        self.accounts.get(&client_id).copied()
+    }
+
+    /// Iterates over all accounts in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &AccountView> + '_ {
+        self.accounts.values()
+    }
+
+    /// Adds the accounts of another copy with different clients, e.g. of another engine
+    pub(crate) fn absorb(&mut self, other: AccountsSnapshot) {
+        self.accounts.extend(other.accounts);
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+}
+
+/// Holder of the latest published [AccountsSnapshot], clones share it
+#[derive(Clone, Debug)]
+pub struct Replica(Arc<RwLock<Arc<AccountsSnapshot>>>);
+
+impl Replica {
+    pub fn new(snapshot: AccountsSnapshot) -> Replica {
+        Replica(Arc::new(RwLock::new(Arc::new(snapshot))))
+    }
+
+    /// Latest published copy, it stays valid while it is held even after newer copies are published
+    pub fn latest(&self) -> Arc<AccountsSnapshot> {
+        Arc::clone(&self.0.read().unwrap_or_else(|err| err.into_inner()))
+    }
+
+    /// Replaces the latest copy, the previous one is dropped by its last reader
+    pub fn publish(&self, snapshot: AccountsSnapshot) {
+        let snapshot = Arc::new(snapshot);
+        let previous = std::mem::replace(
+            &mut *self.0.write().unwrap_or_else(|err| err.into_inner()),
+            snapshot,
+        );
+        // dropped outside of the lock, it may be the last copy of all the accounts
+        drop(previous);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish() {
+        let mut accounts = Accounts::default();
+        accounts.deposit(1, amount!(10)).unwrap();
+        let replica = Replica::new(AccountsSnapshot::of(&accounts, 1));
+        let held = replica.latest();
+
+        accounts.deposit(2, amount!(5)).unwrap();
+        replica.publish(AccountsSnapshot::of(&accounts, 2));
+
+        assert_eq!((held.applied, held.len()), (1, 1), "held copy is unchanged");
+        assert_eq!(held.get(2), None);
+        let latest = replica.clone().latest();
+        assert_eq!(latest.applied, 2);
+        assert_eq!(
+            latest.get(2).map(|account| account.available),
+            Some(amount!(5))
+        );
+    }
+}
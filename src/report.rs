@@ -0,0 +1,836 @@
+use crate::accounts::{AccountView, Accounts, SegmentTotals};
+use crate::aliases::*;
+use crate::compress::{CompressedWriter, Compression};
+use eyre::{eyre, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Periodically writes snapshot of the accounts report while the journal is still being processed.
+/// New snapshot atomically replaces the file, so pollers never read a half-written report nor find the file missing.
+/// Previous snapshots are kept with `.1` to `.<keep>` suffixes, `.1` is the newest of them.
+/// Accounts which changed since the previous snapshot can be written into another file, so pollers don't have to
+/// read the whole report
+pub struct ReportSnapshots {
+    path: PathBuf,
+    interval: Duration,
+    last: Instant,
+    /// Number of previous snapshots kept
+    keep: usize,
+    /// Symbolic link pointed to the snapshots once the first one is written, e.g. at a path which stays the same
+    /// across runs writing their reports into different directories
+    link: Option<PathBuf>,
+    linked: bool,
+    /// File with only the accounts changed since the previous snapshot
+    changes: Option<PathBuf>,
+    /// Accounts as they were in the previous snapshot, kept only with `changes`
+    previous: HashMap<ClientID, AccountView>,
+    /// Overrides compression given by the extensions of the files
+    compression: Option<Compression>,
+}
+
+impl ReportSnapshots {
+    pub fn new(path: PathBuf, interval: Duration) -> Self {
+        ReportSnapshots {
+            path,
+            interval,
+            last: Instant::now(),
+            keep: 1,
+            link: None,
+            linked: false,
+            changes: None,
+            previous: HashMap::new(),
+            compression: None,
+        }
+    }
+
+    /// Writes accounts changed since the previous snapshot into `changes` with every snapshot,
+    /// the first one has all accounts
+    pub fn with_changes(mut self, changes: Option<PathBuf>) -> Self {
+        self.changes = changes;
+        self
+    }
+
+    /// Keeps this many previous snapshots, none are kept with 0
+    pub fn with_keep(mut self, keep: usize) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    /// Points the symbolic link to the snapshots once the first one is written, replacing whatever the link pointed to
+    pub fn with_link(mut self, link: Option<PathBuf>) -> Self {
+        self.link = link;
+        self
+    }
+
+    /// Compresses the snapshots, by default they are compressed by the extension of the files, see [Compression::of_path]
+    pub fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Time left until the next snapshot is due
+    pub fn remaining(&self) -> Duration {
+        self.interval.saturating_sub(self.last.elapsed())
+    }
+
+    /// Writes the snapshot if the interval elapsed since the last one
+    pub fn write_if_due(&mut self, accounts: &Accounts) -> Result<()> {
+        if !self.remaining().is_zero() {
+            return Ok(());
+        }
+        self.last = Instant::now();
+
+        let compression = Compression::resolve(self.compression, &self.path);
+        replace_file(&self.path, self.keep, compression, |writer| {
+            accounts.write_report(writer)
+        })
+        .wrap_err("failed to write report snapshot")?;
+        debug!(path = %self.path.display(), "written report snapshot");
+        if let Some(link) = self.link.as_ref().filter(|_| !self.linked) {
+            replace_link(link, &self.path).wrap_err_with(|| {
+                format!("failed to link {} to report snapshots", link.display())
+            })?;
+            self.linked = true;
+        }
+
+        if let Some(changes) = self.changes.as_ref() {
+            let mut changed: Vec<_> = accounts
+                .iter()
+                .filter(|account| self.previous.get(&account.client_id) != Some(account))
+                .collect();
+            changed.sort_unstable_by_key(|account| account.client_id);
+            let compression = Compression::resolve(self.compression, changes);
+            replace_file(changes, 0, compression, |writer| {
+                accounts
+                    .write_clients_report(writer, changed.iter().map(|account| account.client_id))
+            })
+            .wrap_err("failed to write changed accounts")?;
+            debug!(path = %changes.display(), changed = changed.len(), "written changed accounts");
+            self.previous.extend(
+                changed
+                    .into_iter()
+                    .map(|account| (account.client_id, account)),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the file with what `write` writes, it is written into temporary file first and renamed over the file,
+/// so readers never see partially written report and the file never goes missing. `keep` previous versions of the
+/// file are kept with `.1` to `.<keep>` suffixes
+fn replace_file(
+    path: &Path,
+    keep: usize,
+    compression: Compression,
+    write: impl FnOnce(&mut CompressedWriter) -> std::io::Result<()>,
+) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut writer = CompressedWriter::create(&tmp, compression)?;
+    write(&mut writer)?;
+    writer.finish()?;
+
+    if keep > 0 && path.exists() {
+        for generation in (1..keep).rev() {
+            let older = numbered(path, generation);
+            if older.exists() {
+                std::fs::rename(&older, numbered(path, generation + 1))
+                    .wrap_err("failed to rotate report file")?;
+            }
+        }
+        // the replaced file stays in place until the rename below, its previous version is a link to it
+        let previous = numbered(path, 1);
+        if previous.exists() {
+            std::fs::remove_file(&previous).wrap_err("failed to rotate report file")?;
+        }
+        if std::fs::hard_link(path, &previous).is_err() {
+            std::fs::copy(path, &previous).wrap_err("failed to rotate report file")?;
+        }
+    }
+    std::fs::rename(&tmp, path).wrap_err("failed to replace report file")?;
+    Ok(())
+}
+
+/// Previous version of the file, e.g. `report.csv.2`
+fn numbered(path: &Path, generation: usize) -> PathBuf {
+    let mut numbered = path.to_path_buf().into_os_string();
+    numbered.push(format!(".{generation}"));
+    numbered.into()
+}
+
+/// Atomically points the symbolic link to the absolute path of the file, new link is created next to it and renamed
+/// over it
+#[cfg(unix)]
+fn replace_link(link: &Path, target: &Path) -> Result<()> {
+    let target = std::fs::canonicalize(target)?;
+    let tmp = link.with_extension("link-tmp");
+    if tmp.symlink_metadata().is_ok() {
+        std::fs::remove_file(&tmp)?;
+    }
+    std::os::unix::fs::symlink(target, &tmp)?;
+    std::fs::rename(&tmp, link)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn replace_link(_link: &Path, _target: &Path) -> Result<()> {
+    Err(eyre!(
+        "symbolic links to report snapshots are supported only on unix"
+    ))
+}
+
+/// How the final report is split into multiple files, so they can be loaded in parallel
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Partition {
+    /// Clients are split into ranges of IDs of this size, `report_00000-09999.csv`, `report_10000-19999.csv`, ...
+    ByClientRange(u64),
+    /// Clients are spread by hash of their ID into this many files, `report_0-of-8.csv`, ...
+    ByHash(u64),
+}
+
+impl FromStr for Partition {
+    type Err = eyre::Report;
+
+    /// Parses `by-client-range=<size>` or `by-hash=<files>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s.split_once('=').ok_or(eyre!(
+            "invalid partition '{s}', expected by-client-range=<size> or by-hash=<files>"
+        ))?;
+        let value = value
+            .parse::<u64>()
+            .ok()
+            .filter(|value| *value > 0)
+            .ok_or(eyre!("partition '{s}' needs positive number"))?;
+        match kind {
+            "by-client-range" => Ok(Partition::ByClientRange(value)),
+            "by-hash" => Ok(Partition::ByHash(value)),
+            _ => Err(eyre!(
+                "invalid partition '{kind}', expected one of by-client-range, by-hash"
+            )),
+        }
+    }
+}
+
+impl Partition {
+    /// Partition the client belongs to
+    pub(crate) fn of(&self, client_id: ClientID) -> u64 {
+        match *self {
+            Partition::ByClientRange(size) => u64::from(client_id) / size,
+            // the same Fibonacci hashing as sample, consecutive IDs don't end up in the same file
+            Partition::ByHash(files) => {
+                (u64::from(client_id).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32) % files
+            }
+        }
+    }
+
+    /// Suffix of the partition file name, ranges are zero-padded so the files sort by client ID
+    fn suffix(&self, partition: u64) -> String {
+        match *self {
+            Partition::ByClientRange(size) => {
+                let width = ClientID::MAX.to_string().len();
+                let start = partition * size;
+                let end = start.saturating_add(size - 1).min(u64::from(ClientID::MAX));
+                format!("{start:0width$}-{end:0width$}")
+            }
+            Partition::ByHash(files) => format!("{partition}-of-{files}"),
+        }
+    }
+}
+
+/// Writes the report split into multiple files named after `path`, `report.csv` is written as
+/// `report_<partition>.csv`. Only partitions with at least one client are written, clients are sorted by ID.
+/// Compressed `report.csv.gz` is written as `report_<partition>.csv.gz`. Returns paths of the written files
+pub fn write_partitioned(
+    accounts: &Accounts,
+    path: &Path,
+    partition: Partition,
+    compression: Option<Compression>,
+) -> Result<Vec<PathBuf>> {
+    let mut partitions: BTreeMap<u64, Vec<ClientID>> = BTreeMap::new();
+    for account in accounts.iter() {
+        partitions
+            .entry(partition.of(account.client_id))
+            .or_default()
+            .push(account.client_id);
+    }
+
+    let compression = Compression::resolve(compression, path);
+    // the partition goes before both extensions of `report.csv.gz`
+    let uncompressed = match Compression::of_path(path) {
+        Compression::None => path.to_path_buf(),
+        _ => path.with_extension(""),
+    };
+    let stem = uncompressed
+        .file_stem()
+        .unwrap_or("report".as_ref())
+        .to_string_lossy();
+    let mut extension = uncompressed
+        .extension()
+        .unwrap_or("csv".as_ref())
+        .to_string_lossy()
+        .into_owned();
+    if let Some(compressed) = compression.extension() {
+        extension = format!("{extension}.{compressed}");
+    }
+    let mut written = Vec::with_capacity(partitions.len());
+    for (key, mut clients) in partitions {
+        clients.sort_unstable();
+        let file = path.with_file_name(format!("{stem}_{}.{extension}", partition.suffix(key)));
+        let mut writer = CompressedWriter::create(&file, compression)?;
+        accounts
+            .write_clients_report(&mut writer, clients)
+            .map_err(eyre::Report::from)
+            .and_then(|_| writer.finish())
+            .wrap_err_with(|| format!("failed to write report file {}", file.display()))?;
+        written.push(file);
+    }
+
+    info!(files = written.len(), "written partitioned report");
+    Ok(written)
+}
+
+/// Writes funds held by open disputes for at least `min_age` records, sorted by client and disputed transaction,
+/// so disputes which were never resolved nor charged back can be chased
+pub fn write_aging_report(
+    accounts: &Accounts,
+    writer: &mut impl Write,
+    min_age: u64,
+) -> std::io::Result<()> {
+    let mut holds: Vec<_> = accounts.aged_holds(min_age).collect();
+    holds.sort_unstable_by_key(|hold| (hold.client_id, hold.transaction_id));
+    writeln!(writer, "client,tx,held,age_records")?;
+    for hold in holds {
+        writeln!(
+            writer,
+            "{},{},{},{}",
+            hold.client_id, hold.transaction_id, hold.amount, hold.age
+        )?;
+    }
+    writer.flush()
+}
+
+/// Writes number of clients and balances summed over every segment, sorted by segment. Clients without a segment
+/// are in the row with empty segment
+pub fn write_segment_totals(accounts: &Accounts, writer: &mut impl Write) -> std::io::Result<()> {
+    writeln!(writer, "segment,clients,available,held,total")?;
+    for (segment, SegmentTotals { clients, totals }) in accounts.segment_totals() {
+        writeln!(
+            writer,
+            "{segment},{clients},{},{},{}",
+            totals.available, totals.held, totals.total
+        )?;
+    }
+    writer.flush()
+}
+
+/// Layout of the report columns describing state of the account, v1 is the baseline layout kept for existing consumers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportVersion {
+    /// Single `locked` boolean column, closed and flagged accounts are reported only by [ReportVersion::V2]
+    #[default]
+    V1,
+    /// Single `status` column, see [status]
+    V2,
+}
+
+impl FromStr for ReportVersion {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1" => Ok(ReportVersion::V1),
+            "2" => Ok(ReportVersion::V2),
+            _ => Err(eyre!("invalid report version '{s}', expected one of 1, 2")),
+        }
+    }
+}
+
+impl ReportVersion {
+    /// Header of the state columns, following `client,available,held,total`
+    pub fn state_header(self) -> &'static str {
+        match self {
+            ReportVersion::V1 => "locked",
+            ReportVersion::V2 => "status",
+        }
+    }
+
+    /// Writes the state columns of the account without the leading separator
+    pub fn write_state(
+        self,
+        writer: &mut impl Write,
+        locked: bool,
+        closed: bool,
+        flagged: bool,
+    ) -> std::io::Result<()> {
+        match self {
+            ReportVersion::V1 => write!(writer, "{locked}"),
+            ReportVersion::V2 => write!(writer, "{}", status(locked, closed, flagged)),
+        }
+    }
+}
+
+/// Status column of [ReportVersion::V2], one of `active`, `frozen`, `closed` or `flagged`.
+/// Flagged is reported only for otherwise active accounts, closed account can't be frozen
+pub fn status(locked: bool, closed: bool, flagged: bool) -> &'static str {
+    match (locked, closed, flagged) {
+        (_, true, _) => "closed",
+        (true, _, _) => "frozen",
+        (_, _, true) => "flagged",
+        _ => "active",
+    }
+}
+
+/// What to do when the same client is locked in one of the merged reports and active in another
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeConflicts {
+    /// Merged account is locked and the conflict is logged
+    #[default]
+    Lock,
+    /// Merge fails on the first conflict
+    Fail,
+}
+
+impl FromStr for MergeConflicts {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lock" => Ok(MergeConflicts::Lock),
+            "fail" => Ok(MergeConflicts::Fail),
+            _ => Err(eyre!(
+                "invalid merge conflicts '{s}', expected one of lock, fail"
+            )),
+        }
+    }
+}
+
+/// Account of single client summed over the merged reports
+#[derive(Default)]
+struct MergedAccount {
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    closed: bool,
+    flagged: bool,
+    open_disputes: u64,
+    chargebacks: u64,
+    /// Reports where the account is locked and where it is active, both set means conflict
+    locked_in: Option<usize>,
+    active_in: Option<usize>,
+}
+
+/// Positions of the report columns, extended columns are `None` if the report doesn't have them
+struct ReportColumns {
+    client: usize,
+    available: usize,
+    held: usize,
+    total: usize,
+    state: StateColumns,
+    open_disputes: Option<usize>,
+    chargebacks: Option<usize>,
+}
+
+impl ReportColumns {
+    fn from_headers(headers: &csv::StringRecord, path: &Path) -> Result<ReportColumns> {
+        let position = |name: &str| headers.iter().position(|header| header == name);
+        let required = |name: &str| {
+            position(name)
+                .ok_or_else(|| eyre!("report {} is missing {name} column", path.display()))
+        };
+
+        Ok(ReportColumns {
+            client: required("client")?,
+            available: required("available")?,
+            held: required("held")?,
+            total: required("total")?,
+            state: match position("status") {
+                Some(status) => StateColumns::Status(status),
+                None => StateColumns::Flags {
+                    locked: required("locked")?,
+                    closed: position("closed"),
+                    flagged: position("flagged"),
+                },
+            },
+            open_disputes: position("open_disputes"),
+            chargebacks: position("chargebacks"),
+        })
+    }
+}
+
+/// State columns of the merged report, either [ReportVersion]. Reports of v1 written before it was reduced to
+/// the baseline `locked` column also have `closed` and `flagged` columns, they are kept when present
+enum StateColumns {
+    Flags {
+        locked: usize,
+        closed: Option<usize>,
+        flagged: Option<usize>,
+    },
+    Status(usize),
+}
+
+impl StateColumns {
+    /// Returns whether the account is locked, closed and flagged
+    fn parse(&self, record: &csv::StringRecord) -> Result<(bool, bool, bool)> {
+        let field = |column: usize| record.get(column).unwrap_or_default();
+        let flag = |column: usize| Ok::<_, eyre::Report>(field(column).parse::<bool>()?);
+        match *self {
+            StateColumns::Flags {
+                locked,
+                closed,
+                flagged,
+            } => {
+                let optional = |column: Option<usize>| column.map_or(Ok(false), flag);
+                Ok((flag(locked)?, optional(closed)?, optional(flagged)?))
+            }
+            StateColumns::Status(status) => match field(status) {
+                "active" => Ok((false, false, false)),
+                "frozen" => Ok((true, false, false)),
+                "closed" => Ok((false, true, false)),
+                "flagged" => Ok((false, false, true)),
+                other => Err(eyre!("invalid status '{other}'")),
+            },
+        }
+    }
+}
+
+/// Sums balances of every client over reports produced from disjoint journals, e.g. runs per region.
+/// Account is closed or flagged if it is in any of the reports. Client locked in one report and active
+/// in another is handled by `conflicts`. Totals rows of the reports are skipped, extended columns are kept
+/// only if all reports have them. Reports of both [ReportVersion]s can be merged, merged report is written
+/// in `version` and sorted by client ID, returns number of conflicts
+pub fn merge_reports(
+    reports: &[PathBuf],
+    writer: &mut impl Write,
+    conflicts: MergeConflicts,
+    totals_row: bool,
+    version: ReportVersion,
+) -> Result<u64> {
+    let mut accounts: BTreeMap<ClientID, MergedAccount> = BTreeMap::new();
+    let mut extended = true;
+    let mut conflict_count = 0;
+
+    for (report, path) in reports.iter().enumerate() {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_path(path)
+            .wrap_err_with(|| format!("failed to open report {}", path.display()))?;
+        let columns = ReportColumns::from_headers(reader.headers()?, path)?;
+        extended &= columns.open_disputes.is_some() && columns.chargebacks.is_some();
+
+        for (index, record) in reader.records().enumerate() {
+            let record = record.wrap_err_with(|| format!("failed to read {}", path.display()))?;
+            let field = |column: usize| record.get(column).unwrap_or_default();
+            if field(columns.client) == "totals" {
+                continue;
+            }
+            let context = || format!("malformed line {} of {}", index + 2, path.display());
+            let client_id = field(columns.client)
+                .parse::<ClientID>()
+                .wrap_err_with(context)?;
+            let amount =
+                |column: usize| Amount::from_str_exact(field(column)).wrap_err_with(context);
+            let count = |column: Option<usize>| match column {
+                Some(column) => field(column).parse::<u64>().wrap_err_with(context),
+                None => Ok(0),
+            };
+
+            let account = accounts.entry(client_id).or_default();
+            let add = |sum: Amount, amount: Amount| {
+                sum.checked_add(amount)
+                    .ok_or_else(|| eyre!("balance of client {client_id} overflows"))
+            };
+            account.available = add(account.available, amount(columns.available)?)?;
+            account.held = add(account.held, amount(columns.held)?)?;
+            account.total = add(account.total, amount(columns.total)?)?;
+            let (locked, closed, flagged) = columns.state.parse(&record).wrap_err_with(context)?;
+            account.closed |= closed;
+            account.flagged |= flagged;
+            account.open_disputes += count(columns.open_disputes)?;
+            account.chargebacks += count(columns.chargebacks)?;
+
+            let conflict = match locked {
+                true => account.locked_in.replace(report).is_none() && account.active_in.is_some(),
+                false => account.active_in.replace(report).is_none() && account.locked_in.is_some(),
+            };
+            account.locked |= locked;
+            if conflict {
+                let (locked_in, active_in) = (
+                    &reports[account.locked_in.unwrap_or(report)],
+                    &reports[account.active_in.unwrap_or(report)],
+                );
+                if conflicts == MergeConflicts::Fail {
+                    return Err(eyre!(
+                        "client {client_id} is locked in {} but active in {}",
+                        locked_in.display(),
+                        active_in.display()
+                    ));
+                }
+                warn!(
+                    client_id,
+                    locked_in = %locked_in.display(),
+                    active_in = %active_in.display(),
+                    "client is locked in one report and active in another, merged account is locked"
+                );
+                conflict_count += 1;
+            }
+        }
+    }
+
+    write!(
+        writer,
+        "client,available,held,total,{}",
+        version.state_header()
+    )?;
+    if extended {
+        write!(writer, ",open_disputes,chargebacks")?;
+    }
+    writeln!(writer)?;
+    for (client_id, account) in accounts.iter() {
+        write!(
+            writer,
+            "{client_id},{},{},{},",
+            account.available, account.held, account.total
+        )?;
+        version.write_state(writer, account.locked, account.closed, account.flagged)?;
+        if extended {
+            write!(writer, ",{},{}", account.open_disputes, account.chargebacks)?;
+        }
+        writeln!(writer)?;
+    }
+    if totals_row {
+        let sum = |f: fn(&MergedAccount) -> Amount| {
+            accounts
+                .values()
+                .fold(Amount::ZERO, |sum, account| sum.saturating_add(f(account)))
+        };
+        write!(
+            writer,
+            "totals,{},{},{},",
+            sum(|account| account.available),
+            sum(|account| account.held),
+            sum(|account| account.total),
+        )?;
+        if extended {
+            write!(writer, ",,")?;
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+
+    info!(
+        reports = reports.len(),
+        clients = accounts.len(),
+        conflicts = conflict_count,
+        "merged reports"
+    );
+    Ok(conflict_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::DisputePolicy;
+
+    #[test]
+    fn test_snapshot_changes() {
+        let dir = std::env::temp_dir();
+        let (report, changes) = (
+            dir.join(format!("tren-test-snapshot-{}.csv", std::process::id())),
+            dir.join(format!("tren-test-changes-{}.csv", std::process::id())),
+        );
+        let mut snapshots = ReportSnapshots::new(report.clone(), Duration::ZERO)
+            .with_changes(Some(changes.clone()));
+        let mut accounts = Accounts::new(DisputePolicy::default());
+        accounts.deposit(1, amount!(10)).unwrap();
+        accounts.deposit(2, amount!(5)).unwrap();
+
+        snapshots.write_if_due(&accounts).unwrap();
+        let first = std::fs::read_to_string(&changes).unwrap();
+        accounts.withdraw(2, amount!(1)).unwrap();
+        accounts.deposit(3, amount!(1)).unwrap();
+        snapshots.write_if_due(&accounts).unwrap();
+        let second = std::fs::read_to_string(&changes).unwrap();
+        snapshots.write_if_due(&accounts).unwrap();
+        let third = std::fs::read_to_string(&changes).unwrap();
+        for path in [report.clone(), report.with_extension("csv.1"), changes] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let header = "client,available,held,total,locked\n";
+        assert_eq!(first, format!("{header}1,10,0,10,false\n2,5,0,5,false\n"));
+        assert_eq!(second, format!("{header}2,4,0,4,false\n3,1,0,1,false\n"));
+        assert_eq!(third, header, "nothing changed");
+    }
+
+    #[test]
+    fn test_snapshot_rotation() {
+        let dir = std::env::temp_dir();
+        let report = dir.join(format!("tren-test-rotation-{}.csv", std::process::id()));
+        let link = dir.join(format!(
+            "tren-test-rotation-link-{}.csv",
+            std::process::id()
+        ));
+        let mut snapshots = ReportSnapshots::new(report.clone(), Duration::ZERO)
+            .with_keep(2)
+            .with_link(Some(link.clone()));
+        let mut accounts = Accounts::new(DisputePolicy::default());
+        for client_id in 1..=4 {
+            accounts.deposit(client_id, amount!(1)).unwrap();
+            snapshots.write_if_due(&accounts).unwrap();
+        }
+
+        let clients = |path: &Path| std::fs::read_to_string(path).unwrap().lines().count() - 1;
+        let got = [
+            clients(&report),
+            clients(&numbered(&report, 1)),
+            clients(&numbered(&report, 2)),
+        ];
+        assert!(!numbered(&report, 3).exists(), "only 2 previous are kept");
+        #[cfg(unix)]
+        assert_eq!(clients(&link), 4, "link points to the latest");
+        for path in [
+            report.clone(),
+            numbered(&report, 1),
+            numbered(&report, 2),
+            link,
+        ] {
+            let _ = std::fs::remove_file(path);
+        }
+        assert_eq!(got, [4, 3, 2]);
+    }
+
+    #[test]
+    fn test_partition() {
+        let width = ClientID::MAX.to_string().len();
+        let tests = vec![
+            (
+                "first range",
+                "by-client-range=10000",
+                9_999,
+                0,
+                format!("{:0width$}-{:0width$}", 0, 9_999),
+            ),
+            (
+                "second range",
+                "by-client-range=10000",
+                10_000,
+                1,
+                format!("{:0width$}-{:0width$}", 10_000, 19_999),
+            ),
+            (
+                "last range",
+                "by-client-range=40000",
+                65_535,
+                1,
+                format!(
+                    "{:0width$}-{:0width$}",
+                    40_000,
+                    79_999.min(u64::from(ClientID::MAX))
+                ),
+            ),
+            ("hash", "by-hash=4", 1, 1, "1-of-4".to_string()),
+        ];
+
+        for (name, partition, client_id, want_key, want_suffix) in tests {
+            let partition: Partition = partition.parse().unwrap();
+            let key = partition.of(client_id);
+            assert_eq!(
+                (key, partition.suffix(key)),
+                (want_key, want_suffix),
+                "failed test {name}"
+            );
+        }
+
+        assert!("by-hash=0".parse::<Partition>().is_err());
+        assert!("by-region=1".parse::<Partition>().is_err());
+    }
+
+    #[test]
+    fn test_merge_reports() {
+        let dir = std::env::temp_dir();
+        let reports: Vec<PathBuf> = ["eu", "us"]
+            .iter()
+            .map(|region| {
+                dir.join(format!(
+                    "tren-test-merge-{region}-{}.csv",
+                    std::process::id()
+                ))
+            })
+            .collect();
+        std::fs::write(
+            &reports[0],
+            "client,available,held,total,locked\n\
+             1,10,2,12,false\n\
+             2,5,0,5,true\n\
+             totals,15,2,17,\n",
+        )
+        .unwrap();
+        // written before v1 was reduced to the baseline columns
+        std::fs::write(
+            &reports[1],
+            "client,available,held,total,locked,closed,flagged\n\
+             2,1.5,0,1.5,false,false,true\n\
+             3,7,0,7,false,true,false\n",
+        )
+        .unwrap();
+
+        let mut got = Vec::new();
+        let conflicts = merge_reports(
+            &reports,
+            &mut got,
+            MergeConflicts::Lock,
+            true,
+            ReportVersion::V1,
+        )
+        .unwrap();
+        assert_eq!(conflicts, 1);
+        assert_eq!(
+            String::from_utf8(got).unwrap(),
+            "client,available,held,total,locked\n\
+             1,10,2,12,false\n\
+             2,6.5,0,6.5,true\n\
+             3,7,0,7,false\n\
+             totals,23.5,2,25.5,\n"
+        );
+
+        let mut got = Vec::new();
+        merge_reports(
+            &reports,
+            &mut got,
+            MergeConflicts::Lock,
+            true,
+            ReportVersion::V2,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(got).unwrap(),
+            "client,available,held,total,status\n\
+             1,10,2,12,active\n\
+             2,6.5,0,6.5,frozen\n\
+             3,7,0,7,closed\n\
+             totals,23.5,2,25.5,\n"
+        );
+
+        let got = merge_reports(
+            &reports,
+            &mut Vec::new(),
+            MergeConflicts::Fail,
+            false,
+            ReportVersion::V1,
+        );
+        for report in reports.iter() {
+            std::fs::remove_file(report).unwrap();
+        }
+        assert!(got.is_err(), "conflict with fail policy");
+    }
+}
@@ -0,0 +1,513 @@
+//! Validation rules given by repeated `--rule`, or by `rules` of the config file, checked by the parser against
+//! every record that passes the sample and client filter. Rule is `<name>:<action>:<conditions>`, e.g.
+//! `--rule 'retail_transfers:reject:segment=retail,type=transfer'`, and matches records meeting all of its
+//! comma separated conditions:
+//! - `type=deposit|withdrawal` or `type!=...`, types of the journal as listed in [TYPES]
+//! - `client=1|2` or `client!=...`, transfers match if either of their clients does
+//! - `segment=retail|business` or `segment!=...`, segments from `--segments`, clients without one are in no segment
+//! - `amount<100`, `amount<=100`, `amount>100` or `amount>=100`, records without amount, e.g. locks or disputes of
+//!   the whole transaction, never match
+//!
+//! Rules are checked in order, a matching rule with action:
+//! - `reject` skips the record and writes it into the dead-letter file, the rules after it are not checked
+//! - `warn` logs the record and keeps it
+//! - `flag` keeps the record and flags the account of its client once the record is applied, see [RuleFlags]
+//!
+//! Rows of custom record types are checked as the entry their handler turns them into, see [crate::record_types]
+use crate::aliases::*;
+use crate::channel::{DisputeLookUpMessage, TransactionMessage};
+use crate::dead_letter::DeadLetter;
+use crate::parser::JournalEntry;
+use crate::segments::Segments;
+use crate::summary::Summary;
+use eyre::{eyre, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+/// Types of journal records the `type` condition accepts
+pub const TYPES: &[&str] = &[
+    "deposit",
+    "withdrawal",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "reversal",
+    "transfer",
+    "adjustment_credit",
+    "adjustment_debit",
+    "lock",
+    "unlock",
+    "close",
+];
+
+/// What happens with the record matching the rule
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleAction {
+    Reject,
+    Warn,
+    Flag,
+}
+
+/// Amount of the record compared with the amount of the condition
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Comparison {
+    Below,
+    AtMost,
+    AtLeast,
+    Above,
+}
+
+/// Single condition of the rule, `negated` ones match the records whose field has none of the values
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Condition {
+    Type {
+        types: Vec<String>,
+        negated: bool,
+    },
+    Client {
+        clients: Vec<ClientID>,
+        negated: bool,
+    },
+    Segment {
+        segments: Vec<String>,
+        negated: bool,
+    },
+    Amount {
+        comparison: Comparison,
+        amount: Amount,
+    },
+}
+
+/// Rule given by `--rule`, written in the config file the same way as on the command line
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rule {
+    pub name: String,
+    pub action: RuleAction,
+    conditions: Vec<Condition>,
+    /// The rule as it was given, written back into the config
+    definition: String,
+}
+
+impl FromStr for Rule {
+    type Err = eyre::Report;
+
+    /// Parses `<name>:<action>:<condition>[,<condition>...]`, e.g. `large_deposits:flag:type=deposit,amount>10000`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let (Some(name), Some(action), Some(conditions)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(eyre!(
+                "invalid rule '{s}', expected <name>:<action>:<conditions>, e.g. large:flag:amount>10000"
+            ));
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(eyre!("rule '{s}' has no name"));
+        }
+        let action = match action.trim() {
+            "reject" => RuleAction::Reject,
+            "warn" => RuleAction::Warn,
+            "flag" => RuleAction::Flag,
+            action => {
+                return Err(eyre!(
+                    "invalid action '{action}' of rule {name}, expected one of reject, warn, flag"
+                ))
+            }
+        };
+        let conditions = conditions
+            .split(',')
+            .map(parse_condition)
+            .collect::<Result<Vec<_>>>()
+            .wrap_err_with(|| format!("invalid condition of rule {name}"))?;
+        Ok(Rule {
+            name: name.to_string(),
+            action,
+            conditions,
+            definition: s.trim().to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for Rule {
+    type Error = eyre::Report;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Rule> for String {
+    fn from(rule: Rule) -> String {
+        rule.definition
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.definition)
+    }
+}
+
+/// Parses `<field><operator><values>`, values of `=` and `!=` are separated by `|`
+fn parse_condition(s: &str) -> Result<Condition> {
+    let split = s
+        .find(['!', '=', '<', '>'])
+        .ok_or_else(|| eyre!("'{s}' has no operator, expected e.g. type=deposit or amount>100"))?;
+    let (field, rest) = (s[..split].trim(), &s[split..]);
+    let (operator, value) = ["!=", "<=", ">=", "=", "<", ">"]
+        .into_iter()
+        .find_map(|operator| {
+            rest.strip_prefix(operator)
+                .map(|value| (operator, value.trim()))
+        })
+        .ok_or_else(|| eyre!("invalid operator in '{s}'"))?;
+    if value.is_empty() {
+        return Err(eyre!("'{s}' has no value"));
+    }
+    let values = || value.split('|').map(str::trim);
+    let negated = operator == "!=";
+    match (field, operator) {
+        ("type", "=" | "!=") => {
+            let types = values().map(str::to_string).collect::<Vec<_>>();
+            if let Some(unknown) = types.iter().find(|name| !TYPES.contains(&name.as_str())) {
+                return Err(eyre!("unknown type '{unknown}' in '{s}'"));
+            }
+            Ok(Condition::Type { types, negated })
+        }
+        ("client", "=" | "!=") => Ok(Condition::Client {
+            clients: values()
+                .map(|client_id| {
+                    client_id
+                        .parse()
+                        .wrap_err_with(|| format!("invalid client id '{client_id}' in '{s}'"))
+                })
+                .collect::<Result<_>>()?,
+            negated,
+        }),
+        ("segment", "=" | "!=") => Ok(Condition::Segment {
+            segments: values().map(str::to_string).collect(),
+            negated,
+        }),
+        ("amount", "<" | "<=" | ">=" | ">") => Ok(Condition::Amount {
+            comparison: match operator {
+                "<" => Comparison::Below,
+                "<=" => Comparison::AtMost,
+                ">=" => Comparison::AtLeast,
+                _ => Comparison::Above,
+            },
+            amount: value
+                .parse()
+                .wrap_err_with(|| format!("invalid amount in '{s}'"))?,
+        }),
+        ("type" | "client" | "segment" | "amount", _) => Err(eyre!(
+            "operator {operator} can't be used with {field} in '{s}'"
+        )),
+        _ => Err(eyre!(
+            "unknown field '{field}' in '{s}', expected one of type, client, segment, amount"
+        )),
+    }
+}
+
+/// Fields of the record the conditions look at
+struct Fields<'s> {
+    name: &'static str,
+    clients: [Option<ClientID>; 2],
+    transaction_id: Option<TransactionID>,
+    amount: Option<Amount>,
+    segments: Option<&'s Segments>,
+}
+
+impl<'s> Fields<'s> {
+    fn of(entry: &JournalEntry, segments: Option<&'s Segments>) -> Fields<'s> {
+        let (name, transaction_id, amount, receiving) = match entry {
+            JournalEntry::Transaction(message) => {
+                let (transaction_id, amount, receiving) = match message {
+                    TransactionMessage::Deposit(transaction)
+                    | TransactionMessage::Withdrawal(transaction)
+                    | TransactionMessage::AdjustmentCredit(transaction)
+                    | TransactionMessage::AdjustmentDebit(transaction) => (
+                        Some(transaction.transaction_id),
+                        Some(transaction.amount),
+                        None,
+                    ),
+                    TransactionMessage::Dispute(dispute)
+                    | TransactionMessage::Resolve(dispute)
+                    | TransactionMessage::Chargeback(dispute)
+                    | TransactionMessage::Reversal(dispute) => {
+                        (Some(dispute.transaction_id), Some(dispute.amount), None)
+                    }
+                    TransactionMessage::Transfer(transfer) => (
+                        Some(transfer.transaction_id),
+                        Some(transfer.amount),
+                        Some(transfer.to_client_id),
+                    ),
+                    TransactionMessage::Lock(_)
+                    | TransactionMessage::Unlock(_)
+                    | TransactionMessage::Close(_) => (None, None, None),
+                };
+                (message.name(), transaction_id, amount, receiving)
+            }
+            JournalEntry::DisputeLookUp(message) => {
+                let name = match message {
+                    DisputeLookUpMessage::Dispute(..) => "dispute",
+                    DisputeLookUpMessage::Resolve(..) => "resolve",
+                    DisputeLookUpMessage::Chargeback(..) => "chargeback",
+                    DisputeLookUpMessage::Reversal(..) => "reversal",
+                };
+                (name, Some(message.transaction_id()), message.amount(), None)
+            }
+        };
+        Fields {
+            name,
+            clients: [Some(entry.client_id()), receiving],
+            transaction_id,
+            amount,
+            segments,
+        }
+    }
+
+    fn clients(&self) -> impl Iterator<Item = ClientID> + '_ {
+        self.clients.iter().flatten().copied()
+    }
+
+    fn segment(&self, client_id: ClientID) -> Option<&'s str> {
+        self.segments.and_then(|segments| segments.get(client_id))
+    }
+}
+
+impl Condition {
+    fn matches(&self, fields: &Fields) -> bool {
+        match self {
+            Condition::Type { types, negated } => {
+                types.iter().any(|name| name == fields.name) != *negated
+            }
+            Condition::Client { clients, negated } => fields
+                .clients()
+                .any(|client_id| clients.contains(&client_id) != *negated),
+            Condition::Segment { segments, negated } => fields.clients().any(|client_id| {
+                let segment = fields.segment(client_id);
+                segments.iter().any(|name| Some(name.as_str()) == segment) != *negated
+            }),
+            Condition::Amount { comparison, amount } => match fields.amount {
+                Some(value) => match comparison {
+                    Comparison::Below => value < *amount,
+                    Comparison::AtMost => value <= *amount,
+                    Comparison::AtLeast => value >= *amount,
+                    Comparison::Above => value > *amount,
+                },
+                None => false,
+            },
+        }
+    }
+
+    fn needs_segments(&self) -> bool {
+        matches!(self, Condition::Segment { .. })
+    }
+}
+
+impl Rule {
+    fn matches(&self, fields: &Fields) -> bool {
+        self.conditions
+            .iter()
+            .all(|condition| condition.matches(fields))
+    }
+}
+
+/// Clients flagged by the rules while parsing with the index of the record which flagged them, the processor
+/// flags their accounts as soon as it applies the record, see [crate::processor::Processor::with_rule_flags].
+/// Clones share the same flags
+#[derive(Clone, Debug, Default)]
+pub struct RuleFlags(Arc<Mutex<VecDeque<(u64, ClientID, String)>>>);
+
+impl RuleFlags {
+    fn flag(&self, index: u64, client_id: ClientID, rule: &str) {
+        let mut flags = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        flags.push_back((index, client_id, rule.to_string()));
+    }
+
+    /// Clients flagged by the records up to `index` with the rule which flagged them, in order they were flagged
+    pub fn take_up_to(&self, index: u64) -> Vec<(ClientID, String)> {
+        let mut flags = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        let count = flags
+            .iter()
+            .take_while(|(flagged_at, ..)| *flagged_at <= index)
+            .count();
+        flags
+            .drain(..count)
+            .map(|(_, client_id, rule)| (client_id, rule))
+            .collect()
+    }
+}
+
+/// Checks the records against the rules, see the [module documentation](self)
+#[derive(Clone)]
+pub struct Validator {
+    rules: Arc<[Rule]>,
+    segments: Option<Arc<Segments>>,
+    dead_letter: Option<DeadLetter>,
+    flags: RuleFlags,
+}
+
+impl Validator {
+    /// Fails if any of the rules has a segment condition and the clients have no segments
+    pub fn new(rules: Vec<Rule>, segments: Option<Segments>) -> Result<Validator> {
+        if segments.is_none() {
+            if let Some(rule) = rules
+                .iter()
+                .find(|rule| rule.conditions.iter().any(Condition::needs_segments))
+            {
+                return Err(eyre!(
+                    "rule {} needs segments of the clients, set them by --segments",
+                    rule.name
+                ));
+            }
+        }
+        Ok(Validator {
+            rules: rules.into(),
+            segments: segments.map(Arc::new),
+            dead_letter: None,
+            flags: RuleFlags::default(),
+        })
+    }
+
+    /// Writes rejected records into the dead-letter file
+    pub fn with_dead_letter(mut self, dead_letter: Option<DeadLetter>) -> Validator {
+        self.dead_letter = dead_letter;
+        self
+    }
+
+    /// Clients flagged by the rules, to be passed to the processor
+    pub fn flags(&self) -> RuleFlags {
+        self.flags.clone()
+    }
+
+    /// Applies actions of all rules the record matches, returns `false` if the record is rejected
+    pub fn check(&self, entry: &JournalEntry, index: u64, summary: &mut Summary) -> bool {
+        let fields = Fields::of(entry, self.segments.as_deref());
+        let client_id = entry.client_id();
+        for rule in self.rules.iter().filter(|rule| rule.matches(&fields)) {
+            match rule.action {
+                RuleAction::Reject => {
+                    warn!(rule = rule.name, client_id, %index, "record rejected by rule");
+                    summary.rule_rejections += 1;
+                    if let Some(dead_letter) = self.dead_letter.as_ref() {
+                        dead_letter.write(
+                            index,
+                            fields.name,
+                            client_id,
+                            fields.transaction_id,
+                            fields.amount,
+                            "rule_rejected",
+                            &format_args!("rejected by rule {}", rule.name),
+                        );
+                    }
+                    return false;
+                }
+                RuleAction::Warn => {
+                    warn!(rule = rule.name, client_id, %index, "record matched rule");
+                    summary.rule_warnings += 1;
+                }
+                RuleAction::Flag => {
+                    self.flags.flag(index, client_id, &rule.name);
+                    summary.rule_flags += 1;
+                }
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let path = std::env::temp_dir().join(format!("tren-test-rules-{}.csv", std::process::id()));
+        std::fs::write(&path, "1,retail\n2,business\n").unwrap();
+        let segments = Segments::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let rules = [
+            "retail_transfers:reject:segment=retail,type=transfer",
+            "unknown_clients:reject:client!=1|2",
+            "large:flag:type=deposit|withdrawal,amount>=100",
+            "small_withdrawals:warn:type=withdrawal,amount<1",
+        ]
+        .into_iter()
+        .map(|rule| rule.parse().unwrap())
+        .collect();
+        let validator = Validator::new(rules, Some(segments)).unwrap();
+
+        let tests = vec![
+            (
+                "retail transfer",
+                TransactionMessage::transfer(2, 1, 1, amount!(5)),
+                (false, 0, 0),
+            ),
+            (
+                "business transfer",
+                TransactionMessage::transfer(2, 2, 1, amount!(5)),
+                (true, 0, 0),
+            ),
+            (
+                "unknown client",
+                TransactionMessage::deposit(3, 1, amount!(5)),
+                (false, 0, 0),
+            ),
+            (
+                "large deposit",
+                TransactionMessage::deposit(1, 1, amount!(100)),
+                (true, 0, 1),
+            ),
+            (
+                "small withdrawal",
+                TransactionMessage::withdrawal(2, 1, amount!(0.5)),
+                (true, 1, 0),
+            ),
+            (
+                "lock has no amount",
+                TransactionMessage::Lock(1),
+                (true, 0, 0),
+            ),
+        ];
+        for (name, message, (kept, warnings, flags)) in tests {
+            let mut summary = Summary::default();
+            let got = validator.check(&JournalEntry::Transaction(message), 0, &mut summary);
+            assert_eq!(
+                (got, summary.rule_warnings, summary.rule_flags),
+                (kept, warnings, flags),
+                "failed test {name}"
+            );
+            assert_eq!(
+                summary.rule_rejections,
+                u64::from(!kept),
+                "failed test {name}"
+            );
+        }
+        assert_eq!(
+            validator.flags().take_up_to(0),
+            vec![(1, "large".to_string())]
+        );
+
+        for invalid in [
+            "large:flag",
+            ":flag:amount>1",
+            "large:block:amount>1",
+            "large:flag:amount=1",
+            "large:flag:type=payment",
+            "large:flag:client=x",
+            "large:flag:size>1",
+            "large:flag:amount>",
+        ] {
+            assert!(invalid.parse::<Rule>().is_err(), "failed test {invalid}");
+        }
+        let segment_rule = vec!["retail:warn:segment=retail".parse().unwrap()];
+        assert!(Validator::new(segment_rule, None).is_err());
+    }
+}
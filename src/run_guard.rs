@@ -0,0 +1,201 @@
+//! Guards set by `--max-runtime` and `--idle-timeout`, so a journal fed by a stuck upstream, e.g. a pipe whose
+//! writer hangs or a hung network mount, doesn't leave the run waiting forever while it holds its files.
+//! Once a guard trips, the run stops as if the journal ended: records applied so far are reported and the run
+//! exits cleanly.
+//! - past the max runtime the parser stops before its next record, see [RunGuard::should_stop]
+//! - the processing gives up once no record arrives for the idle timeout, or for [STOP_GRACE] past the max
+//!   runtime when the parser is blocked and can't stop on its own, see [RunGuard::give_up_at]
+//!
+//! When the parser stops, the end of the journal is where it stopped, so `--since-offset` continues from there.
+//! When the processing gives up, the records the parser is blocked on are lost and the offset file is not updated.
+//! With idle timeout records are not batched, so a record parsed before the stuck read is still applied.
+//! Journal read from a pipe needs `--input-format` and `--trim-whitespace` or `--no-trim-whitespace`, their
+//! detection reads ahead and would wait for the stuck upstream too
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long the processing waits past the max runtime for the parser to stop, and how long the pipeline waits
+/// for its threads once the processing gave up
+pub const STOP_GRACE: Duration = Duration::from_secs(1);
+
+/// Number of records between two checks of the max runtime by the parser
+const CHECK_INTERVAL: u64 = 1024;
+
+/// Why the run was stopped before the end of the journal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    MaxRuntime = 1,
+    IdleTimeout = 2,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::MaxRuntime => f.write_str("max runtime"),
+            Stop::IdleTimeout => f.write_str("idle timeout"),
+        }
+    }
+}
+
+/// Max runtime and idle timeout of the run shared by its threads, clones share whether the run was stopped
+#[derive(Clone, Debug)]
+pub struct RunGuard {
+    deadline: Option<Instant>,
+    idle_timeout: Option<Duration>,
+    /// [Stop] of the run, 0 while it runs
+    stopped: Arc<AtomicU8>,
+}
+
+impl RunGuard {
+    /// Guard of the run starting now, `None` if neither limit is set
+    pub fn new(max_runtime: Option<Duration>, idle_timeout: Option<Duration>) -> Option<RunGuard> {
+        (max_runtime.is_some() || idle_timeout.is_some()).then(|| RunGuard {
+            deadline: max_runtime.map(|max_runtime| Instant::now() + max_runtime),
+            idle_timeout,
+            stopped: Arc::default(),
+        })
+    }
+
+    /// `true` once the run was stopped or, checked every [CHECK_INTERVAL] records, it is past the max runtime
+    pub fn should_stop(&self, index: u64) -> bool {
+        if self.stopped().is_some() {
+            return true;
+        }
+        let past_deadline = index.is_multiple_of(CHECK_INTERVAL)
+            && self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline);
+        if past_deadline {
+            self.stop(Stop::MaxRuntime);
+        }
+        past_deadline
+    }
+
+    /// When the processing, which received its last record at `last_record`, gives up waiting for the next one
+    pub fn give_up_at(&self, last_record: Instant) -> Option<Instant> {
+        let idle = self.idle_timeout.map(|timeout| last_record + timeout);
+        let past_deadline = self
+            .deadline
+            .map(|deadline| deadline.max(last_record) + STOP_GRACE);
+        idle.into_iter().chain(past_deadline).min()
+    }
+
+    /// With idle timeout records are sent to the processing one by one, so records parsed before the parser
+    /// blocked on a stuck read are not left in its batch
+    pub fn has_idle_timeout(&self) -> bool {
+        self.idle_timeout.is_some()
+    }
+
+    /// Reason to give up if it is time to, see [RunGuard::give_up_at]
+    pub fn expired(&self, last_record: Instant, now: Instant) -> Option<Stop> {
+        if self.give_up_at(last_record).is_none_or(|at| now < at) {
+            return None;
+        }
+        match self.idle_timeout {
+            Some(timeout) if now >= last_record + timeout => Some(Stop::IdleTimeout),
+            _ => Some(Stop::MaxRuntime),
+        }
+    }
+
+    /// Stops the run, the first stop wins
+    pub fn stop(&self, stop: Stop) {
+        let _ = self
+            .stopped
+            .compare_exchange(0, stop as u8, Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    pub fn stopped(&self) -> Option<Stop> {
+        match self.stopped.load(Ordering::Relaxed) {
+            1 => Some(Stop::MaxRuntime),
+            2 => Some(Stop::IdleTimeout),
+            _ => None,
+        }
+    }
+}
+
+/// `true` if the guard is set and stops the parser before the record
+pub fn should_stop(guard: Option<&RunGuard>, index: u64) -> bool {
+    guard.is_some_and(|guard| guard.should_stop(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expired() {
+        let second = Duration::from_secs(1);
+        let guard = |max_runtime: Option<u64>, idle_timeout: Option<u64>| {
+            RunGuard::new(
+                max_runtime.map(Duration::from_secs),
+                idle_timeout.map(Duration::from_secs),
+            )
+        };
+        assert!(guard(None, None).is_none(), "no limits");
+
+        let start = Instant::now();
+        let tests = vec![
+            ("idle", guard(None, Some(5)), 0, 4, None),
+            (
+                "idle timeout",
+                guard(None, Some(5)),
+                1,
+                6,
+                Some(Stop::IdleTimeout),
+            ),
+            ("before max runtime", guard(Some(10), None), 0, 10, None),
+            (
+                "max runtime",
+                guard(Some(10), None),
+                9,
+                12,
+                Some(Stop::MaxRuntime),
+            ),
+            (
+                "record after max runtime",
+                guard(Some(10), None),
+                11,
+                11,
+                None,
+            ),
+            (
+                "idle before max runtime",
+                guard(Some(10), Some(2)),
+                3,
+                5,
+                Some(Stop::IdleTimeout),
+            ),
+        ];
+
+        for (name, guard, last_record, now, want) in tests {
+            let guard = guard.unwrap();
+            assert_eq!(
+                guard.expired(start + second * last_record, start + second * now),
+                want,
+                "failed test {name}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_should_stop() {
+        let guard = RunGuard::new(Some(Duration::ZERO), None).unwrap();
+        let shared = guard.clone();
+        assert!(
+            !guard.should_stop(1),
+            "max runtime is not checked every record"
+        );
+        assert!(shared.stopped().is_none());
+        assert!(guard.should_stop(CHECK_INTERVAL));
+        assert_eq!(shared.stopped(), Some(Stop::MaxRuntime));
+
+        let guard = RunGuard::new(None, Some(Duration::from_secs(60))).unwrap();
+        assert!(!should_stop(Some(&guard), 0));
+        guard.stop(Stop::IdleTimeout);
+        guard.stop(Stop::MaxRuntime);
+        assert!(should_stop(Some(&guard), 1), "stopped by other thread");
+        assert_eq!(guard.stopped(), Some(Stop::IdleTimeout), "first stop wins");
+    }
+}
@@ -0,0 +1,92 @@
+//! Identifier of a single run of the binary, so logs of concurrent runs written into the same file can be told apart
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Crockford's base32 alphabet used by ULIDs, without `I`, `L`, `O` and `U`
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// [ULID](https://github.com/ulid/spec): 48 bits of milliseconds since Unix epoch followed by 80 random bits,
+/// written as 26 characters. IDs of runs started in different milliseconds sort by their start
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RunId(u128);
+
+impl RunId {
+    /// New ID of a run starting now. The random bits come from the randomly seeded std hasher,
+    /// they are unique enough for telling runs apart but not suitable for anything secret
+    pub fn generate() -> RunId {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(now.as_nanos());
+        hasher.write_u32(std::process::id());
+        let high = hasher.finish();
+        hasher.write_u8(0);
+        let low = hasher.finish();
+
+        RunId::from_parts(
+            now.as_millis() as u64,
+            (u128::from(high) << 16) | u128::from(low & 0xffff),
+        )
+    }
+
+    /// ID from milliseconds since Unix epoch and random bits, only the lower 48 and 80 bits of them are used
+    pub fn from_parts(millis: u64, random: u128) -> RunId {
+        let millis = u128::from(millis) & ((1 << 48) - 1);
+        RunId((millis << 80) | (random & ((1 << 80) - 1)))
+    }
+}
+
+impl fmt::Display for RunId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let encoded: String = (0..26)
+            .rev()
+            .map(|position| ALPHABET[((self.0 >> (5 * position)) & 31) as usize] as char)
+            .collect();
+        f.write_str(&encoded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let tests = vec![
+            (
+                "zero",
+                RunId::from_parts(0, 0),
+                "00000000000000000000000000",
+            ),
+            (
+                "max",
+                RunId::from_parts(u64::MAX, u128::MAX),
+                "7ZZZZZZZZZZZZZZZZZZZZZZZZZ",
+            ),
+            (
+                "timestamp",
+                RunId::from_parts(1469918176385, 0),
+                "01ARYZ6S410000000000000000",
+            ),
+            (
+                "random",
+                RunId::from_parts(0, 33),
+                "00000000000000000000000011",
+            ),
+        ];
+
+        for (name, run_id, want) in tests {
+            assert_eq!(run_id.to_string(), want, "failed test {name}");
+        }
+    }
+
+    #[test]
+    fn test_generate() {
+        let (first, second) = (RunId::generate(), RunId::generate());
+        assert_ne!(first, second);
+        assert_eq!(first.to_string().len(), 26);
+    }
+}
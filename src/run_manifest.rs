@@ -0,0 +1,276 @@
+//! Machine readable manifest of a run written by `--run-manifest` once the run ends, so orchestration can verify
+//! runs without scraping the logs. It is a single JSON object with the run ID, status, hashes of the input files,
+//! the config the run used, counters of the [Summary], timing of the run and its stages, and the written files.
+//! Inputs are hashed like by `tren checksum`, see [Manifest::digest]. Outputs are listed only if they exist when
+//! the run ends, a report printed to stdout is not listed
+use crate::config::Config;
+use crate::manifest::{self, Manifest};
+use crate::run_id::RunId;
+use crate::summary::Summary;
+use eyre::{Context, Result};
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub struct RunManifest {
+    run_id: RunId,
+    started_at: SystemTime,
+    started: Instant,
+    /// Config of the run in RON, the same format as `--config`
+    config: String,
+    inputs: Vec<PathBuf>,
+    outputs: Vec<PathBuf>,
+}
+
+impl RunManifest {
+    /// Manifest of a run starting now, inputs and outputs are taken from the config. Call it before the config
+    /// is consumed by the run
+    pub fn new(run_id: RunId, input: &Path, config: &Config) -> RunManifest {
+        let inputs = std::iter::once(Some(input))
+            .chain([
+                config.limits.as_deref(),
+                config.segments.as_deref(),
+                config.recover.as_deref(),
+                config.initial_state.as_deref(),
+                config.load_state.as_deref(),
+                config.only_clients.as_deref(),
+                config.ignore_clients.as_deref(),
+                config.client_remap.as_deref(),
+                config.verify_manifest.as_deref(),
+            ])
+            .flatten()
+            .map(Path::to_path_buf)
+            .collect();
+        let outputs = [
+            config.dead_letter.as_deref(),
+            config.audit.as_deref(),
+            config.report_interval.map(|_| {
+                config
+                    .report_file
+                    .as_deref()
+                    .unwrap_or_else(|| "report.csv".as_ref())
+            }),
+            config.report_changes.as_deref(),
+            config.report_link.as_deref(),
+            config.aging_report.as_deref(),
+            config.segment_totals.as_deref(),
+            config.wal.as_deref(),
+            config.record_decisions.as_deref(),
+            config.since_offset.as_deref(),
+            config.dispute_cache_file.as_deref(),
+            config.save_state.as_deref(),
+            config.webhook.failure_file.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .map(Path::to_path_buf)
+        .collect();
+
+        RunManifest {
+            run_id,
+            started_at: SystemTime::now(),
+            started: Instant::now(),
+            config: ron::to_string(config).unwrap_or_default(),
+            inputs,
+            outputs,
+        }
+    }
+
+    /// Adds file written outside of the pipeline, e.g. the partitioned report
+    pub fn with_output(mut self, path: PathBuf) -> RunManifest {
+        if !self.outputs.contains(&path) {
+            self.outputs.push(path);
+        }
+        self
+    }
+
+    /// Writes the manifest of the finished run, `outcome` is the summary of the run or the error which failed it
+    pub fn write(&self, path: &Path, outcome: Result<&Summary, &eyre::Report>) -> Result<()> {
+        let mut json = String::new();
+        self.render(&mut json, outcome, self.started.elapsed())
+            .expect("writing into string doesn't fail");
+        std::fs::File::create(path)
+            .and_then(|mut file| file.write_all(json.as_bytes()))
+            .wrap_err_with(|| format!("failed to write run manifest {}", path.display()))
+    }
+
+    fn render(
+        &self,
+        json: &mut String,
+        outcome: Result<&Summary, &eyre::Report>,
+        elapsed: Duration,
+    ) -> std::fmt::Result {
+        let started_at = self
+            .started_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        writeln!(json, "{{")?;
+        writeln!(json, "  \"run_id\": \"{}\",", self.run_id)?;
+        writeln!(json, "  \"version\": \"{}\",", env!("CARGO_PKG_VERSION"))?;
+        match outcome {
+            Ok(_) => writeln!(json, "  \"status\": \"succeeded\",")?,
+            Err(err) => {
+                writeln!(json, "  \"status\": \"failed\",")?;
+                writeln!(json, "  \"error\": {},", quote(&format!("{err:#}")))?;
+            }
+        }
+        writeln!(json, "  \"started_at_ms\": {},", started_at.as_millis())?;
+        writeln!(json, "  \"elapsed_ms\": {},", elapsed.as_millis())?;
+
+        writeln!(json, "  \"inputs\": [")?;
+        for (index, input) in self.inputs.iter().enumerate() {
+            let separator = if index + 1 < self.inputs.len() {
+                ","
+            } else {
+                ""
+            };
+            let path = quote(&input.to_string_lossy());
+            match Manifest::of_journal(input, manifest::CHUNK_SIZE) {
+                Ok(manifest) => writeln!(
+                    json,
+                    "    {{\"path\": {path}, \"size\": {}, \"fnv1a\": \"{:016x}\"}}{separator}",
+                    manifest.size,
+                    manifest.digest()
+                )?,
+                Err(err) => writeln!(
+                    json,
+                    "    {{\"path\": {path}, \"error\": {}}}{separator}",
+                    quote(&format!("{err:#}"))
+                )?,
+            }
+        }
+        writeln!(json, "  ],")?;
+        writeln!(json, "  \"config\": {},", quote(&self.config))?;
+
+        if let Ok(summary) = outcome {
+            let records = summary.journal_end.map(|end| end.index);
+            match records {
+                Some(records) => writeln!(json, "  \"records\": {records},")?,
+                None => writeln!(json, "  \"records\": null,")?,
+            }
+            writeln!(json, "  \"counters\": {{")?;
+            let counters = summary.counters();
+            for (index, (name, value)) in counters.iter().enumerate() {
+                let separator = if index + 1 < counters.len() { "," } else { "" };
+                // counts and amounts are both plain decimal numbers
+                writeln!(json, "    \"{name}\": {value}{separator}")?;
+            }
+            writeln!(json, "  }},")?;
+            writeln!(json, "  \"timings\": {{")?;
+            let stages: Vec<_> = summary.timings.stages().collect();
+            for (index, (name, histogram)) in stages.iter().enumerate() {
+                let separator = if index + 1 < stages.len() { "," } else { "" };
+                writeln!(
+                    json,
+                    "    \"{name}\": {{\"count\": {}, \"total_us\": {}, \"p50_us\": {}, \"p99_us\": {}, \"max_us\": {}}}{separator}",
+                    histogram.count(),
+                    histogram.total().as_micros(),
+                    histogram.percentile(50).as_micros(),
+                    histogram.percentile(99).as_micros(),
+                    histogram.max().as_micros()
+                )?;
+            }
+            writeln!(json, "  }},")?;
+        }
+
+        let outputs: Vec<_> = self.outputs.iter().filter(|path| path.exists()).collect();
+        writeln!(json, "  \"outputs\": [")?;
+        for (index, output) in outputs.iter().enumerate() {
+            let separator = if index + 1 < outputs.len() { "," } else { "" };
+            writeln!(json, "    {}{separator}", quote(&output.to_string_lossy()))?;
+        }
+        writeln!(json, "  ]")?;
+        writeln!(json, "}}")
+    }
+}
+
+/// JSON string literal of the text
+pub(crate) fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let journal =
+            std::env::temp_dir().join(format!("tren-test-run-manifest-{}.csv", std::process::id()));
+        std::fs::write(&journal, "type,client,tx,amount\ndeposit,1,1,1\n").unwrap();
+        let config = Config {
+            aging_report: Some(journal.clone()),
+            segment_totals: Some("missing.csv".into()),
+            ..Default::default()
+        };
+        let manifest = RunManifest::new(RunId::from_parts(0, 0), &journal, &config);
+        let summary = Summary {
+            overflows: 2,
+            ..Default::default()
+        };
+        let digest = Manifest::of_journal(&journal, manifest::CHUNK_SIZE)
+            .unwrap()
+            .digest();
+        let path = quote(&journal.to_string_lossy());
+        let err = eyre::eyre!("broken \"journal\"");
+
+        let tests = vec![
+            (
+                "succeeded",
+                Ok(&summary),
+                vec![
+                    "\"status\": \"succeeded\",".to_string(),
+                    format!("{{\"path\": {path}, \"size\": 36, \"fnv1a\": \"{digest:016x}\"}}"),
+                    "\"overflows\": 2,".into(),
+                    "\"fees_collected\": 0,".into(),
+                    format!("\"outputs\": [\n    {path}\n  ]"),
+                ],
+            ),
+            (
+                "failed",
+                Err(&err),
+                vec![
+                    "\"status\": \"failed\",".to_string(),
+                    r#""error": "broken \"journal\"","#.into(),
+                ],
+            ),
+        ];
+
+        for (name, outcome, want) in tests {
+            let mut json = String::new();
+            manifest
+                .render(&mut json, outcome, Duration::from_millis(5))
+                .unwrap();
+            assert!(json.contains("\"elapsed_ms\": 5,"), "failed test {name}");
+            for want in want {
+                assert!(
+                    json.contains(&want),
+                    "failed test {name}: {want} not in {json}"
+                );
+            }
+            assert_eq!(
+                json.contains("\"counters\""),
+                outcome.is_ok(),
+                "failed test {name}"
+            );
+        }
+        std::fs::remove_file(&journal).unwrap();
+    }
+}
@@ -0,0 +1,58 @@
+use crate::aliases::*;
+use eyre::{eyre, Result};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+
+/// Systematic sample of clients used for quick inspection of huge journals. Whether a client is sampled
+/// depends only on its ID, so all records of sampled client are processed and its balances are exact
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sample {
+    /// Clients whose hash is below this value are sampled, hashes are spread over `0..2^32`
+    threshold: u64,
+}
+
+impl Sample {
+    /// Creates sample of roughly `fraction` of all clients, `fraction` has to be between 0 and 1
+    pub fn new(fraction: Decimal) -> Result<Sample> {
+        if fraction < Decimal::ZERO || fraction > Decimal::ONE {
+            return Err(eyre!("sample {fraction} has to be between 0 and 1"));
+        }
+        let threshold = (fraction * Decimal::from(1u64 << 32))
+            .to_u64()
+            .ok_or(eyre!("invalid sample {fraction}"))?;
+
+        Ok(Sample { threshold })
+    }
+
+    pub fn contains(&self, client_id: ClientID) -> bool {
+        // Fibonacci hashing spreads consecutive IDs evenly, so the sample isn't just the lowest IDs
+        let hash = u64::from(client_id).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32;
+        hash < self.threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_sample() {
+        let tests = vec![
+            ("nothing", dec!(0), 0),
+            ("everything", dec!(1), 10_000),
+            ("tenth", dec!(0.1), 1_000),
+        ];
+
+        for (name, fraction, want) in tests {
+            let sample = Sample::new(fraction).unwrap();
+            let got = (0..10_000).filter(|id| sample.contains(*id)).count();
+            assert!(
+                got.abs_diff(want) <= 50,
+                "failed test {name}: sampled {got}, expected around {want}"
+            );
+        }
+
+        assert!(Sample::new(dec!(1.5)).is_err());
+    }
+}
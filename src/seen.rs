@@ -0,0 +1,150 @@
+use crate::aliases::*;
+use crate::parser::Found;
+use eyre::eyre;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Bits of the filter per expected transaction, with [HASHES] hashes about 1% of unseen IDs pass the filter
+const BITS_PER_TRANSACTION: u64 = 10;
+const HASHES: u64 = 7;
+
+/// Bloom filter of IDs of deposits and withdrawals, filled by the parser while it streams the journal and checked
+/// by the dispute look-up, so disputes of transactions which were never seen fail right away instead of scanning
+/// the whole journal. Clones share the same filter. Seen transaction always passes, unseen one passes rarely and
+/// is looked up as usual. Costs 1.25 bytes per expected transaction, more transactions only let more unseen
+/// ones pass
+#[derive(Clone, Debug)]
+pub struct SeenTransactions(Arc<[AtomicU64]>);
+
+impl SeenTransactions {
+    /// Filter sized for `expected` transactions
+    pub fn new(expected: u64) -> SeenTransactions {
+        let words = expected
+            .max(1)
+            .saturating_mul(BITS_PER_TRANSACTION)
+            .div_ceil(64);
+        SeenTransactions((0..words).map(|_| AtomicU64::new(0)).collect())
+    }
+
+    /// Bits of the transaction, derived from two hashes of its ID
+    #[allow(clippy::useless_conversion)]
+    fn bits(&self, transaction_id: TransactionID) -> impl Iterator<Item = (usize, u64)> {
+        let len = self.0.len() as u64 * 64;
+        let first = mix(u64::from(transaction_id));
+        let second = mix(first) | 1;
+        (0..HASHES).map(move |i| {
+            let bit = first.wrapping_add(i.wrapping_mul(second)) % len;
+            ((bit / 64) as usize, 1 << (bit % 64))
+        })
+    }
+
+    // relaxed ordering is enough, disputes reach the look-up through a channel after their transactions are recorded
+    pub fn record(&self, transaction_id: TransactionID) {
+        for (word, mask) in self.bits(transaction_id) {
+            self.0[word].fetch_or(mask, Ordering::Relaxed);
+        }
+    }
+
+    /// `false` if the transaction was certainly not recorded
+    pub fn may_contain(&self, transaction_id: TransactionID) -> bool {
+        self.bits(transaction_id)
+            .all(|(word, mask)| self.0[word].load(Ordering::Relaxed) & mask != 0)
+    }
+}
+
+/// SplitMix64 finalizer, spreads sequential IDs over the whole filter
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// `true` if the filter is set and the transaction certainly wasn't seen
+pub(crate) fn is_unseen(seen: Option<&SeenTransactions>, transaction_id: TransactionID) -> bool {
+    seen.is_some_and(|seen| !seen.may_contain(transaction_id))
+}
+
+pub(crate) fn unseen_error(transaction_id: TransactionID) -> eyre::Report {
+    eyre!("transaction {transaction_id} was never seen in the journal")
+}
+
+/// Passes only the requests of possibly seen transactions to `look_up`, the rest fails right away.
+/// Results are in the order of `requests`
+pub(crate) fn look_up_seen(
+    seen: Option<&SeenTransactions>,
+    requests: &[(ClientID, TransactionID)],
+    look_up: impl FnOnce(&[(ClientID, TransactionID)]) -> Vec<Result<Found, eyre::Report>>,
+) -> Vec<Result<Found, eyre::Report>> {
+    let possible: Vec<_> = requests
+        .iter()
+        .filter(|(_, transaction_id)| !is_unseen(seen, *transaction_id))
+        .copied()
+        .collect();
+    if possible.len() == requests.len() {
+        return look_up(requests);
+    }
+    let mut found = match possible.is_empty() {
+        true => Vec::new(),
+        false => look_up(&possible),
+    }
+    .into_iter();
+
+    requests
+        .iter()
+        .map(
+            |(_, transaction_id)| match is_unseen(seen, *transaction_id) {
+                true => Err(unseen_error(*transaction_id)),
+                false => found
+                    .next()
+                    .unwrap_or_else(|| Err(eyre!("transaction was not looked up"))),
+            },
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seen_transactions() {
+        let seen = SeenTransactions::new(1_000);
+        let shared = seen.clone();
+        for transaction_id in (0..2_000).step_by(2) {
+            seen.record(transaction_id);
+        }
+
+        assert!(
+            (0..2_000).step_by(2).all(|tx| shared.may_contain(tx)),
+            "seen transaction always passes"
+        );
+        let passed = (1..2_000)
+            .step_by(2)
+            .filter(|tx| shared.may_contain(*tx))
+            .count();
+        assert!(passed < 50, "{passed} of 1000 unseen transactions passed");
+
+        let found = |tx| {
+            Ok((
+                1,
+                tx,
+                amount!(1),
+                None,
+                crate::channel::TransactionKind::Deposit,
+            ))
+        };
+        let results = look_up_seen(Some(&shared), &[(1, 2), (1, 3), (1, 4)], |requests| {
+            requests.iter().map(|(_, tx)| found(*tx)).collect()
+        });
+        let results: Vec<_> = results.into_iter().map(|result| result.ok()).collect();
+        assert_eq!(
+            results,
+            vec![
+                found(2).ok(),
+                shared.may_contain(3).then(|| found(3).unwrap()),
+                found(4).ok()
+            ]
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Client segments, e.g. `retail` or `business`, joined into the report from a `client,segment` file given by
+//! `--segments`. The report gets an extra `segment` column and subtotals of every segment can be written by
+//! `--segment-totals`, see [crate::report::write_segment_totals]
+use crate::aliases::*;
+use eyre::{eyre, Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Segment of every listed client, clients which are not listed have no segment
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Segments(HashMap<ClientID, String>);
+
+impl Segments {
+    /// Reads `client,segment` lines, the header is optional and `#` comments are skipped. Segments can't contain
+    /// characters which would need quoting in the report
+    pub fn load(path: &Path) -> Result<Segments> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .trim(csv::Trim::All)
+            .comment(Some(b'#'))
+            .from_path(path)
+            .wrap_err_with(|| format!("failed to open segments {}", path.display()))?;
+
+        let mut segments = HashMap::new();
+        for (index, record) in reader.records().enumerate() {
+            let record = record.wrap_err_with(|| format!("failed to read {}", path.display()))?;
+            let (client, segment) = (
+                record.get(0).unwrap_or_default(),
+                record.get(1).unwrap_or_default(),
+            );
+            if index == 0 && matches!(client, "client" | "client_id") {
+                continue;
+            }
+            let context = || format!("malformed line {} of {}", index + 1, path.display());
+            let client_id = client.parse::<ClientID>().wrap_err_with(context)?;
+            if segment.contains([',', '"', '\n', '\r']) {
+                return Err(eyre!("segment '{segment}' can't contain commas nor quotes")
+                    .wrap_err(context()));
+            }
+            if segments.insert(client_id, segment.to_string()).is_some() {
+                return Err(eyre!("client {client_id} is in {} twice", path.display()));
+            }
+        }
+        Ok(Segments(segments))
+    }
+
+    pub fn get(&self, client_id: ClientID) -> Option<&str> {
+        self.0.get(&client_id).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let path =
+            std::env::temp_dir().join(format!("tren-test-segments-{}.csv", std::process::id()));
+        let tests = vec![
+            (
+                "with header",
+                "client,segment\n1,retail\n# vip clients\n2, business \n",
+                Some(vec![(1, Some("retail")), (2, Some("business")), (3, None)]),
+            ),
+            (
+                "without header",
+                "1,retail\n",
+                Some(vec![(1, Some("retail"))]),
+            ),
+            (
+                "crlf",
+                "client,segment\r\n1,retail\r\n# vip\r\n2,business\r\n",
+                Some(vec![(1, Some("retail")), (2, Some("business"))]),
+            ),
+            ("duplicate client", "1,retail\n1,business\n", None),
+            ("invalid client", "1,retail\nx,business\n", None),
+            ("quoted segment", "1,\"retail, eu\"\n", None),
+        ];
+
+        for (name, content, want) in tests {
+            std::fs::write(&path, content).unwrap();
+            let segments = Segments::load(&path);
+            let got = segments.as_ref().ok().map(|segments| {
+                want.iter()
+                    .flatten()
+                    .map(|(client_id, _)| (*client_id, segments.get(*client_id)))
+                    .collect::<Vec<_>>()
+            });
+            assert_eq!(got, want, "failed test {name}");
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
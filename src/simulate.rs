@@ -0,0 +1,244 @@
+//! What-if analysis of disputes which were not filed yet. The journal is processed as usual and then
+//! the hypothetical disputes, resolves and chargebacks are applied on top of the final accounts,
+//! disputed transactions are looked up in the same journal. Only clients whose accounts changed are reported.
+//! `tren preview-dispute` looks at a single transaction instead, it shows the client's account after each step
+//! of its dispute
+use crate::accounts::{AccountError, AccountView, Accounts, DisputePreview};
+use crate::aliases::*;
+use crate::binary::BinaryParser;
+use crate::config::Config;
+use crate::dispute_look_up::{BoxedResolver, DisputeResolver};
+use crate::engine::Engine;
+use crate::format::FormatRegistry;
+use crate::parser::{CsvParser, JournalEntry};
+use crate::pipeline;
+use crate::summary::Summary;
+use crate::webhook::WebhookConfig;
+use eyre::{eyre, Context, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+/// Account of the client before and after the hypothetical disputes were applied
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Impact {
+    pub client_id: ClientID,
+    pub before: AccountView,
+    pub after: AccountView,
+}
+
+/// Processes the journal and applies the disputes on top of it. Returns impact on every changed account,
+/// sorted by client, and the counters of the simulated disputes
+pub fn simulate(journal: &Path, disputes: &Path, config: Config) -> Result<(Vec<Impact>, Summary)> {
+    let resolver = open_resolver(journal, &config)?;
+    let (accounts, _) = pipeline::run(journal, config)?;
+    let before: HashMap<_, _> = accounts
+        .iter()
+        .map(|account| (account.client_id, account))
+        .collect();
+
+    let (accounts, summary) = apply_disputes(accounts, resolver, disputes)?;
+    let mut impacts: Vec<_> = accounts
+        .iter()
+        .filter_map(|after| {
+            let before = before.get(&after.client_id)?;
+            (*before != after).then_some(Impact {
+                client_id: after.client_id,
+                before: *before,
+                after,
+            })
+        })
+        .collect();
+    impacts.sort_unstable_by_key(|impact| impact.client_id);
+    Ok((impacts, summary))
+}
+
+/// Processes the journal and previews dispute of the client's transaction on top of it, see [Accounts::preview_dispute].
+/// Nothing is written, options which write files or send notifications during the processing are ignored.
+/// Returns amount of the transaction with the preview
+pub fn preview_dispute(
+    journal: &Path,
+    client_id: ClientID,
+    transaction_id: TransactionID,
+    mut config: Config,
+) -> Result<(Amount, DisputePreview)> {
+    let (_, _, amount, _, kind) = open_resolver(journal, &config)?
+        .find_transaction(client_id, transaction_id)
+        .wrap_err_with(|| {
+            format!("failed to find transaction {transaction_id} of client {client_id}")
+        })?;
+
+    config.dead_letter = None;
+    config.audit = None;
+    config.report_interval = None;
+    config.report_changes = None;
+    config.webhook = WebhookConfig::default();
+    config.wal = None;
+    config.record_decisions = None;
+    config.since_offset = None;
+    let (mut accounts, _) = pipeline::run(journal, config)?;
+    let preview = accounts
+        .preview_dispute(client_id, transaction_id, amount, kind)
+        .map_err(|err| eyre!("failed to preview dispute of client {client_id}: {err}"))?;
+    Ok((amount, preview))
+}
+
+/// Disputes refer only to the transactions of the journal, so they can't create any account
+fn apply_disputes(
+    accounts: Accounts,
+    resolver: BoxedResolver,
+    disputes: &Path,
+) -> Result<(Accounts, Summary)> {
+    let file = File::open(disputes)
+        .wrap_err_with(|| format!("failed to open disputes {}", disputes.display()))?;
+    let mut engine = Engine::new(accounts).with_resolver(resolver);
+    CsvParser::new(file).read_entries(|entry, timestamp| match entry {
+        JournalEntry::DisputeLookUp(_) => {
+            engine.submit(entry, timestamp);
+            Ok(())
+        }
+        JournalEntry::Transaction(_) => Err(eyre!(
+            "simulated disputes can contain only disputes, resolves and chargebacks"
+        )),
+    })?;
+    Ok(engine.finish())
+}
+
+/// Parser of the journal answering look-ups of the disputed transactions
+fn open_resolver(journal: &Path, config: &Config) -> Result<BoxedResolver> {
+    let prepared = FormatRegistry::default().open(journal, config.input_format.as_deref())?;
+    let file = File::open(&prepared.path)
+        .wrap_err_with(|| format!("failed to open journal {}", prepared.path.display()))?;
+    let resolver: BoxedResolver = match prepared.delimiter() {
+        Some(delimiter) => Box::new(
+            CsvParser::new(file)
+                .with_delimiter(delimiter)
+                .with_amount_format(config.journal_amount_format())
+                .with_unordered_input(config.unordered_input),
+        ),
+        None => Box::new(BinaryParser::new(file)?.with_unordered_input(config.unordered_input)),
+    };
+    Ok(resolver)
+}
+
+/// Writes CSV with the balances and the state of every changed account before and after the simulated disputes
+pub fn write_impacts(writer: &mut impl Write, impacts: &[Impact]) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        "client,available_before,available_after,held_before,held_after,total_before,total_after,locked_before,locked_after"
+    )?;
+    for Impact {
+        client_id,
+        before,
+        after,
+    } in impacts
+    {
+        writeln!(
+            writer,
+            "{client_id},{},{},{},{},{},{},{},{}",
+            before.available,
+            after.available,
+            before.held,
+            after.held,
+            before.total,
+            after.total,
+            before.status.is_frozen(),
+            after.status.is_frozen()
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the amount of the disputed transaction and CSV with the account after every step of the dispute,
+/// balances of rejected steps are empty
+pub fn write_preview(
+    writer: &mut impl Write,
+    amount: Amount,
+    preview: &DisputePreview,
+) -> std::io::Result<()> {
+    writeln!(writer, "amount: {amount}")?;
+    writeln!(writer, "step,available,held,total,locked,outcome")?;
+    let steps: [(&str, Result<&AccountView, &AccountError>); 4] = [
+        ("current", Ok(&preview.current)),
+        ("dispute", preview.dispute.as_ref()),
+        ("resolve", preview.resolve.as_ref()),
+        ("chargeback", preview.chargeback.as_ref()),
+    ];
+    for (step, account) in steps {
+        match account {
+            Ok(account) => writeln!(
+                writer,
+                "{step},{},{},{},{},{}",
+                account.available,
+                account.held,
+                account.total,
+                account.status.is_frozen(),
+                if step == "current" { "" } else { "applied" }
+            )?,
+            Err(err) => writeln!(writer, "{step},,,,,\"rejected: {err}\"")?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulate() {
+        let journal =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/05_multiple_clients.csv");
+        let disputes =
+            std::env::temp_dir().join(format!("tren-test-simulate-{}.csv", std::process::id()));
+        std::fs::write(
+            &disputes,
+            "type,client,tx,amount\ndispute,2,2,\nchargeback,2,2,\ndispute,1,1,\ndispute,1,99,\n",
+        )
+        .unwrap();
+        let config = Config {
+            progress: false,
+            ..Default::default()
+        };
+        let got = simulate(&journal, &disputes, config);
+        std::fs::remove_file(&disputes).unwrap();
+
+        let (impacts, _) = got.unwrap();
+        let mut report = Vec::new();
+        write_impacts(&mut report, &impacts).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,available_before,available_after,held_before,held_after,total_before,total_after,locked_before,locked_after\n\
+             1,5,-5,0,10,5,5,false,false\n\
+             2,22.5001,2.5001,0,0,22.5001,2.5001,false,true\n"
+        );
+    }
+
+    #[test]
+    fn test_preview_dispute() {
+        let journal =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/05_multiple_clients.csv");
+        let config = Config {
+            progress: false,
+            ..Default::default()
+        };
+        let (amount, preview) = preview_dispute(&journal, 2, 2, config.clone()).unwrap();
+        let mut written = Vec::new();
+        write_preview(&mut written, amount, &preview).unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "amount: 20\n\
+             step,available,held,total,locked,outcome\n\
+             current,22.5001,0,22.5001,false,\n\
+             dispute,2.5001,20,22.5001,false,applied\n\
+             resolve,22.5001,0,22.5001,false,applied\n\
+             chargeback,2.5001,0,2.5001,true,applied\n"
+        );
+
+        assert!(
+            preview_dispute(&journal, 2, 99, config).is_err(),
+            "missing transaction"
+        );
+    }
+}
@@ -0,0 +1,472 @@
+//! Outputs of the final report set by repeated `--output <kind>:<path>`, e.g.
+//! `--output csv:report.csv --output json:report.json --output stdout`. Every output is a [ReportSink]:
+//! - `csv:<file>` the report as it is printed
+//! - `json:<file>` array of objects with the report columns, amounts and flags are JSON numbers and booleans
+//! - `sql:<file>` script recreating `accounts` table with the report, it can be loaded by `sqlite3` and `psql`
+//! - `stdout` the report printed to stdout, the same as without any output
+//!
+//! Files are compressed like the other reports, see [crate::compress]. Parquet, SQLite and Postgres can't be
+//! written directly, they need drivers which are not part of the build, `sql:<file>` can be loaded into both
+//! databases instead.
+//!
+//! With `--output-errors all-or-nothing` every output is written next to its file first and the files are moved
+//! into place only once all of them were written, so a failed output leaves none of them behind. With
+//! `best-effort` every output is written on its own and failures are only logged
+use crate::accounts::Accounts;
+use crate::compress::{CompressedWriter, Compression};
+use crate::run_manifest::quote;
+use eyre::{eyre, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::{error, info};
+
+/// Output of the final report given by `--output`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Output {
+    Csv(PathBuf),
+    Json(PathBuf),
+    Sql(PathBuf),
+    Stdout,
+}
+
+impl FromStr for Output {
+    type Err = eyre::Report;
+
+    /// Parses `<kind>:<path>` or `stdout`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "stdout" {
+            return Ok(Output::Stdout);
+        }
+        let (kind, path) = s
+            .split_once(':')
+            .filter(|(_, path)| !path.is_empty())
+            .ok_or(eyre!(
+                "invalid output '{s}', expected <kind>:<path> or stdout"
+            ))?;
+        match kind {
+            "csv" => Ok(Output::Csv(path.into())),
+            "json" => Ok(Output::Json(path.into())),
+            "sql" => Ok(Output::Sql(path.into())),
+            "parquet" | "sqlite" | "postgres" => Err(eyre!(
+                "output '{kind}' is not supported by this build, write sql:<file> and load it by sqlite3 or psql"
+            )),
+            _ => Err(eyre!(
+                "invalid output '{kind}', expected one of csv, json, sql, stdout"
+            )),
+        }
+    }
+}
+
+impl Output {
+    /// File the output writes, `None` for stdout
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Output::Csv(path) | Output::Json(path) | Output::Sql(path) => Some(path),
+            Output::Stdout => None,
+        }
+    }
+
+    /// Sink writing the output, files are compressed by `compression` or by their extension
+    pub fn sink(&self, compression: Option<Compression>) -> Box<dyn ReportSink> {
+        let file = |format: Format, path: &Path| {
+            Box::new(FileSink {
+                path: path.to_path_buf(),
+                format,
+                compression: Compression::resolve(compression, path),
+                staged: None,
+            }) as Box<dyn ReportSink>
+        };
+        match self {
+            Output::Csv(path) => file(Format::Csv, path),
+            Output::Json(path) => file(Format::Json, path),
+            Output::Sql(path) => file(Format::Sql, path),
+            Output::Stdout => Box::<StdoutSink>::default(),
+        }
+    }
+}
+
+/// What happens with the other outputs when one of them fails
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputErrors {
+    /// No output is written and the run fails
+    #[default]
+    AllOrNothing,
+    /// The other outputs are written, the failure is logged
+    BestEffort,
+}
+
+impl FromStr for OutputErrors {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all-or-nothing" => Ok(OutputErrors::AllOrNothing),
+            "best-effort" => Ok(OutputErrors::BestEffort),
+            _ => Err(eyre!(
+                "invalid output errors '{s}', expected one of all-or-nothing, best-effort"
+            )),
+        }
+    }
+}
+
+/// Destination of the final report. The report is staged first and made visible by [ReportSink::commit],
+/// so all sinks can be dropped if one of them fails
+pub trait ReportSink {
+    /// Name of the sink in logs and errors
+    fn name(&self) -> String;
+
+    /// Writes the report where it is not visible yet
+    fn stage(&mut self, accounts: &Accounts) -> Result<()>;
+
+    /// Makes the staged report visible
+    fn commit(&mut self) -> Result<()>;
+
+    /// Drops the staged report
+    fn abort(&mut self);
+}
+
+/// Format of the report written by [FileSink]
+#[derive(Clone, Copy, Debug)]
+enum Format {
+    Csv,
+    Json,
+    Sql,
+}
+
+/// Writes the report into file, it is staged in a temporary file next to it
+struct FileSink {
+    path: PathBuf,
+    format: Format,
+    compression: Compression,
+    /// Temporary file with the staged report
+    staged: Option<PathBuf>,
+}
+
+impl ReportSink for FileSink {
+    fn name(&self) -> String {
+        let kind = match self.format {
+            Format::Csv => "csv",
+            Format::Json => "json",
+            Format::Sql => "sql",
+        };
+        format!("{kind}:{}", self.path.display())
+    }
+
+    fn stage(&mut self, accounts: &Accounts) -> Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        self.staged = Some(tmp.clone());
+
+        let mut writer = CompressedWriter::create(&tmp, self.compression)?;
+        match self.format {
+            Format::Csv => accounts.write_report(&mut writer)?,
+            Format::Json => write_json(&csv_report(accounts)?, &mut writer)?,
+            Format::Sql => write_sql(&csv_report(accounts)?, &mut writer)?,
+        }
+        writer.finish()
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let staged = self.staged.take().ok_or(eyre!("report was not staged"))?;
+        std::fs::rename(&staged, &self.path)
+            .wrap_err_with(|| format!("failed to move report into {}", self.path.display()))
+    }
+
+    fn abort(&mut self) {
+        if let Some(staged) = self.staged.take() {
+            let _ = std::fs::remove_file(staged);
+        }
+    }
+}
+
+/// Prints the report, it is held in memory until it is committed
+#[derive(Default)]
+struct StdoutSink {
+    staged: Option<Vec<u8>>,
+}
+
+impl ReportSink for StdoutSink {
+    fn name(&self) -> String {
+        "stdout".to_string()
+    }
+
+    fn stage(&mut self, accounts: &Accounts) -> Result<()> {
+        self.staged = Some(csv_report(accounts)?);
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let staged = self.staged.take().ok_or(eyre!("report was not staged"))?;
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&staged)?;
+        Ok(stdout.flush()?)
+    }
+
+    fn abort(&mut self) {
+        self.staged = None;
+    }
+}
+
+fn csv_report(accounts: &Accounts) -> Result<Vec<u8>> {
+    let mut report = Vec::new();
+    accounts.write_report(&mut report)?;
+    Ok(report)
+}
+
+/// Writes the report into all outputs, returns the written files. See [OutputErrors] for what happens when
+/// one of them fails
+pub fn write_outputs(
+    accounts: &Accounts,
+    outputs: &[Output],
+    compression: Option<Compression>,
+    errors: OutputErrors,
+) -> Result<Vec<PathBuf>> {
+    let mut sinks: Vec<_> = outputs
+        .iter()
+        .map(|output| (output.sink(compression), output.path()))
+        .collect();
+    let mut written = Vec::new();
+
+    match errors {
+        OutputErrors::AllOrNothing => {
+            let staged = sinks.iter_mut().try_for_each(|(sink, _)| {
+                sink.stage(accounts)
+                    .wrap_err_with(|| format!("failed to write output {}", sink.name()))
+            });
+            if let Err(err) = staged {
+                sinks.iter_mut().for_each(|(sink, _)| sink.abort());
+                return Err(err);
+            }
+            for (sink, path) in sinks.iter_mut() {
+                sink.commit()
+                    .wrap_err_with(|| format!("failed to write output {}", sink.name()))?;
+                written.extend(path.map(Path::to_path_buf));
+            }
+        }
+        OutputErrors::BestEffort => {
+            for (sink, path) in sinks.iter_mut() {
+                match sink.stage(accounts).and_then(|()| sink.commit()) {
+                    Ok(()) => written.extend(path.map(Path::to_path_buf)),
+                    Err(err) => {
+                        sink.abort();
+                        error!(%err, output = sink.name(), "failed to write output");
+                    }
+                }
+            }
+        }
+    }
+    info!(
+        outputs = outputs.len(),
+        files = written.len(),
+        "written outputs"
+    );
+    Ok(written)
+}
+
+/// Value of report field, amounts and flags keep their type in JSON and SQL
+enum Value<'a> {
+    Number(&'a str),
+    Bool(bool),
+    Text(&'a str),
+    Null,
+}
+
+impl<'a> Value<'a> {
+    fn of(field: &'a str) -> Value<'a> {
+        match field {
+            "" => Value::Null,
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            field if field.parse::<f64>().is_ok_and(f64::is_finite) => Value::Number(field),
+            field => Value::Text(field),
+        }
+    }
+}
+
+/// Reads the rows of the CSV report with its header
+fn read_report(report: &[u8]) -> Result<(csv::StringRecord, Vec<csv::StringRecord>)> {
+    let mut reader = csv::Reader::from_reader(report);
+    let headers = reader.headers()?.clone();
+    let rows = reader
+        .records()
+        .collect::<Result<_, _>>()
+        .wrap_err("failed to read report")?;
+    Ok((headers, rows))
+}
+
+/// Writes the CSV report as JSON array of objects, one per row
+fn write_json(report: &[u8], writer: &mut impl Write) -> Result<()> {
+    let (headers, rows) = read_report(report)?;
+    writeln!(writer, "[")?;
+    for (index, row) in rows.iter().enumerate() {
+        let fields: Vec<_> = headers
+            .iter()
+            .zip(row.iter())
+            .map(|(header, field)| {
+                let value = match Value::of(field) {
+                    Value::Number(number) => number.to_string(),
+                    Value::Bool(flag) => flag.to_string(),
+                    Value::Text(text) => quote(text),
+                    Value::Null => "null".to_string(),
+                };
+                format!("{}: {value}", quote(header))
+            })
+            .collect();
+        let separator = if index + 1 < rows.len() { "," } else { "" };
+        writeln!(writer, "  {{{}}}{separator}", fields.join(", "))?;
+    }
+    writeln!(writer, "]")?;
+    Ok(())
+}
+
+/// Writes the CSV report as SQL script replacing `accounts` table. Column is numeric or boolean if all its
+/// values are, text otherwise
+fn write_sql(report: &[u8], writer: &mut impl Write) -> Result<()> {
+    let (headers, rows) = read_report(report)?;
+    let columns: Vec<_> = headers
+        .iter()
+        .enumerate()
+        .map(|(column, header)| {
+            let values = rows
+                .iter()
+                .map(|row| Value::of(row.get(column).unwrap_or_default()));
+            let mut kinds = values
+                .filter(|value| !matches!(value, Value::Null))
+                .map(|value| match value {
+                    Value::Number(_) => "NUMERIC",
+                    Value::Bool(_) => "BOOLEAN",
+                    _ => "TEXT",
+                });
+            let first = kinds.next().unwrap_or("TEXT");
+            let kind = if kinds.all(|kind| kind == first) {
+                first
+            } else {
+                "TEXT"
+            };
+            format!("{header} {kind}")
+        })
+        .collect();
+
+    writeln!(writer, "BEGIN;")?;
+    writeln!(writer, "DROP TABLE IF EXISTS accounts;")?;
+    writeln!(writer, "CREATE TABLE accounts ({});", columns.join(", "))?;
+    for row in rows.iter() {
+        let values: Vec<_> = row
+            .iter()
+            .zip(columns.iter())
+            .map(|(field, column)| match Value::of(field) {
+                Value::Number(number) if column.ends_with("NUMERIC") => number.to_string(),
+                Value::Bool(flag) if column.ends_with("BOOLEAN") => flag.to_string().to_uppercase(),
+                Value::Null => "NULL".to_string(),
+                _ => format!("'{}'", field.replace('\'', "''")),
+            })
+            .collect();
+        writeln!(
+            writer,
+            "INSERT INTO accounts VALUES ({});",
+            values.join(", ")
+        )?;
+    }
+    writeln!(writer, "COMMIT;")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::DisputePolicy;
+
+    #[test]
+    fn test_write_outputs() {
+        let dir = std::env::temp_dir();
+        let path = |name: &str| dir.join(format!("tren-test-sink-{}-{name}", std::process::id()));
+        let (csv, json, sql) = (path("report.csv"), path("report.json"), path("report.sql"));
+        // directory which doesn't exist can't be written into
+        let broken = Output::Csv(path("missing").join("report.csv"));
+        let mut accounts = Accounts::new(DisputePolicy::default());
+        accounts.deposit(1, amount!(10)).unwrap();
+        accounts.deposit(1, amount!(0.5)).unwrap();
+        let outputs = [
+            Output::Csv(csv.clone()),
+            Output::Json(json.clone()),
+            Output::Sql(sql.clone()),
+        ];
+
+        let tests = vec![
+            (
+                "all-or-nothing",
+                OutputErrors::AllOrNothing,
+                vec![broken.clone()],
+                None,
+            ),
+            (
+                "best-effort",
+                OutputErrors::BestEffort,
+                vec![broken.clone()],
+                Some(vec![csv.clone(), json.clone(), sql.clone()]),
+            ),
+            (
+                "all written",
+                OutputErrors::AllOrNothing,
+                vec![],
+                Some(vec![csv.clone(), json.clone(), sql.clone()]),
+            ),
+        ];
+
+        for (name, errors, extra, want) in tests {
+            let outputs: Vec<_> = outputs.iter().cloned().chain(extra).collect();
+            let got = write_outputs(&accounts, &outputs, None, errors).ok();
+            assert_eq!(got, want, "failed test {name}");
+            let exist = [&csv, &json, &sql].map(|path| path.exists());
+            assert_eq!(exist, [want.is_some(); 3], "failed test {name}");
+            if want.is_some() {
+                assert_eq!(
+                    std::fs::read_to_string(&json).unwrap(),
+                    "[\n  {\"client\": 1, \"available\": 10.5, \"held\": 0, \"total\": 10.5, \
+                     \"locked\": false}\n]\n",
+                    "failed test {name}"
+                );
+                assert_eq!(
+                    std::fs::read_to_string(&sql).unwrap(),
+                    "BEGIN;\nDROP TABLE IF EXISTS accounts;\n\
+                     CREATE TABLE accounts (client NUMERIC, available NUMERIC, held NUMERIC, total NUMERIC, \
+                     locked BOOLEAN);\n\
+                     INSERT INTO accounts VALUES (1, 10.5, 0, 10.5, FALSE);\nCOMMIT;\n",
+                    "failed test {name}"
+                );
+                for path in [&csv, &json, &sql] {
+                    std::fs::remove_file(path).unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_output() {
+        let tests = vec![
+            (
+                "csv",
+                "csv:report.csv",
+                Some(Output::Csv("report.csv".into())),
+            ),
+            (
+                "windows path",
+                "json:C:\\out\\report.json",
+                Some(Output::Json("C:\\out\\report.json".into())),
+            ),
+            ("stdout", "stdout", Some(Output::Stdout)),
+            ("missing path", "sql:", None),
+            ("unsupported", "sqlite:accounts.db", None),
+            ("unknown", "xml:report.xml", None),
+        ];
+
+        for (name, input, want) in tests {
+            assert_eq!(input.parse::<Output>().ok(), want, "failed test {name}");
+        }
+    }
+}
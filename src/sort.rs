@@ -0,0 +1,274 @@
+use crate::format::FormatRegistry;
+use crate::timestamp::parse_timestamp;
+use csv::ByteRecord;
+use eyre::{eyre, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::str::{from_utf8, FromStr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::{debug, info};
+
+/// What the journal is sorted by. Records with the same key keep their order from the journal,
+/// so disputes stay after the transactions they refer to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    /// Transaction ID, the order expected by the dispute look-up
+    #[default]
+    Tx,
+    /// Client ID
+    Client,
+    /// Timestamp, records without one go first
+    Timestamp,
+}
+
+impl FromStr for SortKey {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tx" => Ok(SortKey::Tx),
+            "client" => Ok(SortKey::Client),
+            "timestamp" => Ok(SortKey::Timestamp),
+            _ => Err(eyre!(
+                "invalid sort key '{s}', expected one of tx, client, timestamp"
+            )),
+        }
+    }
+}
+
+/// Sorts the journal into CSV file at `output` with external merge sort. At most `buffer` records are held
+/// in memory, sorted runs of that size are written into temporary files and merged. Records are copied as
+/// they are, only the delimiter of the output is always comma. Returns number of sorted records
+pub fn sort(
+    input: &Path,
+    output: &Path,
+    formats: &FormatRegistry,
+    input_format: Option<&str>,
+    key: SortKey,
+    buffer: usize,
+) -> Result<u64> {
+    let journal = formats.open(input, input_format)?;
+    let delimiter = journal
+        .delimiter()
+        .ok_or(eyre!("binary journal {} can't be sorted", input.display()))?;
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .delimiter(delimiter)
+        .from_path(&journal.path)
+        .wrap_err_with(|| format!("failed to open journal {}", input.display()))?;
+    let headers = reader
+        .byte_headers()
+        .wrap_err("failed to read journal header")?
+        .clone();
+    let column = match key {
+        SortKey::Tx => 2,
+        SortKey::Client => 1,
+        SortKey::Timestamp => headers
+            .iter()
+            .position(|header| header.trim_ascii() == b"timestamp")
+            .ok_or(eyre!("journal has no timestamp column to sort by"))?,
+    };
+
+    let mut runs = Vec::new();
+    let mut records = Vec::new();
+    let mut count = 0;
+    for record in reader.byte_records() {
+        let record = record.wrap_err("failed to read journal")?;
+        let sort_key = parse_key(&record, column, key)
+            .wrap_err_with(|| format!("malformed record {count}"))?;
+        records.push((sort_key, count, record));
+        count += 1;
+        if records.len() >= buffer.max(1) {
+            runs.push(Run::write(runs.len(), &mut records)?);
+        }
+    }
+
+    let mut writer = csv::WriterBuilder::new()
+        .flexible(true)
+        .from_path(output)
+        .wrap_err_with(|| format!("failed to create {}", output.display()))?;
+    writer.write_byte_record(&headers)?;
+    match runs.is_empty() {
+        // whole journal fits into the buffer
+        true => {
+            records.sort_unstable_by_key(|(sort_key, index, _)| (*sort_key, *index));
+            for (_, _, record) in records.iter() {
+                writer.write_byte_record(record)?;
+            }
+        }
+        false => {
+            if !records.is_empty() {
+                runs.push(Run::write(runs.len(), &mut records)?);
+            }
+            merge(&mut runs, column, key, &mut writer)?;
+        }
+    }
+    writer.flush().wrap_err("failed to write sorted journal")?;
+
+    info!(%count, runs = runs.len(), output = %output.display(), "sorted journal");
+    Ok(count)
+}
+
+/// Writes the smallest record of all runs until all of them are empty
+fn merge(
+    runs: &mut [Run],
+    column: usize,
+    key: SortKey,
+    writer: &mut csv::Writer<File>,
+) -> Result<()> {
+    let mut heads = BinaryHeap::new();
+    let mut records = vec![ByteRecord::new(); runs.len()];
+    for (run, record) in runs.iter_mut().zip(records.iter_mut()) {
+        if let Some(index) = run.next(record)? {
+            heads.push(Reverse((parse_key(record, column, key)?, index, run.id)));
+        }
+    }
+
+    while let Some(Reverse((_, _, id))) = heads.pop() {
+        writer.write_byte_record(&records[id])?;
+        if let Some(index) = runs[id].next(&mut records[id])? {
+            heads.push(Reverse((parse_key(&records[id], column, key)?, index, id)));
+        }
+    }
+    Ok(())
+}
+
+/// Key of the record, IDs and timestamps all fit into `i128`. Missing or empty value sorts first,
+/// locks and other records without transaction ID have no tx
+fn parse_key(record: &ByteRecord, column: usize, key: SortKey) -> Result<i128> {
+    let value = match record.get(column).map(|value| value.trim_ascii()) {
+        None | Some(b"") => return Ok(i128::MIN),
+        Some(value) => from_utf8(value).wrap_err("failed to parse sort key")?,
+    };
+    match key {
+        SortKey::Tx | SortKey::Client => Ok(value
+            .parse::<u64>()
+            .wrap_err_with(|| format!("failed to parse {key:?} ID '{value}'"))?
+            .into()),
+        SortKey::Timestamp => Ok(parse_timestamp(value)?.into()),
+    }
+}
+
+/// Sorted part of the journal in temporary file, every record is prefixed with its index in the journal.
+/// The file is removed once the run is dropped
+struct Run {
+    id: usize,
+    path: PathBuf,
+    reader: Option<csv::Reader<File>>,
+}
+
+impl Run {
+    /// Sorts and drains the records into new run, `id` is its position among the runs
+    fn write(id: usize, records: &mut Vec<(i128, u64, ByteRecord)>) -> Result<Run> {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        let number = RUNS.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!(
+            "{}-sort-{}-{number}.csv",
+            env!("CARGO_PKG_NAME"),
+            std::process::id(),
+        ));
+        let mut run = Run {
+            id,
+            path,
+            reader: None,
+        };
+
+        records.sort_unstable_by_key(|(sort_key, index, _)| (*sort_key, *index));
+        let mut writer = csv::WriterBuilder::new()
+            .flexible(true)
+            .from_path(&run.path)
+            .wrap_err_with(|| format!("failed to create sort run {}", run.path.display()))?;
+        let mut prefixed = ByteRecord::new();
+        for (_, index, record) in records.drain(..) {
+            prefixed.clear();
+            prefixed.push_field(index.to_string().as_bytes());
+            prefixed.extend(record.iter());
+            writer.write_byte_record(&prefixed)?;
+        }
+        writer.flush().wrap_err("failed to write sort run")?;
+        debug!(path = %run.path.display(), "wrote sorted run");
+
+        run.reader = Some(
+            csv::ReaderBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .from_path(&run.path)?,
+        );
+        Ok(run)
+    }
+
+    /// Reads next record of the run, returns its index in the journal or `None` at the end of the run
+    fn next(&mut self, record: &mut ByteRecord) -> Result<Option<u64>> {
+        let reader = self.reader.as_mut().expect("run is opened once written");
+        let mut prefixed = ByteRecord::new();
+        if !reader.read_byte_record(&mut prefixed)? {
+            return Ok(None);
+        }
+        let index = from_utf8(&prefixed[0])?.parse()?;
+        record.clear();
+        record.extend(prefixed.iter().skip(1));
+        Ok(Some(index))
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sort() {
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("tren-test-sort-{}.csv", std::process::id()));
+        let output = dir.join(format!("tren-test-sorted-{}.csv", std::process::id()));
+        std::fs::write(
+            &input,
+            "type,client,tx,amount,to_client,timestamp\n\
+             deposit,2,3,1.0,,2022-08-03\n\
+             deposit,1,1,2.0,,2022-08-02\n\
+             dispute,2,3,,,2022-08-04\n\
+             withdrawal,1,2,0.5,,2022-08-01\n\
+             lock,1,,,,2022-08-05\n",
+        )
+        .unwrap();
+
+        let tests = vec![
+            ("tx", SortKey::Tx, 10, vec![4, 1, 3, 0, 2]),
+            ("tx in runs", SortKey::Tx, 2, vec![4, 1, 3, 0, 2]),
+            ("client", SortKey::Client, 1, vec![1, 3, 4, 0, 2]),
+            ("timestamp", SortKey::Timestamp, 3, vec![3, 1, 0, 2, 4]),
+        ];
+        let lines = std::fs::read_to_string(&input).unwrap();
+        let lines: Vec<&str> = lines.lines().skip(1).collect();
+        for (name, key, buffer, want) in tests {
+            let count = sort(
+                &input,
+                &output,
+                &FormatRegistry::default(),
+                None,
+                key,
+                buffer,
+            )
+            .unwrap_or_else(|err| panic!("failed test {name}: {err:?}"));
+            assert_eq!(count, 5, "failed test {name}");
+            let want: Vec<&str> = want.into_iter().map(|i| lines[i]).collect();
+            let got = std::fs::read_to_string(&output).unwrap();
+            assert_eq!(
+                got.lines().skip(1).collect::<Vec<_>>(),
+                want,
+                "failed test {name}"
+            );
+        }
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&output).unwrap();
+    }
+}
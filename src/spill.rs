@@ -0,0 +1,292 @@
+use crate::aliases::*;
+use crate::amount::{from_decimal, into_decimal};
+use crate::channel::{DisputeLookUpMessage, Indexed};
+use crate::memory::{self, Component, MemoryBudget};
+use eyre::{eyre, Context, Result};
+use rust_decimal::Decimal;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use tracing::{debug, error};
+
+/// Size of single spilled message: record index, kind, client, tx, timestamp flag, timestamp, amount flag and
+/// amount of partial dispute
+const RECORD_SIZE: usize = 8 + 1 + 8 + 8 + 1 + 8 + 1 + 16;
+
+/// Threshold of the channel while the estimated memory is over `--max-memory`, see [crate::memory]
+const MEMORY_PRESSURE_THRESHOLD: usize = 1024;
+
+/// Sender of dispute look-up requests which keeps the unbounded channel from growing without limit.
+/// Once the channel holds more than `threshold` messages, new messages are appended to temporary file
+/// and replayed into the channel in the same order once it drains below half of the threshold.
+/// Without threshold it just forwards messages into the channel. With [MemoryBudget] the threshold is lowered
+/// to [MEMORY_PRESSURE_THRESHOLD] while the estimated memory is over the limit.
+pub struct SpillingSender {
+    sender: crossbeam_channel::Sender<Indexed<DisputeLookUpMessage>>,
+    threshold: Option<usize>,
+    memory: Option<MemoryBudget>,
+    spill: Option<SpillFile>,
+    /// Number of messages which went through the spill file
+    spilled: u64,
+}
+
+struct SpillFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    reader: BufReader<File>,
+    /// Messages written but not yet replayed
+    pending: usize,
+}
+
+impl SpillingSender {
+    pub fn new(
+        sender: crossbeam_channel::Sender<Indexed<DisputeLookUpMessage>>,
+        threshold: Option<usize>,
+    ) -> Self {
+        SpillingSender {
+            sender,
+            threshold,
+            memory: None,
+            spill: None,
+            spilled: 0,
+        }
+    }
+
+    pub fn with_memory_budget(mut self, memory: Option<MemoryBudget>) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    /// Threshold of the channel, spilled messages are replayed with [MEMORY_PRESSURE_THRESHOLD] even without one
+    fn threshold(&self) -> Option<usize> {
+        let pending = self.spill.as_ref().is_some_and(|spill| spill.pending > 0);
+        match (self.threshold, memory::is_over(self.memory.as_ref())) {
+            (Some(threshold), true) => Some(threshold.min(MEMORY_PRESSURE_THRESHOLD)),
+            (None, true) => Some(MEMORY_PRESSURE_THRESHOLD),
+            (None, false) if pending => Some(MEMORY_PRESSURE_THRESHOLD),
+            (threshold, false) => threshold,
+        }
+    }
+
+    /// Sends message into the channel or into the spill file if the channel is over the threshold.
+    /// Errors are handled internally the same way as in [crate::channel::Sender]
+    pub fn send(&mut self, message: Indexed<DisputeLookUpMessage>) {
+        if let Err(err) = self.try_send(message) {
+            error!(%err, "failed to send dispute look-up message");
+        }
+    }
+
+    fn try_send(&mut self, message: Indexed<DisputeLookUpMessage>) -> Result<()> {
+        if let Some(memory) = self.memory.as_ref() {
+            memory.record(
+                Component::DisputeQueue,
+                memory::queue_bytes::<Indexed<DisputeLookUpMessage>>(self.sender.len()),
+            );
+        }
+        let Some(threshold) = self.threshold() else {
+            return self.forward(message);
+        };
+
+        if self.spill.as_ref().is_some_and(|spill| spill.pending > 0)
+            && self.sender.len() <= threshold / 2
+        {
+            self.replay(threshold)?;
+        }
+
+        // once anything is spilled, following messages have to be spilled too to keep the order
+        if self.sender.len() >= threshold || self.spill.as_ref().is_some_and(|s| s.pending > 0) {
+            if self.spill.is_none() {
+                self.spill = Some(SpillFile::create()?);
+            }
+            self.spilled += 1;
+            return self
+                .spill
+                .as_mut()
+                .expect("spill file was just created")
+                .write(&message);
+        }
+
+        self.forward(message)
+    }
+
+    fn forward(&self, message: Indexed<DisputeLookUpMessage>) -> Result<()> {
+        self.sender
+            .send(message)
+            .wrap_err("failed to send message over channel")
+    }
+
+    /// Moves spilled messages back into the channel until it reaches the threshold
+    fn replay(&mut self, threshold: usize) -> Result<()> {
+        if let Some(spill) = self.spill.as_mut() {
+            spill
+                .writer
+                .flush()
+                .wrap_err("failed to flush spill file")?;
+            while spill.pending > 0 && self.sender.len() < threshold {
+                let message = spill.read()?;
+                self.sender
+                    .send(message)
+                    .wrap_err("failed to send message over channel")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replays all remaining spilled messages, waiting for the channel to drain so it stays within the threshold.
+    /// Has to be called once all messages were sent. Returns how many messages went through the spill file
+    pub fn finish(mut self) -> u64 {
+        let spilled = self.spill.is_some().then_some(MEMORY_PRESSURE_THRESHOLD);
+        let Some(threshold) = self.threshold.or(spilled) else {
+            return self.spilled;
+        };
+
+        while self.spill.as_ref().is_some_and(|spill| spill.pending > 0) {
+            if let Err(err) = self.replay(threshold) {
+                error!(%err, "failed to replay spilled dispute look-up messages");
+                break;
+            }
+            std::thread::yield_now();
+        }
+        debug!(
+            spilled = self.spilled,
+            "finished replaying spilled messages"
+        );
+        self.spilled
+    }
+}
+
+impl SpillFile {
+    fn create() -> Result<SpillFile> {
+        let path = std::env::temp_dir().join(format!(
+            "{}-dispute-spill-{}.bin",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        let writer = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .wrap_err_with(|| format!("failed to create spill file {}", path.display()))?;
+        let reader = File::open(&path)
+            .wrap_err_with(|| format!("failed to open spill file {}", path.display()))?;
+        debug!(path = %path.display(), "dispute look-up queue is full, spilling to disk");
+
+        Ok(SpillFile {
+            path,
+            writer: BufWriter::new(writer),
+            reader: BufReader::new(reader),
+            pending: 0,
+        })
+    }
+
+    fn write(&mut self, message: &Indexed<DisputeLookUpMessage>) -> Result<()> {
+        self.writer
+            .write_all(&encode(message))
+            .wrap_err("failed to write into spill file")?;
+        self.pending += 1;
+        Ok(())
+    }
+
+    fn read(&mut self) -> Result<Indexed<DisputeLookUpMessage>> {
+        let mut record = [0; RECORD_SIZE];
+        self.reader
+            .read_exact(&mut record)
+            .wrap_err("failed to read from spill file")?;
+        self.pending -= 1;
+        decode(&record)
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            error!(%err, "failed to remove spill file");
+        }
+    }
+}
+
+// IDs are stored as u64 so the format doesn't depend on `wide-ids` feature
+#[allow(clippy::useless_conversion)]
+fn encode(indexed: &Indexed<DisputeLookUpMessage>) -> [u8; RECORD_SIZE] {
+    let message = &indexed.message;
+    let (kind, timestamp) = match message {
+        DisputeLookUpMessage::Dispute(_, _, timestamp, _) => (0, *timestamp),
+        DisputeLookUpMessage::Resolve(..) => (1, None),
+        DisputeLookUpMessage::Chargeback(..) => (2, None),
+        DisputeLookUpMessage::Reversal(..) => (3, None),
+    };
+
+    let mut record = [0; RECORD_SIZE];
+    record[..8].copy_from_slice(&indexed.index.to_le_bytes());
+    record[8] = kind;
+    record[9..17].copy_from_slice(&u64::from(message.client_id()).to_le_bytes());
+    record[17..25].copy_from_slice(&u64::from(message.transaction_id()).to_le_bytes());
+    record[25] = u8::from(timestamp.is_some());
+    record[26..34].copy_from_slice(&timestamp.unwrap_or_default().to_le_bytes());
+    let amount = message.amount();
+    record[34] = u8::from(amount.is_some());
+    record[35..].copy_from_slice(&into_decimal(amount.unwrap_or(Amount::ZERO)).serialize());
+    record
+}
+
+#[allow(clippy::useless_conversion)]
+fn decode(record: &[u8; RECORD_SIZE]) -> Result<Indexed<DisputeLookUpMessage>> {
+    let index = u64::from_le_bytes(record[..8].try_into()?);
+    let client_id = ClientID::try_from(u64::from_le_bytes(record[9..17].try_into()?))?;
+    let transaction_id = TransactionID::try_from(u64::from_le_bytes(record[17..25].try_into()?))?;
+    let timestamp = Timestamp::from_le_bytes(record[26..34].try_into()?);
+    let timestamp = (record[25] == 1).then_some(timestamp);
+    let amount = match record[34] {
+        1 => Some(from_decimal(Decimal::deserialize(
+            record[35..].try_into()?,
+        ))?),
+        _ => None,
+    };
+
+    let message = match record[8] {
+        0 => DisputeLookUpMessage::Dispute(client_id, transaction_id, timestamp, amount),
+        1 => DisputeLookUpMessage::Resolve(client_id, transaction_id, amount),
+        2 => DisputeLookUpMessage::Chargeback(client_id, transaction_id, amount),
+        3 => DisputeLookUpMessage::Reversal(client_id, transaction_id),
+        kind => return Err(eyre!("invalid spilled message kind {kind}")),
+    };
+    Ok(Indexed::new(index, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spill_keeps_order() {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let mut sender = SpillingSender::new(sender, Some(2));
+
+        let messages = vec![
+            Indexed::new(
+                0,
+                DisputeLookUpMessage::Dispute(1, 1, Some(1661990399), None),
+            ),
+            Indexed::new(
+                3,
+                DisputeLookUpMessage::Dispute(2, 2, None, Some(amount!(0.25))),
+            ),
+            Indexed::new(4, DisputeLookUpMessage::Resolve(1, 1, None)),
+            Indexed::new(
+                9,
+                DisputeLookUpMessage::Chargeback(2, 2, Some(amount!(0.25))),
+            ),
+        ];
+        for message in messages.iter() {
+            sender.send(message.clone());
+        }
+        assert_eq!(receiver.len(), 2, "channel should stay at the threshold");
+
+        let handle = std::thread::spawn(move || sender.finish());
+        let received: Vec<_> = receiver.iter().take(messages.len()).collect();
+
+        assert_eq!(handle.join().unwrap(), 2);
+        assert_eq!(received, messages);
+    }
+}
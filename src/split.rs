@@ -0,0 +1,190 @@
+//! `tren split <journal> --shards <n> [-o <dir>]` splits the journal into `n` journals by hash of the client, the
+//! same hash as `--partition-output by-hash=<n>`. Every client ends up in a single shard with its rows in their
+//! order from the journal, so the shards can be processed on separate machines and their reports joined by
+//! `tren merge`. Transfers between clients of different shards can't be split and fail the split
+use crate::aliases::*;
+use crate::format::FormatRegistry;
+use crate::parser::Columns;
+use crate::report::Partition;
+use csv::ByteRecord;
+use eyre::{eyre, Context, Result};
+use std::path::{Path, PathBuf};
+use std::str::from_utf8;
+use tracing::info;
+
+/// Splits the journal into `shards` CSV files in `output`, named after the journal, e.g. `journal_0-of-8.csv`.
+/// Every shard has the header of the journal, even if it has no rows. Records are copied as they are, only the
+/// delimiter of the output is always comma and `#` comment lines are dropped. Returns number of rows of every shard
+pub fn split(
+    input: &Path,
+    output: &Path,
+    formats: &FormatRegistry,
+    input_format: Option<&str>,
+    shards: u64,
+) -> Result<Vec<u64>> {
+    if shards == 0 {
+        return Err(eyre!("journal can't be split into 0 shards"));
+    }
+    let journal = formats.open(input, input_format)?;
+    let delimiter = journal
+        .delimiter()
+        .ok_or(eyre!("binary journal {} can't be split", input.display()))?;
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .delimiter(delimiter)
+        .comment(Some(b'#'))
+        .from_path(&journal.path)
+        .wrap_err_with(|| format!("failed to open journal {}", input.display()))?;
+    let headers = reader
+        .byte_headers()
+        .wrap_err("failed to read journal header")?
+        .clone();
+    let to_client = Columns::from_headers(&headers).to_client;
+
+    std::fs::create_dir_all(output)
+        .wrap_err_with(|| format!("failed to create {}", output.display()))?;
+    let mut writers = shard_paths(input, output, shards)
+        .into_iter()
+        .map(|path| {
+            let mut writer = csv::WriterBuilder::new()
+                .flexible(true)
+                .from_path(&path)
+                .wrap_err_with(|| format!("failed to create {}", path.display()))?;
+            writer.write_byte_record(&headers)?;
+            Ok((writer, path))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let partition = Partition::ByHash(shards);
+    let mut counts = vec![0; writers.len()];
+    for (index, record) in reader.byte_records().enumerate() {
+        let record = record.wrap_err("failed to read journal")?;
+        let shard = shard_of(&record, to_client, partition)
+            .wrap_err_with(|| format!("failed to split record {index}"))?;
+        writers[shard].0.write_byte_record(&record)?;
+        counts[shard] += 1;
+    }
+
+    for (mut writer, path) in writers {
+        writer
+            .flush()
+            .wrap_err_with(|| format!("failed to write {}", path.display()))?;
+    }
+    info!(shards, records = counts.iter().sum::<u64>(), output = %output.display(), "split journal");
+    Ok(counts)
+}
+
+/// Shard of the record's client, transfers fail unless the receiving client is in the same shard
+fn shard_of(record: &ByteRecord, to_client: usize, partition: Partition) -> Result<usize> {
+    let shard = partition.of(client_id(record, 1)?);
+    if record.get(0).map(|kind| kind.trim_ascii()) == Some(b"transfer".as_slice()) {
+        let to_shard = partition.of(client_id(record, to_client)?);
+        if to_shard != shard {
+            return Err(eyre!(
+                "transfer goes from shard {shard} to shard {to_shard}, only transfers within a shard can be split"
+            ));
+        }
+    }
+    Ok(shard as usize)
+}
+
+fn client_id(record: &ByteRecord, column: usize) -> Result<ClientID> {
+    let value = record
+        .get(column)
+        .ok_or(eyre!("record is missing client in column {column}"))?;
+    from_utf8(value.trim_ascii())
+        .wrap_err("failed to parse client ID")?
+        .parse()
+        .wrap_err("failed to parse client ID")
+}
+
+/// Paths of the shards [split] writes for the journal
+pub fn shard_paths(input: &Path, output: &Path, shards: u64) -> Vec<PathBuf> {
+    let stem = input
+        .file_stem()
+        .unwrap_or("journal".as_ref())
+        .to_string_lossy();
+    (0..shards)
+        .map(|shard| output.join(format!("{stem}_{shard}-of-{shards}.csv")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("tren-test-split-{}.csv", std::process::id()));
+        let output = dir.join(format!("tren-test-split-{}", std::process::id()));
+        let partition = Partition::ByHash(2);
+        // pick clients in both shards
+        let first = (1..100).find(|client| partition.of(*client) == 0).unwrap();
+        let second = (1..100).find(|client| partition.of(*client) == 1).unwrap();
+
+        let tests = vec![
+            (
+                "rows of every client keep their order",
+                format!(
+                    "type,client,tx,amount\n\
+                     deposit,{first},1,1.0\n\
+                     # comment\n\
+                     deposit, {second} ,2,2.0\n\
+                     dispute,{first},1,\n\
+                     withdrawal,{second},3,1.0\n"
+                ),
+                Some(vec![
+                    vec![
+                        format!("deposit,{first},1,1.0"),
+                        format!("dispute,{first},1,"),
+                    ],
+                    vec![
+                        format!("deposit, {second} ,2,2.0"),
+                        format!("withdrawal,{second},3,1.0"),
+                    ],
+                ]),
+            ),
+            (
+                "transfer within shard",
+                format!("type,client,tx,amount,to_client\ntransfer,{first},1,1.0,{first}\n"),
+                Some(vec![
+                    vec![format!("transfer,{first},1,1.0,{first}")],
+                    vec![],
+                ]),
+            ),
+            (
+                "transfer between shards",
+                format!("type,client,tx,amount,to_client\ntransfer,{first},1,1.0,{second}\n"),
+                None,
+            ),
+            (
+                "malformed client",
+                "type,client,tx,amount\ndeposit,x,1,1.0\n".to_string(),
+                None,
+            ),
+        ];
+
+        for (name, journal, want) in tests {
+            std::fs::write(&input, journal).unwrap();
+            let got = split(&input, &output, &FormatRegistry::default(), None, 2)
+                .ok()
+                .map(|_| {
+                    shard_paths(&input, &output, 2)
+                        .iter()
+                        .map(|path| {
+                            std::fs::read_to_string(path)
+                                .unwrap()
+                                .lines()
+                                .skip(1)
+                                .map(str::to_string)
+                                .collect::<Vec<_>>()
+                        })
+                        .collect::<Vec<_>>()
+                });
+            assert_eq!(got, want, "failed test {name}");
+        }
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+}
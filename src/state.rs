@@ -0,0 +1,259 @@
+//! Snapshot of the exact state of the accounts, written at the end of the run by `--save-state` and loaded as the
+//! starting state of the next run by `--load-state`. Unlike `--initial-state`, which reads the report, snapshot
+//! keeps the funds held by open disputes per transaction, the transaction whose chargeback froze the account and
+//! the reversed transactions, so the next run can resolve, charge back and unfreeze them.
+//!
+//! Snapshot is binary: [MAGIC], version of the format as u16, the accounts and FNV-1a checksum of everything
+//! before it as u64, all little endian. Snapshots of every version up to [VERSION] are loaded. Once the format
+//! changes, the version is increased and [decode] reads the new fields only from snapshots of the new version,
+//! older snapshots get the value accounts had before the field existed
+use crate::accounts::{AccountStatus, Accounts};
+use crate::aliases::*;
+use crate::amount::{from_decimal, into_decimal};
+use crate::manifest::fnv1a;
+use eyre::{eyre, Context, Result};
+use rust_decimal::Decimal;
+use std::io::Write;
+use std::path::Path;
+
+/// Start of every snapshot
+const MAGIC: [u8; 4] = *b"TSTA";
+
+/// Version of the format written by this build
+pub const VERSION: u16 = 1;
+
+/// State of single account in the snapshot
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccountState {
+    pub client_id: ClientID,
+    pub status: AccountStatus,
+    pub flagged: bool,
+    pub total: Amount,
+    pub available: Amount,
+    pub held: Amount,
+    /// Transaction whose chargeback froze the account
+    pub frozen_by: Option<TransactionID>,
+    /// Amounts held by open disputes, `held` is their sum
+    pub holds: Vec<(TransactionID, Amount)>,
+    /// Disputed withdrawals among `holds`
+    pub reversing: Vec<TransactionID>,
+    /// Transactions reversed by reversal records
+    pub reversed: Vec<TransactionID>,
+    /// Deposits and chargebacks since the account was opened, fraud rules keep counting from them
+    pub deposits: u64,
+    pub chargebacks: u64,
+}
+
+/// Writes state of all accounts into the snapshot, ordered by client so equal accounts give equal snapshots
+pub fn save(accounts: &Accounts, path: &Path) -> Result<()> {
+    let mut states = accounts.export_state();
+    states.sort_by_key(|state| state.client_id);
+    let snapshot = encode(&states);
+    let mut file = std::fs::File::create(path)
+        .wrap_err_with(|| format!("failed to create state snapshot {}", path.display()))?;
+    file.write_all(&snapshot)
+        .and_then(|_| file.sync_all())
+        .wrap_err_with(|| format!("failed to write state snapshot {}", path.display()))
+}
+
+/// Loads the snapshot into the accounts, see [Accounts::import_state]. Returns number of loaded accounts
+pub fn load(accounts: &mut Accounts, path: &Path) -> Result<usize> {
+    let snapshot = std::fs::read(path)
+        .wrap_err_with(|| format!("failed to read state snapshot {}", path.display()))?;
+    let states =
+        decode(&snapshot).wrap_err_with(|| format!("invalid state snapshot {}", path.display()))?;
+    accounts.import_state(states)
+}
+
+// IDs are stored as u64 so the format doesn't depend on `wide-ids` feature
+#[allow(clippy::useless_conversion)]
+fn encode(states: &[AccountState]) -> Vec<u8> {
+    let mut snapshot = MAGIC.to_vec();
+    snapshot.extend(VERSION.to_le_bytes());
+    snapshot.extend((states.len() as u64).to_le_bytes());
+    for state in states {
+        snapshot.extend(u64::from(state.client_id).to_le_bytes());
+        snapshot.push(match state.status {
+            AccountStatus::Active => 0,
+            AccountStatus::Frozen => 1,
+            AccountStatus::Closed => 2,
+        });
+        snapshot.push(u8::from(state.flagged));
+        for amount in [state.total, state.available, state.held] {
+            snapshot.extend(into_decimal(amount).serialize());
+        }
+        snapshot.push(u8::from(state.frozen_by.is_some()));
+        snapshot.extend(u64::from(state.frozen_by.unwrap_or_default()).to_le_bytes());
+        snapshot.extend((state.holds.len() as u32).to_le_bytes());
+        for (transaction_id, amount) in state.holds.iter() {
+            snapshot.extend(u64::from(*transaction_id).to_le_bytes());
+            snapshot.extend(into_decimal(*amount).serialize());
+        }
+        for transaction_ids in [&state.reversing, &state.reversed] {
+            snapshot.extend((transaction_ids.len() as u32).to_le_bytes());
+            for transaction_id in transaction_ids.iter() {
+                snapshot.extend(u64::from(*transaction_id).to_le_bytes());
+            }
+        }
+        snapshot.extend(state.deposits.to_le_bytes());
+        snapshot.extend(state.chargebacks.to_le_bytes());
+    }
+    let checksum = fnv1a(&snapshot);
+    snapshot.extend(checksum.to_le_bytes());
+    snapshot
+}
+
+/// Reads accounts of snapshot of any version up to [VERSION]
+fn decode(snapshot: &[u8]) -> Result<Vec<AccountState>> {
+    if snapshot.len() < MAGIC.len() + 2 + 8 || !snapshot.starts_with(&MAGIC) {
+        return Err(eyre!("not a state snapshot"));
+    }
+    let (content, checksum) = snapshot.split_at(snapshot.len() - 8);
+    if fnv1a(content).to_le_bytes() != checksum {
+        return Err(eyre!("checksum doesn't match, the snapshot is damaged"));
+    }
+    let mut reader = Reader(&content[MAGIC.len()..]);
+    let version = u16::from_le_bytes(reader.take()?);
+    if version == 0 || version > VERSION {
+        return Err(eyre!(
+            "snapshot version {version} is not supported, this tren reads versions up to {VERSION}"
+        ));
+    }
+
+    let count = reader.u64()?;
+    let mut states = Vec::new();
+    for _ in 0..count {
+        states.push(reader.account()?);
+    }
+    if !reader.0.is_empty() {
+        return Err(eyre!("unexpected data after the last account"));
+    }
+    Ok(states)
+}
+
+/// Reads fields of the snapshot in order, fails once the snapshot ends
+struct Reader<'s>(&'s [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        if self.0.len() < N {
+            return Err(eyre!("snapshot ends in the middle of an account"));
+        }
+        let (field, rest) = self.0.split_at(N);
+        self.0 = rest;
+        Ok(field.try_into()?)
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn count(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn amount(&mut self) -> Result<Amount> {
+        from_decimal(Decimal::deserialize(self.take()?))
+    }
+
+    fn client_id(&mut self) -> Result<ClientID> {
+        Ok(ClientID::try_from(self.u64()?)?)
+    }
+
+    fn transaction_id(&mut self) -> Result<TransactionID> {
+        Ok(TransactionID::try_from(self.u64()?)?)
+    }
+
+    fn transaction_ids(&mut self) -> Result<Vec<TransactionID>> {
+        (0..self.count()?).map(|_| self.transaction_id()).collect()
+    }
+
+    fn account(&mut self) -> Result<AccountState> {
+        let client_id = self.client_id()?;
+        let status = match self.take::<1>()?[0] {
+            0 => AccountStatus::Active,
+            1 => AccountStatus::Frozen,
+            2 => AccountStatus::Closed,
+            status => return Err(eyre!("invalid status {status} of client {client_id}")),
+        };
+        let flagged = self.take::<1>()?[0] == 1;
+        let (total, available, held) = (self.amount()?, self.amount()?, self.amount()?);
+        let frozen = self.take::<1>()?[0] == 1;
+        let frozen_by = self.transaction_id()?;
+        let holds = (0..self.count()?)
+            .map(|_| Ok((self.transaction_id()?, self.amount()?)))
+            .collect::<Result<_>>()?;
+        Ok(AccountState {
+            client_id,
+            status,
+            flagged,
+            total,
+            available,
+            held,
+            frozen_by: frozen.then_some(frozen_by),
+            holds,
+            reversing: self.transaction_ids()?,
+            reversed: self.transaction_ids()?,
+            deposits: self.u64()?,
+            chargebacks: self.u64()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::{AccountView, DisputePolicy};
+
+    #[test]
+    fn test_save_load() {
+        let path = std::env::temp_dir().join(format!("tren-test-state-{}.bin", std::process::id()));
+        let mut accounts = Accounts::new(DisputePolicy::Allow);
+        accounts.deposit(1, amount!(10.5)).unwrap();
+        accounts.deposit(1, amount!(4)).unwrap();
+        accounts.dispute(1, 2, amount!(4)).unwrap();
+        accounts.deposit(2, amount!(3)).unwrap();
+        accounts.dispute(2, 3, amount!(3)).unwrap();
+        accounts.chargeback(2, 3).unwrap();
+        accounts.deposit(3, amount!(1)).unwrap();
+        accounts.withdraw(3, amount!(1)).unwrap();
+        accounts.dispute_withdrawal(3, 5, amount!(1)).unwrap();
+        save(&accounts, &path).unwrap();
+
+        let mut loaded = Accounts::new(DisputePolicy::Allow);
+        assert_eq!(load(&mut loaded, &path).unwrap(), 3);
+        let views = |accounts: &Accounts| {
+            let mut views: Vec<AccountView> = accounts.iter().collect();
+            views.sort_by_key(|view| view.client_id);
+            views
+        };
+        assert_eq!(views(&loaded), views(&accounts));
+        assert_eq!(loaded.integrity_mismatch(), None);
+        // held funds and the chargeback survive the snapshot
+        assert!(loaded.resolve(1, 2).is_ok(), "open dispute is resolved");
+        assert!(loaded.resolve(1, 1).is_err(), "not disputed");
+        assert!(loaded.chargeback(3, 5).is_ok(), "disputed withdrawal");
+
+        let snapshot = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut damaged = snapshot.clone();
+        damaged[20] ^= 1;
+        let mut newer = snapshot[..snapshot.len() - 8].to_vec();
+        newer[4] = 2;
+        newer.extend(fnv1a(&newer).to_le_bytes());
+        let tests = vec![
+            ("not a snapshot", b"deposit,1,1,1".to_vec(), "not a state"),
+            ("damaged", damaged, "checksum"),
+            ("newer version", newer, "version 2 is not supported"),
+            (
+                "truncated",
+                snapshot[..snapshot.len() - 1].to_vec(),
+                "checksum",
+            ),
+        ];
+        for (name, snapshot, want) in tests {
+            let err = decode(&snapshot).unwrap_err().to_string();
+            assert!(err.contains(want), "failed test {name}: {err}");
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use crate::accounts::AccountError;
+use crate::alerts::Alert;
+use crate::aliases::Amount;
+use crate::checkpoint::ResumePoint;
+use crate::run_id::RunId;
+use crate::timings::StageTimings;
+
+/// Counters collected while processing the journal, printed out to stderr at the end of the run
+/// so they don't get mixed with the report
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// ID of the run which collected the counters, set by the binary
+    pub run_id: Option<RunId>,
+    /// Position after the last parsed record, set by the parser once it reached the end of the journal
+    pub journal_end: Option<ResumePoint>,
+    /// Withdrawals rejected because of insufficient funds
+    pub rejected_withdrawals: u64,
+    /// Operations which would overflow account's balances, such accounts are frozen
+    pub overflows: u64,
+    /// Disputes, resolves and chargebacks referencing non-existent account
+    pub unknown_accounts: u64,
+    /// Disputes higher than available funds, rejected because of the dispute policy, over the cap of open disputes
+    /// or of reversed transactions
+    pub rejected_disputes: u64,
+    /// Disputes of transactions which were already under dispute
+    pub duplicate_disputes: u64,
+    /// Disputes of transactions which belong to another client, see [crate::dispute_look_up::MismatchedDisputePolicy]
+    pub mismatched_disputes: u64,
+    /// Resolves and chargebacks of transactions which were not under dispute
+    pub ignored_without_dispute: u64,
+    /// Transfers rejected because of insufficient funds or frozen account
+    pub rejected_transfers: u64,
+    /// Reversals rejected because the reversed deposit is not available anymore, the transaction is under dispute
+    /// or it was already reversed
+    pub rejected_reversals: u64,
+    /// Operations rejected because the account is closed
+    pub closed_accounts: u64,
+    /// Closures rejected because the account still has held funds
+    pub rejected_closures: u64,
+    /// Records skipped because their timestamp is outside of the requested time window
+    pub outside_window: u64,
+    /// Disputes ignored because the disputed transaction is outside of the requested time window
+    pub disputes_outside_window: u64,
+    /// Disputes which arrived before their transaction and were looked up again, see
+    /// [crate::dispute_look_up::PendingRetry]
+    pub retried_disputes: u64,
+    /// Disputes rejected because they were filed after the dispute eligibility window
+    pub late_disputes: u64,
+    /// Partial disputes, resolves and chargebacks rejected because their amount exceeds the transaction
+    pub excessive_dispute_amounts: u64,
+    /// Accounts flagged by fraud screening
+    pub flagged_accounts: u64,
+    /// Deposits and withdrawals rejected because the account is frozen
+    pub frozen_accounts: u64,
+    /// Operations rejected because they exceeded client's limits or their amount is not positive
+    pub limit_rejections: u64,
+    /// Dispute look-up requests which were spilled to disk because the look-up queue was full
+    pub spilled_disputes: u64,
+    /// Broken account invariants found by `--check-invariants`
+    pub invariant_violations: u64,
+    /// Records skipped because they couldn't be parsed, only with lenient parse error policy
+    pub malformed_records: u64,
+    /// Records skipped because their client is not in the `--sample`
+    pub sampled_out: u64,
+    /// Records skipped because their client is excluded by `--only-clients` or `--ignore-clients`
+    pub filtered_clients: u64,
+    /// Records skipped because they matched a `--rule` with `reject` action
+    pub rule_rejections: u64,
+    /// Records which matched a `--rule` with `warn` action, they are still processed
+    pub rule_warnings: u64,
+    /// Records which matched a `--rule` with `flag` action, the account of their client is flagged
+    pub rule_flags: u64,
+    /// Flags of `--rule` with `flag` action dropped because their client had no account
+    pub rule_flags_without_account: u64,
+    /// Operations replayed from the write-ahead log before the journal was processed
+    pub recovered_operations: u64,
+    /// Records skipped because they were applied before, by `--skip-until` or the recovered write-ahead log
+    pub already_applied: u64,
+    /// Grand total of all accounts didn't match deposits minus withdrawals and chargebacks, see [crate::accounts::Movements]
+    pub integrity_mismatches: u64,
+    /// `#` comment lines and whitespace-only lines skipped by the parser, empty lines are dropped by the CSV reader
+    /// and not counted
+    pub comment_lines: u64,
+    /// Transactions delivered again while they were in the de-duplication window, they were applied only once
+    pub duplicate_transactions: u64,
+    /// Deposits and withdrawals with lower transaction ID than one before them, counted by `--check-order`
+    pub out_of_order_transactions: u64,
+    /// Deposits and withdrawals reusing transaction ID of one before them, counted by `--check-order`
+    pub reused_transaction_ids: u64,
+    /// Settled accounts evicted from memory at the end of processing, see `--eviction-interval`
+    pub evicted_accounts: u64,
+    /// Withdrawals and chargebacks charged with a fee, see [crate::fees::FeeSchedule]
+    pub fees_charged: u64,
+    /// Sum of the charged fees credited to the house account
+    pub fees_collected: Amount,
+    /// Accounts credited with interest once the journal was processed, see [crate::interest]
+    pub interest_accounts: u64,
+    pub interest_credited: Amount,
+    /// Webhook notifications which were not acknowledged by the endpoint even after retries, see [crate::webhook]
+    pub undelivered_notifications: u64,
+    /// 1 if the run was stopped before the end of the journal by `--max-runtime` or `--idle-timeout`, see
+    /// [crate::run_guard]
+    pub stopped_early: u64,
+    /// Accounts crossing the balance thresholds of `--alert` at the end of the run, see [crate::alerts]
+    pub alerts: Vec<Alert>,
+    /// Latency of the pipeline stages and time they waited on each other
+    pub timings: StageTimings,
+}
+
+impl Summary {
+    /// Increments counter matching the rejected operation and the reason of the rejection
+    pub fn record_rejection(&mut self, operation: &str, err: &AccountError) {
+        match err {
+            AccountError::Overflow => self.overflows += 1,
+            AccountError::AccountClosed(_) => self.closed_accounts += 1,
+            _ if operation == "transfer" => self.rejected_transfers += 1,
+            _ if operation == "reversal" => self.rejected_reversals += 1,
+            AccountError::InsufficientFunds { .. } => self.rejected_withdrawals += 1,
+            AccountError::AccountNotFound => self.unknown_accounts += 1,
+            AccountError::DisputeExceedsAvailable { .. } | AccountError::TooManyOpenDisputes(_) => {
+                self.rejected_disputes += 1
+            }
+            AccountError::HeldFundsOnClose(_) => self.rejected_closures += 1,
+            AccountError::LimitExceeded { .. } | AccountError::NonPositiveAmount(_) => {
+                self.limit_rejections += 1
+            }
+            AccountError::AccountFrozen(_) => self.frozen_accounts += 1,
+            AccountError::AlreadyDisputed(_) => self.duplicate_disputes += 1,
+            AccountError::AlreadyReversed(_) => self.rejected_disputes += 1,
+            AccountError::NotDisputed(_) => self.ignored_without_dispute += 1,
+            AccountError::SelfTransfer => (),
+        }
+    }
+
+    /// Adds counters collected by other part of the pipeline
+    pub fn merge(&mut self, other: Summary) {
+        self.run_id = self.run_id.or(other.run_id);
+        self.journal_end = self.journal_end.or(other.journal_end);
+        self.rejected_withdrawals += other.rejected_withdrawals;
+        self.overflows += other.overflows;
+        self.unknown_accounts += other.unknown_accounts;
+        self.rejected_disputes += other.rejected_disputes;
+        self.duplicate_disputes += other.duplicate_disputes;
+        self.mismatched_disputes += other.mismatched_disputes;
+        self.ignored_without_dispute += other.ignored_without_dispute;
+        self.rejected_transfers += other.rejected_transfers;
+        self.rejected_reversals += other.rejected_reversals;
+        self.closed_accounts += other.closed_accounts;
+        self.rejected_closures += other.rejected_closures;
+        self.outside_window += other.outside_window;
+        self.disputes_outside_window += other.disputes_outside_window;
+        self.retried_disputes += other.retried_disputes;
+        self.late_disputes += other.late_disputes;
+        self.excessive_dispute_amounts += other.excessive_dispute_amounts;
+        self.flagged_accounts += other.flagged_accounts;
+        self.frozen_accounts += other.frozen_accounts;
+        self.limit_rejections += other.limit_rejections;
+        self.spilled_disputes += other.spilled_disputes;
+        self.invariant_violations += other.invariant_violations;
+        self.malformed_records += other.malformed_records;
+        self.sampled_out += other.sampled_out;
+        self.filtered_clients += other.filtered_clients;
+        self.rule_rejections += other.rule_rejections;
+        self.rule_warnings += other.rule_warnings;
+        self.rule_flags += other.rule_flags;
+        self.rule_flags_without_account += other.rule_flags_without_account;
+        self.recovered_operations += other.recovered_operations;
+        self.already_applied += other.already_applied;
+        self.integrity_mismatches += other.integrity_mismatches;
+        self.comment_lines += other.comment_lines;
+        self.duplicate_transactions += other.duplicate_transactions;
+        self.out_of_order_transactions += other.out_of_order_transactions;
+        self.reused_transaction_ids += other.reused_transaction_ids;
+        self.evicted_accounts += other.evicted_accounts;
+        self.fees_charged += other.fees_charged;
+        self.fees_collected = self.fees_collected.saturating_add(other.fees_collected);
+        self.interest_accounts += other.interest_accounts;
+        self.interest_credited = self
+            .interest_credited
+            .saturating_add(other.interest_credited);
+        self.undelivered_notifications += other.undelivered_notifications;
+        self.stopped_early = self.stopped_early.max(other.stopped_early);
+        self.alerts.extend(other.alerts);
+        self.timings.merge(&other.timings);
+    }
+
+    /// Counters by their name, in the order they are printed
+    pub fn counters(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (
+                "rejected_withdrawals",
+                self.rejected_withdrawals.to_string(),
+            ),
+            ("overflows", self.overflows.to_string()),
+            ("unknown_accounts", self.unknown_accounts.to_string()),
+            ("rejected_disputes", self.rejected_disputes.to_string()),
+            ("duplicate_disputes", self.duplicate_disputes.to_string()),
+            ("mismatched_disputes", self.mismatched_disputes.to_string()),
+            (
+                "ignored_without_dispute",
+                self.ignored_without_dispute.to_string(),
+            ),
+            ("rejected_transfers", self.rejected_transfers.to_string()),
+            ("rejected_reversals", self.rejected_reversals.to_string()),
+            ("closed_accounts", self.closed_accounts.to_string()),
+            ("rejected_closures", self.rejected_closures.to_string()),
+            ("outside_window", self.outside_window.to_string()),
+            (
+                "disputes_outside_window",
+                self.disputes_outside_window.to_string(),
+            ),
+            ("retried_disputes", self.retried_disputes.to_string()),
+            ("late_disputes", self.late_disputes.to_string()),
+            (
+                "excessive_dispute_amounts",
+                self.excessive_dispute_amounts.to_string(),
+            ),
+            ("flagged_accounts", self.flagged_accounts.to_string()),
+            ("frozen_accounts", self.frozen_accounts.to_string()),
+            ("limit_rejections", self.limit_rejections.to_string()),
+            ("spilled_disputes", self.spilled_disputes.to_string()),
+            (
+                "invariant_violations",
+                self.invariant_violations.to_string(),
+            ),
+            ("malformed_records", self.malformed_records.to_string()),
+            ("sampled_out", self.sampled_out.to_string()),
+            ("filtered_clients", self.filtered_clients.to_string()),
+            ("rule_rejections", self.rule_rejections.to_string()),
+            ("rule_warnings", self.rule_warnings.to_string()),
+            ("rule_flags", self.rule_flags.to_string()),
+            (
+                "rule_flags_without_account",
+                self.rule_flags_without_account.to_string(),
+            ),
+            (
+                "recovered_operations",
+                self.recovered_operations.to_string(),
+            ),
+            ("already_applied", self.already_applied.to_string()),
+            (
+                "integrity_mismatches",
+                self.integrity_mismatches.to_string(),
+            ),
+            ("comment_lines", self.comment_lines.to_string()),
+            (
+                "duplicate_transactions",
+                self.duplicate_transactions.to_string(),
+            ),
+            (
+                "out_of_order_transactions",
+                self.out_of_order_transactions.to_string(),
+            ),
+            (
+                "reused_transaction_ids",
+                self.reused_transaction_ids.to_string(),
+            ),
+            ("evicted_accounts", self.evicted_accounts.to_string()),
+            ("fees_charged", self.fees_charged.to_string()),
+            ("fees_collected", self.fees_collected.to_string()),
+            ("interest_accounts", self.interest_accounts.to_string()),
+            ("interest_credited", self.interest_credited.to_string()),
+            (
+                "undelivered_notifications",
+                self.undelivered_notifications.to_string(),
+            ),
+            ("stopped_early", self.stopped_early.to_string()),
+            ("balance_alerts", self.alerts.len().to_string()),
+        ]
+    }
+
+    pub fn print(&self) {
+        if let Some(run_id) = self.run_id {
+            eprintln!("run_id: {run_id}");
+        }
+        for (name, value) in self.counters() {
+            eprintln!("{name}: {value}");
+        }
+        for alert in &self.alerts {
+            eprintln!("alert: {alert}");
+        }
+        self.timings.print();
+    }
+}
@@ -0,0 +1,149 @@
+use crate::aliases::Timestamp;
+use eyre::{eyre, Context, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use time::format_description::{self, well_known::Rfc3339};
+use time::{Date, OffsetDateTime};
+
+/// Parses timestamp from the journal or command line. Accepted formats are unix timestamp in seconds,
+/// RFC 3339 date-time (`2022-08-31T23:59:59Z`) or plain date (`2022-08-31`), which is taken as midnight UTC
+pub fn parse_timestamp(s: &str) -> Result<Timestamp> {
+    let s = s.trim();
+    if let Ok(seconds) = s.parse::<Timestamp>() {
+        return Ok(seconds);
+    }
+
+    if let Ok(date_time) = OffsetDateTime::parse(s, &Rfc3339) {
+        return Ok(date_time.unix_timestamp());
+    }
+
+    let date_format = format_description::parse("[year]-[month]-[day]")?;
+    Date::parse(s, &date_format)
+        .map(|date| date.midnight().assume_utc().unix_timestamp())
+        .wrap_err_with(|| eyre!("invalid timestamp '{s}'"))
+}
+
+/// Parses duration from the command line into seconds, accepts plain seconds or number with `s`, `m` or `h` suffix,
+/// for example `90`, `60s`, `5m` or `1h`
+pub fn parse_duration(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let (value, multiplier) = match s.as_bytes().last() {
+        Some(b's') => (&s[..s.len() - 1], 1),
+        Some(b'm') => (&s[..s.len() - 1], 60),
+        Some(b'h') => (&s[..s.len() - 1], 60 * 60),
+        _ => (s, 1),
+    };
+
+    value
+        .parse::<u64>()
+        .map(|value| value * multiplier)
+        .wrap_err_with(|| eyre!("invalid duration '{s}', expected for example 60s, 5m or 1h"))
+}
+
+/// Time window used to filter the journal records, `from` is inclusive and `to` is exclusive
+/// so consecutive windows don't overlap. Unbounded if not set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeWindow {
+    #[serde(default, deserialize_with = "deserialize_timestamp")]
+    pub from: Option<Timestamp>,
+    #[serde(default, deserialize_with = "deserialize_timestamp")]
+    pub to: Option<Timestamp>,
+}
+
+/// Accepts timestamp either as unix seconds or as any string accepted by [parse_timestamp]
+fn deserialize_timestamp<'de, D>(deserializer: D) -> Result<Option<Timestamp>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Seconds(Timestamp),
+        Text(String),
+    }
+
+    match Option::<Raw>::deserialize(deserializer)? {
+        Some(Raw::Seconds(seconds)) => Ok(Some(seconds)),
+        Some(Raw::Text(text)) => parse_timestamp(&text)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+impl TimeWindow {
+    pub fn is_unbounded(&self) -> bool {
+        self.from.is_none() && self.to.is_none()
+    }
+
+    /// Records without timestamp can't be filtered, so they are always considered to be inside the window
+    pub fn contains(&self, timestamp: Option<Timestamp>) -> bool {
+        match timestamp {
+            Some(timestamp) => {
+                self.from.is_none_or(|from| timestamp >= from)
+                    && self.to.is_none_or(|to| timestamp < to)
+            }
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        let tests = vec![
+            ("unix seconds", "1661990399", 1661990399),
+            ("rfc 3339", "2022-08-31T23:59:59Z", 1661990399),
+            (
+                "rfc 3339 with offset",
+                "2022-09-01T01:59:59+02:00",
+                1661990399,
+            ),
+            ("date", "2022-09-01", 1661990400),
+            ("with spaces", " 2022-09-01 ", 1661990400),
+        ];
+
+        for (name, input, want) in tests {
+            let got =
+                parse_timestamp(input).unwrap_or_else(|err| panic!("failed test {name}: {err}"));
+            assert_eq!(got, want, "failed test {name}");
+        }
+
+        assert!(parse_timestamp("31.08.2022").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        let tests = vec![
+            ("plain seconds", "90", 90),
+            ("seconds", "60s", 60),
+            ("minutes", "5m", 300),
+            ("hours", "1h", 3600),
+        ];
+
+        for (name, input, want) in tests {
+            let got =
+                parse_duration(input).unwrap_or_else(|err| panic!("failed test {name}: {err}"));
+            assert_eq!(got, want, "failed test {name}");
+        }
+
+        assert!(parse_duration("5d").is_err());
+    }
+
+    #[test]
+    fn test_window_contains() {
+        let window = TimeWindow {
+            from: Some(10),
+            to: Some(20),
+        };
+
+        assert!(!window.contains(Some(9)));
+        assert!(window.contains(Some(10)));
+        assert!(window.contains(Some(19)));
+        assert!(!window.contains(Some(20)));
+        assert!(window.contains(None));
+        assert!(TimeWindow::default().contains(Some(0)));
+    }
+}
@@ -0,0 +1,141 @@
+use std::time::Duration;
+
+/// Number of records parsed or applied between two samples of the batch latency
+pub const TIMING_BATCH: u64 = 4096;
+
+/// Histogram of durations with power of two buckets, bucket `i` counts durations shorter than `2^i` microseconds.
+/// Percentiles are upper bounds of their bucket, so they are at most twice the real value
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    buckets: [u64; 32],
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl Histogram {
+    pub fn record(&mut self, duration: Duration) {
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(self.buckets.len() - 1)] += 1;
+        self.count += 1;
+        self.total = self.total.saturating_add(duration);
+        self.max = self.max.max(duration);
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        for (bucket, other) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *bucket += other;
+        }
+        self.count += other.count;
+        self.total = self.total.saturating_add(other.total);
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Upper bound of the duration under which `percentile` of the samples are, `percentile` is from 0 to 100
+    pub fn percentile(&self, percentile: u64) -> Duration {
+        let rank = (self.count * percentile.min(100)).div_ceil(100).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_micros(1 << bucket).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+/// Latency of the pipeline stages and time they spent waiting on each other, tells whether parsing,
+/// dispute look-ups or applying to the accounts is the bottleneck. Stage waiting on its input is starved
+/// by the stage before it, parser waiting on the full queue is slowed down by the processing
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StageTimings {
+    /// Parsing of [TIMING_BATCH] records, including the time blocked on the full queue
+    pub parse: Histogram,
+    /// Parser blocked because the queue of transactions was full
+    pub parse_queue_wait: Histogram,
+    /// Batch of dispute look-ups, see [crate::dispute_look_up::DisputeFinder::with_batch_size]
+    pub dispute_look_up: Histogram,
+    /// Dispute look-up waiting for requests from the parser
+    pub dispute_queue_wait: Histogram,
+    /// Applying [TIMING_BATCH] operations to the accounts, without the time waiting for them
+    pub apply: Histogram,
+    /// Processing waiting for transactions from the parser and the dispute look-up
+    pub apply_queue_wait: Histogram,
+}
+
+impl StageTimings {
+    pub fn merge(&mut self, other: &StageTimings) {
+        self.parse.merge(&other.parse);
+        self.parse_queue_wait.merge(&other.parse_queue_wait);
+        self.dispute_look_up.merge(&other.dispute_look_up);
+        self.dispute_queue_wait.merge(&other.dispute_queue_wait);
+        self.apply.merge(&other.apply);
+        self.apply_queue_wait.merge(&other.apply_queue_wait);
+    }
+
+    /// Stages which have at least one sample, by their name
+    pub fn stages(&self) -> impl Iterator<Item = (&'static str, &Histogram)> {
+        [
+            ("parse", &self.parse),
+            ("parse_queue_wait", &self.parse_queue_wait),
+            ("dispute_look_up", &self.dispute_look_up),
+            ("dispute_queue_wait", &self.dispute_queue_wait),
+            ("apply", &self.apply),
+            ("apply_queue_wait", &self.apply_queue_wait),
+        ]
+        .into_iter()
+        .filter(|(_, histogram)| histogram.count() > 0)
+    }
+
+    /// Prints stages which have at least one sample, one line per stage
+    pub fn print(&self) {
+        for (name, histogram) in self.stages() {
+            eprintln!(
+                "timing_{name}: count={} total={:?} p50={:?} p99={:?} max={:?}",
+                histogram.count(),
+                histogram.total(),
+                histogram.percentile(50),
+                histogram.percentile(99),
+                histogram.max
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let mut histogram = Histogram::default();
+        for micros in [1, 3, 3, 100, 5_000] {
+            histogram.record(Duration::from_micros(micros));
+        }
+        let tests = vec![
+            ("p0", 0, Duration::from_micros(2)),
+            ("p50", 50, Duration::from_micros(4)),
+            ("p80", 80, Duration::from_micros(128)),
+            ("p100 capped by max", 100, Duration::from_micros(5_000)),
+        ];
+
+        for (name, percentile, want) in tests {
+            assert_eq!(histogram.percentile(percentile), want, "failed test {name}");
+        }
+        assert_eq!(Histogram::default().percentile(50), Duration::ZERO);
+    }
+}
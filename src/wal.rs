@@ -0,0 +1,283 @@
+use crate::aliases::*;
+use crate::amount::{from_decimal, into_decimal};
+use crate::channel::{
+    Dispute, Indexed, Transaction, TransactionKind, TransactionMessage, Transfer,
+};
+use eyre::{eyre, Context, Result};
+use rust_decimal::Decimal;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::{debug, error, warn};
+
+/// Start of every write-ahead log, the last byte is the version of the format
+const MAGIC: [u8; 5] = *b"TWAL\x02";
+
+/// Size of single record: journal record index, operation, client, tx, amount and receiving client of transfers
+const RECORD_SIZE: usize = 8 + 1 + 8 + 8 + 16 + 8;
+
+/// Appended records are synced to disk after this many records and when the log is dropped
+const SYNC_BATCH: usize = 1_000;
+
+/// Storage the log is appended to, the file it was opened from outside of the tests
+pub(crate) trait LogFile: Write + Send {
+    /// Waits until the written data is on disk
+    fn sync_data(&self) -> io::Result<()>;
+}
+
+impl LogFile for File {
+    fn sync_data(&self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+}
+
+/// Write-ahead log of operations applied to the accounts. Every [TransactionMessage] is appended before it is applied,
+/// so the accounts can be rebuilt by [replay] after a crash. Index of the journal record is stored with every operation,
+/// so the journal can be processed again without applying it twice, see [crate::checkpoint::Checkpoint].
+/// Records are fixed-width, amounts are stored exactly
+pub struct WriteAheadLog {
+    path: PathBuf,
+    writer: BufWriter<Box<dyn LogFile>>,
+    /// Records appended since the last sync
+    unsynced: usize,
+}
+
+impl WriteAheadLog {
+    /// Opens the log for appending, creates it if it doesn't exist. Incomplete last record left by a crash
+    /// is cut off, so appended records stay aligned
+    pub fn open(path: &Path) -> Result<WriteAheadLog> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(path)
+            .wrap_err_with(|| format!("failed to open write-ahead log {}", path.display()))?;
+
+        let len = file.metadata()?.len();
+        if len == 0 {
+            file.write_all(&MAGIC)
+                .wrap_err("failed to write write-ahead log header")?;
+        } else {
+            read_header(&mut file)?;
+            let records = (len - MAGIC.len() as u64) / RECORD_SIZE as u64;
+            let complete = MAGIC.len() as u64 + records * RECORD_SIZE as u64;
+            if complete != len {
+                warn!(
+                    path = %path.display(),
+                    "cutting off incomplete last record of write-ahead log"
+                );
+                file.set_len(complete)?;
+            }
+            file.seek(SeekFrom::End(0))?;
+        }
+
+        Ok(WriteAheadLog {
+            path: path.to_path_buf(),
+            writer: BufWriter::new(Box::new(file)),
+            unsynced: 0,
+        })
+    }
+
+    /// Log appending straight into the `file`, without the header and buffering, so failed writes
+    /// fail the append
+    #[cfg(test)]
+    pub(crate) fn unbuffered(file: impl LogFile + 'static) -> WriteAheadLog {
+        WriteAheadLog {
+            path: PathBuf::from("unbuffered"),
+            writer: BufWriter::with_capacity(0, Box::new(file)),
+            unsynced: 0,
+        }
+    }
+
+    /// Appends the message, the log is synced to disk once every [SYNC_BATCH] records
+    pub fn append(&mut self, message: &Indexed<TransactionMessage>) -> Result<()> {
+        self.writer
+            .write_all(&encode(message))
+            .wrap_err("failed to write into write-ahead log")?;
+        self.unsynced += 1;
+        if self.unsynced >= SYNC_BATCH {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes appended records and waits until they are on disk
+    pub fn sync(&mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .wrap_err("failed to flush write-ahead log")?;
+        self.writer
+            .get_ref()
+            .sync_data()
+            .wrap_err("failed to sync write-ahead log")?;
+        debug!(records = self.unsynced, "synced write-ahead log");
+        self.unsynced = 0;
+        Ok(())
+    }
+}
+
+impl Drop for WriteAheadLog {
+    fn drop(&mut self) {
+        if let Err(err) = self.sync() {
+            error!(%err, path = %self.path.display(), "failed to sync write-ahead log");
+        }
+    }
+}
+
+/// Calls `f` with every message of the log in the order they were appended. Incomplete last record left by
+/// a crash is ignored, those operations were never applied. Returns number of replayed messages
+pub fn replay(path: &Path, mut f: impl FnMut(Indexed<TransactionMessage>)) -> Result<u64> {
+    let mut reader = BufReader::new(
+        File::open(path)
+            .wrap_err_with(|| format!("failed to open write-ahead log {}", path.display()))?,
+    );
+    read_header(&mut reader)?;
+
+    let mut count = 0;
+    let mut record = [0; RECORD_SIZE];
+    loop {
+        match reader.read_exact(&mut record) {
+            Ok(()) => (),
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err).wrap_err("failed to read write-ahead log"),
+        }
+        f(
+            decode(&record)
+                .wrap_err_with(|| format!("malformed write-ahead log record {count}"))?,
+        );
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn read_header(reader: &mut impl Read) -> Result<()> {
+    let mut magic = [0; MAGIC.len()];
+    reader
+        .read_exact(&mut magic)
+        .wrap_err("failed to read write-ahead log header")?;
+    match magic == MAGIC {
+        true => Ok(()),
+        false => Err(eyre!("not a write-ahead log or unsupported version")),
+    }
+}
+
+// IDs are stored as u64 so the format doesn't depend on `wide-ids` feature
+#[allow(clippy::useless_conversion)]
+fn encode(indexed: &Indexed<TransactionMessage>) -> [u8; RECORD_SIZE] {
+    let (tag, client_id, transaction_id, amount, to_client_id) = match &indexed.message {
+        TransactionMessage::Deposit(t) => (0, t.client_id, t.transaction_id, t.amount, 0),
+        TransactionMessage::Withdrawal(t) => (1, t.client_id, t.transaction_id, t.amount, 0),
+        TransactionMessage::Dispute(d) => match d.kind {
+            TransactionKind::Deposit => (2, d.client_id, d.transaction_id, d.amount, 0),
+            TransactionKind::Withdrawal => (11, d.client_id, d.transaction_id, d.amount, 0),
+        },
+        TransactionMessage::Resolve(d) => (3, d.client_id, d.transaction_id, d.amount, 0),
+        TransactionMessage::Chargeback(d) => (4, d.client_id, d.transaction_id, d.amount, 0),
+        TransactionMessage::Transfer(t) => (
+            5,
+            t.from_client_id,
+            t.transaction_id,
+            t.amount,
+            t.to_client_id,
+        ),
+        TransactionMessage::AdjustmentCredit(t) => (6, t.client_id, t.transaction_id, t.amount, 0),
+        TransactionMessage::AdjustmentDebit(t) => (7, t.client_id, t.transaction_id, t.amount, 0),
+        TransactionMessage::Lock(client_id) => (8, *client_id, 0, Amount::ZERO, 0),
+        TransactionMessage::Unlock(client_id) => (9, *client_id, 0, Amount::ZERO, 0),
+        TransactionMessage::Close(client_id) => (10, *client_id, 0, Amount::ZERO, 0),
+        TransactionMessage::Reversal(d) => match d.kind {
+            TransactionKind::Deposit => (12, d.client_id, d.transaction_id, d.amount, 0),
+            TransactionKind::Withdrawal => (13, d.client_id, d.transaction_id, d.amount, 0),
+        },
+    };
+
+    let mut record = [0; RECORD_SIZE];
+    record[..8].copy_from_slice(&indexed.index.to_le_bytes());
+    record[8] = tag;
+    record[9..17].copy_from_slice(&u64::from(client_id).to_le_bytes());
+    record[17..25].copy_from_slice(&u64::from(transaction_id).to_le_bytes());
+    record[25..41].copy_from_slice(&into_decimal(amount).serialize());
+    record[41..].copy_from_slice(&u64::from(to_client_id).to_le_bytes());
+    record
+}
+
+#[allow(clippy::useless_conversion)]
+fn decode(record: &[u8; RECORD_SIZE]) -> Result<Indexed<TransactionMessage>> {
+    let index = u64::from_le_bytes(record[..8].try_into()?);
+    let client_id = ClientID::try_from(u64::from_le_bytes(record[9..17].try_into()?))?;
+    let transaction_id = TransactionID::try_from(u64::from_le_bytes(record[17..25].try_into()?))?;
+    let amount = from_decimal(Decimal::deserialize(record[25..41].try_into()?))?;
+    let transaction = Transaction::new(client_id, transaction_id, amount);
+    let dispute = Dispute::new(client_id, transaction_id, amount);
+
+    let message = match record[8] {
+        0 => TransactionMessage::Deposit(transaction),
+        1 => TransactionMessage::Withdrawal(transaction),
+        2 => TransactionMessage::Dispute(dispute),
+        3 => TransactionMessage::Resolve(dispute),
+        4 => TransactionMessage::Chargeback(dispute),
+        5 => TransactionMessage::Transfer(Transfer {
+            from_client_id: client_id,
+            to_client_id: ClientID::try_from(u64::from_le_bytes(record[41..].try_into()?))?,
+            transaction_id,
+            amount,
+        }),
+        6 => TransactionMessage::AdjustmentCredit(transaction),
+        7 => TransactionMessage::AdjustmentDebit(transaction),
+        8 => TransactionMessage::Lock(client_id),
+        9 => TransactionMessage::Unlock(client_id),
+        10 => TransactionMessage::Close(client_id),
+        11 => TransactionMessage::Dispute(dispute.with_kind(TransactionKind::Withdrawal)),
+        12 => TransactionMessage::Reversal(dispute),
+        13 => TransactionMessage::Reversal(dispute.with_kind(TransactionKind::Withdrawal)),
+        tag => return Err(eyre!("invalid write-ahead log operation {tag}")),
+    };
+    Ok(Indexed::new(index, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_replay() {
+        let path = std::env::temp_dir().join(format!("tren-test-{}.wal", std::process::id()));
+        let messages = vec![
+            Indexed::new(0, TransactionMessage::deposit(1, 1, amount!(10.1234))),
+            Indexed::new(1, TransactionMessage::transfer(1, 2, 2, amount!(2.5))),
+            Indexed::new(5, TransactionMessage::chargeback(1, 1, amount!(10.1234))),
+            Indexed::new(3, TransactionMessage::Unlock(1)),
+            Indexed::new(
+                6,
+                TransactionMessage::Dispute(
+                    Dispute::new(2, 4, amount!(1)).with_kind(TransactionKind::Withdrawal),
+                ),
+            ),
+        ];
+
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        wal.append(&messages[0]).unwrap();
+        drop(wal);
+        // reopened log is appended to
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        for message in messages[1..].iter() {
+            wal.append(message).unwrap();
+        }
+        drop(wal);
+        // torn record left by a crash is ignored
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[0; 7])
+            .unwrap();
+
+        let mut got = Vec::new();
+        let count = replay(&path, |message| got.push(message));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(count.unwrap(), 5);
+        assert_eq!(got, messages);
+    }
+}
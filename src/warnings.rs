@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Rolls up repeated warnings, so a bad journal doesn't produce millions of identical log lines.
+/// Within every interval only the first warning of each kind is logged, the rest is counted and logged
+/// as a single summary line once the interval elapses. Without interval every warning is logged
+pub struct WarningAggregator {
+    interval: Option<Duration>,
+    started: Instant,
+    /// Occurrences of each kind of warning in the current interval
+    counts: BTreeMap<&'static str, u64>,
+}
+
+impl WarningAggregator {
+    pub fn new(interval: Option<Duration>) -> Self {
+        WarningAggregator {
+            interval,
+            started: Instant::now(),
+            counts: BTreeMap::new(),
+        }
+    }
+
+    /// Counts the warning, returns `true` if it should be logged
+    pub fn should_log(&mut self, kind: &'static str) -> bool {
+        let Some(interval) = self.interval else {
+            return true;
+        };
+        if self.started.elapsed() >= interval {
+            self.flush();
+        }
+
+        let count = self.counts.entry(kind).or_default();
+        *count += 1;
+        *count == 1
+    }
+
+    /// Logs how many warnings of each kind were suppressed in the current interval and starts a new one
+    pub fn flush(&mut self) {
+        for (kind, count) in std::mem::take(&mut self.counts) {
+            if count > 1 {
+                warn!(
+                    kind,
+                    suppressed = count - 1,
+                    "repeated warnings were suppressed"
+                );
+            }
+        }
+        self.started = Instant::now();
+    }
+}
+
+impl Drop for WarningAggregator {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_log() {
+        let mut warnings = WarningAggregator::new(Some(Duration::from_secs(3600)));
+        let got: Vec<bool> = ["funds", "funds", "frozen", "funds"]
+            .into_iter()
+            .map(|kind| warnings.should_log(kind))
+            .collect();
+        assert_eq!(got, vec![true, false, true, false]);
+        assert_eq!(warnings.counts["funds"], 3);
+
+        warnings.flush();
+        assert!(warnings.should_log("funds"), "new interval logs again");
+        assert!(WarningAggregator::new(None).should_log("funds"));
+    }
+}
@@ -0,0 +1,485 @@
+//! Notifications of frozen accounts and large chargebacks posted to a webhook while the journal is processed.
+//! They are delivered by a background thread, so slow or unavailable endpoint doesn't hold up the processing.
+//! Notification is delivered once the endpoint acknowledges it with 2xx status, failed ones wait in a bounded
+//! retry queue while the following ones are delivered. Notifications which can't be delivered are written into
+//! the failure file, so none is lost silently. Only plain `http://` URLs are supported
+use crate::accounts::AccountError;
+use crate::aliases::*;
+use crate::processor::{Completed, Hook};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use eyre::{eyre, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, warn};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Delay before the first retry, it doubles with every following one
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    /// Endpoint the notifications are posted to, webhooks are disabled if not set
+    pub url: Option<String>,
+    /// Body of the request, `{event}`, `{client}`, `{tx}` and `{amount}` are replaced. JSON object with all of them
+    /// if not set, e.g. `{"event":"chargeback","client":1,"tx":5,"amount":"120.5"}`
+    pub template: Option<String>,
+    /// Only chargebacks of at least this amount are notified, all of them if not set. Frozen accounts are always notified
+    pub chargeback_threshold: Option<Amount>,
+    /// Delivery attempts after the first failed one, delays between them double from one second
+    pub retries: u32,
+    /// At most this many failed notifications wait for their retry, the ones over it are undelivered right away
+    pub retry_queue: usize,
+    /// Bodies of undelivered notifications are appended into this file one per line, they are only logged if not set
+    pub failure_file: Option<PathBuf>,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            url: None,
+            template: None,
+            chargeback_threshold: None,
+            retries: 3,
+            retry_queue: 1_000,
+            failure_file: None,
+        }
+    }
+}
+
+/// Notified event, `name` is `frozen` or `chargeback`
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Event {
+    name: &'static str,
+    client_id: ClientID,
+    transaction_id: Option<TransactionID>,
+    amount: Option<Amount>,
+}
+
+/// Running delivery thread, [Webhook::hook] is added to the processor and [Webhook::finish] waits
+/// until all notifications are delivered once the processing is done
+pub struct Webhook {
+    sender: Sender<Event>,
+    chargeback_threshold: Option<Amount>,
+    delivery: JoinHandle<u64>,
+}
+
+impl Webhook {
+    /// Starts the delivery thread, returns `None` if no URL is configured
+    pub fn start(config: &WebhookConfig) -> Result<Option<Webhook>> {
+        let Some(url) = config.url.as_deref() else {
+            return Ok(None);
+        };
+        let failures = config
+            .failure_file
+            .as_ref()
+            .map(|path| {
+                File::options()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .wrap_err_with(|| {
+                        format!("failed to open webhook failure file {}", path.display())
+                    })
+            })
+            .transpose()?;
+        let delivery = Delivery {
+            endpoint: Endpoint::parse(url)?,
+            template: config.template.clone(),
+            retries: config.retries,
+            retry_delay: RETRY_DELAY,
+            retry_queue: config.retry_queue,
+            failures,
+            retrying: Vec::new(),
+            undelivered: 0,
+        };
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let delivery = std::thread::Builder::new()
+            .name("tren-webhook".into())
+            .spawn(move || delivery.run(receiver))
+            .wrap_err("failed to start webhook thread")?;
+
+        Ok(Some(Webhook {
+            sender,
+            chargeback_threshold: config.chargeback_threshold,
+            delivery,
+        }))
+    }
+
+    /// Hook queueing notifications of the completed operations
+    pub fn hook(&self) -> Hook {
+        let (sender, threshold) = (self.sender.clone(), self.chargeback_threshold);
+        Box::new(move |completed| {
+            for event in events(completed, threshold) {
+                if sender.send(event).is_err() {
+                    error!("webhook thread stopped, dropping notification");
+                }
+            }
+        })
+    }
+
+    /// Waits until notifications queued by all hooks are delivered or run out of retries, the hooks have to be
+    /// dropped first. Returns number of undelivered notifications
+    pub fn finish(self) -> u64 {
+        drop(self.sender);
+        self.delivery.join().unwrap_or_else(|_| {
+            error!("webhook thread panicked");
+            0
+        })
+    }
+}
+
+/// Events of the completed operation. Chargeback freezes the account, so it is notified as frozen too
+fn events(completed: &Completed, threshold: Option<Amount>) -> Vec<Event> {
+    let event = |name| Event {
+        name,
+        client_id: completed.client_id,
+        transaction_id: completed.transaction_id,
+        amount: completed.amount,
+    };
+    match (completed.operation, completed.rejection) {
+        ("chargeback", None) => {
+            let large = threshold.is_none_or(|threshold| {
+                completed
+                    .amount
+                    .is_some_and(|amount| amount.abs() >= threshold)
+            });
+            match large {
+                true => vec![event("chargeback"), event("frozen")],
+                false => vec![event("frozen")],
+            }
+        }
+        ("lock", None) | (_, Some(AccountError::Overflow)) => vec![event("frozen")],
+        _ => Vec::new(),
+    }
+}
+
+/// Notification waiting for its next delivery attempt
+struct Pending {
+    event: Event,
+    body: String,
+    /// Failed attempts so far
+    attempts: u32,
+    due: Instant,
+}
+
+/// State of the delivery thread
+struct Delivery {
+    endpoint: Endpoint,
+    template: Option<String>,
+    retries: u32,
+    retry_delay: Duration,
+    retry_queue: usize,
+    failures: Option<File>,
+    /// Failed notifications waiting for their retry
+    retrying: Vec<Pending>,
+    undelivered: u64,
+}
+
+impl Delivery {
+    /// Delivers notifications until all senders are dropped and no retry is left, returns number of undelivered ones
+    fn run(mut self, receiver: Receiver<Event>) -> u64 {
+        let mut open = true;
+        loop {
+            let next_retry = self.retrying.iter().map(|pending| pending.due).min();
+            let received = match (open, next_retry) {
+                (true, Some(due)) => receiver.recv_deadline(due),
+                (true, None) => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                (false, Some(due)) => {
+                    std::thread::sleep(due.saturating_duration_since(Instant::now()));
+                    Err(RecvTimeoutError::Timeout)
+                }
+                (false, None) => break,
+            };
+            match received {
+                Ok(event) => {
+                    let body = render(self.template.as_deref(), &event);
+                    self.attempt(Pending {
+                        event,
+                        body,
+                        attempts: 0,
+                        due: Instant::now(),
+                    });
+                }
+                Err(RecvTimeoutError::Disconnected) => open = false,
+                Err(RecvTimeoutError::Timeout) => (),
+            }
+
+            let now = Instant::now();
+            let (due, waiting) = std::mem::take(&mut self.retrying)
+                .into_iter()
+                .partition(|pending| pending.due <= now);
+            self.retrying = waiting;
+            for pending in due {
+                self.attempt(pending);
+            }
+        }
+        self.undelivered
+    }
+
+    fn attempt(&mut self, mut pending: Pending) {
+        let err = match self.endpoint.post(&pending.body) {
+            Ok(()) => {
+                debug!(event = ?pending.event, "delivered webhook notification");
+                return;
+            }
+            Err(err) => err,
+        };
+        if pending.attempts >= self.retries {
+            return self.fail(pending, &err);
+        }
+        if self.retrying.len() >= self.retry_queue {
+            return self.fail(pending, &err.wrap_err("webhook retry queue is full"));
+        }
+        warn!(%err, attempt = pending.attempts, "failed to deliver webhook notification, retrying");
+        pending.due = Instant::now() + self.retry_delay * 2u32.saturating_pow(pending.attempts);
+        pending.attempts += 1;
+        self.retrying.push(pending);
+    }
+
+    /// Notification ran out of retries, it is written into the failure file
+    fn fail(&mut self, pending: Pending, err: &eyre::Report) {
+        error!(%err, event = ?pending.event, "failed to deliver webhook notification");
+        self.undelivered += 1;
+        if let Some(failures) = self.failures.as_mut() {
+            if let Err(err) = writeln!(failures, "{}", pending.body) {
+                error!(%err, body = pending.body, "failed to write undelivered webhook notification");
+            }
+        }
+    }
+}
+
+fn render(template: Option<&str>, event: &Event) -> String {
+    let (tx, amount) = (
+        event.transaction_id.map(|tx| tx.to_string()),
+        event.amount.map(|amount| amount.to_string()),
+    );
+    match template {
+        Some(template) => template
+            .replace("{event}", event.name)
+            .replace("{client}", &event.client_id.to_string())
+            .replace("{tx}", tx.as_deref().unwrap_or_default())
+            .replace("{amount}", amount.as_deref().unwrap_or_default()),
+        None => format!(
+            r#"{{"event":"{}","client":{},"tx":{},"amount":{}}}"#,
+            event.name,
+            event.client_id,
+            tx.as_deref().unwrap_or("null"),
+            amount.map_or("null".into(), |amount| format!(r#""{amount}""#))
+        ),
+    }
+}
+
+/// Host and path of `http://host[:port]/path`
+#[derive(Debug, PartialEq, Eq)]
+struct Endpoint {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> Result<Endpoint> {
+        let rest = url.strip_prefix("http://").ok_or(eyre!(
+            "unsupported webhook url '{url}', only http:// is supported"
+        ))?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .wrap_err_with(|| format!("invalid port in webhook url '{url}'"))?,
+            ),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(eyre!("webhook url '{url}' is missing host"));
+        }
+
+        Ok(Endpoint {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// Posts the body, fails unless the response status is 2xx
+    fn post(&self, body: &str) -> Result<()> {
+        let address = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or(eyre!("failed to resolve {}", self.host))?;
+        let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            self.path,
+            self.host,
+            body.len()
+        );
+        stream.write_all(request.as_bytes())?;
+
+        let mut status = [0; 12];
+        stream.read_exact(&mut status)?;
+        match &status[9..10] {
+            b"2" => Ok(()),
+            _ => Err(eyre!(
+                "webhook responded with {}",
+                String::from_utf8_lossy(&status[9..])
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_events() {
+        let completed = |operation, amount, rejection| Completed {
+            operation,
+            client_id: 1,
+            transaction_id: Some(2),
+            amount,
+            rejection,
+        };
+        let tests = vec![
+            (
+                "large chargeback",
+                completed("chargeback", Some(amount!(100)), None),
+                vec!["chargeback", "frozen"],
+            ),
+            (
+                "small chargeback",
+                completed("chargeback", Some(amount!(1)), None),
+                vec!["frozen"],
+            ),
+            ("lock", completed("lock", None, None), vec!["frozen"]),
+            (
+                "overflow",
+                completed("deposit", Some(amount!(1)), Some(&AccountError::Overflow)),
+                vec!["frozen"],
+            ),
+            (
+                "deposit",
+                completed("deposit", Some(amount!(1)), None),
+                vec![],
+            ),
+        ];
+
+        for (name, completed, want) in tests {
+            let got: Vec<_> = events(&completed, Some(amount!(50)))
+                .into_iter()
+                .map(|event| event.name)
+                .collect();
+            assert_eq!(got, want, "failed test {name}");
+        }
+    }
+
+    #[test]
+    fn test_post() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (mut request, mut buffer) = (Vec::new(), [0; 1024]);
+            while !request.ends_with(b"}") {
+                let len = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..len]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let endpoint = Endpoint::parse(&format!("http://127.0.0.1:{port}/risk")).unwrap();
+        let event = Event {
+            name: "frozen",
+            client_id: 7,
+            transaction_id: None,
+            amount: None,
+        };
+        endpoint.post(&render(None, &event)).unwrap();
+        let request = server.join().unwrap();
+
+        assert!(request.starts_with("POST /risk HTTP/1.1\r\n"), "{request}");
+        assert!(
+            request.ends_with(r#"{"event":"frozen","client":7,"tx":null,"amount":null}"#),
+            "{request}"
+        );
+        assert!(Endpoint::parse("https://example.com").is_err());
+    }
+
+    #[test]
+    fn test_retries() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // first notification is acknowledged on its retry, the second never is
+        let server = std::thread::spawn(move || {
+            let mut attempts_of_first = 0;
+            for _ in 0..4 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let (mut request, mut buffer) = (Vec::new(), [0; 1024]);
+                while !request.ends_with(b"}") {
+                    let len = stream.read(&mut buffer).unwrap();
+                    request.extend_from_slice(&buffer[..len]);
+                }
+                let first = String::from_utf8_lossy(&request).contains(r#""client":1,"#);
+                attempts_of_first += u32::from(first);
+                let status = match first && attempts_of_first > 1 {
+                    true => "200 OK",
+                    false => "503 Service Unavailable",
+                };
+                // single write, the client hangs up as soon as it has the status
+                stream
+                    .write_all(format!("HTTP/1.1 {status}\r\n\r\n").as_bytes())
+                    .unwrap();
+            }
+        });
+
+        let path =
+            std::env::temp_dir().join(format!("tren-test-webhook-{}.jsonl", std::process::id()));
+        let delivery = Delivery {
+            endpoint: Endpoint::parse(&format!("http://127.0.0.1:{port}/")).unwrap(),
+            template: None,
+            retries: 1,
+            retry_delay: Duration::from_millis(10),
+            retry_queue: 10,
+            failures: Some(File::create(&path).unwrap()),
+            retrying: Vec::new(),
+            undelivered: 0,
+        };
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        for client_id in [1, 2] {
+            sender
+                .send(Event {
+                    name: "frozen",
+                    client_id,
+                    transaction_id: None,
+                    amount: None,
+                })
+                .unwrap();
+        }
+        drop(sender);
+
+        assert_eq!(delivery.run(receiver), 1);
+        server.join().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "{\"event\":\"frozen\",\"client\":2,\"tx\":null,\"amount\":null}\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tren::accounts::{Accounts, DisputePolicy};
+use tren::aliases::*;
+use tren::channel::{Indexed, Sender, TransactionMessage};
+use tren::config::Config;
+use tren::dispute_look_up::DisputeResolver;
+use tren::parser::{CsvParser, Found, JournalSource};
+use tren::pipeline::PipelineBuilder;
+use tren::processor::Completed;
+use tren::spill::SpillingSender;
+use tren::summary::Summary;
+
+/// Embedder supplies its own accounts and observes every operation through a hook
+#[test]
+fn test_builder_with_accounts_and_hook() {
+    let journal = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/fixtures/totals_row.csv");
+    let (applied, rejected) = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0)));
+    let (hook_applied, hook_rejected) = (applied.clone(), rejected.clone());
+
+    let mut accounts = Accounts::new(DisputePolicy::Reject);
+    accounts.deposit(9, Amount::ONE).unwrap();
+    let (accounts, summary) = PipelineBuilder::new(Config {
+        progress: false,
+        ..Default::default()
+    })
+    .with_input(&journal)
+    .with_accounts(accounts)
+    .with_hook(Box::new(move |completed: &Completed| {
+        match completed.rejection {
+            None => hook_applied.fetch_add(1, Ordering::Relaxed),
+            Some(_) => hook_rejected.fetch_add(1, Ordering::Relaxed),
+        };
+    }))
+    .build()
+    .unwrap()
+    .run()
+    .unwrap();
+
+    assert_eq!(accounts.len(), 4, "pre-loaded account is kept");
+    assert_eq!(applied.load(Ordering::Relaxed), 4);
+    assert_eq!(rejected.load(Ordering::Relaxed), 0);
+    assert_eq!(summary.integrity_mismatches, 0);
+
+    assert!(
+        PipelineBuilder::new(Config::default()).build().is_err(),
+        "missing input"
+    );
+}
+
+/// Dispute look-up which can't look anything up
+struct PanickingLookUp;
+
+impl DisputeResolver for PanickingLookUp {
+    fn find_transaction(&mut self, _: ClientID, _: TransactionID) -> eyre::Result<Found> {
+        panic!("look-up store is down")
+    }
+}
+
+/// Panic of the dispute look-up fails the whole run instead of silently skipping disputes
+#[test]
+fn test_worker_panic_aborts_run() {
+    let journal =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/fixtures/dispute_resolve.csv");
+    let got = PipelineBuilder::new(Config {
+        progress: false,
+        ..Default::default()
+    })
+    .with_sources(
+        Box::new(CsvParser::new(File::open(journal).unwrap())),
+        Box::new(PanickingLookUp),
+    )
+    .build()
+    .unwrap()
+    .run();
+
+    let Err(err) = got else {
+        panic!("run with panicked worker succeeded");
+    };
+    assert_eq!(
+        err.to_string(),
+        "dispute look-up thread panicked: look-up store is down"
+    );
+}
+
+/// Upstream which sends a single deposit and then never sends anything again nor ends
+struct StuckFeed;
+
+impl DisputeResolver for StuckFeed {
+    fn find_transaction(
+        &mut self,
+        _: ClientID,
+        transaction_id: TransactionID,
+    ) -> eyre::Result<Found> {
+        Err(eyre::eyre!("transaction {transaction_id} not found"))
+    }
+}
+
+impl JournalSource for StuckFeed {
+    fn parse_journal(
+        &mut self,
+        transaction_sender: Sender<Indexed<TransactionMessage>>,
+        _: SpillingSender,
+    ) -> eyre::Result<Summary> {
+        transaction_sender.send(Indexed::new(
+            0,
+            TransactionMessage::deposit(1, 1, Amount::ONE),
+        ));
+        loop {
+            std::thread::park();
+        }
+    }
+}
+
+/// Stuck upstream doesn't keep the run waiting, the records applied so far are reported
+#[test]
+fn test_run_guards() {
+    let journal =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/fixtures/deposit_withdrawal.csv");
+    let (accounts, summary) = PipelineBuilder::new(Config {
+        progress: false,
+        max_runtime: Some(0),
+        ..Default::default()
+    })
+    .with_input(&journal)
+    .build()
+    .unwrap()
+    .run()
+    .unwrap();
+    assert_eq!(accounts.len(), 0, "stopped before the first record");
+    assert_eq!(summary.stopped_early, 1);
+    assert_eq!(
+        summary.journal_end.map(|end| end.index),
+        Some(0),
+        "next run continues with the first record"
+    );
+
+    let (accounts, summary) = PipelineBuilder::new(Config {
+        progress: false,
+        idle_timeout: Some(1),
+        ..Default::default()
+    })
+    .with_sources(Box::new(StuckFeed), Box::new(StuckFeed))
+    .build()
+    .unwrap()
+    .run()
+    .unwrap();
+    assert_eq!(accounts.len(), 1, "deposit sent before the feed got stuck");
+    assert_eq!(summary.stopped_early, 1);
+    assert_eq!(summary.journal_end, None);
+}
+
+/// Rejected records in the dead-letter file and operations in the audit trail point to their lines, quoted
+/// fields spanning lines and comments are counted
+#[test]
+fn test_provenance() {
+    let path = |name: &str| {
+        std::env::temp_dir().join(format!("tren-provenance-{name}-{}.csv", std::process::id()))
+    };
+    let (journal, dead_letter, audit) = (path("journal"), path("dead-letter"), path("audit"));
+    std::fs::write(
+        &journal,
+        "type,client,tx,amount,note\ndeposit,1,1,5,\"first\nsecond\"\nwithdrawal,1,2,10,\n# comment\nwithdrawal,2,3,1,\n",
+    )
+    .unwrap();
+
+    PipelineBuilder::new(Config {
+        progress: false,
+        provenance: true,
+        dead_letter: Some(dead_letter.clone()),
+        audit: Some(audit.clone()),
+        ..Default::default()
+    })
+    .with_input(&journal)
+    .build()
+    .unwrap()
+    .run()
+    .unwrap();
+
+    let sources = |path: &Path| -> Vec<String> {
+        let mut reader = csv::Reader::from_path(path).unwrap();
+        assert_eq!(reader.headers().unwrap().iter().next_back(), Some("source"));
+        reader
+            .records()
+            .map(|record| record.unwrap().iter().next_back().unwrap().to_string())
+            .collect()
+    };
+    let line = |line: u32| format!("{}:{line}", journal.display());
+    assert_eq!(sources(&dead_letter), vec![line(4), line(6)]);
+    assert_eq!(sources(&audit), vec![line(2), line(4), line(6)]);
+    for path in [&journal, &dead_letter, &audit] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
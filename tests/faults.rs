@@ -0,0 +1,179 @@
+//! Pipeline under injected faults, run with `cargo test --features fault-injection`
+#![cfg(feature = "fault-injection")]
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tren::accounts::{AccountView, Accounts};
+use tren::config::Config;
+use tren::dispute_look_up::BoxedResolver;
+use tren::fault::{ChannelFaults, Faults, FaultyReader, FaultyResolver};
+use tren::parser::CsvParser;
+use tren::pipeline::{BoxedSource, PipelineBuilder};
+use tren::summary::Summary;
+
+const JOURNAL: &str = "type,client,tx,amount
+deposit,1,1,1
+deposit,1,2,2
+deposit,1,3,3
+withdrawal,1,4,1
+dispute,1,2,
+deposit,1,5,4
+resolve,1,2,
+dispute,1,3,
+";
+
+fn journal(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("tren-faults-{name}-{}.csv", std::process::id()));
+    std::fs::write(&path, JOURNAL).unwrap();
+    path
+}
+
+fn run(
+    config: Config,
+    faults: Faults,
+    source: BoxedSource,
+    resolver: BoxedResolver,
+) -> (Accounts, Summary) {
+    PipelineBuilder::new(Config {
+        progress: false,
+        ..config
+    })
+    .with_sources(source, resolver)
+    .with_faults(faults)
+    .build()
+    .unwrap()
+    .run()
+    .unwrap()
+}
+
+fn parser(path: &Path) -> CsvParser<File> {
+    CsvParser::new(File::open(path).unwrap())
+}
+
+/// (available, held) of the only client
+fn balances(accounts: &Accounts) -> (String, String) {
+    let AccountView {
+        available, held, ..
+    } = accounts.get(1).unwrap();
+    (available.to_string(), held.to_string())
+}
+
+/// Delayed and duplicated messages don't change the result, disputes still reach the processing after the
+/// transactions they refer to and duplicates are skipped by the dedup window. Dropped messages are lost
+#[test]
+fn test_channel_faults() {
+    let path = journal("channel");
+    let delay = Duration::from_millis(2);
+    let dedup = Config {
+        dedup_window: Some(16),
+        ..Default::default()
+    };
+    let tests = vec![
+        (
+            "no faults",
+            Config::default(),
+            Faults::default(),
+            ("6", "3"),
+            0,
+        ),
+        (
+            "delayed",
+            Config::default(),
+            Faults {
+                parsed: ChannelFaults::default().with_delay_every(1, delay),
+                looked_up: ChannelFaults::default().with_delay_every(1, delay),
+            },
+            ("6", "3"),
+            0,
+        ),
+        (
+            "duplicated",
+            dedup,
+            Faults {
+                parsed: ChannelFaults::default().with_duplicate_every(2),
+                looked_up: ChannelFaults::default().with_duplicate_every(1),
+            },
+            ("6", "3"),
+            2,
+        ),
+        (
+            "dropped deposit",
+            Config::default(),
+            Faults {
+                parsed: ChannelFaults::default().with_drop_every(5),
+                ..Default::default()
+            },
+            ("2", "3"),
+            0,
+        ),
+        (
+            "dropped dispute",
+            Config::default(),
+            Faults {
+                looked_up: ChannelFaults::default().with_drop_every(3),
+                ..Default::default()
+            },
+            ("9", "0"),
+            0,
+        ),
+    ];
+
+    for (name, config, faults, (available, held), duplicates) in tests {
+        let (accounts, summary) = run(
+            config,
+            faults,
+            Box::new(parser(&path)),
+            Box::new(parser(&path)),
+        );
+        assert_eq!(
+            balances(&accounts),
+            (available.to_string(), held.to_string()),
+            "failed test {name}"
+        );
+        assert_eq!(
+            summary.duplicate_transactions, duplicates,
+            "failed test {name}"
+        );
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// Read error in the middle of the journal stops the parser and fails the run, no accounts are reported from
+/// the part of the journal before it
+#[test]
+fn test_read_error() {
+    let path = journal("read");
+    let fail_at = JOURNAL.find("withdrawal").unwrap() as u64;
+    let source = CsvParser::new(FaultyReader::new(File::open(&path).unwrap(), fail_at))
+        .with_trim_whitespace(Some(false));
+
+    let result = PipelineBuilder::new(Config {
+        progress: false,
+        ..Default::default()
+    })
+    .with_sources(Box::new(source), Box::new(parser(&path)))
+    .with_faults(Faults::default())
+    .build()
+    .unwrap()
+    .run();
+    let err = result.err().expect("run has to fail on the read error");
+    assert!(
+        format!("{err:#}").contains("failed to parse transaction journal"),
+        "unexpected error {err:#}"
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// Failed look-ups skip the disputes and the resolve of a dispute which never started, the run still succeeds
+#[test]
+fn test_look_up_errors() {
+    let path = journal("look-up");
+    let (accounts, _) = run(
+        Config::default(),
+        Faults::default(),
+        Box::new(parser(&path)),
+        Box::new(FaultyResolver::new(parser(&path), 1)),
+    );
+    assert_eq!(balances(&accounts), ("9".to_string(), "0".to_string()));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,48 @@
+use std::path::Path;
+
+/// Fixtures with amounts of more than 4 decimal places, which are rejected with `fixed-amount` feature
+#[cfg(feature = "fixed-amount")]
+const PRECISE_FIXTURES: &[&str] = &["rounding"];
+
+/// Runs every golden-file fixture from `test_data/fixtures` through the full pipeline
+#[test]
+fn test_fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/fixtures");
+    let fixtures = tren::fixtures::discover(&dir).expect("failed to discover fixtures");
+    assert!(
+        !fixtures.is_empty(),
+        "no fixtures found in {}",
+        dir.display()
+    );
+
+    for fixture in fixtures {
+        #[cfg(feature = "fixed-amount")]
+        if PRECISE_FIXTURES.contains(&fixture.name.as_str()) {
+            continue;
+        }
+        let mismatch = fixture
+            .run()
+            .unwrap_or_else(|err| panic!("failed fixture {}: {err:#}", fixture.name));
+        assert!(mismatch.is_none(), "failed fixture {mismatch:#?}");
+    }
+}
+
+/// Journals deep in the directory tree of batch jobs have paths over the 260 characters of Windows' `MAX_PATH`
+#[test]
+fn test_long_path() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/fixtures");
+    let root = std::env::temp_dir().join(format!("tren-test-long-path-{}", std::process::id()));
+    let dir = (0..8).fold(root.clone(), |dir, level| {
+        dir.join(format!("{level}-{}", "nested".repeat(7)))
+    });
+    assert!(dir.as_os_str().len() > 260);
+    std::fs::create_dir_all(&dir).unwrap();
+    for file in ["crlf_line_endings.csv", "crlf_line_endings.expected"] {
+        std::fs::copy(fixtures.join(file), dir.join(file)).unwrap();
+    }
+
+    let fixtures = tren::fixtures::discover(&dir).expect("failed to discover fixtures");
+    let mismatch = fixtures[0].run();
+    std::fs::remove_dir_all(&root).unwrap();
+    assert!(matches!(mismatch, Ok(None)), "failed fixture {mismatch:#?}");
+}
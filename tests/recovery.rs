@@ -0,0 +1,98 @@
+use std::path::Path;
+use tren::accounts::AccountView;
+use tren::config::Config;
+use tren::pipeline;
+
+fn sorted(accounts: &tren::accounts::Accounts) -> Vec<AccountView> {
+    let mut views: Vec<_> = accounts.iter().collect();
+    views.sort_by_key(|view| view.client_id);
+    views
+}
+
+/// Interrupted run continued from its write-ahead log ends with the same accounts as a single run
+#[test]
+fn test_recover_and_skip_applied() {
+    let journal =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/fixtures/dispute_resolve.csv");
+    let wal = std::env::temp_dir().join(format!("tren-recovery-{}.wal", std::process::id()));
+    let config = Config {
+        progress: false,
+        ..Default::default()
+    };
+
+    let (want, _) = pipeline::run(&journal, config.clone()).unwrap();
+    let interrupted = pipeline::run(
+        &journal,
+        Config {
+            limit: Some(3),
+            wal: Some(wal.clone()),
+            ..config.clone()
+        },
+    );
+    let continued = pipeline::run(
+        &journal,
+        Config {
+            recover: Some(wal.clone()),
+            wal: Some(wal.clone()),
+            ..config
+        },
+    );
+    std::fs::remove_file(&wal).unwrap();
+
+    interrupted.unwrap();
+    let (got, summary) = continued.unwrap();
+    assert_eq!(sorted(&got), sorted(&want));
+    assert!(summary.recovered_operations > 0);
+    assert_eq!(summary.already_applied, summary.recovered_operations);
+}
+
+/// Journal processed in two runs, the second one parsing only the appended records, ends with the same accounts
+#[test]
+fn test_since_offset() {
+    let fixture =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/fixtures/dispute_resolve.csv");
+    let content = std::fs::read_to_string(&fixture).unwrap();
+    let head = &content[..content.find("dispute").unwrap()];
+    let temp = |extension: &str| {
+        std::env::temp_dir().join(format!("tren-since-{}.{extension}", std::process::id()))
+    };
+    let (journal, wal, offset_file) = (temp("csv"), temp("wal"), temp("offset"));
+    let config = Config {
+        progress: false,
+        wal: Some(wal.clone()),
+        since_offset: Some(offset_file.clone()),
+        ..Default::default()
+    };
+
+    std::fs::write(&journal, head).unwrap();
+    let first = pipeline::run(&journal, config.clone());
+    std::fs::write(&journal, &content).unwrap();
+    let second = pipeline::run(
+        &journal,
+        Config {
+            recover: Some(wal.clone()),
+            ..config
+        },
+    );
+    let end = std::fs::read_to_string(&offset_file);
+    for file in [&journal, &wal, &offset_file] {
+        std::fs::remove_file(file).unwrap();
+    }
+
+    first.unwrap();
+    let (got, summary) = second.unwrap();
+    let (want, _) = pipeline::run(
+        &fixture,
+        Config {
+            progress: false,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(sorted(&got), sorted(&want));
+    assert_eq!(
+        summary.already_applied, 0,
+        "records before the offset are not parsed"
+    );
+    assert_eq!(end.unwrap(), format!("offset {}\nindex 4\n", content.len()));
+}